@@ -0,0 +1,543 @@
+use axum::{
+	extract::{Path as UrlPath, State},
+	http::{header, HeaderMap, StatusCode, Uri},
+	response::{IntoResponse, Redirect, Response},
+	routing::get,
+	Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use dashmap::DashMap;
+use instant_acme::{
+	Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, LetsEncrypt,
+	NewAccount, NewOrder, OrderStatus,
+};
+use rcgen::{Certificate, CertificateParams, DistinguishedName};
+use sluggy_core::{
+	err,
+	error::{Error, Result},
+	map_err,
+};
+use std::{
+	fs,
+	io::Write,
+	net::TcpListener,
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::{Duration, SystemTime},
+};
+use tokio::time::sleep;
+use tracing::instrument;
+
+/// Path which the ACME server requests the key authorization of each HTTP-01 challenge from.
+const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/:token";
+
+/// Credentials of the ACME account, which is created on the first start.
+const ACCOUNT_FILE: &str = "account.json";
+
+/// Let's Encrypt certificates are valid for 90 days, so they're renewed 30 days before they
+/// expire.
+const RENEW_AFTER: Duration = Duration::from_secs(60 * 24 * 60 * 60);
+
+/// How often the age of the certificate is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How long to wait before trying again after a renewal fails.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Times the order is polled while it's being validated, and while the certificate is issued.
+const MAX_POLLS: u32 = 10;
+
+/// Certificates of `domains` which are issued by an ACME server, such as Let's Encrypt, with
+/// HTTP-01 challenges, and kept in `dir`.
+#[derive(Debug, Clone)]
+pub struct Acme {
+	domains: Vec<String>,
+	/// Contact URLs of the account, e.g. `mailto:admin@example.com`.
+	contact: Vec<String>,
+	/// URL of the directory of the ACME server.
+	directory: String,
+	/// Whether the terms of service of the ACME server are agreed to when the account is created.
+	terms_of_service_agreed: bool,
+	dir: PathBuf,
+	/// Port of the HTTP server which answers challenges.
+	http_port: u16,
+	/// Token -> key authorization of each pending challenge.
+	challenges: Arc<DashMap<String, String>>,
+}
+
+#[derive(Debug, Clone)]
+struct ChallengeState {
+	challenges: Arc<DashMap<String, String>>,
+	https_port: u16,
+}
+
+impl Acme {
+	/// Certificates are requested from `directory`, or otherwise Let's Encrypt's production or
+	/// staging server. Accounts aren't created unless its terms of service are agreed to.
+	pub fn new(
+		domains: Vec<String>,
+		contact: Vec<String>,
+		directory: Option<String>,
+		staging: bool,
+		terms_of_service_agreed: bool,
+		dir: PathBuf,
+		http_port: u16,
+	) -> Result<Self> {
+		if domains.is_empty() {
+			return Err(err!(Validation("`domains` is empty")));
+		}
+		if !terms_of_service_agreed {
+			return Err(err!(Validation(
+				"the terms of service of the ACME server must be agreed to with \
+				 `terms_of_service_agreed = true`"
+			)));
+		}
+
+		let directory = directory.unwrap_or_else(|| {
+			if staging {
+				LetsEncrypt::Staging.url().to_string()
+			} else {
+				LetsEncrypt::Production.url().to_string()
+			}
+		});
+
+		Ok(Self {
+			domains,
+			contact,
+			directory,
+			terms_of_service_agreed,
+			dir,
+			http_port,
+			challenges: Arc::new(DashMap::new()),
+		})
+	}
+
+	/// Starts the HTTP server which answers challenges, and redirects everything else to HTTPS on
+	/// `https_port`.
+	pub fn serve_challenges(&self, host: &str, https_port: u16) -> Result<()> {
+		let address = format!("{host}:{}", self.http_port);
+		let listener = map_err!(
+			TcpListener::bind(&address),
+			IoError(format!("Unable to bind to {address}")),
+		)?;
+
+		let app = Router::new()
+			.route(CHALLENGE_PATH, get(challenge_handler))
+			.fallback(https_redirect_handler)
+			.with_state(ChallengeState {
+				challenges: self.challenges.clone(),
+				https_port,
+			});
+		let server = axum::Server::from_tcp(listener)?.serve(app.into_make_service());
+
+		tokio::spawn(async move {
+			if let Err(error) = server.await {
+				tracing::error!(%error, "ACME challenge server error");
+			}
+		});
+
+		Ok(())
+	}
+
+	/// TLS config with the kept certificate, which is issued first when there's none or it's due
+	/// to be renewed.
+	#[instrument(skip(self))]
+	pub async fn rustls_config(&self) -> Result<RustlsConfig> {
+		let (cert, key) = if self.is_due() {
+			self.issue().await?
+		} else {
+			let cert = map_err!(
+				fs::read(self.cert_path()),
+				IoError("failed to read the ACME certificate"),
+			)?;
+			let key = map_err!(
+				fs::read(self.key_path()),
+				IoError("failed to read the ACME certificate's key"),
+			)?;
+			(cert, key)
+		};
+
+		map_err!(
+			RustlsConfig::from_pem(cert, key).await,
+			IoError("failed to load the ACME certificate"),
+		)
+	}
+
+	/// Renews the certificate when it's due, and swaps it into `rustls_config` without
+	/// restarting the server.
+	pub async fn renew(self, rustls_config: RustlsConfig) {
+		loop {
+			sleep(CHECK_INTERVAL).await;
+
+			while self.is_due() {
+				let renewed = match self.issue().await {
+					Ok((cert, key)) => map_err!(
+						rustls_config.reload_from_pem(cert, key).await,
+						IoError("failed to load the renewed ACME certificate"),
+					),
+					Err(error) => Err(error),
+				};
+
+				match renewed {
+					Ok(()) => tracing::info!(domains = ?self.domains, "Renewed certificate"),
+					Err(error) => {
+						tracing::error!(%error, "Unable to renew certificate");
+						sleep(RETRY_INTERVAL).await;
+					}
+				}
+			}
+		}
+	}
+
+	/// Whether there's no certificate yet, or it's old enough to be renewed.
+	#[inline]
+	fn is_due(&self) -> bool {
+		let modified = fs::metadata(self.cert_path()).and_then(|metadata| metadata.modified());
+		match (modified, fs::metadata(self.key_path())) {
+			(Ok(modified), Ok(_)) => SystemTime::now()
+				.duration_since(modified)
+				.is_ok_and(|age| age >= RENEW_AFTER),
+			_ => true,
+		}
+	}
+
+	/// Orders a certificate of the domains, answering their challenges, and keeps it in `dir`.
+	#[instrument(skip(self))]
+	async fn issue(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+		let account = self.account().await?;
+
+		let identifiers = self
+			.domains
+			.iter()
+			.map(|domain| Identifier::Dns(domain.clone()))
+			.collect::<Vec<_>>();
+		let mut order = account
+			.new_order(&NewOrder {
+				identifiers: &identifiers,
+			})
+			.await
+			.map_err(acme_error)?;
+
+		let authorizations = order.authorizations().await.map_err(acme_error)?;
+		let mut challenge_urls = vec![];
+		for authorization in &authorizations {
+			if matches!(authorization.status, AuthorizationStatus::Valid) {
+				continue;
+			}
+
+			let Identifier::Dns(domain) = &authorization.identifier;
+			let challenge = authorization
+				.challenges
+				.iter()
+				.find(|challenge| challenge.r#type == ChallengeType::Http01)
+				.ok_or_else(|| {
+					Error::Validation(format!("no HTTP-01 challenge was offered for {domain}"))
+				})?;
+
+			self.challenges.insert(
+				challenge.token.clone(),
+				order.key_authorization(challenge).as_str().to_string(),
+			);
+			challenge_urls.push(challenge.url.clone());
+		}
+
+		for challenge_url in &challenge_urls {
+			order
+				.set_challenge_ready(challenge_url)
+				.await
+				.map_err(acme_error)?;
+		}
+
+		let mut delay = Duration::from_millis(250);
+		let mut status = order.state().status;
+		for _ in 0..MAX_POLLS {
+			if matches!(status, OrderStatus::Ready | OrderStatus::Invalid) {
+				break;
+			}
+
+			sleep(delay).await;
+			delay = (delay * 2).min(Duration::from_secs(10));
+			status = order.refresh().await.map_err(acme_error)?.status;
+		}
+		self.challenges.clear();
+
+		if !matches!(status, OrderStatus::Ready) {
+			return Err(Error::Validation(format!(
+				"the certificate order of {:?} is {status:?}, not ready",
+				self.domains
+			)));
+		}
+
+		let mut params = CertificateParams::new(self.domains.clone());
+		params.distinguished_name = DistinguishedName::new();
+		let certificate = Certificate::from_params(params).map_err(|error| {
+			Error::Validation(format!("failed to generate a certificate key: {error}"))
+		})?;
+		let csr = certificate.serialize_request_der().map_err(|error| {
+			Error::Validation(format!("failed to generate a certificate request: {error}"))
+		})?;
+		order.finalize(&csr).await.map_err(acme_error)?;
+
+		let mut cert = None;
+		for _ in 0..MAX_POLLS {
+			cert = order.certificate().await.map_err(acme_error)?;
+			if cert.is_some() {
+				break;
+			}
+			sleep(Duration::from_secs(1)).await;
+		}
+		let cert = cert.ok_or_else(|| {
+			Error::Validation(format!(
+				"the certificate of {:?} wasn't issued in time",
+				self.domains
+			))
+		})?;
+		let key = certificate.serialize_private_key_pem();
+
+		map_err!(
+			fs::create_dir_all(&self.dir),
+			IoError(format!("failed to create {}", self.dir.display())),
+		)?;
+		write_private(&self.key_path(), key.as_bytes())?;
+		map_err!(
+			fs::write(self.cert_path(), &cert),
+			IoError("failed to write the ACME certificate"),
+		)?;
+
+		Ok((cert.into_bytes(), key.into_bytes()))
+	}
+
+	/// The account of the kept credentials, or a new account, whose credentials are kept.
+	#[inline]
+	async fn account(&self) -> Result<Account> {
+		let account_path = self.dir.join(ACCOUNT_FILE);
+		if let Ok(credentials) = fs::read_to_string(&account_path) {
+			let credentials: AccountCredentials = map_err!(
+				serde_json::from_str(&credentials),
+				SerdeJsonError(format!("failed to parse {}", account_path.display())),
+			)?;
+			return Account::from_credentials(credentials)
+				.await
+				.map_err(acme_error);
+		}
+
+		let contact = self.contact.iter().map(String::as_str).collect::<Vec<_>>();
+		let (account, credentials) = Account::create(
+			&NewAccount {
+				contact: &contact,
+				terms_of_service_agreed: self.terms_of_service_agreed,
+				only_return_existing: false,
+			},
+			&self.directory,
+			None,
+		)
+		.await
+		.map_err(acme_error)?;
+
+		let credentials = map_err!(
+			serde_json::to_string_pretty(&credentials),
+			SerdeJsonError("failed to serialize the ACME account"),
+		)?;
+		map_err!(
+			fs::create_dir_all(&self.dir),
+			IoError(format!("failed to create {}", self.dir.display())),
+		)?;
+		write_private(&account_path, credentials.as_bytes())?;
+
+		Ok(account)
+	}
+
+	/// Certificates are kept by their domains, so that a new one is issued when they change.
+	#[inline]
+	fn cert_path(&self) -> PathBuf {
+		self.dir.join(format!("{}.pem", self.domains.join("+")))
+	}
+
+	#[inline]
+	fn key_path(&self) -> PathBuf {
+		self.dir.join(format!("{}.key.pem", self.domains.join("+")))
+	}
+}
+
+/// Writes `contents` to `path`, which only the owner can read, as it's a key or has one.
+#[inline]
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+	let mut options = fs::OpenOptions::new();
+	options.write(true).create(true).truncate(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+		options.mode(0o600);
+		// Files which were written before keep their mode unless it's set.
+		if path.exists() {
+			map_err!(
+				fs::set_permissions(path, fs::Permissions::from_mode(0o600)),
+				IoError(format!(
+					"failed to set the permissions of {}",
+					path.display()
+				)),
+			)?;
+		}
+	}
+
+	let mut file = map_err!(
+		options.open(path),
+		IoError(format!("failed to open {}", path.display())),
+	)?;
+	map_err!(
+		file.write_all(contents),
+		IoError(format!("failed to write {}", path.display())),
+	)
+}
+
+#[inline]
+fn acme_error(error: instant_acme::Error) -> Error {
+	Error::Validation(format!("ACME request failed: {error}"))
+}
+
+#[inline]
+async fn challenge_handler(
+	State(state): State<ChallengeState>,
+	UrlPath(token): UrlPath<String>,
+) -> Response {
+	match state.challenges.get(&token) {
+		Some(key_authorization) => (
+			[(header::CONTENT_TYPE, "text/plain")],
+			key_authorization.value().clone(),
+		)
+			.into_response(),
+		None => StatusCode::NOT_FOUND.into_response(),
+	}
+}
+
+/// Redirects requests over HTTP to the same path over HTTPS.
+#[inline]
+async fn https_redirect_handler(
+	State(state): State<ChallengeState>,
+	headers: HeaderMap,
+	uri: Uri,
+) -> Response {
+	let Some(host) = headers
+		.get(header::HOST)
+		.and_then(|host| host.to_str().ok())
+		.map(|host| host.split(':').next().unwrap_or(host))
+	else {
+		return StatusCode::BAD_REQUEST.into_response();
+	};
+
+	let path = uri
+		.path_and_query()
+		.map(|path_and_query| path_and_query.as_str())
+		.unwrap_or("/");
+	let location = match state.https_port {
+		443 => format!("https://{host}{path}"),
+		port => format!("https://{host}:{port}{path}"),
+	};
+
+	Redirect::permanent(&location).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn acme(dir: PathBuf) -> Acme {
+		Acme::new(
+			vec!["example.com".into(), "www.example.com".into()],
+			vec![],
+			None,
+			true,
+			true,
+			dir,
+			80,
+		)
+		.unwrap()
+	}
+
+	#[test]
+	fn new_rejects_empty_domains() {
+		let result = Acme::new(vec![], vec![], None, false, true, PathBuf::from("acme"), 80);
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn new_requires_terms_of_service_to_be_agreed() {
+		let result = Acme::new(
+			vec!["example.com".into()],
+			vec![],
+			None,
+			false,
+			false,
+			PathBuf::from("acme"),
+			80,
+		);
+		assert!(result
+			.unwrap_err()
+			.to_string()
+			.contains("terms_of_service_agreed"));
+	}
+
+	#[test]
+	fn new_defaults_to_lets_encrypt() {
+		let acme = acme(PathBuf::from("acme"));
+		assert_eq!(acme.directory, LetsEncrypt::Staging.url());
+
+		let acme = Acme::new(
+			vec!["example.com".into()],
+			vec![],
+			Some("https://acme.example.com/directory".into()),
+			true,
+			true,
+			PathBuf::from("acme"),
+			80,
+		)
+		.unwrap();
+		assert_eq!(acme.directory, "https://acme.example.com/directory");
+	}
+
+	#[test]
+	fn certificates_are_kept_by_their_domains() {
+		let acme = acme(PathBuf::from("acme"));
+		assert_eq!(
+			acme.cert_path(),
+			PathBuf::from("acme/example.com+www.example.com.pem")
+		);
+		assert_eq!(
+			acme.key_path(),
+			PathBuf::from("acme/example.com+www.example.com.key.pem")
+		);
+	}
+
+	#[test]
+	fn is_due_without_a_certificate() {
+		let dir = tempfile::tempdir().unwrap();
+		let acme = acme(dir.path().to_path_buf());
+		assert!(acme.is_due());
+
+		fs::write(acme.cert_path(), "cert").unwrap();
+		assert!(acme.is_due(), "the key is missing");
+
+		fs::write(acme.key_path(), "key").unwrap();
+		assert!(!acme.is_due());
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn write_private_is_only_readable_by_the_owner() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join(ACCOUNT_FILE);
+
+		write_private(&path, b"{}").unwrap();
+		let mode = fs::metadata(&path).unwrap().permissions().mode();
+		assert_eq!(mode & 0o777, 0o600);
+
+		fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+		write_private(&path, b"{ }").unwrap();
+		let mode = fs::metadata(&path).unwrap().permissions().mode();
+		assert_eq!(mode & 0o777, 0o600);
+		assert_eq!(fs::read_to_string(&path).unwrap(), "{ }");
+	}
+}
@@ -1,21 +1,60 @@
-use miette::{Context, IntoDiagnostic};
+use miette::{miette, Context, IntoDiagnostic};
+use regex::Regex;
 use serde::Deserialize;
+use sluggy_core::error::Error;
+#[cfg(feature = "serve-store")]
+use sluggy_core::store::InMemoryStore;
 use sluggy_core::{
-	common::http::ContentEncoding,
-	store::{InMemoryStore, NoStore},
+	encoding::ContentEncoding,
+	generate::config::{
+		ArchivesConfig, BlogrollConfig, BudgetsConfig, CalendarConfig, CompressionConfig,
+		ContentFilesConfig, DiagramsConfig, FeedConfig, FetchConfig, FingerprintConfig,
+		ImagesConfig, JsConfig, KindConfig, MarkdownConfig, MediaConfig, PostprocessConfig,
+		RedirectConfig, ReportChanges, SanitizeConfig, SortBy, StaleOutputs, StructuredDataConfig,
+		TaxonomyConfig, TransformConfig, UrlConflicts, Version,
+	},
+	store::NoStore,
 };
-use std::{env, path::PathBuf, str::FromStr};
-use toml::Value;
+use std::{
+	collections::BTreeMap,
+	env, fs,
+	path::{Path, PathBuf},
+	str::FromStr,
+	time::Duration,
+};
+use toml::{Table, Value};
 
-use crate::server::{ServerConfig as SluggyServerConfig, Store as ServerStore};
-use sluggy_core::generate::config::Config as SluggyGenerateConfig;
+#[cfg(feature = "acme")]
+use crate::acme::Acme;
+#[cfg(feature = "s3")]
+use crate::bucket::{Bucket, Credentials};
+use crate::deploy::Target;
+#[cfg(feature = "purge")]
+use crate::purge::{Provider, Purge};
+#[cfg(feature = "tls")]
+use crate::server::Tls;
+use crate::server::{
+	AssetProxy, BuildError, HttpConfig as SluggyHttpConfig, LastChanges, LiveReload, OnDemandPages,
+	PageHeaders, Redirects, RequestLog, ServerConfig as SluggyServerConfig, Store as ServerStore,
+};
+use sluggy_core::generate::{config::Config as SluggyGenerateConfig, Hooks};
 
 pub const DEFAULT_OUT_DIR: &str = "./out";
 pub const DEFAULT_CONTENT_DIR: &str = "./content";
 pub const DEFAULT_CSS_DIR: &str = "./css";
+pub const DEFAULT_JS_DIR: &str = "./js";
 pub const DEFAULT_TEMPLATES_DIR: &str = "./templates";
 pub const DEFAULT_ASSETS_DIR: &str = "./assets";
 pub const DEFAULT_DATA_DIR: &str = "./data";
+pub const DEFAULT_CACHE_DIR: &str = "./.sluggy-cache";
+pub const DEFAULT_TESTS_DIR: &str = "./tests";
+pub const DEFAULT_SYNTAXES_DIR: &str = "./syntaxes";
+pub const DEFAULT_STREAM_THRESHOLD_BYTES: u64 = 8 * 1024 * 1024;
+pub const DEFAULT_DEBOUNCE_MS: u64 = 250;
+pub const DEFAULT_LOCK_TIMEOUT_SECS: u64 = 60;
+pub const DEFAULT_HTTP2_KEEP_ALIVE_TIMEOUT_SECS: u64 = 20;
+#[cfg(feature = "s3")]
+pub const DEFAULT_BUCKET_REFRESH_SECS: u64 = 60;
 
 pub const PROTECTED_COMPRESSION_DIR_NAME: &str = "___compressed";
 pub const PROCESSED_IMAGES_DIR: &str = "___processed_images";
@@ -25,26 +64,382 @@ pub struct Config {
 	pub out_dir: Option<PathBuf>,
 	pub compress_content: Option<bool>,
 	pub compressed_content_dir: Option<PathBuf>,
+	#[serde(default)]
+	pub compression: CompressionConfig,
+	#[serde(default)]
+	pub postprocess: PostprocessConfig,
 	pub processed_images_dir: Option<PathBuf>,
 	pub generate: GenerateConfig,
 	pub serve: ServeConfig,
+	#[serde(default)]
+	pub watch: WatchConfig,
+	/// Overrides which are selected with `--profile`, keyed on their name.
+	#[serde(default)]
+	pub profiles: BTreeMap<String, ProfileConfig>,
+	#[serde(default)]
+	pub deploy: DeployConfig,
+	/// The config file and the files it includes, which are watched while serving.
+	#[serde(skip)]
+	pub files: Vec<PathBuf>,
+}
+
+/// Settings which differ between builds of the same site, such as local and deployed builds.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ProfileConfig {
+	pub base_url: Option<String>,
+	pub minify: Option<bool>,
+	pub compress_content: Option<bool>,
+	pub compression: Option<CompressionConfig>,
+	/// Include drafts and entries which are published in the future.
+	pub drafts: Option<bool>,
+}
+
+impl Config {
+	/// Overrides the config with the profile called `name`. Returns whether drafts are included,
+	/// if the profile sets it.
+	pub fn apply_profile(&mut self, name: &str) -> miette::Result<Option<bool>> {
+		let profile = self.profiles.remove(name).ok_or_else(|| {
+			miette!("Unknown profile `{name}`, which is configured with `[profiles.{name}]`")
+		})?;
+
+		if let Some(base_url) = profile.base_url {
+			self.generate.base_url = Some(base_url);
+		}
+		if let Some(minify) = profile.minify {
+			self.generate.minify = Some(minify);
+		}
+		if let Some(compress_content) = profile.compress_content {
+			self.compress_content = Some(compress_content);
+		}
+		if let Some(compression) = profile.compression {
+			self.compression = compression;
+		}
+
+		Ok(profile.drafts)
+	}
+
+	/// Reads the config file at `path`, merged over the config files of its `include` array. Paths
+	/// of includes are relative to the file which includes them, and they're merged in order, so
+	/// later files take precedence. Tables are merged, while other values, including arrays, are
+	/// replaced. Dirs and files which included files set are relative to the included file.
+	pub fn from_file(path: &Path) -> miette::Result<Self> {
+		let source = read_config_file(path)?;
+		let table: Table = parse_config_file(path, &source)?;
+		if !table.contains_key("include") {
+			// Parsed again, so that errors are labelled in the source.
+			let mut config: Self = parse_config_file(path, &source)?;
+			config.files = vec![path.to_path_buf()];
+			return Ok(config);
+		}
+
+		let mut files = vec![path.to_path_buf()];
+		let table = merge_includes(path, table, &mut vec![path.to_path_buf()], &mut files)?;
+		let mut config: Self = Value::Table(table)
+			.try_into()
+			.into_diagnostic()
+			.wrap_err_with(|| format!("Failed to parse config file {}", path.display()))?;
+		config.files = files;
+
+		Ok(config)
+	}
+}
+
+#[inline]
+fn read_config_file(path: &Path) -> miette::Result<String> {
+	fs::read_to_string(path)
+		.into_diagnostic()
+		.wrap_err_with(|| format!("Failed to read config file {}", path.display()))
+}
+
+#[inline]
+fn parse_config_file<T: serde::de::DeserializeOwned>(
+	path: &Path,
+	source: &str,
+) -> Result<T, Error> {
+	toml::from_str(source).map_err(|error| {
+		Error::toml_in_source(
+			"Failed to parse config file".into(),
+			path.display().to_string(),
+			source,
+			error,
+		)
+	})
+}
+
+/// Settings of dirs and files, which are relative to the working dir, or to the config file which
+/// sets them when it's included. `*` is each table of an array.
+const PATH_SETTINGS: &[&[&str]] = &[
+	&["out_dir"],
+	&["generate", "content_dir"],
+	&["generate", "css_dir"],
+	&["generate", "js_dir"],
+	&["generate", "template_dir"],
+	&["generate", "assets_dir"],
+	&["generate", "data_dir"],
+	&["generate", "cache_dir"],
+	&["generate", "tests_dir"],
+	&["generate", "syntaxes_dir"],
+	&["generate", "output_manifest"],
+	&["generate", "versions", "*", "content_dir"],
+	&["serve", "tls", "cert"],
+	&["serve", "tls", "key"],
+	&["serve", "tls", "acme", "dir"],
+];
+
+/// Merges `table`, of the config file at `path`, over the files it includes. `including` are the
+/// files which are being included, to catch files which include themselves, and each included file
+/// is added to `files`.
+fn merge_includes(
+	path: &Path,
+	mut table: Table,
+	including: &mut Vec<PathBuf>,
+	files: &mut Vec<PathBuf>,
+) -> miette::Result<Table> {
+	let includes = match table.remove("include") {
+		None => vec![],
+		Some(Value::Array(includes)) => includes,
+		Some(_) => {
+			return Err(miette!(
+				"`include` of {} must be an array of paths",
+				path.display()
+			))
+		}
+	};
+
+	let dir = path.parent().unwrap_or_else(|| Path::new("."));
+	let mut merged = Table::new();
+	for include in includes {
+		let Value::String(include) = include else {
+			return Err(miette!(
+				"`include` of {} must be an array of paths",
+				path.display()
+			));
+		};
+		let include_path = dir
+			.join(&include)
+			.canonicalize()
+			.into_diagnostic()
+			.wrap_err_with(|| {
+				format!(
+					"Failed to find config file {include}, included by {}",
+					path.display()
+				)
+			})?;
+		if including.contains(&include_path) {
+			return Err(miette!(
+				"{} includes itself, through {}",
+				include_path.display(),
+				path.display()
+			));
+		}
+
+		let source = read_config_file(&include_path)?;
+		let mut included = parse_config_file(&include_path, &source)?;
+		if let Some(include_dir) = include_path.parent() {
+			for keys in PATH_SETTINGS {
+				resolve_path(&mut included, keys, include_dir);
+			}
+		}
+		if !files.contains(&include_path) {
+			files.push(include_path.clone());
+		}
+		including.push(include_path.clone());
+		let included = merge_includes(&include_path, included, including, files)?;
+		including.pop();
+
+		merge_table(&mut merged, included);
+	}
+	merge_table(&mut merged, table);
+
+	Ok(merged)
+}
+
+/// Resolves the relative path of the setting at `keys` of `table` against `dir`.
+fn resolve_path(table: &mut Table, keys: &[&str], dir: &Path) {
+	let [key, keys @ ..] = keys else {
+		return;
+	};
+
+	match (table.get_mut(*key), keys) {
+		(Some(Value::String(path)), []) => {
+			if Path::new(path.as_str()).is_relative() {
+				*path = dir.join(path.as_str()).display().to_string();
+			}
+		}
+		(Some(Value::Table(table)), keys) => resolve_path(table, keys, dir),
+		(Some(Value::Array(values)), ["*", keys @ ..]) => {
+			for value in values {
+				if let Value::Table(table) = value {
+					resolve_path(table, keys, dir);
+				}
+			}
+		}
+		_ => {}
+	}
+}
+
+/// Merges `overrides` into `base`. Tables are merged, while other values replace those of `base`.
+#[inline]
+fn merge_table(base: &mut Table, overrides: Table) {
+	for (key, value) in overrides {
+		match (base.get_mut(&key), value) {
+			(Some(Value::Table(base)), Value::Table(value)) => merge_table(base, value),
+			(_, value) => {
+				base.insert(key, value);
+			}
+		}
+	}
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct WatchConfig {
+	/// How long to wait for changes to settle before regenerating, in milliseconds. Defaults to
+	/// 250.
+	pub debounce_ms: Option<u64>,
+	/// Log the pages which changed content after each rebuild: `off`, `files`, or `diff` to also
+	/// log some of the text which changed. Defaults to `files`.
+	pub report_changes: Option<ReportChanges>,
 }
 
 #[derive(Debug, Default, Deserialize)]
 pub struct GenerateConfig {
 	pub content_dir: Option<PathBuf>,
 	pub css_dir: Option<PathBuf>,
+	/// Inline source maps in bundled stylesheets. Defaults to `true` when `minify` is `false`.
+	pub css_source_maps: Option<bool>,
+	/// Scripts which are bundled. Defaults to `./js`.
+	pub js_dir: Option<PathBuf>,
 	pub template_dir: Option<PathBuf>,
 	pub assets_dir: Option<PathBuf>,
 	pub data_dir: Option<PathBuf>,
+	pub cache_dir: Option<PathBuf>,
+	/// How long to wait for another sluggy process to finish writing to `out_dir`, in seconds.
+	/// Defaults to 60.
+	pub lock_timeout_secs: Option<u64>,
+	/// Template test cases for `sluggy test`. Defaults to `./tests`.
+	pub tests_dir: Option<PathBuf>,
+	/// Extra syntax definitions and packs for highlighting code. Defaults to `./syntaxes`.
+	pub syntaxes_dir: Option<PathBuf>,
 	pub base_url: Option<String>,
 	pub minify: Option<bool>,
+	pub keep_going: Option<bool>,
+	/// Environment variables which templates can read with `env(name = "...")`.
+	#[serde(default)]
+	pub template_env: Vec<String>,
+	/// Check the internal links of generated pages. Defaults to `false`.
+	pub check_links: Option<bool>,
+	/// Fail the build on broken links, when they are checked. Defaults to `false`.
+	pub deny_broken_links: Option<bool>,
+	/// Write a `sitemap.xml` of the generated pages. Defaults to `false`.
+	pub sitemap: Option<bool>,
+	/// Skip outputs which haven't changed since the last build. Defaults to `true`.
+	pub build_cache: Option<bool>,
+	/// Write a JSON manifest of the emitted files to this path after each build.
+	pub output_manifest: Option<PathBuf>,
+	/// Build into a copy of `out_dir` which replaces it once the build succeeds. Defaults to
+	/// `false`.
+	pub atomic_builds: Option<bool>,
+	/// Report or remove files of `out_dir` which a build didn't emit. Defaults to `keep`.
+	pub stale_outputs: Option<StaleOutputs>,
+	/// Patterns of files which aren't stale though the build didn't emit them.
+	#[serde(default)]
+	pub keep_outputs: Vec<String>,
+	/// Slug pattern of entries in sections which don't set their own `slug_pattern`.
+	pub slug_pattern: Option<String>,
+	/// Defaults to `true`.
+	pub date_slug: Option<bool>,
+	/// Order of the entries of sections which don't set their own `sort_by`. Defaults to `date`.
+	pub section_sort_by: Option<SortBy>,
+	/// `error` or `warn` when entries from two files, like `about.md` and `about/index.md`, would
+	/// be written to the same page. When warning, the entry whose file path sorts first is kept.
+	/// Defaults to `error`.
+	pub url_conflicts: Option<UrlConflicts>,
+	#[serde(default)]
+	pub images: ImagesConfig,
+	#[serde(default)]
+	pub js: JsConfig,
+	#[serde(default)]
+	pub fingerprint: FingerprintConfig,
+	#[serde(default)]
+	pub content_files: ContentFilesConfig,
+	#[serde(default)]
+	pub budgets: BudgetsConfig,
+	#[serde(default)]
+	pub media: MediaConfig,
+	#[serde(default)]
+	pub diagrams: DiagramsConfig,
+	#[serde(default)]
+	pub markdown: MarkdownConfig,
+	#[serde(default)]
+	pub sanitize: SanitizeConfig,
+	#[serde(default)]
+	pub fetch: FetchConfig,
+	#[serde(default)]
+	pub taxonomies: TaxonomiesConfig,
+	pub archives: Option<ArchivesConfig>,
+	/// `[generate.kinds.<name>]` tables of custom kinds of entries, or of the defaults of `post`
+	/// and `page`.
+	#[serde(default)]
+	pub kinds: BTreeMap<String, KindConfig>,
+	#[serde(default)]
+	pub feeds: Vec<FeedConfig>,
+	#[serde(default)]
+	pub calendars: Vec<CalendarConfig>,
+	pub blogroll: Option<BlogrollConfig>,
 	#[serde(default)]
-	pub taxonomies: Vec<String>,
+	pub structured_data: Vec<StructuredDataConfig>,
+	#[serde(default)]
+	pub transforms: Vec<TransformConfig>,
+	#[serde(default)]
+	pub redirects: Vec<RedirectConfig>,
+	/// Write pages which redirect with a meta refresh at the paths of redirects, for hosts which
+	/// don't read `_redirects`. Defaults to `false`.
+	pub redirect_pages: Option<bool>,
+	#[serde(default)]
+	pub versions: Vec<VersionConfig>,
 	#[serde(flatten)]
 	pub extra: Option<Value>,
 }
 
+/// Either a list of taxonomies whose terms are only collected, e.g. `taxonomies = ["tags"]`, or a
+/// `[generate.taxonomies.<name>]` table for each taxonomy.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum TaxonomiesConfig {
+	Names(Vec<String>),
+	Tables(BTreeMap<String, TaxonomyConfig>),
+}
+
+impl Default for TaxonomiesConfig {
+	fn default() -> Self {
+		Self::Names(vec![])
+	}
+}
+
+impl From<TaxonomiesConfig> for BTreeMap<String, TaxonomyConfig> {
+	fn from(value: TaxonomiesConfig) -> Self {
+		match value {
+			TaxonomiesConfig::Names(names) => names
+				.into_iter()
+				.map(|name| (name, TaxonomyConfig::collect_only()))
+				.collect(),
+			TaxonomiesConfig::Tables(tables) => tables,
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VersionConfig {
+	/// Directory name of the version's tree, e.g. `v1`.
+	pub name: String,
+	/// Defaults to `name`.
+	pub label: Option<String>,
+	pub content_dir: PathBuf,
+	/// The latest version is also generated into `/latest/`, which canonical links point to.
+	#[serde(default)]
+	pub latest: bool,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ServeConfig {
 	#[serde(default = "default_true")]
@@ -55,8 +450,327 @@ pub struct ServeConfig {
 	pub port: Option<u16>,
 	#[serde(default)]
 	pub content_encoding: ContentEncoding,
+	/// Patterns matched against request paths which are always served without a content encoding.
+	#[serde(default)]
+	pub identity_paths: Vec<String>,
+	/// Patterns matched against the `User-Agent` of clients which are always served without a
+	/// content encoding.
+	#[serde(default)]
+	pub identity_user_agents: Vec<String>,
+	/// Log 1 in every `log_sample_rate` requests. Defaults to logging every request.
+	pub log_sample_rate: Option<u64>,
+	/// Requests slower than this many milliseconds are always logged, regardless of sampling.
+	pub slow_request_ms: Option<u64>,
 	#[serde(default)]
 	pub store: Store,
+	/// Keep only the compressed variants of compressible files in the store, and decompress them
+	/// for clients which don't accept them, trading CPU for memory.
+	#[serde(default)]
+	pub store_compressed_only: bool,
+	/// Reload pages in the browser after each build. Only while watching, so that sites which are
+	/// served without watching don't get the reload script.
+	#[serde(default = "default_true")]
+	pub live_reload: bool,
+	/// Include drafts and entries which are published in the future while serving.
+	#[serde(default)]
+	pub drafts: bool,
+	/// Render entries when they're requested instead of generating the site, for a faster edit
+	/// loop. Assets, feeds and taxonomy pages are still written to `out_dir`, but rendered pages
+	/// aren't postprocessed or compressed. Defaults to `false`.
+	#[serde(default)]
+	pub render_on_request: bool,
+	/// Files larger than this many bytes are streamed instead of being kept in the store. Defaults
+	/// to 8 MiB.
+	pub stream_threshold_bytes: Option<u64>,
+	/// Memory-map files, and their compressed variants, instead of reading them, so that the
+	/// store shares their content with the page cache. Files are read where they can't be
+	/// mapped. Only with `atomic_builds`, which move new builds into place rather than rewriting
+	/// the files which are mapped. Ignored while watching a site which sluggy doesn't generate, as
+	/// whatever does may rewrite files in place. Defaults to `false`.
+	#[serde(default)]
+	pub mmap: bool,
+	/// Keep the last this many requests for content, with the encoding and bytes they were served,
+	/// for debugging. They're listed at `/_sluggy/requests`. Defaults to `0`, which keeps none.
+	#[serde(default)]
+	pub request_log_size: usize,
+	/// Pages served with error statuses, relative to `out_dir`, e.g. `{ 404 = "not-found.html" }`.
+	/// Statuses which aren't listed are served with `_error/<status>/index.html`, which is
+	/// generated from `_error/<status>.md` in `content_dir`, or otherwise `_error/_/index.html`.
+	#[serde(default)]
+	pub error_pages: BTreeMap<String, PathBuf>,
+	/// Serve over HTTPS.
+	pub tls: Option<TlsConfig>,
+	#[serde(default)]
+	pub http: HttpConfig,
+	/// Proxy requests for assets to a separate dev server, such as vite or esbuild.
+	pub asset_proxy: Option<AssetProxyConfig>,
+	/// Serve content from an S3-compatible bucket instead of `out_dir`, which is populated by
+	/// `sluggy generate` elsewhere. The site isn't generated or watched.
+	pub bucket: Option<BucketConfig>,
+	/// Purge the URLs of the pages and assets which are written by each rebuild from the cache of
+	/// a CDN in front of the server. Everything is purged when the server starts, as the site may
+	/// have changed or had files removed since it was last served.
+	pub purge: Option<PurgeConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PurgeConfig {
+	/// URL of the site as the CDN serves it. Defaults to `base_url`.
+	pub base_url: Option<String>,
+	#[serde(flatten)]
+	pub provider: PurgeProviderConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum PurgeProviderConfig {
+	Cloudflare {
+		zone_id: String,
+		/// Defaults to `CLOUDFLARE_API_TOKEN`.
+		api_token: Option<String>,
+	},
+	Fastly {
+		service_id: String,
+		/// Defaults to `FASTLY_API_TOKEN`.
+		api_token: Option<String>,
+	},
+	/// `POST`s the purged URLs as JSON, `{ "urls": [...] }`, or `{ "everything": true }` when
+	/// everything is purged.
+	Webhook {
+		url: String,
+		/// Headers of the request, e.g. `{ Authorization = "Bearer ..." }`.
+		#[serde(default)]
+		headers: BTreeMap<String, String>,
+	},
+}
+
+#[cfg(feature = "purge")]
+impl PurgeConfig {
+	/// The purge of the CDN, with API tokens from the environment unless they're set.
+	fn to_purge(&self, base_url: &str) -> miette::Result<Purge> {
+		let api_token = |api_token: &Option<String>, var: &str| {
+			api_token
+				.clone()
+				.or_else(|| env::var(var).ok())
+				.ok_or_else(|| miette!("`serve.purge` needs an `api_token`, or {var} to be set"))
+		};
+
+		let provider = match &self.provider {
+			PurgeProviderConfig::Cloudflare {
+				zone_id,
+				api_token: token,
+			} => Provider::Cloudflare {
+				zone_id: zone_id.clone(),
+				api_token: api_token(token, "CLOUDFLARE_API_TOKEN")?,
+			},
+			PurgeProviderConfig::Fastly {
+				service_id,
+				api_token: token,
+			} => Provider::Fastly {
+				service_id: service_id.clone(),
+				api_token: api_token(token, "FASTLY_API_TOKEN")?,
+			},
+			PurgeProviderConfig::Webhook { url, headers } => Provider::Webhook {
+				url: url.clone(),
+				headers: headers
+					.iter()
+					.map(|(name, value)| (name.clone(), value.clone()))
+					.collect(),
+			},
+		};
+
+		let base_url = self.base_url.as_deref().unwrap_or(base_url);
+		if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+			return Err(miette!(
+				"`serve.purge` needs an absolute `base_url`, instead of \"{base_url}\""
+			));
+		}
+
+		Ok(Purge::new(provider, base_url))
+	}
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BucketConfig {
+	/// e.g. `https://s3.eu-west-1.amazonaws.com`, or `http://localhost:9000` for MinIO.
+	pub endpoint: String,
+	pub name: String,
+	/// Defaults to `us-east-1`.
+	pub region: Option<String>,
+	/// Prefix of the keys of the site within the bucket.
+	pub prefix: Option<String>,
+	/// Request `{endpoint}/{name}/{key}` instead of `{name}.{endpoint}/{key}`. Defaults to
+	/// `false`.
+	#[serde(default)]
+	pub path_style: bool,
+	/// Defaults to `AWS_ACCESS_KEY_ID`. Requests aren't signed without credentials.
+	pub access_key_id: Option<String>,
+	/// Defaults to `AWS_SECRET_ACCESS_KEY`.
+	pub secret_access_key: Option<String>,
+	/// Seconds between checks of a served bucket for a new deploy, after which the store is
+	/// cleared. Defaults to 60.
+	pub refresh_secs: Option<u64>,
+}
+
+#[cfg(feature = "s3")]
+impl BucketConfig {
+	/// The bucket, with credentials from the environment unless they're set. `option` is the
+	/// name of the option in errors.
+	fn to_bucket(&self, option: &str) -> miette::Result<Bucket> {
+		let access_key_id = self
+			.access_key_id
+			.clone()
+			.or_else(|| env::var("AWS_ACCESS_KEY_ID").ok());
+		let secret_access_key = self
+			.secret_access_key
+			.clone()
+			.or_else(|| env::var("AWS_SECRET_ACCESS_KEY").ok());
+		let credentials = match (access_key_id, secret_access_key) {
+			(Some(access_key_id), Some(secret_access_key)) => Some(Credentials {
+				access_key_id,
+				secret_access_key,
+				session_token: env::var("AWS_SESSION_TOKEN").ok(),
+			}),
+			(None, None) => None,
+			_ => {
+				return Err(miette!(
+					"`{option}` needs both an access key ID and a secret access key"
+				))
+			}
+		};
+
+		Bucket::new(
+			&self.endpoint,
+			&self.name,
+			self.region.clone().unwrap_or_else(|| "us-east-1".into()),
+			self.prefix.clone(),
+			self.path_style,
+			credentials,
+		)
+		.map_err(|error| miette!("Invalid `{option}`: {error}"))
+	}
+}
+
+/// Where `sluggy deploy` syncs `out_dir` to, which is one of `bucket`, `rsync` or `sftp`.
+#[derive(Debug, Default, Deserialize)]
+pub struct DeployConfig {
+	/// An S3-compatible bucket, which can also be served with `serve.bucket`.
+	pub bucket: Option<BucketConfig>,
+	pub rsync: Option<RemoteConfig>,
+	pub sftp: Option<RemoteConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemoteConfig {
+	/// e.g. `user@host:/var/www/site`.
+	pub destination: String,
+	/// Extra arguments of the command, such as `["-e", "ssh -p 2222"]` for `rsync`.
+	#[serde(default)]
+	pub args: Vec<String>,
+}
+
+impl DeployConfig {
+	pub fn target(self) -> miette::Result<Target> {
+		match self {
+			#[cfg(feature = "s3")]
+			Self {
+				bucket: Some(bucket),
+				rsync: None,
+				sftp: None,
+			} => Ok(Target::Bucket(bucket.to_bucket("deploy.bucket")?)),
+			#[cfg(not(feature = "s3"))]
+			Self {
+				bucket: Some(_),
+				rsync: None,
+				sftp: None,
+			} => Err(miette!(
+				"`deploy.bucket` is set, but sluggy was built without the `s3` feature"
+			)),
+			Self {
+				bucket: None,
+				rsync: Some(rsync),
+				sftp: None,
+			} => Ok(Target::Rsync {
+				destination: rsync.destination,
+				args: rsync.args,
+			}),
+			Self {
+				bucket: None,
+				rsync: None,
+				sftp: Some(sftp),
+			} => Ok(Target::Sftp {
+				destination: sftp.destination,
+				args: sftp.args,
+			}),
+			_ => Err(miette!("`deploy` needs one of `bucket`, `rsync` or `sftp`")),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssetProxyConfig {
+	/// Origin of the dev server, e.g. `http://localhost:5173`. Only `http://` URLs are supported.
+	/// WebSocket connections, such as those of hot module replacement, are proxied too.
+	pub url: String,
+	/// Requests for paths which start with any of these are proxied, e.g. `["/@vite/", "/src/"]`.
+	pub paths: Vec<String>,
+	/// Module script of the dev server which is loaded by each page, e.g. `/@vite/client`. It
+	/// should be covered by `paths`.
+	pub client: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HttpConfig {
+	/// Accept HTTP/2 connections as well as HTTP/1. Defaults to `true`.
+	pub http2: Option<bool>,
+	/// Keep HTTP/1 connections open between requests. Defaults to `true`.
+	pub keep_alive: Option<bool>,
+	/// Ping HTTP/2 connections this often to keep them alive. They aren't pinged by default.
+	pub http2_keep_alive_interval_secs: Option<u64>,
+	/// Close HTTP/2 connections which don't answer a ping within this many seconds. Defaults to
+	/// 20.
+	pub http2_keep_alive_timeout_secs: Option<u64>,
+	/// Maximum number of open connections. Unlimited by default.
+	pub max_connections: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TlsConfig {
+	/// PEM encoded certificate chain.
+	pub cert: Option<PathBuf>,
+	/// PEM encoded private key of the certificate.
+	pub key: Option<PathBuf>,
+	/// Generate a self-signed certificate for `localhost` and the host on each start, for
+	/// development. Used when `cert` and `key` aren't set.
+	#[serde(default)]
+	pub self_signed: bool,
+	/// Obtain a certificate from an ACME server, such as Let's Encrypt, and renew it while
+	/// serving. Used when `cert` and `key` aren't set.
+	pub acme: Option<AcmeConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AcmeConfig {
+	/// Domains of the certificate, which must resolve to this server.
+	pub domains: Vec<String>,
+	/// Contact URLs of the account, e.g. `["mailto:admin@example.com"]`.
+	#[serde(default)]
+	pub contact: Vec<String>,
+	/// URL of the directory of the ACME server. Defaults to Let's Encrypt.
+	pub directory: Option<String>,
+	/// Use Let's Encrypt's staging server, which has higher rate limits, but issues untrusted
+	/// certificates. Defaults to `false`.
+	#[serde(default)]
+	pub staging: bool,
+	/// Agree to the terms of service of the ACME server, which it requires to create an account.
+	/// Must be set to `true`.
+	#[serde(default)]
+	pub terms_of_service_agreed: bool,
+	/// Where the account and certificates are kept. Defaults to `acme` in `cache_dir`.
+	pub dir: Option<PathBuf>,
+	/// Port of the HTTP server which answers challenges, and redirects everything else to HTTPS.
+	/// Defaults to 80.
+	pub http_port: Option<u16>,
 }
 
 impl Default for ServeConfig {
@@ -67,7 +781,24 @@ impl Default for ServeConfig {
 			host: Option::default(),
 			port: Option::default(),
 			content_encoding: ContentEncoding::default(),
+			identity_paths: Vec::default(),
+			identity_user_agents: Vec::default(),
+			log_sample_rate: Option::default(),
+			slow_request_ms: Option::default(),
 			store: Store::default(),
+			store_compressed_only: bool::default(),
+			live_reload: default_true(),
+			drafts: bool::default(),
+			render_on_request: bool::default(),
+			stream_threshold_bytes: Option::default(),
+			mmap: bool::default(),
+			request_log_size: usize::default(),
+			error_pages: BTreeMap::default(),
+			tls: Option::default(),
+			http: HttpConfig::default(),
+			asset_proxy: Option::default(),
+			bucket: Option::default(),
+			purge: Option::default(),
 		}
 	}
 }
@@ -89,6 +820,17 @@ impl Default for Store {
 	}
 }
 
+fn compile_patterns(patterns: &[String]) -> miette::Result<Vec<Regex>> {
+	patterns
+		.iter()
+		.map(|pattern| {
+			Regex::new(pattern)
+				.into_diagnostic()
+				.wrap_err(format!("Failed to parse pattern \"{pattern}\""))
+		})
+		.collect()
+}
+
 fn canonicalize(path: PathBuf) -> miette::Result<PathBuf> {
 	path.canonicalize()
 		.into_diagnostic()
@@ -121,6 +863,29 @@ impl TryFrom<Config> for (SluggyGenerateConfig, SluggyServerConfig) {
 
 		let generate_config = config.generate;
 
+		let cache_dir = generate_config
+			.cache_dir
+			.unwrap_or(PathBuf::from_str(DEFAULT_CACHE_DIR).into_diagnostic()?);
+
+		if !cache_dir.exists() {
+			std::fs::create_dir_all(&cache_dir).into_diagnostic()?
+		}
+
+		// The tests dir is optional, so it's only canonicalized when it exists.
+		let tests_dir = generate_config
+			.tests_dir
+			.unwrap_or(PathBuf::from_str(DEFAULT_TESTS_DIR).into_diagnostic()?);
+
+		// As is the js dir.
+		let js_dir = generate_config
+			.js_dir
+			.unwrap_or(PathBuf::from_str(DEFAULT_JS_DIR).into_diagnostic()?);
+
+		// And the syntaxes dir.
+		let syntaxes_dir = generate_config
+			.syntaxes_dir
+			.unwrap_or(PathBuf::from_str(DEFAULT_SYNTAXES_DIR).into_diagnostic()?);
+
 		let base_url = match generate_config.base_url {
 			None => env::var("BASE_URL").ok(),
 			Some(base_url) => Some(base_url),
@@ -136,6 +901,33 @@ impl TryFrom<Config> for (SluggyGenerateConfig, SluggyServerConfig) {
 			"/".into()
 		};
 
+		let versions = generate_config
+			.versions
+			.into_iter()
+			.map(|version| {
+				Ok(Version {
+					url: format!("{base_url}{}/", version.name),
+					label: version.label.unwrap_or_else(|| version.name.clone()),
+					is_latest: version.latest,
+					content_dir: canonicalize(version.content_dir)?,
+					name: version.name,
+				})
+			})
+			.collect::<miette::Result<Vec<_>>>()?;
+
+		if versions.iter().filter(|version| version.is_latest).count() > 1 {
+			return Err(miette!("Only one version can be marked as latest"));
+		}
+
+		// ammonia removes the content of scripts and styles along with them, so they can't be kept.
+		if let Some(tag) = generate_config.sanitize.tags.iter().flatten().find(|tag| {
+			["script", "style"]
+				.iter()
+				.any(|content_tag| tag.eq_ignore_ascii_case(content_tag))
+		}) {
+			return Err(miette!("`sanitize.tags` can't keep `{tag}` tags"));
+		}
+
 		let generate_config = SluggyGenerateConfig {
 			content_dir: canonicalize(
 				generate_config
@@ -147,6 +939,10 @@ impl TryFrom<Config> for (SluggyGenerateConfig, SluggyServerConfig) {
 					.css_dir
 					.unwrap_or(PathBuf::from_str(DEFAULT_CSS_DIR).into_diagnostic()?),
 			)?,
+			js_dir: canonicalize(js_dir.clone()).unwrap_or(js_dir),
+			js: generate_config.js,
+			fingerprint: generate_config.fingerprint,
+			content_files: generate_config.content_files,
 			template_dir: canonicalize(
 				generate_config
 					.template_dir
@@ -162,17 +958,71 @@ impl TryFrom<Config> for (SluggyGenerateConfig, SluggyServerConfig) {
 					.data_dir
 					.unwrap_or(PathBuf::from_str(DEFAULT_DATA_DIR).into_diagnostic()?),
 			)?,
+			cache_dir: canonicalize(cache_dir)?,
+			lock_timeout: Duration::from_secs(
+				generate_config
+					.lock_timeout_secs
+					.unwrap_or(DEFAULT_LOCK_TIMEOUT_SECS),
+			),
+			tests_dir: canonicalize(tests_dir.clone()).unwrap_or(tests_dir),
+			syntaxes_dir: canonicalize(syntaxes_dir.clone()).unwrap_or(syntaxes_dir),
 			processed_images_dir,
+			images: generate_config.images,
+			media: generate_config.media,
+			diagrams: generate_config.diagrams,
+			markdown: generate_config.markdown,
+			sanitize: generate_config.sanitize,
+			fetch: generate_config.fetch,
 			out_dir: serve_dir.clone(),
 			base_url,
+			css_source_maps: generate_config
+				.css_source_maps
+				.unwrap_or(!generate_config.minify.unwrap_or(true)),
 			minify: generate_config.minify.unwrap_or(true),
+			keep_going: generate_config.keep_going.unwrap_or(false),
+			check_links: generate_config.check_links.unwrap_or(false),
+			deny_broken_links: generate_config.deny_broken_links.unwrap_or(false),
+			budgets: generate_config.budgets,
+			sitemap: generate_config.sitemap.unwrap_or(false),
+			build_cache: generate_config.build_cache.unwrap_or(true),
+			output_manifest: generate_config.output_manifest,
+			atomic_builds: generate_config.atomic_builds.unwrap_or(false),
+			stale_outputs: generate_config.stale_outputs.unwrap_or_default(),
+			keep_outputs: generate_config.keep_outputs,
+			report_changes: ReportChanges::Off,
+			hooks: Hooks::default(),
+			live_reload: false,
+			serve_mode: false,
+			template_env: generate_config.template_env,
+			dev_client_script: None,
+			drafts: false,
+			slug_pattern: generate_config.slug_pattern,
+			date_slug: generate_config.date_slug.unwrap_or(true),
+			section_sort_by: generate_config.section_sort_by.unwrap_or_default(),
+			url_conflicts: generate_config.url_conflicts.unwrap_or_default(),
 			extra: generate_config.extra,
 			compress_content,
 			compressed_content_dir: compressed_content_dir.clone(),
-			taxonomies: generate_config.taxonomies,
+			compression: config.compression,
+			postprocess: config.postprocess,
+			taxonomies: generate_config.taxonomies.into(),
+			archives: generate_config.archives,
+			kinds: generate_config.kinds,
+			feeds: generate_config.feeds,
+			calendars: generate_config.calendars,
+			blogroll: generate_config.blogroll,
+			structured_data: generate_config.structured_data,
+			transforms: generate_config.transforms,
+			redirects: generate_config.redirects,
+			redirect_pages: generate_config.redirect_pages.unwrap_or(false),
+			versions,
+			current_version: None,
+			canonical_base_url: None,
+			dump_context: None,
 		};
 
 		let server_config = config.serve;
+		let watch_config = config.watch;
 		let port = match server_config.port {
 			None => match env::var("PORT") {
 				Ok(port) => port
@@ -192,21 +1042,266 @@ impl TryFrom<Config> for (SluggyGenerateConfig, SluggyServerConfig) {
 			Some(host) => host,
 		};
 
+		#[cfg(not(feature = "tls"))]
+		if server_config.tls.is_some() {
+			return Err(miette!(
+				"`serve.tls` is set, but sluggy was built without the `tls` feature"
+			));
+		}
+
+		#[cfg(all(feature = "tls", not(feature = "acme")))]
+		if matches!(&server_config.tls, Some(TlsConfig { acme: Some(_), .. })) {
+			return Err(miette!(
+				"`serve.tls.acme` is set, but sluggy was built without the `acme` feature"
+			));
+		}
+
+		#[cfg(feature = "tls")]
+		let tls = match server_config.tls {
+			None => None,
+			Some(TlsConfig {
+				cert: Some(cert),
+				key: Some(key),
+				..
+			}) => Some(Tls::Files {
+				cert: canonicalize(cert)?,
+				key: canonicalize(key)?,
+			}),
+			Some(TlsConfig {
+				cert: None,
+				key: None,
+				self_signed: true,
+				acme: None,
+			}) => Some(Tls::SelfSigned),
+			#[cfg(feature = "acme")]
+			Some(TlsConfig {
+				cert: None,
+				key: None,
+				self_signed: false,
+				acme: Some(acme),
+			}) => Some(Tls::Acme(
+				Acme::new(
+					acme.domains,
+					acme.contact,
+					acme.directory,
+					acme.staging,
+					acme.terms_of_service_agreed,
+					acme.dir
+						.unwrap_or_else(|| generate_config.cache_dir.join("acme")),
+					acme.http_port.unwrap_or(80),
+				)
+				.map_err(|error| miette!("Invalid `serve.tls.acme`: {error}"))?,
+			)),
+			Some(_) => {
+				return Err(miette!(
+					"`serve.tls` needs one of both `cert` and `key`, `self_signed = true` or `acme`"
+				))
+			}
+		};
+
+		let error_pages = server_config
+			.error_pages
+			.into_iter()
+			.map(|(status, page)| match status.parse::<u16>() {
+				Ok(status) if (400..600).contains(&status) => Ok((status, page)),
+				_ => Err(miette!(
+					"`serve.error_pages` has an invalid error status \"{status}\""
+				)),
+			})
+			.collect::<miette::Result<BTreeMap<_, _>>>()?;
+
+		let asset_proxy = server_config
+			.asset_proxy
+			.map(|asset_proxy| {
+				// Dev servers are local, so they're only proxied over plain http.
+				if !asset_proxy.url.starts_with("http://") {
+					return Err(miette!(
+						"`serve.asset_proxy.url` must be an `http://` URL, instead of \"{}\"",
+						asset_proxy.url
+					));
+				}
+
+				AssetProxy::new(asset_proxy.url, asset_proxy.paths, asset_proxy.client)
+					.into_diagnostic()
+					.wrap_err("Invalid `serve.asset_proxy.url`")
+			})
+			.transpose()?;
+
+		#[cfg(not(feature = "s3"))]
+		if server_config.bucket.is_some() {
+			return Err(miette!(
+				"`serve.bucket` is set, but sluggy was built without the `s3` feature"
+			));
+		}
+
+		#[cfg(feature = "s3")]
+		let bucket = server_config
+			.bucket
+			.as_ref()
+			.map(|bucket| bucket.to_bucket("serve.bucket"))
+			.transpose()?;
+
+		#[cfg(not(feature = "purge"))]
+		if server_config.purge.is_some() {
+			return Err(miette!(
+				"`serve.purge` is set, but sluggy was built without the `purge` feature"
+			));
+		}
+
+		#[cfg(feature = "purge")]
+		let purge = server_config
+			.purge
+			.as_ref()
+			.map(|purge| purge.to_purge(&generate_config.base_url))
+			.transpose()?;
+
+		// Content in a bucket is generated elsewhere.
+		let serves_bucket = server_config.bucket.is_some();
+
+		// Files which are mapped mustn't be truncated or rewritten while they're served, which
+		// atomic builds don't do, as they move each build into place.
+		if server_config.mmap && !generate_config.atomic_builds {
+			return Err(miette!("`serve.mmap` needs `generate.atomic_builds`"));
+		}
+
 		let server_config = SluggyServerConfig {
-			generate: server_config.generate,
-			watch: server_config.watch,
+			generate: server_config.generate && !serves_bucket,
+			watch: server_config.watch && !serves_bucket,
+			watch_debounce: Duration::from_millis(
+				watch_config.debounce_ms.unwrap_or(DEFAULT_DEBOUNCE_MS),
+			),
+			config_files: config.files,
 			host,
 			port,
 			serve_dir,
 			compress_content,
 			compressed_content_dir,
 			content_encoding: server_config.content_encoding,
+			identity_paths: compile_patterns(&server_config.identity_paths)?,
+			identity_user_agents: compile_patterns(&server_config.identity_user_agents)?,
+			log_sample_rate: server_config.log_sample_rate.unwrap_or(1),
+			slow_request_threshold: server_config.slow_request_ms.map(Duration::from_millis),
 			store: match server_config.store {
 				Store::None => ServerStore::NoStore(NoStore::new()),
+				#[cfg(feature = "serve-store")]
 				Store::InMemory => ServerStore::InMemoryStore(InMemoryStore::new()),
+				#[cfg(not(feature = "serve-store"))]
+				Store::InMemory => {
+					tracing::warn!(
+						"ignoring `store = \"in-memory\"`, sluggy was built without the `serve-store` feature"
+					);
+					ServerStore::NoStore(NoStore::new())
+				}
+			},
+			store_compressed_only: server_config.store_compressed_only,
+			build_error: BuildError::default(),
+			live_reload: server_config.live_reload && server_config.watch && !serves_bucket,
+			drafts: server_config.drafts,
+			reload: LiveReload::default(),
+			report_changes: watch_config.report_changes.unwrap_or(ReportChanges::Files),
+			changes: LastChanges::default(),
+			page_headers: PageHeaders::default(),
+			redirects: Redirects::default(),
+			render_on_request: server_config.render_on_request
+				&& server_config.generate
+				&& !serves_bucket,
+			pages: OnDemandPages::default(),
+			error_pages,
+			request_log: RequestLog::new(server_config.request_log_size),
+			stream_threshold: server_config
+				.stream_threshold_bytes
+				.unwrap_or(DEFAULT_STREAM_THRESHOLD_BYTES),
+			mmap: server_config.mmap && (!server_config.watch || server_config.generate),
+			#[cfg(feature = "tls")]
+			tls,
+			#[cfg(feature = "s3")]
+			bucket_refresh: Duration::from_secs(
+				server_config
+					.bucket
+					.as_ref()
+					.and_then(|bucket| bucket.refresh_secs)
+					.unwrap_or(DEFAULT_BUCKET_REFRESH_SECS),
+			),
+			#[cfg(feature = "s3")]
+			bucket,
+			#[cfg(feature = "purge")]
+			purge,
+			http: SluggyHttpConfig {
+				http2: server_config.http.http2.unwrap_or(true),
+				keep_alive: server_config.http.keep_alive.unwrap_or(true),
+				http2_keep_alive_interval: server_config
+					.http
+					.http2_keep_alive_interval_secs
+					.map(Duration::from_secs),
+				http2_keep_alive_timeout: Duration::from_secs(
+					server_config
+						.http
+						.http2_keep_alive_timeout_secs
+						.unwrap_or(DEFAULT_HTTP2_KEEP_ALIVE_TIMEOUT_SECS),
+				),
+				max_connections: server_config.http.max_connections,
 			},
+			asset_proxy,
 		};
 
 		Ok((generate_config, server_config))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn included_paths_are_relative_to_the_included_file() {
+		let temp_dir = tempfile::tempdir().unwrap();
+		let dir = temp_dir.path().canonicalize().unwrap();
+		fs::create_dir_all(dir.join("shared")).unwrap();
+		fs::create_dir_all(dir.join("site")).unwrap();
+
+		fs::write(
+			dir.join("shared/common.toml"),
+			r#"
+out_dir = "/srv/site"
+
+[generate]
+template_dir = "templates"
+content_dir = "content"
+minify = true
+
+[[generate.versions]]
+name = "v1"
+content_dir = "v1"
+"#,
+		)
+		.unwrap();
+		let path = dir.join("site/sluggy.toml");
+		fs::write(
+			&path,
+			r#"
+include = ["../shared/common.toml"]
+
+[generate]
+content_dir = "posts"
+
+[serve]
+"#,
+		)
+		.unwrap();
+
+		let config = Config::from_file(&path).unwrap();
+		assert_eq!(
+			config.generate.template_dir,
+			Some(dir.join("shared/templates"))
+		);
+		// Paths of the file itself are relative to the working dir.
+		assert_eq!(config.generate.content_dir, Some(PathBuf::from("posts")));
+		assert_eq!(config.out_dir, Some(PathBuf::from("/srv/site")));
+		assert_eq!(config.generate.minify, Some(true));
+		assert_eq!(
+			config.generate.versions[0].content_dir,
+			dir.join("shared/v1")
+		);
+		assert_eq!(config.files, vec![path, dir.join("shared/common.toml")]);
+	}
+}
@@ -0,0 +1,648 @@
+#[cfg(feature = "s3")]
+use crate::bucket::Bucket;
+#[cfg(feature = "s3")]
+use sluggy_core::{encoding::variant_of, utils::path_to_content_type};
+use sluggy_core::{
+	error::{Error, Result},
+	generate::config::Config as GenerateConfig,
+	map_err,
+	utils::hex_digest_file,
+};
+#[cfg(feature = "s3")]
+use std::future::Future;
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fmt, fs,
+	io::Write,
+	path::{Path, PathBuf},
+	process::{Command, Stdio},
+};
+#[cfg(feature = "s3")]
+use tokio::runtime::Handle;
+use tracing::instrument;
+
+/// Manifest of the deployed files and the SHA-256 of their content, which is kept on the target
+/// next to the dir of the site rather than in it, so that it isn't served along with the site,
+/// e.g. as `.site.sluggy-deploy.json` next to `/var/www/site`. Sites at the root of a bucket or of
+/// a remote keep it at their root. Files are only uploaded when their hash differs from the
+/// manifest's.
+pub(crate) const MANIFEST_FILE: &str = ".sluggy-deploy.json";
+
+/// Files of the manifests are kept in this directory under `cache_dir` while deploying.
+const DEPLOY_CACHE_DIR: &str = "deploy";
+
+/// Where the manifest of the last deploy is copied to from `rsync` and `sftp` targets.
+const DEPLOYED_MANIFEST_FILE: &str = "deployed.json";
+
+/// Path of each deployed file within `out_dir` -> the SHA-256 of its content.
+type Manifest = BTreeMap<String, String>;
+
+/// Where `sluggy deploy` syncs `out_dir` to.
+#[derive(Debug)]
+pub enum Target {
+	/// An S3-compatible bucket, which objects are uploaded to with their `Content-Type` and
+	/// `Content-Encoding`.
+	#[cfg(feature = "s3")]
+	Bucket(Bucket),
+	/// A local or remote directory which `rsync` copies files to, e.g. `user@host:/var/www/site`.
+	Rsync {
+		destination: String,
+		/// Extra arguments of `rsync`, such as `["-e", "ssh -p 2222"]`.
+		args: Vec<String>,
+	},
+	/// A remote directory which `sftp` copies files to, e.g. `user@host:/var/www/site`.
+	Sftp {
+		destination: String,
+		/// Extra arguments of `sftp`, such as `["-P", "2222"]`.
+		args: Vec<String>,
+	},
+}
+
+impl fmt::Display for Target {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			#[cfg(feature = "s3")]
+			Self::Bucket(bucket) => write!(f, "bucket {}", bucket.location()),
+			Self::Rsync { destination, .. } => write!(f, "rsync {destination}"),
+			Self::Sftp { destination, .. } => write!(f, "sftp {destination}"),
+		}
+	}
+}
+
+/// Files of `out_dir` which were uploaded, and files of the last deploy which were removed.
+#[derive(Debug, Default)]
+pub struct Changes {
+	pub uploaded: Vec<String>,
+	pub removed: Vec<String>,
+	/// Number of files which were already deployed as they are.
+	pub unchanged: usize,
+	/// Size of each uploaded file.
+	pub sizes: BTreeMap<String, u64>,
+	/// Estimate of the requests to the target, or the commands of `rsync` and `sftp`, which
+	/// syncing the changes takes, including those for the manifest.
+	pub requests: usize,
+}
+
+impl Changes {
+	/// Total size of the uploaded files.
+	#[inline]
+	pub fn uploaded_bytes(&self) -> u64 {
+		self.sizes.values().sum()
+	}
+}
+
+/// Syncs `out_dir` to a target, uploading the files which changed since the last deploy and
+/// removing the files which are no longer generated.
+#[derive(Debug)]
+pub struct Deploy {
+	target: Target,
+	out_dir: PathBuf,
+	/// Compressed variants are uploaded to buckets with their `Content-Encoding`.
+	#[cfg(feature = "s3")]
+	compressed_content_dir: PathBuf,
+	cache_dir: PathBuf,
+}
+
+impl Deploy {
+	pub fn new(target: Target, config: &GenerateConfig) -> Self {
+		Self {
+			target,
+			out_dir: config.out_dir.clone(),
+			#[cfg(feature = "s3")]
+			compressed_content_dir: config.out_dir.join(&config.compressed_content_dir),
+			cache_dir: config.cache_dir.join(DEPLOY_CACHE_DIR),
+		}
+	}
+
+	/// Deploys `out_dir`, or only works out what would change when `dry_run` is set. Files aren't
+	/// removed unless they were deployed by sluggy, as they're in the previous manifest.
+	#[instrument(skip(self))]
+	pub fn deploy(&self, dry_run: bool) -> Result<Changes> {
+		map_err!(
+			fs::create_dir_all(&self.cache_dir),
+			IoError(format!("failed to create {}", self.cache_dir.display())),
+		)?;
+
+		let mut manifest = Manifest::new();
+		self.hash_files(&self.out_dir, &mut manifest)?;
+		let deployed = self.deployed_manifest()?;
+		let changes = self.changes(&manifest, &deployed)?;
+
+		if dry_run || (changes.uploaded.is_empty() && changes.removed.is_empty()) {
+			return Ok(changes);
+		}
+
+		let manifest_path = self.cache_dir.join(MANIFEST_FILE);
+		let json = map_err!(
+			serde_json::to_vec_pretty(&manifest),
+			SerdeJsonError("failed to serialize the deploy manifest"),
+		)?;
+		map_err!(
+			fs::write(&manifest_path, json),
+			IoError(format!("failed to write {}", manifest_path.display())),
+		)?;
+
+		match &self.target {
+			#[cfg(feature = "s3")]
+			Target::Bucket(bucket) => self.sync_bucket(bucket, &manifest, &changes, &manifest_path)?,
+			Target::Rsync { destination, args } => {
+				self.sync_rsync(destination, args, &changes, &manifest_path)?
+			}
+			Target::Sftp { destination, args } => {
+				self.sync_sftp(destination, args, &changes, &manifest_path)?
+			}
+		}
+
+		Ok(changes)
+	}
+
+	/// The files of `manifest` which differ from those of `deployed`, the manifest of the last
+	/// deploy, and the files of `deployed` which are no longer in `manifest`.
+	#[inline]
+	fn changes(&self, manifest: &Manifest, deployed: &Manifest) -> Result<Changes> {
+		let mut changes = Changes::default();
+		for (path, hash) in manifest {
+			if deployed.get(path) == Some(hash) {
+				changes.unchanged += 1;
+			} else {
+				let size = map_err!(
+					fs::metadata(self.out_dir.join(path)),
+					IoError(format!("failed to read {path}")),
+				)?
+				.len();
+				changes.sizes.insert(path.clone(), size);
+				changes.uploaded.push(path.clone());
+			}
+		}
+		changes.removed = deployed
+			.keys()
+			.filter(|path| !manifest.contains_key(*path))
+			.cloned()
+			.collect();
+		changes.requests = self.estimate_requests(&changes);
+
+		Ok(changes)
+	}
+
+	/// Requests, or commands, which syncing `changes` takes, besides reading the manifest of the
+	/// last deploy.
+	#[inline]
+	fn estimate_requests(&self, changes: &Changes) -> usize {
+		if changes.uploaded.is_empty() && changes.removed.is_empty() {
+			return 0;
+		}
+
+		match &self.target {
+			// Each object is put or deleted, and then the manifest is put.
+			#[cfg(feature = "s3")]
+			Target::Bucket(_) => changes.uploaded.len() + changes.removed.len() + 1,
+			// The files are copied by one run, and the manifest by another.
+			Target::Rsync { .. } => 2,
+			// A batch of commands, of which each dir, file and removal is one.
+			Target::Sftp { .. } => {
+				upload_dirs(changes).len() + changes.uploaded.len() + changes.removed.len() + 1
+			}
+		}
+	}
+
+	/// Hashes the files of `dir`, keyed on their path within `out_dir`.
+	#[inline]
+	fn hash_files(&self, dir: &Path, manifest: &mut Manifest) -> Result<()> {
+		for entry in map_err!(
+			fs::read_dir(dir),
+			IoError(format!("failed to read dir {}", dir.display())),
+		)? {
+			let path = map_err!(entry, IoError("dir entry failed"))?.path();
+
+			if path.is_dir() {
+				self.hash_files(&path, manifest)?;
+			} else {
+				let hash = map_err!(
+					hex_digest_file(&path),
+					IoError(format!("failed to read {}", path.display())),
+				)?;
+				manifest.insert(self.key(&path)?, hash);
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Path of `path` within `out_dir`, with forward slashes.
+	#[inline]
+	fn key(&self, path: &Path) -> Result<String> {
+		let key = map_err!(
+			path.strip_prefix(&self.out_dir),
+			StripPathPrefix(format!(
+				"failed to strip prefix {} from {}",
+				self.out_dir.display(),
+				path.display()
+			)),
+		)?;
+		Ok(key
+			.components()
+			.map(|component| component.as_os_str().to_string_lossy())
+			.collect::<Vec<_>>()
+			.join("/"))
+	}
+
+	/// The manifest of the last deploy to the target, which is empty when there hasn't been one.
+	#[instrument(skip(self))]
+	fn deployed_manifest(&self) -> Result<Manifest> {
+		let json = match &self.target {
+			#[cfg(feature = "s3")]
+			Target::Bucket(bucket) => {
+				let (bucket, manifest_path) = bucket_manifest(bucket);
+				block_on(bucket.get(&manifest_path)).map(|json| json.to_vec())
+			}
+			Target::Rsync { destination, args } => {
+				let deployed_path = self.cache_dir.join(DEPLOYED_MANIFEST_FILE);
+				let _ = fs::remove_file(&deployed_path);
+				// Fails when there's no manifest yet, and otherwise fails again when the files are
+				// copied.
+				let _ = run(
+					Command::new("rsync")
+						.args(args)
+						.arg(rsync_manifest(destination))
+						.arg(&deployed_path),
+					None,
+				);
+				fs::read(&deployed_path).ok()
+			}
+			Target::Sftp { destination, args } => {
+				let deployed_path = self.cache_dir.join(DEPLOYED_MANIFEST_FILE);
+				let _ = fs::remove_file(&deployed_path);
+				run(
+					Command::new("sftp")
+						.args(args)
+						.arg("-b")
+						.arg("-")
+						.arg(destination),
+					Some(format!(
+						"-get {} {}\n",
+						quote(&sftp_manifest(destination)),
+						quote(&deployed_path.to_string_lossy())
+					)),
+				)?;
+				fs::read(&deployed_path).ok()
+			}
+		};
+
+		match json {
+			Some(json) => map_err!(
+				serde_json::from_slice(&json),
+				SerdeJsonError("failed to parse the deploy manifest of the target"),
+			),
+			None => Ok(Manifest::new()),
+		}
+	}
+
+	/// Uploads each file with its `Content-Type`, and the `Content-Encoding` of compressed
+	/// variants, so that hosts can serve them as they are. Files are streamed, and signed with
+	/// their hash of `manifest`.
+	#[cfg(feature = "s3")]
+	#[instrument(skip_all)]
+	fn sync_bucket(
+		&self,
+		bucket: &Bucket,
+		manifest: &Manifest,
+		changes: &Changes,
+		manifest_path: &Path,
+	) -> Result<()> {
+		for path in &changes.uploaded {
+			block_on(bucket.put_file(
+				Path::new(path),
+				&self.out_dir.join(path),
+				&manifest[path],
+				&self.object_headers(path),
+			))
+			.map_err(Error::Validation)?;
+			tracing::info!(path, "Uploaded");
+		}
+
+		for path in &changes.removed {
+			block_on(bucket.delete(Path::new(path))).map_err(Error::Validation)?;
+			tracing::info!(path, "Removed");
+		}
+
+		// Written last, so that a failed deploy is tried again.
+		let manifest = map_err!(
+			fs::read(manifest_path),
+			IoError(format!("failed to read {}", manifest_path.display())),
+		)?;
+		let (bucket, path) = bucket_manifest(bucket);
+		block_on(bucket.put(
+			&path,
+			manifest,
+			&[("content-type", "application/json".to_string())],
+		))
+		.map_err(Error::Validation)
+	}
+
+	/// `content-type` of the file at `path`, and the `content-encoding` of compressed variants,
+	/// which are typed as the file they're compressed from.
+	#[cfg(feature = "s3")]
+	#[inline]
+	fn object_headers(&self, path: &str) -> Vec<(&'static str, String)> {
+		let file_path = self.out_dir.join(path);
+		match variant_of(&self.compressed_content_dir, &file_path) {
+			Some((name, encoding)) => vec![
+				("content-type", content_type(&name)),
+				(
+					"content-encoding",
+					encoding
+						.to_header_value()
+						.to_str()
+						.unwrap_or_default()
+						.to_string(),
+				),
+			],
+			None => vec![("content-type", content_type(&file_path))],
+		}
+	}
+
+	/// Copies the changed files with `--files-from`, which removes those which are missing with
+	/// `--delete-missing-args`.
+	#[instrument(skip_all)]
+	fn sync_rsync(
+		&self,
+		destination: &str,
+		args: &[String],
+		changes: &Changes,
+		manifest_path: &Path,
+	) -> Result<()> {
+		let files = changes
+			.uploaded
+			.iter()
+			.chain(&changes.removed)
+			.map(|path| format!("{path}\n"))
+			.collect::<String>();
+		run(
+			Command::new("rsync")
+				.args(args)
+				.args(["--files-from=-", "--delete-missing-args", "--times"])
+				.arg(format!("{}/", self.out_dir.display()))
+				.arg(destination),
+			Some(files),
+		)?;
+
+		run(
+			Command::new("rsync")
+				.args(args)
+				.arg(manifest_path)
+				.arg(rsync_manifest(destination)),
+			None,
+		)
+	}
+
+	/// Copies the changed files, and removes the others, with a batch of `sftp` commands.
+	#[instrument(skip_all)]
+	fn sync_sftp(
+		&self,
+		destination: &str,
+		args: &[String],
+		changes: &Changes,
+		manifest_path: &Path,
+	) -> Result<()> {
+		// Commands prefixed with `-` may fail, such as creating a dir which exists.
+		let mut batch = String::new();
+		for dir in upload_dirs(changes) {
+			batch.push_str(&format!("-mkdir {}\n", quote(&dir)));
+		}
+		for path in &changes.uploaded {
+			batch.push_str(&format!(
+				"put {} {}\n",
+				quote(&self.out_dir.join(path).to_string_lossy()),
+				quote(path)
+			));
+		}
+		for path in &changes.removed {
+			batch.push_str(&format!("-rm {}\n", quote(path)));
+		}
+		batch.push_str(&format!(
+			"put {} {}\n",
+			quote(&manifest_path.to_string_lossy()),
+			quote(&sftp_manifest(destination))
+		));
+
+		run(
+			Command::new("sftp")
+				.args(args)
+				.arg("-b")
+				.arg("-")
+				.arg(destination),
+			Some(batch),
+		)
+	}
+}
+
+/// Waits for a request of a bucket. Deploys run with `block_in_place`, so that they can block the
+/// thread they're on.
+#[cfg(feature = "s3")]
+#[inline]
+fn block_on<F: Future>(future: F) -> F::Output {
+	Handle::current().block_on(future)
+}
+
+/// Name of the manifest next to the dir of the site called `name`, e.g. `.site.sluggy-deploy.json`.
+#[inline]
+fn sibling_manifest(name: &str) -> String {
+	format!(".{name}{MANIFEST_FILE}")
+}
+
+/// The bucket which the manifest of the site in `bucket` is kept in, and its path.
+#[cfg(feature = "s3")]
+#[inline]
+pub(crate) fn bucket_manifest(bucket: &Bucket) -> (Bucket, PathBuf) {
+	match bucket.parent() {
+		Some((parent, name)) => (parent, PathBuf::from(sibling_manifest(&name))),
+		None => (bucket.clone(), PathBuf::from(MANIFEST_FILE)),
+	}
+}
+
+/// Where `rsync` copies the manifest of the site at `destination` to, e.g.
+/// `user@host:/var/www/.site.sluggy-deploy.json` for `user@host:/var/www/site`.
+#[inline]
+fn rsync_manifest(destination: &str) -> String {
+	let destination = destination.trim_end_matches('/');
+	// The path of remote destinations follows the host, e.g. `host:path`.
+	let (host, path) = match destination.split_once(':') {
+		Some((host, path)) if !host.contains('/') => (&destination[..=host.len()], path),
+		_ => ("", destination),
+	};
+
+	match path.rsplit_once('/') {
+		Some((parent, name)) => format!("{host}{parent}/{}", sibling_manifest(name)),
+		None if !path.is_empty() => format!("{host}{}", sibling_manifest(path)),
+		None => format!("{host}{MANIFEST_FILE}"),
+	}
+}
+
+/// Path of the manifest of the site at `destination`, relative to the dir of the site which `sftp`
+/// starts in.
+#[inline]
+fn sftp_manifest(destination: &str) -> String {
+	let path = destination
+		.split_once(':')
+		.map_or("", |(_, path)| path)
+		.trim_end_matches('/');
+	match path.rsplit('/').next().filter(|name| !name.is_empty()) {
+		Some(name) => format!("../{}", sibling_manifest(name)),
+		None => MANIFEST_FILE.to_string(),
+	}
+}
+
+/// Dirs of the uploaded files, which `sftp` creates before putting them, parents first.
+#[inline]
+fn upload_dirs(changes: &Changes) -> BTreeSet<String> {
+	changes
+		.uploaded
+		.iter()
+		.flat_map(|path| Path::new(path).ancestors().skip(1))
+		.filter(|dir| !dir.as_os_str().is_empty())
+		.map(|dir| dir.to_string_lossy().to_string())
+		.collect()
+}
+
+#[cfg(feature = "s3")]
+#[inline]
+fn content_type(path: &Path) -> String {
+	path_to_content_type(path)
+		.to_str()
+		.unwrap_or_default()
+		.to_string()
+}
+
+/// Quotes an argument of an `sftp` batch command.
+#[inline]
+fn quote(arg: &str) -> String {
+	format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Runs `command` to completion, writing `stdin` to it, and fails unless it succeeds.
+#[inline]
+fn run(command: &mut Command, stdin: Option<String>) -> Result<()> {
+	let program = command.get_program().to_string_lossy().to_string();
+	let mut child = map_err!(
+		command
+			.stdin(if stdin.is_some() {
+				Stdio::piped()
+			} else {
+				Stdio::null()
+			})
+			.stdout(Stdio::null())
+			.stderr(Stdio::piped())
+			.spawn(),
+		IoError(format!("failed to run {program}")),
+	)?;
+
+	if let (Some(stdin), Some(mut child_stdin)) = (stdin, child.stdin.take()) {
+		map_err!(
+			child_stdin.write_all(stdin.as_bytes()),
+			IoError(format!("failed to write to {program}")),
+		)?;
+	}
+
+	let output = map_err!(
+		child.wait_with_output(),
+		IoError(format!("failed to run {program}")),
+	)?;
+	if !output.status.success() {
+		return Err(Error::Validation(format!(
+			"{program} failed with {}: {}",
+			output.status,
+			String::from_utf8_lossy(&output.stderr).trim()
+		)));
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn manifest_next_to_site() {
+		assert_eq!(
+			rsync_manifest("user@host:/var/www/site/"),
+			"user@host:/var/www/.site.sluggy-deploy.json"
+		);
+		assert_eq!(
+			rsync_manifest("user@host:site"),
+			"user@host:.site.sluggy-deploy.json"
+		);
+		assert_eq!(
+			rsync_manifest("user@host:"),
+			"user@host:.sluggy-deploy.json"
+		);
+		assert_eq!(
+			rsync_manifest("/var/www/site"),
+			"/var/www/.site.sluggy-deploy.json"
+		);
+
+		assert_eq!(
+			sftp_manifest("user@host:/var/www/site"),
+			"../.site.sluggy-deploy.json"
+		);
+		assert_eq!(sftp_manifest("user@host"), ".sluggy-deploy.json");
+	}
+
+	#[test]
+	fn changes_since_deployed() {
+		let dir = tempfile::tempdir().unwrap();
+		let out_dir = dir.path().to_path_buf();
+		fs::create_dir_all(out_dir.join("posts")).unwrap();
+		fs::write(out_dir.join("index.html"), "home").unwrap();
+		fs::write(out_dir.join("posts/index.html"), "posts").unwrap();
+
+		let deploy = Deploy::new(
+			Target::Rsync {
+				destination: "user@host:/var/www/site".into(),
+				args: vec![],
+			},
+			&GenerateConfig {
+				out_dir: out_dir.clone(),
+				..GenerateConfig::default()
+			},
+		);
+
+		let mut manifest = Manifest::new();
+		deploy.hash_files(&out_dir, &mut manifest).unwrap();
+		assert_eq!(
+			manifest["index.html"],
+			hex_digest_file(&out_dir.join("index.html")).unwrap()
+		);
+
+		let deployed = Manifest::from([
+			("index.html".to_string(), manifest["index.html"].clone()),
+			("posts/index.html".to_string(), "stale".to_string()),
+			("old.html".to_string(), "old".to_string()),
+		]);
+		let changes = deploy.changes(&manifest, &deployed).unwrap();
+
+		assert_eq!(changes.uploaded, ["posts/index.html"]);
+		assert_eq!(changes.removed, ["old.html"]);
+		assert_eq!(changes.unchanged, 1);
+		assert_eq!(changes.uploaded_bytes(), 5);
+		assert_eq!(changes.requests, 2);
+	}
+
+	#[test]
+	fn sftp_upload_dirs() {
+		let changes = Changes {
+			uploaded: vec![
+				"a/b/index.html".into(),
+				"a/c.css".into(),
+				"index.html".into(),
+			],
+			..Changes::default()
+		};
+
+		assert_eq!(
+			upload_dirs(&changes).into_iter().collect::<Vec<_>>(),
+			["a", "a/b"]
+		);
+		assert_eq!(quote("a \"b\"\\c"), "\"a \\\"b\\\"\\\\c\"");
+	}
+}
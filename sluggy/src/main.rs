@@ -5,37 +5,55 @@ use tikv_jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use config::Config;
 use dotenvy::dotenv;
 use miette::{Context, IntoDiagnostic};
 use opentelemetry_api::trace::Tracer;
 
-use sluggy_core::{error::Result, store::Cache};
+use sluggy_core::{
+	error::{Error, Result},
+	map_err,
+	store::Cache,
+};
 
+#[cfg(feature = "acme")]
+mod acme;
+#[cfg(feature = "s3")]
+mod bucket;
 mod debouncer;
+mod deploy;
+#[cfg(feature = "purge")]
+mod purge;
 mod server;
 mod watch;
 
 use debouncer::DebouncedEvent;
+use deploy::{Deploy, Target};
 use server::{serve, ServerConfig};
-use sluggy_core::generate::{config::Config as GenerateConfig, Generator};
+use sluggy_core::generate::{
+	config::{Config as GenerateConfig, DumpContext, ReportChanges},
+	Generator, OnDemand,
+};
 use std::{
 	fs,
-	io::{self},
+	io::{self, IsTerminal},
 	path::{Path, PathBuf},
 	str::FromStr,
-	sync::Arc,
-	time::Duration,
+	sync::{Arc, RwLock},
+};
+use tokio::{
+	io::{AsyncBufReadExt, BufReader},
+	select,
+	task::block_in_place,
 };
-use tokio::select;
 use tracing::{instrument, Level};
 use tracing_subscriber::{fmt::format::FmtSpan, prelude::*, EnvFilter, Registry};
 use watch::Watch;
 
 mod config;
 
-#[derive(Debug, Parser)]
+#[derive(Clone, Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
 	/// Config file
@@ -54,6 +72,27 @@ struct Cli {
 	#[arg(long)]
 	worker_threads: Option<usize>,
 
+	/// Continue generating the rest of the site when an entry fails to render
+	#[arg(long)]
+	keep_going: bool,
+
+	/// Include drafts and entries which are published in the future
+	#[arg(long)]
+	drafts: bool,
+
+	/// Profile of the config file which overrides its settings
+	#[arg(long)]
+	profile: Option<String>,
+
+	/// Base URL of the site, which overrides `generate.base_url` and the profile's
+	#[arg(long)]
+	base_url: Option<String>,
+
+	/// Write the render context of an entry, or of every entry when no path is given, to the
+	/// `context` dir of the cache dir
+	#[arg(long, value_name = "ENTRY")]
+	dump_context: Option<Option<PathBuf>>,
+
 	#[command(subcommand)]
 	command: Command,
 }
@@ -64,13 +103,45 @@ enum Command {
 	Generate,
 	/// Serve site
 	Serve,
+	/// Run the template test cases of the tests dir
+	Test {
+		/// Write the snapshots of the test cases again with the current output
+		#[arg(long)]
+		update_snapshots: bool,
+	},
+	/// Export the graph of the entries, sections, taxonomy terms and links between entries
+	Graph {
+		#[arg(long, value_enum, default_value_t = GraphFormat::Json)]
+		format: GraphFormat,
+		/// File the graph is written to, instead of stdout
+		#[arg(short, long)]
+		output: Option<PathBuf>,
+	},
+	/// Generate the site, and sync the files which changed to the `deploy` target
+	Deploy {
+		/// List the files which would be uploaded and removed, with their sizes and the requests
+		/// syncing them would take, without changing the target
+		#[arg(long)]
+		dry_run: bool,
+		/// Deploy `out_dir` as it is, without generating the site first
+		#[arg(long)]
+		no_generate: bool,
+	},
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum GraphFormat {
+	Json,
+	Graphml,
 }
 
 impl Command {
 	async fn exec(
 		&self,
+		cli: &Cli,
 		generate_config: GenerateConfig,
 		server_config: ServerConfig,
+		deploy_target: Option<Target>,
 	) -> Result<()> {
 		let generate_config = Arc::new(generate_config);
 		let server_config = Arc::new(server_config);
@@ -79,10 +150,102 @@ impl Command {
 			Self::Generate => {
 				Generator::generate(generate_config.clone()).await?;
 			}
+			Self::Test { update_snapshots } => {
+				let results =
+					Generator::test_templates(generate_config.clone(), *update_snapshots)?;
+
+				for result in &results {
+					let status = if result.passed() { "ok" } else { "FAILED" };
+					println!(
+						"test {}::{} ... {status}",
+						result.file.display(),
+						result.name
+					);
+					for failure in &result.failures {
+						println!("    {failure}");
+					}
+				}
+
+				let failed = results.iter().filter(|result| !result.passed()).count();
+				println!("\n{} passed, {failed} failed", results.len() - failed);
+
+				if failed > 0 {
+					return Err(Error::TemplateTestsFailed(failed));
+				}
+			}
+			Self::Graph { format, output } => {
+				let graph = Generator::content_graph(generate_config.clone()).await?;
+				let graph = match format {
+					GraphFormat::Json => map_err!(
+						serde_json::to_string_pretty(&graph),
+						SerdeJsonError("failed to serialize content graph"),
+					)?,
+					GraphFormat::Graphml => graph.to_graphml(),
+				};
+
+				match output {
+					Some(output) => map_err!(
+						fs::write(output, graph),
+						IoError(format!("failed to write {}", output.display())),
+					)?,
+					None => println!("{graph}"),
+				}
+			}
+			Self::Deploy {
+				dry_run,
+				no_generate,
+			} => {
+				// The target is read from the config along with the command.
+				let Some(deploy_target) = deploy_target else {
+					return Ok(());
+				};
+
+				if !no_generate {
+					Generator::generate(generate_config.clone()).await?;
+				}
+
+				let target = deploy_target.to_string();
+				let deploy = Deploy::new(deploy_target, &generate_config);
+				let changes = block_in_place(|| deploy.deploy(*dry_run))?;
+
+				if *dry_run {
+					println!("Dry run of deploying to {target}\n");
+				} else {
+					println!("Deployed to {target}\n");
+				}
+
+				let verb = if *dry_run { "Would upload" } else { "Uploaded" };
+				for path in &changes.uploaded {
+					let size = changes.sizes.get(path).copied().unwrap_or_default();
+					println!("{verb} {path} ({size} bytes)");
+				}
+				let verb = if *dry_run { "Would remove" } else { "Removed" };
+				for path in &changes.removed {
+					println!("{verb} {path}");
+				}
+				println!(
+					"\n{} uploaded ({} bytes), {} removed, {} unchanged",
+					changes.uploaded.len(),
+					changes.uploaded_bytes(),
+					changes.removed.len(),
+					changes.unchanged
+				);
+				let verb = if *dry_run { "would take" } else { "took" };
+				println!("Syncing {verb} about {} requests", changes.requests);
+			}
 			Self::Serve => {
-				if server_config.generate {
+				if server_config.render_on_request {
+					server_config
+						.pages
+						.set(OnDemand::load(generate_config.clone()).await?);
+				} else if server_config.generate {
 					Generator::generate(generate_config.clone()).await?;
 				}
+				// Files may have changed or been removed since the site was last served.
+				purge_everything(&server_config);
+
+				// Replaced when the config file changes.
+				let live_config = Arc::new(RwLock::new(generate_config.clone()));
 
 				let watcher = Watch::new(
 					[
@@ -92,45 +255,147 @@ impl Command {
 						generate_config.css_dir.clone(),
 						generate_config.out_dir.clone(),
 					]
+					.into_iter()
+					// The js dir is optional, and can't be watched when it doesn't exist.
+					.chain(
+						generate_config
+							.js_dir
+							.is_dir()
+							.then(|| generate_config.js_dir.clone()),
+					)
+					.chain(
+						generate_config
+							.versions
+							.iter()
+							.map(|version| version.content_dir.clone()),
+					)
+					.collect::<Vec<_>>()
 					.into_iter(),
-					Duration::from_millis(250),
+					server_config.watch_debounce,
 					{
 						let server_config = server_config.clone();
-						let generate_config = generate_config.clone();
+						let live_config = live_config.clone();
+						let cli = cli.clone();
 						move |events: Vec<_>| {
 							let server_config = server_config.clone();
-							let generate_config = generate_config.clone();
+							let live_config = live_config.clone();
+							let cli = cli.clone();
 							async move {
-								if server_config.generate
-									&& !notify_events_all(&events[..], &server_config.serve_dir)
+								if notify_events_config(&events[..], &server_config.config_files) {
+									reload_config(&cli, &live_config, &server_config).await;
+									return Ok(());
+								}
+
+								let generate_config = live_config.read().unwrap().clone();
+
+								// Fast paths are skipped after a failed build, as it may have left other
+								// pages out of date, and when fingerprinting, as pages link to the names
+								// of the stylesheets and assets.
+								let can_skip_generate = server_config.generate
+									&& !server_config.build_error.is_set()
+									&& !generate_config.fingerprint.enabled;
+
+								if can_skip_generate
+									&& notify_events_assets_only(
+										&events[..],
+										&generate_config.assets_dir,
+									) {
+									let span = tracing::span!(Level::INFO, "copy_assets");
+									let _enter = span.enter();
+
+									let mut files = events
+										.iter()
+										.map(|event| event.path.clone())
+										.collect::<Vec<_>>();
+									files.sort();
+									files.dedup();
+
+									match Generator::copy_assets(generate_config.clone(), &files)
+										.await
+									{
+										Ok(paths) => {
+											for path in &paths {
+												server_config.store.invalidate(path);
+											}
+											purge_written(&server_config);
+										}
+										Err(error) => {
+											tracing::event!(
+												Level::ERROR,
+												%error,
+												"Unable to copy assets"
+											);
+											server_config.build_error.set(error);
+										}
+									}
+
+									server_config.reload.reload();
+								} else if can_skip_generate
+									&& server_config.render_on_request
+									&& notify_events_all(&events[..], &generate_config.template_dir)
+								{
+									let span = tracing::span!(Level::INFO, "reload_templates");
+									let _enter = span.enter();
+
+									if let Some(Err(error)) = server_config.pages.reload_templates()
+									{
+										tracing::event!(
+											Level::ERROR,
+											%error,
+											"Unable to parse templates"
+										);
+										server_config.build_error.set(error);
+									}
+
+									server_config.store.invalidate_all();
+									server_config.reload.reload();
+								} else if can_skip_generate
+									&& !server_config.render_on_request
+									&& notify_events_all(&events[..], &generate_config.css_dir)
 								{
-									let span = tracing::span!(Level::INFO, "reload_and_generate");
+									let span = tracing::span!(Level::INFO, "regenerate_css");
 									let _enter = span.enter();
 
 									if let Err(error) =
-										Generator::generate(generate_config.clone()).await
+										Generator::regenerate_css(generate_config.clone()).await
 									{
 										tracing::event!(
 											Level::ERROR,
 											%error,
-											"Unable to render templates"
+											"Unable to bundle css"
 										);
+										server_config.build_error.set(error);
 									}
 
+									report_changes(&server_config);
 									server_config.store.invalidate_all();
+									purge_written(&server_config);
+									server_config.reload.reload();
+								} else if server_config.generate
+									&& !notify_events_all(&events[..], &server_config.serve_dir)
+								{
+									let changed = events
+										.iter()
+										.map(|event| event.path.clone())
+										.collect::<Vec<_>>();
+									regenerate(&generate_config, &server_config, &changed).await;
 								} else if !server_config.generate
 									&& notify_events_any(&events[..], &server_config.serve_dir)
 								{
 									let span = tracing::span!(Level::INFO, "invalidate_store_only");
 									let _enter = span.enter();
 									server_config.store.invalidate_all();
+									server_config.page_headers.invalidate();
+									server_config.redirects.load(&server_config).await;
+									server_config.reload.reload();
 								}
 
 								Ok(())
 							}
 						}
 					},
-				);
+				)
+				.with_files(server_config.config_files.clone());
 
 				let serve_handle = tokio::spawn({
 					let server_config = server_config.clone();
@@ -154,9 +419,18 @@ impl Command {
 					}
 				});
 
+				let commands_handle = tokio::spawn({
+					let server_config = server_config.clone();
+					async move { read_commands(live_config, server_config).await }
+				});
+
+				let bucket_handle = tokio::spawn(refresh_bucket(server_config.clone()));
+
 				select! {
 					_ = serve_handle => {},
 					_ = watch_handle => {},
+					_ = commands_handle => {},
+					_ = bucket_handle => {},
 				}
 			}
 		}
@@ -165,12 +439,224 @@ impl Command {
 	}
 }
 
+/// Generates the site again, or loads it again when entries are rendered on request, and reloads
+/// the pages which are open once it's done. `changed` are the files which have changed, or none
+/// when it isn't known what has.
+#[instrument(name = "reload_and_generate", skip_all)]
+async fn regenerate(
+	generate_config: &Arc<GenerateConfig>,
+	server_config: &Arc<ServerConfig>,
+	changed: &[PathBuf],
+) {
+	let result = if server_config.render_on_request {
+		server_config
+			.pages
+			.reload(generate_config.clone(), changed)
+			.await
+	} else {
+		Generator::generate(generate_config.clone()).await
+	};
+
+	match result {
+		Ok(_) => server_config.build_error.clear(),
+		Err(error) => {
+			tracing::event!(Level::ERROR, %error, "Unable to render templates");
+			server_config.build_error.set(error);
+		}
+	}
+
+	report_changes(server_config);
+	// The store is invalidated first, so that the CDN doesn't fetch stale content from it again.
+	server_config.store.invalidate_all();
+	server_config.page_headers.invalidate();
+	server_config.redirects.load(server_config).await;
+	purge_written(server_config);
+	server_config.reload.reload();
+}
+
+/// Reads the settings of generating from the config file again, and regenerates the site with
+/// them. The site is served as it is when they can't be read.
+async fn reload_config(
+	cli: &Cli,
+	live_config: &RwLock<Arc<GenerateConfig>>,
+	server_config: &Arc<ServerConfig>,
+) {
+	let generate_config = match reload_generate_config(cli) {
+		Ok(generate_config) => Arc::new(generate_config),
+		Err(error) => {
+			tracing::event!(Level::ERROR, ?error, "Unable to reload the config");
+			return;
+		}
+	};
+	tracing::event!(
+		Level::INFO,
+		"Reloaded the config, changes to `serve` apply once the server restarts"
+	);
+	*live_config.write().unwrap() = generate_config.clone();
+
+	if server_config.generate {
+		regenerate(&generate_config, server_config, &[]).await;
+	}
+}
+
+/// Logs the pages which changed content in the last build, and keeps them for the changes
+/// endpoint.
+fn report_changes(server_config: &ServerConfig) {
+	let changes = Generator::take_changes();
+	for change in &changes {
+		tracing::event!(
+			Level::INFO,
+			path = %change.path.display(),
+			created = change.created,
+			added = change.added,
+			removed = change.removed,
+			"Page changed"
+		);
+		for hunk in &change.summary {
+			tracing::event!(Level::INFO, path = %change.path.display(), "{hunk}");
+		}
+	}
+
+	if server_config.report_changes != ReportChanges::Off {
+		tracing::event!(Level::INFO, count = changes.len(), "Pages changed");
+	}
+	server_config.changes.set(changes);
+}
+
+/// Purges the URLs of the files which were written since the last purge from the CDN in front of
+/// the server, in the background so that pages are reloaded without waiting for it.
+fn purge_written(server_config: &ServerConfig) {
+	let written = Generator::take_written();
+
+	#[cfg(feature = "purge")]
+	if let Some(purge) = server_config.purge.clone() {
+		let urls = purge.urls(&written, &server_config.serve_dir);
+		tokio::task::spawn_blocking(move || match purge.purge(&urls) {
+			Ok(()) => tracing::event!(Level::INFO, count = urls.len(), "Purged URLs"),
+			Err(error) => tracing::event!(Level::WARN, %error, "Unable to purge URLs"),
+		});
+	}
+
+	#[cfg(not(feature = "purge"))]
+	let _ = (server_config, written);
+}
+
+/// Purges everything from the CDN in front of the server, in the background, forgetting the files
+/// which were written.
+fn purge_everything(server_config: &ServerConfig) {
+	Generator::take_written();
+
+	#[cfg(feature = "purge")]
+	if let Some(purge) = server_config.purge.clone() {
+		tokio::task::spawn_blocking(move || match purge.purge_everything() {
+			Ok(()) => tracing::event!(Level::INFO, "Purged everything"),
+			Err(error) => tracing::event!(Level::WARN, %error, "Unable to purge everything"),
+		});
+	}
+
+	#[cfg(not(feature = "purge"))]
+	let _ = server_config;
+}
+
+/// Reads commands from stdin while serving, one per line: `r` to regenerate the site, `c` to clear
+/// the store and `q` to quit. For when the watcher misses changes, such as on network shares.
+async fn read_commands(
+	live_config: Arc<RwLock<Arc<GenerateConfig>>>,
+	server_config: Arc<ServerConfig>,
+) {
+	if io::stdin().is_terminal() {
+		println!("Enter r to regenerate, c to clear the cache or q to quit");
+
+		let mut lines = BufReader::new(tokio::io::stdin()).lines();
+		while let Ok(Some(line)) = lines.next_line().await {
+			match line.trim() {
+				"r" => {
+					if server_config.generate {
+						let generate_config = live_config.read().unwrap().clone();
+						regenerate(&generate_config, &server_config, &[]).await;
+					} else {
+						server_config.store.invalidate_all();
+						server_config.page_headers.invalidate();
+						server_config.redirects.load(&server_config).await;
+						server_config.reload.reload();
+					}
+				}
+				"c" => {
+					server_config.store.invalidate_all();
+					server_config.page_headers.invalidate();
+					server_config.redirects.load(&server_config).await;
+					println!("Cleared the cache");
+				}
+				"q" => return,
+				"" => {}
+				command => println!("Unknown command `{command}`"),
+			}
+		}
+	}
+
+	// Keep serving when there's no terminal to read commands from.
+	std::future::pending::<()>().await
+}
+
+/// Clears the store whenever the manifest of the bucket which is served changes, as the bucket is
+/// deployed to without the server knowing. Buckets which aren't deployed by sluggy have no
+/// manifest, so the store is cleared on each check.
+async fn refresh_bucket(server_config: Arc<ServerConfig>) {
+	#[cfg(feature = "s3")]
+	if let Some(bucket) = server_config.bucket.clone() {
+		let mut interval = tokio::time::interval(server_config.bucket_refresh);
+		interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+		// The first tick completes immediately, when there's nothing in the store yet.
+		interval.tick().await;
+		let (manifest_bucket, manifest_path) = deploy::bucket_manifest(&bucket);
+		let mut deployed = manifest_bucket.etag(&manifest_path).await;
+
+		loop {
+			interval.tick().await;
+
+			let etag = manifest_bucket.etag(&manifest_path).await;
+			if etag.is_none() || etag != deployed {
+				tracing::event!(Level::DEBUG, "Clearing the store of the bucket");
+				server_config.store.invalidate_all();
+				server_config.page_headers.invalidate();
+				server_config.redirects.load(&server_config).await;
+			}
+			deployed = etag;
+		}
+	}
+
+	#[cfg(not(feature = "s3"))]
+	let _ = server_config;
+
+	std::future::pending::<()>().await
+}
+
+/// Whether any of the events are for the config file, or the files it includes.
+#[inline]
+#[instrument(level = "debug", skip_all)]
+fn notify_events_config(events: &[DebouncedEvent], config_files: &[PathBuf]) -> bool {
+	events
+		.iter()
+		.any(|event| config_files.contains(&event.path))
+}
+
 #[inline]
 #[instrument(level = "debug", skip(events))]
 fn notify_events_all(events: &[DebouncedEvent], prefix: &Path) -> bool {
 	events.iter().all(|event| event.path.starts_with(prefix))
 }
 
+/// Whether the events are only for assets which still exist, which can be copied without
+/// regenerating the site.
+#[inline]
+#[instrument(level = "debug", skip(events))]
+fn notify_events_assets_only(events: &[DebouncedEvent], assets_dir: &Path) -> bool {
+	!events.is_empty()
+		&& events
+			.iter()
+			.all(|event| event.path.starts_with(assets_dir) && event.path.is_file())
+}
+
 #[inline]
 #[instrument(level = "debug", skip(events))]
 fn notify_events_any(events: &[DebouncedEvent], prefix: &Path) -> bool {
@@ -182,51 +668,118 @@ fn main() -> miette::Result<()> {
 
 	let cli = Cli::parse();
 
+	let (mut config_file, profile_drafts) = read_config(&cli)?;
+
+	let worker_threads = cli
+		.worker_threads
+		.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, usize::from));
+
+	let runtime = tokio::runtime::Builder::new_multi_thread()
+		.worker_threads(worker_threads)
+		.enable_all()
+		.build()
+		.unwrap();
+
+	let deploy_target = match cli.command {
+		Command::Deploy { .. } => Some(std::mem::take(&mut config_file.deploy).target()?),
+		_ => None,
+	};
+
+	let (mut generate_config, server_config): (GenerateConfig, ServerConfig) =
+		config_file.try_into()?;
+	apply_cli(&cli, &mut generate_config, &server_config, profile_drafts);
+
+	runtime.block_on(exec(cli, generate_config, server_config, deploy_target))?;
+
+	Ok(())
+}
+
+/// Reads the config file, or the defaults when there isn't one, with the profile and base URL of
+/// `cli`. Returns whether drafts are included, if the profile sets it.
+fn read_config(cli: &Cli) -> miette::Result<(Config, Option<bool>)> {
 	let config_file = &cli.config.as_ref();
 	let config_file = PathBuf::from_str(config_file.unwrap_or(&"sluggy.toml".into()))
 		.into_diagnostic()
 		.wrap_err("Invalid config path")?
 		.canonicalize();
 
-	let config_file = match config_file {
+	let mut config_file = match config_file {
 		Err(_) if cli.config.is_none() => Config::default(),
 		Err(error) => {
 			return Err(error)
 				.into_diagnostic()
 				.wrap_err("Failed to find config file")
 		}
-		Ok(config_file) => toml::from_str(
-			&fs::read_to_string(config_file)
-				.into_diagnostic()
-				.wrap_err("Failed to read config file")?,
-		)
-		.into_diagnostic()
-		.wrap_err("Failed to parse config file")?,
+		Ok(config_file) => Config::from_file(&config_file)?,
 	};
 
-	let worker_threads = cli
-		.worker_threads
-		.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, usize::from));
+	let profile_drafts = match &cli.profile {
+		Some(profile) => config_file.apply_profile(profile)?,
+		None => None,
+	};
+	if let Some(base_url) = &cli.base_url {
+		config_file.generate.base_url = Some(base_url.clone());
+	}
 
-	let runtime = tokio::runtime::Builder::new_multi_thread()
-		.worker_threads(worker_threads)
-		.enable_all()
-		.build()
-		.unwrap();
+	Ok((config_file, profile_drafts))
+}
 
-	let (generate_config, server_config) = config_file.try_into()?;
+/// Overrides the settings of generating with the arguments of `cli`, and with those of serving.
+fn apply_cli(
+	cli: &Cli,
+	generate_config: &mut GenerateConfig,
+	server_config: &ServerConfig,
+	profile_drafts: Option<bool>,
+) {
+	if cli.keep_going {
+		generate_config.keep_going = true;
+	}
 
-	runtime
-		.block_on(exec(cli, generate_config, server_config))
-		.into_diagnostic()?;
+	if let Some(entry_path) = &cli.dump_context {
+		generate_config.dump_context = Some(match entry_path {
+			Some(entry_path) => DumpContext::Entry(entry_path.clone()),
+			None => DumpContext::All,
+		});
+	}
 
-	Ok(())
+	if cli.drafts || profile_drafts.unwrap_or(cli.command == Command::Serve && server_config.drafts)
+	{
+		generate_config.drafts = true;
+	}
+
+	if cli.command == Command::Serve && server_config.live_reload {
+		generate_config.live_reload = true;
+	}
+
+	if cli.command == Command::Serve && server_config.watch {
+		generate_config.report_changes = server_config.report_changes;
+	}
+
+	if cli.command == Command::Serve {
+		generate_config.serve_mode = true;
+		generate_config.dev_client_script = server_config
+			.asset_proxy
+			.as_ref()
+			.and_then(|asset_proxy| asset_proxy.client_script.clone());
+	}
+}
+
+/// Reads the settings of generating from the config file again, once it or one of the files it
+/// includes changes while serving. Settings of serving only change once the server restarts.
+fn reload_generate_config(cli: &Cli) -> miette::Result<GenerateConfig> {
+	let (config_file, profile_drafts) = read_config(cli)?;
+	let (mut generate_config, server_config): (GenerateConfig, ServerConfig) =
+		config_file.try_into()?;
+	apply_cli(cli, &mut generate_config, &server_config, profile_drafts);
+
+	Ok(generate_config)
 }
 
 async fn exec(
 	cli: Cli,
 	generate_config: GenerateConfig,
 	server_config: ServerConfig,
+	deploy_target: Option<Target>,
 ) -> Result<()> {
 	let env_filter =
 		EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("sluggy=info"));
@@ -266,5 +819,7 @@ async fn exec(
 	let tracing_subscriber = tracing_subscriber.with(fmt_layer); //.with(otlp_layer);
 	tracing::subscriber::set_global_default(tracing_subscriber)?;
 
-	cli.command.exec(generate_config, server_config).await
+	cli.command
+		.exec(&cli, generate_config, server_config, deploy_target)
+		.await
 }
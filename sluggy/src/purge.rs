@@ -0,0 +1,174 @@
+use reqwest::blocking::{Client, RequestBuilder};
+use serde_json::json;
+use std::{
+	fmt,
+	path::{Path, PathBuf},
+};
+use tracing::instrument;
+
+/// Cloudflare purges at most this many URLs with each request.
+const CLOUDFLARE_BATCH_SIZE: usize = 30;
+
+/// How the cache of a CDN is purged.
+#[derive(Clone)]
+pub enum Provider {
+	/// Purges by URL with the API of a Cloudflare zone.
+	Cloudflare { zone_id: String, api_token: String },
+	/// Purges each URL with Fastly's API, or everything of the service.
+	Fastly {
+		service_id: String,
+		api_token: String,
+	},
+	/// `POST`s `{ "urls": [...] }` to `url`, or `{ "everything": true }` to purge everything, for
+	/// CDNs and scripts which aren't supported.
+	Webhook {
+		url: String,
+		headers: Vec<(String, String)>,
+	},
+}
+
+impl fmt::Debug for Provider {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Cloudflare { zone_id, .. } => f
+				.debug_struct("Cloudflare")
+				.field("zone_id", zone_id)
+				.finish_non_exhaustive(),
+			Self::Fastly { service_id, .. } => f
+				.debug_struct("Fastly")
+				.field("service_id", service_id)
+				.finish_non_exhaustive(),
+			Self::Webhook { url, .. } => f
+				.debug_struct("Webhook")
+				.field("url", url)
+				.finish_non_exhaustive(),
+		}
+	}
+}
+
+/// Purges the URLs of the files which are written while serving from the cache of a CDN in front
+/// of the server, so that only the changed pages and assets are fetched again.
+#[derive(Debug, Clone)]
+pub struct Purge {
+	provider: Provider,
+	/// URL of the site as the CDN serves it, with a trailing slash.
+	base_url: String,
+	client: Client,
+}
+
+impl Purge {
+	pub fn new(provider: Provider, base_url: &str) -> Self {
+		Self {
+			provider,
+			base_url: format!("{}/", base_url.trim_end_matches('/')),
+			// Created with the config, before the runtime is started, as the blocking client can't
+			// be created within it.
+			client: Client::new(),
+		}
+	}
+
+	/// URLs of `files`, which were written within `serve_dir`. Index pages are also purged at the
+	/// URL of their directory, which is how they're usually requested.
+	pub fn urls(&self, files: &[PathBuf], serve_dir: &Path) -> Vec<String> {
+		let mut urls = vec![];
+		for file in files {
+			let Ok(path) = file.strip_prefix(serve_dir) else {
+				continue;
+			};
+			let path = path.to_string_lossy().replace('\\', "/");
+
+			if let Some(dir) = path.strip_suffix("index.html") {
+				if dir.is_empty() || dir.ends_with('/') {
+					urls.push(format!("{}{dir}", self.base_url));
+				}
+			}
+			urls.push(format!("{}{path}", self.base_url));
+		}
+		urls
+	}
+
+	/// Purges `urls` from the CDN's cache.
+	#[instrument(skip(self, urls), fields(count = urls.len()))]
+	pub fn purge(&self, urls: &[String]) -> Result<(), String> {
+		if urls.is_empty() {
+			return Ok(());
+		}
+
+		match &self.provider {
+			Provider::Cloudflare { zone_id, api_token } => {
+				for batch in urls.chunks(CLOUDFLARE_BATCH_SIZE) {
+					send(
+						self.client
+							.post(format!(
+								"https://api.cloudflare.com/client/v4/zones/{zone_id}/purge_cache"
+							))
+							.bearer_auth(api_token)
+							.json(&json!({ "files": batch })),
+					)?;
+				}
+			}
+			Provider::Fastly { api_token, .. } => {
+				for url in urls {
+					let cached_url = url
+						.split_once("://")
+						.map_or(url.as_str(), |(_, cached_url)| cached_url);
+					send(
+						self.client
+							.post(format!("https://api.fastly.com/purge/{cached_url}"))
+							.header("Fastly-Key", api_token),
+					)?;
+				}
+			}
+			Provider::Webhook { url, headers } => {
+				let request = headers.iter().fold(
+					self.client.post(url).json(&json!({ "urls": urls })),
+					|request, (name, value)| request.header(name, value),
+				);
+				send(request)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Purges everything of the site from the CDN's cache, with one request.
+	#[instrument(skip(self))]
+	pub fn purge_everything(&self) -> Result<(), String> {
+		let request = match &self.provider {
+			Provider::Cloudflare { zone_id, api_token } => self
+				.client
+				.post(format!(
+					"https://api.cloudflare.com/client/v4/zones/{zone_id}/purge_cache"
+				))
+				.bearer_auth(api_token)
+				.json(&json!({ "purge_everything": true })),
+			Provider::Fastly {
+				service_id,
+				api_token,
+			} => self
+				.client
+				.post(format!(
+					"https://api.fastly.com/service/{service_id}/purge_all"
+				))
+				.header("Fastly-Key", api_token),
+			Provider::Webhook { url, headers } => headers.iter().fold(
+				self.client.post(url).json(&json!({ "everything": true })),
+				|request, (name, value)| request.header(name, value),
+			),
+		};
+
+		send(request)
+	}
+}
+
+#[inline]
+fn send(request: RequestBuilder) -> Result<(), String> {
+	let response = request
+		.send()
+		.map_err(|error| format!("purge request failed: {error}"))?;
+	if !response.status().is_success() {
+		return Err(format!("purge request failed with {}", response.status()));
+	}
+
+	Ok(())
+}
@@ -1,33 +1,94 @@
+#[cfg(feature = "acme")]
+use crate::acme::Acme;
+#[cfg(feature = "s3")]
+use crate::bucket::Bucket;
+#[cfg(feature = "purge")]
+use crate::purge::Purge;
 use axum::{
 	body::{Body, Bytes},
-	extract::State,
-	handler::Handler,
+	extract::{
+		ws::{Message, WebSocket, WebSocketUpgrade},
+		State,
+	},
+	headers::{ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, LastModified},
 	http::{
 		header, HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Uri,
 	},
+	response::IntoResponse,
+	routing::get,
+	Json, Router,
+};
+#[cfg(feature = "tls")]
+use axum_server::{
+	accept::Accept,
+	tls_rustls::{RustlsAcceptor, RustlsConfig},
+	Handle, HttpConfig as TlsHttpConfig,
 };
+#[cfg(feature = "tls")]
+use futures::future::BoxFuture;
+use futures::Stream;
+use hyper::{
+	client::HttpConnector,
+	http::uri::InvalidUri,
+	server::{accept, Builder},
+	Client,
+};
+use memmap2::Mmap;
+use miette::{GraphicalReportHandler, GraphicalTheme};
+use regex::Regex;
+use serde_derive::Serialize;
+#[cfg(feature = "serve-store")]
+use sluggy_core::store::InMemoryStore;
 use sluggy_core::{
-	common::http::ContentEncoding,
+	common::http::{
+		parse_headers_file, parse_redirects_file, CHANGES_PATH, ERROR_PAGES_DIR,
+		FALLBACK_ERROR_PAGE, HEADERS_FILE, LIVE_RELOAD_PATH, REDIRECTS_FILE, REQUESTS_PATH,
+	},
+	encoding::{negotiate, ContentEncoding},
 	error::{Error, Result},
+	generate::{
+		config::{Config as GenerateConfig, ReportChanges},
+		OnDemand, OutputChange,
+	},
 	map_err,
-	store::{Cache, InMemoryStore, NoStore},
-	utils::can_compress,
+	store::{Cache, NoStore},
+	utils::{can_compress, escape_xml, hex_digest, LockResultExt},
 };
 use std::{
-	fs,
-	io::ErrorKind,
+	collections::{BTreeMap, VecDeque},
+	fs::{self, Metadata},
+	future::Future,
+	io::{self, ErrorKind},
 	net::TcpListener,
 	path::{Component, Path, PathBuf},
+	pin::Pin,
 	str::FromStr,
-	sync::Arc,
-	time::Duration,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, RwLock,
+	},
+	task::{Context, Poll},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+	fs::File as TokioFile,
+	io::{copy_bidirectional, AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+	net::{TcpListener as TokioTcpListener, TcpStream as TokioTcpStream},
+	signal::{self, unix::SignalKind},
+	sync::{
+		broadcast::{self, error::RecvError},
+		OwnedSemaphorePermit, Semaphore,
+	},
+	task::block_in_place,
 };
-use tokio::signal::{self, unix::SignalKind};
 use tower_http::{
 	classify::ServerErrorsFailureClass, set_header::SetResponseHeaderLayer, trace::TraceLayer,
 };
 use tracing::{field, instrument, Level, Span};
 
+/// Size of the chunks which large files are streamed in.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
 	pub compress_content: bool,
@@ -35,15 +96,537 @@ pub struct ServerConfig {
 	pub serve_dir: PathBuf,
 	pub generate: bool,
 	pub watch: bool,
+	/// How long the watcher waits for changes to settle before handling them.
+	pub watch_debounce: Duration,
+	/// The config file and the files it includes, which the settings of generating are read from
+	/// again while watching when they change.
+	pub config_files: Vec<PathBuf>,
 	pub host: String,
 	pub port: u16,
 	pub content_encoding: ContentEncoding,
+	/// Request paths which are always served without a content encoding.
+	pub identity_paths: Vec<Regex>,
+	/// Clients, by `User-Agent`, which are always served without a content encoding.
+	pub identity_user_agents: Vec<Regex>,
+	/// Only 1 in every `log_sample_rate` responses is logged.
+	pub log_sample_rate: u64,
+	/// Responses slower than this are always logged.
+	pub slow_request_threshold: Option<Duration>,
 	pub store: Store,
+	/// Drop the identity content of compressible files from the store once a compressed variant
+	/// of it is stored, and decode the variant for clients which don't accept it.
+	pub store_compressed_only: bool,
+	pub build_error: BuildError,
+	/// Whether pages are reloaded by the live reload script after each build.
+	pub live_reload: bool,
+	pub reload: LiveReload,
+	/// Whether the pages which change in each rebuild are reported.
+	pub report_changes: ReportChanges,
+	pub changes: LastChanges,
+	/// Whether drafts are generated while serving.
+	pub drafts: bool,
+	pub page_headers: PageHeaders,
+	pub redirects: Redirects,
+	/// Whether entries are rendered when they're requested, instead of being generated.
+	pub render_on_request: bool,
+	pub pages: OnDemandPages,
+	/// Pages served with each error status, relative to `serve_dir`, in place of the page in
+	/// `_error`.
+	pub error_pages: BTreeMap<u16, PathBuf>,
+	/// The last requests for content, for debugging which encoding and bytes clients are served.
+	pub request_log: RequestLog,
+	/// Files larger than this many bytes are streamed from disk instead of being read into memory.
+	pub stream_threshold: u64,
+	/// Whether files are memory-mapped instead of being read.
+	pub mmap: bool,
+	/// Serves over HTTPS when set.
+	#[cfg(feature = "tls")]
+	pub tls: Option<Tls>,
+	pub http: HttpConfig,
+	/// Requests for assets which are proxied to a separate dev server.
+	pub asset_proxy: Option<AssetProxy>,
+	/// Serves content from a bucket instead of `serve_dir`.
+	#[cfg(feature = "s3")]
+	pub bucket: Option<Bucket>,
+	/// How often the bucket is checked for a new deploy.
+	#[cfg(feature = "s3")]
+	pub bucket_refresh: Duration,
+	/// Purges the URLs of the files written by each rebuild from a CDN.
+	#[cfg(feature = "purge")]
+	pub purge: Option<Purge>,
+}
+
+/// Proxies requests for some paths to a dev server, such as vite or esbuild, while everything else
+/// is served from `serve_dir`.
+#[derive(Debug, Clone)]
+pub struct AssetProxy {
+	/// Origin of the dev server, without a trailing slash.
+	origin: String,
+	/// Request paths which start with any of these are proxied.
+	paths: Vec<String>,
+	/// Module script of the dev server which is loaded by each page.
+	pub client_script: Option<String>,
+	client: Client<HttpConnector>,
+}
+
+impl AssetProxy {
+	pub fn new(
+		origin: String,
+		paths: Vec<String>,
+		client_script: Option<String>,
+	) -> std::result::Result<Self, InvalidUri> {
+		Uri::from_str(&origin)?;
+
+		Ok(Self {
+			origin: origin.trim_end_matches('/').to_string(),
+			paths,
+			client_script,
+			client: Client::new(),
+		})
+	}
+
+	#[inline]
+	fn is_proxied(&self, uri: &Uri) -> bool {
+		self.paths.iter().any(|path| uri.path().starts_with(path))
+	}
+
+	/// Forwards the request to the dev server, responding with a `502` when it can't be reached.
+	/// Requests to upgrade the connection, such as those of the dev server's WebSocket, are
+	/// connected to the dev server's upgraded connection once it switches protocols.
+	#[instrument(skip(self, request))]
+	async fn proxy(&self, mut request: Request<Body>) -> Response<Body> {
+		let upgrade = request
+			.headers()
+			.contains_key(header::UPGRADE)
+			.then(|| hyper::upgrade::on(&mut request));
+
+		let path_and_query = request
+			.uri()
+			.path_and_query()
+			.map(|path_and_query| path_and_query.as_str())
+			.unwrap_or("/");
+
+		match Uri::from_str(&format!("{}{path_and_query}", self.origin)) {
+			Ok(uri) => *request.uri_mut() = uri,
+			Err(error) => {
+				tracing::warn!(%error, "Unable to proxy request");
+				return status_response(StatusCode::BAD_GATEWAY);
+			}
+		}
+		// Set again by the client for the dev server.
+		request.headers_mut().remove(header::HOST);
+
+		match self.client.request(request).await {
+			Ok(mut response) => {
+				if let Some(upgrade) =
+					upgrade.filter(|_| response.status() == StatusCode::SWITCHING_PROTOCOLS)
+				{
+					let upstream = hyper::upgrade::on(&mut response);
+					tokio::spawn(async move {
+						match tokio::try_join!(upgrade, upstream) {
+							Ok((mut upgraded, mut upstream)) => {
+								if let Err(error) =
+									copy_bidirectional(&mut upgraded, &mut upstream).await
+								{
+									tracing::debug!(%error, "Proxied connection closed");
+								}
+							}
+							Err(error) => {
+								tracing::warn!(%error, "Unable to upgrade proxied connection");
+							}
+						}
+					});
+				}
+
+				response
+			}
+			Err(error) => {
+				tracing::warn!(%error, origin = %self.origin, "Asset dev server is unavailable");
+				status_response(StatusCode::BAD_GATEWAY)
+			}
+		}
+	}
+}
+
+#[inline]
+fn status_response(status_code: StatusCode) -> Response<Body> {
+	let mut response = Response::new(Body::from(status_code.to_string()));
+	*response.status_mut() = status_code;
+	response
+}
+
+/// Tuning of the connections of the server.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+	/// Accept HTTP/2 connections as well as HTTP/1.
+	pub http2: bool,
+	/// Keep HTTP/1 connections open between requests.
+	pub keep_alive: bool,
+	/// How often HTTP/2 connections are pinged to keep them alive. They aren't pinged when unset.
+	pub http2_keep_alive_interval: Option<Duration>,
+	/// HTTP/2 connections are closed when a ping isn't answered within this time.
+	pub http2_keep_alive_timeout: Duration,
+	/// Connections beyond this many wait to be accepted until others are closed.
+	pub max_connections: Option<usize>,
+}
+
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone)]
+pub enum Tls {
+	/// PEM encoded certificate chain and private key.
+	Files { cert: PathBuf, key: PathBuf },
+	/// A certificate which is generated on each start, for development.
+	SelfSigned,
+	/// A certificate which is issued by an ACME server, and renewed while serving.
+	#[cfg(feature = "acme")]
+	Acme(Acme),
+}
+
+type HeaderRules = Vec<(String, Vec<(HeaderName, HeaderValue)>)>;
+
+/// The loaded site whose entries are rendered on request, once it's set after the first build.
+#[derive(Debug, Clone, Default)]
+pub struct OnDemandPages(Arc<RwLock<Option<Arc<OnDemand>>>>);
+
+impl OnDemandPages {
+	pub fn set(&self, on_demand: OnDemand) {
+		*self.0.write().acquire() = Some(Arc::new(on_demand));
+	}
+
+	/// Loads the site again after `changed` files have changed, keeping what they don't affect of
+	/// the loaded site, if there is one.
+	pub async fn reload(&self, config: Arc<GenerateConfig>, changed: &[PathBuf]) -> Result<()> {
+		let on_demand = match self.get() {
+			Some(on_demand) => on_demand.reload(changed).await?,
+			None => OnDemand::load(config).await?,
+		};
+		self.set(on_demand);
+
+		Ok(())
+	}
+
+	/// Parses the templates of the loaded site again, if there is one.
+	pub fn reload_templates(&self) -> Option<Result<()>> {
+		self.get().map(|on_demand| on_demand.reload_templates())
+	}
+
+	#[inline]
+	fn get(&self) -> Option<Arc<OnDemand>> {
+		self.0.read().acquire().clone()
+	}
+
+	#[inline]
+	fn resolve(&self, path: &Path) -> Option<PathBuf> {
+		self.get()?.resolve(path)
+	}
+
+	#[inline]
+	fn render(&self, path: &Path) -> Option<Result<Bytes>> {
+		let on_demand = self.get()?;
+		block_in_place(|| on_demand.render(path))
+			.map(|rendered| rendered.map(Bytes::from))
+			.transpose()
+	}
+}
+
+/// Response headers of the `_headers` file in `serve_dir` (or the bucket), which is read again on the first
+/// request after being invalidated.
+#[derive(Debug, Clone, Default)]
+pub struct PageHeaders(Arc<RwLock<Option<Arc<HeaderRules>>>>);
+
+impl PageHeaders {
+	pub fn invalidate(&self) {
+		*self.0.write().acquire() = None;
+	}
+
+	/// Headers of each rule which matches `path`, in the order of the file. Rules ending in `*`
+	/// match any path with that prefix.
+	#[inline]
+	async fn get(&self, path: &str, config: &ServerConfig) -> Vec<(HeaderName, HeaderValue)> {
+		let rules = self.rules(config).await;
+		let path = normalize_page_path(path);
+
+		rules
+			.iter()
+			.filter(|(pattern, _)| match pattern.strip_suffix('*') {
+				Some(prefix) => path.starts_with(prefix),
+				None => normalize_page_path(pattern) == path,
+			})
+			.flat_map(|(_, headers)| headers.iter().cloned())
+			.collect()
+	}
+
+	#[inline]
+	async fn rules(&self, config: &ServerConfig) -> Arc<HeaderRules> {
+		if let Some(rules) = self.0.read().acquire().as_ref() {
+			return rules.clone();
+		}
+
+		let rules: Arc<HeaderRules> = Arc::new(
+			read_file(
+				config,
+				&config.serve_dir.join(HEADERS_FILE),
+				&config.serve_dir,
+				&ContentEncoding::Identity,
+			)
+			.await
+			.map(|source| {
+				parse_headers_file(&String::from_utf8_lossy(&source))
+					.into_iter()
+					.map(|(pattern, headers)| {
+						let headers = headers
+							.into_iter()
+							.filter_map(|(name, value)| {
+								match (HeaderName::from_str(&name), HeaderValue::from_str(&value)) {
+									(Ok(name), Ok(value)) => Some((name, value)),
+									_ => {
+										tracing::warn!("skipping invalid header `{name}: {value}`");
+										None
+									}
+								}
+							})
+							.collect();
+						(pattern, headers)
+					})
+					.collect()
+			})
+			.unwrap_or_default(),
+		);
+
+		*self.0.write().acquire() = Some(rules.clone());
+		rules
+	}
+}
+
+type RedirectRules = Vec<(String, String, StatusCode)>;
+
+/// Redirects of the `_redirects` file in `serve_dir` (or the bucket), which are loaded at startup
+/// and again after each build.
+#[derive(Debug, Clone, Default)]
+pub struct Redirects(Arc<RwLock<Arc<RedirectRules>>>);
+
+impl Redirects {
+	pub async fn load(&self, config: &ServerConfig) {
+		let rules = read_file(
+			config,
+			&config.serve_dir.join(REDIRECTS_FILE),
+			&config.serve_dir,
+			&ContentEncoding::Identity,
+		)
+		.await
+		.map(|source| {
+			parse_redirects_file(&String::from_utf8_lossy(&source))
+				.into_iter()
+				.filter_map(|(from, to, status)| match StatusCode::from_u16(status) {
+					Ok(status) if status.is_redirection() => Some((from, to, status)),
+					_ => {
+						tracing::warn!(
+							"skipping redirect from {from} with invalid status {status}"
+						);
+						None
+					}
+				})
+				.collect()
+		})
+		.unwrap_or_default();
+
+		*self.0.write().acquire() = Arc::new(rules);
+	}
+
+	/// Status and location of the first rule which matches `uri`, in the order of the file. Rules
+	/// ending in `*` match any path with that prefix, which replaces `:splat` in the location.
+	#[inline]
+	fn get(&self, uri: &Uri) -> Option<(StatusCode, String)> {
+		let rules = self.0.read().acquire().clone();
+		let path = normalize_page_path(uri.path());
+
+		let (location, status) = rules.iter().find_map(|(from, to, status)| {
+			let location = match from.strip_suffix('*') {
+				Some(prefix) => uri
+					.path()
+					.strip_prefix(prefix)
+					.map(|splat| to.replace(":splat", splat)),
+				None => (normalize_page_path(from) == path).then(|| to.clone()),
+			};
+			location.map(|location| (location, *status))
+		})?;
+
+		// The query is kept, unless the location has its own.
+		match uri.query() {
+			Some(query) if !location.contains('?') => Some((status, format!("{location}?{query}"))),
+			_ => Some((status, location)),
+		}
+	}
+}
+
+/// Request paths and `_headers` paths match with or without trailing slashes and `index.html`.
+#[inline]
+fn normalize_page_path(path: &str) -> String {
+	let path = path.strip_suffix("index.html").unwrap_or(path);
+	format!("/{}", path.trim_matches('/'))
+}
+
+/// The error from the most recent build in watch mode, which is shown in place of pages until the
+/// next successful build.
+#[derive(Debug, Clone, Default)]
+pub struct BuildError(Arc<RwLock<Option<String>>>);
+
+impl BuildError {
+	/// Keeps the report of `error`, which is shown in place of pages until it's cleared.
+	pub fn set(&self, error: Error) {
+		*self.0.write().acquire() = Some(render_report(error));
+	}
+
+	pub fn clear(&self) {
+		*self.0.write().acquire() = None;
+	}
+
+	pub fn is_set(&self) -> bool {
+		self.0.read().acquire().is_some()
+	}
+
+	#[inline]
+	fn get(&self) -> Option<String> {
+		self.0.read().acquire().clone()
+	}
+}
+
+/// Renders `error` the same way as errors are reported in the terminal, without colours.
+#[inline]
+fn render_report(error: Error) -> String {
+	let report = miette::Report::new(error);
+
+	let mut rendered = String::new();
+	if GraphicalReportHandler::new_themed(GraphicalTheme::unicode_nocolor())
+		.render_report(&mut rendered, report.as_ref())
+		.is_err()
+	{
+		rendered = format!("{report:?}");
+	}
+
+	rendered
+}
+
+/// The pages which changed content in the last rebuild.
+#[derive(Debug, Clone, Default)]
+pub struct LastChanges(Arc<RwLock<Vec<OutputChange>>>);
+
+impl LastChanges {
+	pub fn set(&self, changes: Vec<OutputChange>) {
+		*self.0.write().acquire() = changes;
+	}
+
+	#[inline]
+	fn get(&self) -> Vec<OutputChange> {
+		self.0.read().acquire().clone()
+	}
+}
+
+/// The last `capacity` requests for content, oldest first. Nothing is recorded when the capacity
+/// is 0.
+#[derive(Debug, Clone, Default)]
+pub struct RequestLog {
+	capacity: usize,
+	requests: Arc<RwLock<VecDeque<LoggedRequest>>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct LoggedRequest {
+	/// RFC 3339 time of the request.
+	at: String,
+	path: String,
+	accept_encoding: Option<String>,
+	/// `HIT` or `MISS` of the store, unless the content wasn't looked up in it, e.g. when it's
+	/// streamed.
+	cache: Option<String>,
+	content_encoding: Option<String>,
+	status: u16,
+	bytes: u64,
+}
+
+impl RequestLog {
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			capacity,
+			requests: Arc::new(RwLock::new(VecDeque::with_capacity(capacity))),
+		}
+	}
+
+	#[inline]
+	fn is_enabled(&self) -> bool {
+		self.capacity > 0
+	}
+
+	#[inline]
+	fn record(
+		&self,
+		uri: &Uri,
+		request_headers: &HeaderMap,
+		status_code: StatusCode,
+		response_headers: &HeaderMap,
+		bytes: u64,
+	) {
+		if !self.is_enabled() {
+			return;
+		}
+
+		let request = LoggedRequest {
+			at: chrono::Utc::now().to_rfc3339(),
+			path: uri.path().to_string(),
+			accept_encoding: header_string(request_headers, header::ACCEPT_ENCODING),
+			cache: header_string(response_headers, HeaderName::from_static("x-sluggy-cache")),
+			content_encoding: header_string(response_headers, header::CONTENT_ENCODING),
+			status: status_code.as_u16(),
+			bytes,
+		};
+
+		let mut requests = self.requests.write().acquire();
+		if requests.len() == self.capacity {
+			requests.pop_front();
+		}
+		requests.push_back(request);
+	}
+
+	#[inline]
+	fn get(&self) -> Vec<LoggedRequest> {
+		self.requests.read().acquire().iter().cloned().collect()
+	}
+}
+
+#[inline]
+fn header_string(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+	headers
+		.get(name)
+		.and_then(|value| value.to_str().ok())
+		.map(str::to_string)
+}
+
+/// Notifies the pages connected to the live reload websocket that a build has finished.
+#[derive(Debug, Clone)]
+pub struct LiveReload(broadcast::Sender<()>);
+
+impl Default for LiveReload {
+	fn default() -> Self {
+		Self(broadcast::channel(16).0)
+	}
+}
+
+impl LiveReload {
+	pub fn reload(&self) {
+		// Sending only fails when there are no connected pages.
+		let _ = self.0.send(());
+	}
+
+	#[inline]
+	fn subscribe(&self) -> broadcast::Receiver<()> {
+		self.0.subscribe()
+	}
 }
 
 #[derive(Clone, Debug)]
 pub enum Store {
 	NoStore(NoStore<PathBuf, (HeaderValue, ContentBytes)>),
+	#[cfg(feature = "serve-store")]
 	InMemoryStore(InMemoryStore<PathBuf, (HeaderValue, ContentBytes)>),
 }
 
@@ -54,6 +637,7 @@ impl Cache<PathBuf, (HeaderValue, ContentBytes)> for Store {
 	fn get(&self, key: &PathBuf) -> Option<Self::Output<'_>> {
 		match self {
 			Self::NoStore(store) => store.get(key),
+			#[cfg(feature = "serve-store")]
 			Self::InMemoryStore(store) => store.get(key),
 		}
 	}
@@ -62,14 +646,25 @@ impl Cache<PathBuf, (HeaderValue, ContentBytes)> for Store {
 	fn insert(&self, key: PathBuf, value: (HeaderValue, ContentBytes)) {
 		match self {
 			Self::NoStore(store) => store.insert(key, value),
+			#[cfg(feature = "serve-store")]
 			Self::InMemoryStore(store) => store.insert(key, value),
 		}
 	}
 
+	#[inline]
+	fn invalidate(&self, key: &PathBuf) {
+		match self {
+			Self::NoStore(store) => store.invalidate(key),
+			#[cfg(feature = "serve-store")]
+			Self::InMemoryStore(store) => store.invalidate(key),
+		}
+	}
+
 	#[inline]
 	fn invalidate_all(&self) {
 		match self {
 			Self::NoStore(store) => store.invalidate_all(),
+			#[cfg(feature = "serve-store")]
 			Self::InMemoryStore(store) => store.invalidate_all(),
 		}
 	}
@@ -79,10 +674,28 @@ impl Cache<PathBuf, (HeaderValue, ContentBytes)> for Store {
 pub struct ContentBytes {
 	file_name: PathBuf,
 	compressed_file_name: PathBuf,
-	identity: Option<Option<Bytes>>,
-	brotli: Option<Option<Bytes>>,
-	gzip: Option<Option<Bytes>>,
-	deflate: Option<Option<Bytes>>,
+	identity: Option<Option<Variant>>,
+	brotli: Option<Option<Variant>>,
+	gzip: Option<Option<Variant>>,
+	deflate: Option<Option<Variant>>,
+	last_modified: Option<SystemTime>,
+}
+
+/// The content in one encoding, with the ETag which is made from it once it's loaded.
+#[derive(Debug, Clone)]
+struct Variant {
+	bytes: Bytes,
+	etag: Option<ETag>,
+}
+
+impl Variant {
+	/// Each content encoding is a different representation, with different bytes, so each has its
+	/// own strong ETag.
+	#[inline]
+	fn new(bytes: Bytes) -> Self {
+		let etag = format!(r#""{}""#, hex_digest(&bytes)).parse().ok();
+		Self { bytes, etag }
+	}
 }
 
 impl ContentBytes {
@@ -91,7 +704,7 @@ impl ContentBytes {
 	fn bytes_from_content_encoding(
 		&self,
 		content_encoding: &ContentEncoding,
-	) -> Option<Option<Bytes>> {
+	) -> Option<Option<Variant>> {
 		// TODO this is called again here. Can it be removed?
 		if can_compress(&self.file_name) {
 			match content_encoding {
@@ -104,116 +717,185 @@ impl ContentBytes {
 			self.identity.clone()
 		}
 	}
+
+	/// Drops the identity content of a compressible file once a compressed variant of it is
+	/// loaded, since it can be decoded from the variant.
+	#[inline]
+	fn drop_identity(&mut self) {
+		if can_compress(&self.file_name) && self.compressed_variant().is_some() {
+			self.identity = None;
+		}
+	}
+
+	/// The identity content, decoded from a loaded compressed variant.
+	#[instrument(level = "trace", skip(self))]
+	#[inline]
+	fn decode_identity(&self) -> Option<Bytes> {
+		let (content_encoding, bytes) = self.compressed_variant()?;
+		match block_in_place(|| {
+			tokio::runtime::Handle::current().block_on(content_encoding.decode(bytes))
+		}) {
+			Ok(decoded) => Some(Bytes::from(decoded)),
+			Err(error) => {
+				tracing::warn!(%error, file = %self.file_name.display(), "Unable to decode content");
+				None
+			}
+		}
+	}
+
+	#[inline]
+	fn compressed_variant(&self) -> Option<(ContentEncoding, &Bytes)> {
+		[
+			(ContentEncoding::Brotli, &self.brotli),
+			(ContentEncoding::Gzip, &self.gzip),
+			(ContentEncoding::Deflate, &self.deflate),
+		]
+		.into_iter()
+		.find_map(|(content_encoding, variant)| match variant {
+			Some(Some(variant)) => Some((content_encoding, &variant.bytes)),
+			_ => None,
+		})
+	}
+}
+
+/// Body of a response, which is either in memory or streamed from a file.
+#[derive(Debug)]
+enum ContentBody {
+	Bytes(Bytes),
+	File { file_name: PathBuf, len: u64 },
+}
+
+impl From<Bytes> for ContentBody {
+	#[inline]
+	fn from(value: Bytes) -> Self {
+		Self::Bytes(value)
+	}
 }
 
 type SharedConfig = Arc<ServerConfig>;
 
 #[instrument(level = "debug", skip(headers))]
 #[inline]
-fn get_content_encoding(headers: &HeaderMap, config: &SharedConfig) -> ContentEncoding {
-	let content_encoding = match headers.get("accept-encoding") {
-		Some(value) => match value.to_str() {
-			Ok(value) => {
-				let mut algos = value
-					.split(',')
-					.filter_map(
-						#[inline]
-						|v| ContentEncoding::try_from(v.trim()).ok(),
-					)
-					.collect::<Vec<_>>();
+fn get_content_encoding(
+	path: &Path,
+	headers: &HeaderMap,
+	config: &SharedConfig,
+) -> ContentEncoding {
+	if !config.compress_content || is_identity_only(path, headers, config) {
+		return ContentEncoding::Identity;
+	}
 
-				// Prefer config defined encoding
-				if let Some(pos) = algos.iter().position(|a| *a == config.content_encoding) {
-					let algo = algos.remove(pos);
-					algos.insert(0, algo);
-				}
+	// Clients which don't send `Accept-Encoding` only ever get the original content.
+	let accept_encoding = headers
+		.get(header::ACCEPT_ENCODING)
+		.and_then(|value| value.to_str().ok());
+	negotiate(accept_encoding, &config.content_encoding)
+}
 
-				if !algos.is_empty() {
-					algos.swap_remove(0)
-				} else {
-					ContentEncoding::Identity
-				}
-			}
-			Err(_) => ContentEncoding::Identity,
-		},
-		_ => ContentEncoding::Identity,
-	};
+/// Whether the path or client has been configured to never receive precompressed content.
+#[inline]
+fn is_identity_only(path: &Path, headers: &HeaderMap, config: &SharedConfig) -> bool {
+	let path = format!("/{}", path.display());
+	if config
+		.identity_paths
+		.iter()
+		.any(|pattern| pattern.is_match(&path))
+	{
+		return true;
+	}
 
-	content_encoding
+	match headers
+		.get(header::USER_AGENT)
+		.and_then(|user_agent| user_agent.to_str().ok())
+	{
+		Some(user_agent) => config
+			.identity_user_agents
+			.iter()
+			.any(|pattern| pattern.is_match(user_agent)),
+		None => false,
+	}
 }
 
 #[instrument(skip(config, on_error, headers))]
 #[inline]
-fn content_or(
+async fn content_or<F>(
 	config: SharedConfig,
 	path: PathBuf,
 	headers: HeaderMap,
-	on_error: impl Fn(ErrorKind) -> (StatusCode, HeaderMap, Bytes),
-) -> (StatusCode, HeaderMap, Bytes) {
-	let entry = config.store.get(&path);
-
-	let entry = entry
-		.map(
-			#[inline]
-			|(content_type, content_bytes)| {
-				Some((true, StatusCode::OK, content_type, content_bytes))
-			},
-		)
-		.unwrap_or_else(
-			#[inline]
-			|| {
-				let (serve_dir, compressed_content_dir) = {
-					(
-						config.serve_dir.clone(),
-						config.compressed_content_dir.clone(),
-					)
-				};
+	on_error: impl FnOnce(ErrorKind) -> F,
+) -> (StatusCode, HeaderMap, ContentBody)
+where
+	F: Future<Output = (StatusCode, HeaderMap, ContentBody)>,
+{
+	// Requests for a file by its own path are answered from the store without touching the file
+	// system. Other paths are resolved first, so that the store only ever holds one entry per file,
+	// and paths which don't resolve to a file are never stored.
+	let (key, entry) = match config.store.get(&path) {
+		Some(entry) => (path.clone(), Some((true, entry))),
+		None => match match config.pages.resolve(&path) {
+			Some(key) => Some(key),
+			None => resolve_file(&config, &path).await,
+		} {
+			Some(key) => match config.store.get(&key) {
+				Some(entry) => (key, Some((true, entry))),
+				None => {
+					let file_name = config.serve_dir.join(&key);
+					let compressed_file_name = config
+						.serve_dir
+						.join(&config.compressed_content_dir)
+						.join(&key);
+					let content_type = sluggy_core::utils::path_to_content_type(&file_name);
 
-				let mut file_name = serve_dir.join(&path);
-				let mut compressed_file_name = serve_dir.join(compressed_content_dir).join(&path);
-				if file_name.is_dir() || !file_name.exists() {
-					file_name = file_name.join("index.html");
-					compressed_file_name = compressed_file_name.join("index.html");
-				}
+					// Pages which are rendered on request have no file, and no compressed variants.
+					let rendered = match config.pages.render(&key) {
+						Some(Ok(rendered)) => Some(rendered),
+						Some(Err(error)) => return build_error_content(&render_report(error)),
+						None => None,
+					};
+					let compressed = rendered.as_ref().map(|_| None);
 
-				// Directory traversal.
-				if !file_name.components().all(|component| {
-					matches!(
-						component,
-						Component::Prefix(_) | Component::RootDir | Component::Normal(_)
-					)
-				}) {
-					file_name = serve_dir.join("_error/404/index.html");
-					compressed_file_name = compressed_file_name.join("_error/404/index.html");
-				}
+					let metadata = fs::metadata(&file_name).ok().filter(|_| rendered.is_none());
+					// Objects of a bucket are never streamed, and don't have a modification time.
+					#[cfg(feature = "s3")]
+					let metadata = metadata.filter(|_| config.bucket.is_none());
+					if let Some(metadata) = metadata
+						.as_ref()
+						.filter(|metadata| metadata.len() > config.stream_threshold)
+					{
+						return streamed_content(file_name, content_type, metadata, &headers);
+					}
 
-				if file_name.exists() {
-					let content_type = sluggy_core::utils::path_to_content_type(&file_name);
+					let last_modified = metadata.and_then(|metadata| metadata.modified().ok());
 
 					let content_bytes = ContentBytes {
 						file_name,
 						compressed_file_name,
-						identity: None,
-						brotli: None,
-						gzip: None,
-						deflate: None,
+						identity: rendered.map(|rendered| Some(Variant::new(rendered))),
+						brotli: compressed.clone(),
+						gzip: compressed.clone(),
+						deflate: compressed,
+						last_modified,
 					};
 
 					config
 						.store
-						.insert(path.clone(), (content_type.clone(), content_bytes.clone()));
+						.insert(key.clone(), (content_type.clone(), content_bytes.clone()));
 
-					Some((false, StatusCode::OK, content_type, content_bytes))
-				} else {
-					None
+					(key, Some((false, (content_type, content_bytes))))
 				}
 			},
-		);
+			None => (path.clone(), None),
+		},
+	};
+	let entry = entry.map(|(cache_hit, (content_type, content_bytes))| {
+		(cache_hit, StatusCode::OK, content_type, content_bytes)
+	});
 
 	let (status_code, headers, bytes) = match entry {
 		Some((cache_hit, status_code, content_type, mut content_bytes)) => {
+			let request_headers = &headers;
 			let content_encoding = if can_compress(&content_bytes.file_name) {
-				get_content_encoding(&headers, &config)
+				get_content_encoding(&path, &headers, &config)
 			} else {
 				ContentEncoding::Identity
 			};
@@ -239,11 +921,25 @@ fn content_or(
 						}
 					}
 					None => {
-						let mut bytes = read_file(
-							&content_bytes.file_name,
-							&content_bytes.compressed_file_name,
-							&content_encoding,
-						);
+						let decoded = match content_encoding {
+							ContentEncoding::Identity if config.store_compressed_only => {
+								content_bytes.decode_identity()
+							}
+							_ => None,
+						};
+						let mut bytes = match decoded {
+							Some(decoded) => Some(decoded),
+							None => {
+								read_file(
+									&config,
+									&content_bytes.file_name,
+									&content_bytes.compressed_file_name,
+									&content_encoding,
+								)
+								.await
+							}
+						}
+						.map(Variant::new);
 
 						let store_bytes = bytes.clone();
 						match content_encoding {
@@ -266,17 +962,24 @@ fn content_or(
 							// If the encoding isn't already Identity, and the requested encoding couldn't be found,
 							// fetch the original, unencoded content.
 							bytes = read_file(
+								&config,
 								&content_bytes.file_name,
 								&content_bytes.compressed_file_name,
 								&ContentEncoding::Identity,
-							);
+							)
+							.await
+							.map(Variant::new);
 							content_bytes.identity = Some(bytes.clone());
 							content_encoding = ContentEncoding::Identity;
 						}
 
+						let mut stored_bytes = content_bytes.clone();
+						if config.store_compressed_only {
+							stored_bytes.drop_identity();
+						}
 						config
 							.store
-							.insert(path, (content_type.clone(), content_bytes));
+							.insert(key, (content_type.clone(), stored_bytes));
 
 						output_bytes = bytes;
 					}
@@ -286,7 +989,7 @@ fn content_or(
 			};
 
 			match bytes {
-				Some(bytes) => {
+				Some(Variant { bytes, etag }) => {
 					let mut headers = HeaderMap::new();
 					headers.append(
 						HeaderName::from_static("x-sluggy-cache"),
@@ -298,61 +1001,312 @@ fn content_or(
 						header::VARY,
 						HeaderValue::from_name(header::CONTENT_ENCODING),
 					);
-					(status_code, headers, bytes)
+					if let Some(etag) = &etag {
+						headers.typed_insert(etag.clone());
+					}
+					if let Some(last_modified) = content_bytes.last_modified {
+						headers.typed_insert(LastModified::from(last_modified));
+					}
+
+					if is_not_modified(request_headers, etag.as_ref(), content_bytes.last_modified)
+					{
+						(StatusCode::NOT_MODIFIED, headers, Bytes::new().into())
+					} else {
+						(status_code, headers, bytes.into())
+					}
 				}
-				None => on_error(ErrorKind::NotFound),
+				None => on_error(ErrorKind::NotFound).await,
 			}
 		}
-		None => on_error(ErrorKind::NotFound),
+		None => on_error(ErrorKind::NotFound).await,
 	};
 
 	(status_code, headers, bytes)
 }
 
-#[instrument(level = "trace")]
+/// Files larger than `stream_threshold` are streamed from disk without a content encoding, and are
+/// never put in the store.
 #[inline]
-fn read_file(
+fn streamed_content(
+	file_name: PathBuf,
+	content_type: HeaderValue,
+	metadata: &Metadata,
+	request_headers: &HeaderMap,
+) -> (StatusCode, HeaderMap, ContentBody) {
+	let len = metadata.len();
+	let last_modified = metadata.modified().ok();
+	// Hashing the content would mean reading all of it, so the tag is made from the size and
+	// modification time instead.
+	let etag = last_modified
+		.and_then(|last_modified| last_modified.duration_since(UNIX_EPOCH).ok())
+		.and_then(|modified| {
+			format!(r#"W/"{len:x}-{:x}""#, modified.as_secs())
+				.parse::<ETag>()
+				.ok()
+		});
+
+	let mut headers = HeaderMap::new();
+	headers.append(header::CONTENT_TYPE, content_type);
+	headers.append(
+		header::CONTENT_ENCODING,
+		ContentEncoding::Identity.to_header_value(),
+	);
+	if let Some(etag) = &etag {
+		headers.typed_insert(etag.clone());
+	}
+	if let Some(last_modified) = last_modified {
+		headers.typed_insert(LastModified::from(last_modified));
+	}
+
+	if is_not_modified(request_headers, etag.as_ref(), last_modified) {
+		(StatusCode::NOT_MODIFIED, headers, Bytes::new().into())
+	} else {
+		(
+			StatusCode::OK,
+			headers,
+			ContentBody::File { file_name, len },
+		)
+	}
+}
+
+/// Whether the client's cached copy of the content is still current. `If-Modified-Since` is only
+/// used when the request has no `If-None-Match`.
+#[inline]
+fn is_not_modified(
+	headers: &HeaderMap,
+	etag: Option<&ETag>,
+	last_modified: Option<SystemTime>,
+) -> bool {
+	if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+		return etag.is_some_and(|etag| !if_none_match.precondition_passes(etag));
+	}
+
+	match (headers.typed_get::<IfModifiedSince>(), last_modified) {
+		(Some(if_modified_since), Some(last_modified)) => {
+			!if_modified_since.is_modified(last_modified)
+		}
+		_ => false,
+	}
+}
+
+/// Path of the file, relative to `serve_dir`, which is served for the request path. Directories are
+/// served by their `index.html`.
+#[instrument(level = "trace", skip(config))]
+#[inline]
+async fn resolve_file(config: &ServerConfig, path: &Path) -> Option<PathBuf> {
+	// Directory traversal.
+	if !path
+		.components()
+		.all(|component| matches!(component, Component::Normal(_)))
+	{
+		return None;
+	}
+
+	#[cfg(feature = "s3")]
+	if let Some(bucket) = &config.bucket {
+		return if !path.as_os_str().is_empty() && bucket.exists(path).await {
+			Some(path.to_path_buf())
+		} else if bucket.exists(&path.join("index.html")).await {
+			Some(path.join("index.html"))
+		} else {
+			None
+		};
+	}
+
+	let file_name = config.serve_dir.join(path);
+	if file_name.is_file() {
+		Some(path.to_path_buf())
+	} else if file_name.join("index.html").is_file() {
+		Some(path.join("index.html"))
+	} else {
+		None
+	}
+}
+
+#[instrument(level = "trace", skip(config))]
+#[cfg_attr(not(feature = "s3"), allow(unused_variables))]
+#[inline]
+async fn read_file(
+	config: &ServerConfig,
 	file_name: &Path,
 	compressed_prefix: &Path,
 	content_encoding: &ContentEncoding,
 ) -> Option<Bytes> {
-	let out = match content_encoding {
-		ContentEncoding::Identity => fs::read(file_name).ok(),
-		_ => {
-			let mut file_to_read = compressed_prefix.to_path_buf();
-			let mut extension = file_name.extension().unwrap_or_default().to_os_string();
-			if let Some(content_encoding_extension) = content_encoding.extension() {
-				extension.push(format!(".{content_encoding_extension}"));
-			}
-			file_to_read.set_extension(extension);
-			fs::read(&file_to_read).ok()
-		}
+	let file_to_read = match content_encoding {
+		ContentEncoding::Identity => file_name.to_path_buf(),
+		_ => content_encoding.variant_path(compressed_prefix),
 	};
 
-	out.map(Bytes::from_iter)
+	// Objects are keyed on their path within `serve_dir`.
+	#[cfg(feature = "s3")]
+	if let Some(bucket) = &config.bucket {
+		let key = file_to_read
+			.strip_prefix(&config.serve_dir)
+			.unwrap_or(&file_to_read);
+		return bucket.get(key).await;
+	}
+
+	if config.mmap {
+		return map_file(&file_to_read);
+	}
+
+	fs::read(&file_to_read).ok().map(Bytes::from_iter)
+}
+
+/// Content of the file at `file_name`, backed by a mapping of it, so that it isn't copied out of
+/// the page cache. Files which can't be mapped are read instead.
+#[inline]
+fn map_file(file_name: &Path) -> Option<Bytes> {
+	let file = fs::File::open(file_name).ok()?;
+	// SAFETY: files are only mapped with atomic builds, which replace `serve_dir` by renaming the
+	// new build into place, rebuilds while watching included. Outputs are unlinked before they're
+	// written, so mapped files are never truncated or rewritten, and their mappings stay valid
+	// until they're dropped. They aren't mapped while watching a site which sluggy doesn't
+	// generate.
+	match unsafe { Mmap::map(&file) } {
+		Ok(mmap) => Some(Bytes::from_owner(mmap)),
+		Err(error) => {
+			tracing::debug!(%error, file = %file_name.display(), "Unable to map file");
+			fs::read(file_name).ok().map(Bytes::from)
+		}
+	}
+}
+
+#[inline]
+fn file_stream(file: TokioFile) -> impl Stream<Item = io::Result<Bytes>> {
+	futures::stream::try_unfold(file, |mut file| async move {
+		let mut buffer = vec![0; STREAM_CHUNK_SIZE];
+		let read = file.read(&mut buffer).await?;
+		if read == 0 {
+			return Ok(None);
+		}
+
+		buffer.truncate(read);
+		Ok(Some((Bytes::from(buffer), file)))
+	})
 }
 
 #[instrument(skip(config, headers))]
 #[inline]
-fn error_content(
+async fn error_content(
 	config: SharedConfig,
 	status_code: StatusCode,
-	headers: HeaderMap,
-) -> (StatusCode, HeaderMap, Bytes) {
-	let (_, content_type, bytes) = content_or(
-		config,
-		PathBuf::from_str(&format!("_error/{}/index.html", status_code.as_u16())).unwrap(),
-		headers,
-		#[inline]
-		|_| {
-			let mut headers = HeaderMap::new();
-			headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
-			(status_code, headers, Bytes::from(format!("{status_code}",)))
-		},
-	);
+	mut headers: HeaderMap,
+) -> (StatusCode, HeaderMap, ContentBody) {
+	// Error pages are always sent in full, since their status isn't the one that the validators
+	// are for.
+	headers.remove(header::IF_NONE_MATCH);
+	headers.remove(header::IF_MODIFIED_SINCE);
+
+	let error_page = config
+		.error_pages
+		.get(&status_code.as_u16())
+		.cloned()
+		.unwrap_or_else(|| error_page_path(&status_code.as_u16().to_string()));
+	let fallback_config = config.clone();
+	let fallback_headers = headers.clone();
+
+	let (_, mut content_type, bytes) = content_or(config, error_page, headers, move |_| {
+		content_or(
+			fallback_config,
+			error_page_path(FALLBACK_ERROR_PAGE),
+			fallback_headers,
+			move |_| async move {
+				let mut headers = HeaderMap::new();
+				headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+				(
+					status_code,
+					headers,
+					Bytes::from(format!("{status_code}",)).into(),
+				)
+			},
+		)
+	})
+	.await;
+	content_type.remove(header::ETAG);
+	content_type.remove(header::LAST_MODIFIED);
+
 	(status_code, content_type, bytes)
 }
 
+/// Path of the generated page called `name` in `_error`, e.g. `_error/404/index.html`.
+#[inline]
+fn error_page_path(name: &str) -> PathBuf {
+	Path::new(ERROR_PAGES_DIR).join(name).join("index.html")
+}
+
+/// Page shown to browsers in place of HTML content while the last build has failed. It refreshes
+/// itself so that the page is shown again once a build succeeds.
+#[inline]
+fn build_error_content(build_error: &str) -> (StatusCode, HeaderMap, ContentBody) {
+	let mut headers = HeaderMap::new();
+	headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+	headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+	let html = format!(
+		r#"<!DOCTYPE html><html><head><meta charset="utf-8"><meta http-equiv="refresh" content="2"><title>Build failed</title><style>body{{margin:0;padding:2rem;background:#1e1e1e;color:#f0f0f0;font-family:sans-serif}}h1{{color:#ff6b6b;font-size:1.25rem}}pre{{white-space:pre-wrap;font-size:0.875rem;line-height:1.4}}</style></head><body><h1>Build failed</h1><pre>{}</pre></body></html>"#,
+		escape_xml(build_error)
+	);
+
+	(
+		StatusCode::INTERNAL_SERVER_ERROR,
+		headers,
+		Bytes::from(html).into(),
+	)
+}
+
+/// Whether the request is a browser navigation, rather than a request for an asset.
+#[inline]
+fn accepts_html(headers: &HeaderMap) -> bool {
+	headers
+		.get(header::ACCEPT)
+		.and_then(|accept| accept.to_str().ok())
+		.map(|accept| accept.contains("text/html"))
+		.unwrap_or(false)
+}
+
+/// Redirects the paths of `_redirects`, proxies requests for assets of the dev server, and serves
+/// everything else from `serve_dir`.
+#[inline]
+async fn fallback_handler(
+	State(config): State<SharedConfig>,
+	request: Request<Body>,
+) -> Response<Body> {
+	if let Some((status_code, location)) = config.redirects.get(request.uri()) {
+		return redirect_response(status_code, &location);
+	}
+
+	match &config.asset_proxy {
+		Some(asset_proxy) if asset_proxy.is_proxied(request.uri()) => {
+			asset_proxy.proxy(request).await
+		}
+		_ => {
+			let uri = request.uri().clone();
+			let method = request.method().clone();
+			let headers = request.headers().clone();
+			static_content_handler(State(config), uri, method, headers).await
+		}
+	}
+}
+
+#[inline]
+fn redirect_response(status_code: StatusCode, location: &str) -> Response<Body> {
+	let mut response = Response::new(Body::empty());
+	*response.status_mut() = status_code;
+
+	match HeaderValue::from_str(location) {
+		Ok(location) => {
+			response.headers_mut().insert(header::LOCATION, location);
+		}
+		Err(error) => {
+			tracing::warn!(%error, location, "Invalid redirect location");
+			*response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+		}
+	}
+
+	response
+}
+
 #[instrument(skip(config, headers))]
 #[inline]
 async fn static_content_handler(
@@ -361,45 +1315,135 @@ async fn static_content_handler(
 	method: Method,
 	headers: HeaderMap,
 ) -> Response<Body> {
-	let (status_code, header_map, bytes) = match method {
-		Method::GET => content_or(
-			config.clone(),
-			PathBuf::from(uri.path().trim_start_matches('/')),
-			headers.clone(),
-			#[inline]
-			move |error_kind| {
-				let (status_code, content_type, bytes) = match error_kind {
-					ErrorKind::NotFound => {
-						error_content(config.clone(), StatusCode::NOT_FOUND, headers.clone())
-					}
-					_ => error_content(
-						config.clone(),
-						StatusCode::INTERNAL_SERVER_ERROR,
-						headers.clone(),
-					),
-				};
+	let build_error = config.build_error.get();
+	let page_headers = config.page_headers.get(uri.path(), &config).await;
+	let request_headers = headers.clone();
+	let request_log = config.request_log.clone();
 
-				let mut headers = HeaderMap::new();
-				headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+	let (status_code, header_map, body) = match method {
+		Method::GET if build_error.is_some() && accepts_html(&headers) => {
+			build_error_content(build_error.as_deref().unwrap_or_default())
+		}
+		Method::GET => {
+			content_or(
+				config.clone(),
+				PathBuf::from(uri.path().trim_start_matches('/')),
+				headers.clone(),
+				move |error_kind| async move {
+					let status_code = match error_kind {
+						ErrorKind::NotFound => StatusCode::NOT_FOUND,
+						_ => StatusCode::INTERNAL_SERVER_ERROR,
+					};
 
-				(status_code, content_type, bytes)
-			},
+					error_content(config, status_code, headers).await
+				},
+			)
+			.await
+		}
+		_ => error_content(config, StatusCode::FORBIDDEN, headers.clone()).await,
+	};
+
+	let (status_code, header_map, content_length, body) = match body {
+		ContentBody::Bytes(bytes) => (
+			status_code,
+			header_map,
+			bytes.len() as u64,
+			Body::from(bytes),
 		),
-		_ => error_content(config, StatusCode::FORBIDDEN, headers.clone()),
+		ContentBody::File { file_name, len } => match TokioFile::open(&file_name).await {
+			Ok(file) => (
+				status_code,
+				header_map,
+				len,
+				Body::wrap_stream(file_stream(file)),
+			),
+			Err(error) => {
+				tracing::error!(%error, file = %file_name.display(), "Unable to open file");
+
+				let status_code = StatusCode::INTERNAL_SERVER_ERROR;
+				let mut headers = HeaderMap::new();
+				headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+				let bytes = Bytes::from(format!("{status_code}"));
+				(status_code, headers, bytes.len() as u64, Body::from(bytes))
+			}
+		},
 	};
+	request_log.record(
+		&uri,
+		&request_headers,
+		status_code,
+		&header_map,
+		if status_code == StatusCode::NOT_MODIFIED {
+			0
+		} else {
+			content_length
+		},
+	);
 
-	let mut response = Response::new(bytes.into());
+	let content_length = HeaderValue::from(content_length);
+	let mut response = Response::new(body);
 
 	*response.status_mut() = status_code;
 	let headers = response.headers_mut();
 
 	headers.extend(header_map);
+	if matches!(status_code, StatusCode::OK | StatusCode::NOT_MODIFIED) {
+		for (name, value) in page_headers {
+			headers.insert(name, value);
+		}
+	}
+	if status_code != StatusCode::NOT_MODIFIED {
+		headers.insert(header::CONTENT_LENGTH, content_length);
+	}
 
 	response
 }
 
+#[instrument(skip(ws, config))]
+async fn live_reload_handler(
+	ws: WebSocketUpgrade,
+	State(config): State<SharedConfig>,
+) -> impl IntoResponse {
+	let reload = config.reload.subscribe();
+	ws.on_upgrade(move |socket| notify_reload(socket, reload))
+}
+
+#[instrument(skip(config))]
+async fn changes_handler(State(config): State<SharedConfig>) -> impl IntoResponse {
+	Json(config.changes.get())
+}
+
+#[instrument(skip(config))]
+async fn requests_handler(State(config): State<SharedConfig>) -> impl IntoResponse {
+	Json(config.request_log.get())
+}
+
+/// Sends a message to the page after each build until either side goes away.
+#[inline]
+async fn notify_reload(mut socket: WebSocket, mut reload: broadcast::Receiver<()>) {
+	while let Ok(_) | Err(RecvError::Lagged(_)) = reload.recv().await {
+		if socket.send(Message::Text("reload".into())).await.is_err() {
+			break;
+		}
+	}
+}
+
 pub async fn serve(config: Arc<ServerConfig>) -> Result<()> {
-	let static_handler = static_content_handler
+	config.redirects.load(&config).await;
+
+	let mut app = Router::new();
+	if config.live_reload {
+		app = app.route(LIVE_RELOAD_PATH, get(live_reload_handler));
+	}
+	if config.report_changes != ReportChanges::Off {
+		app = app.route(CHANGES_PATH, get(changes_handler));
+	}
+	if config.request_log.is_enabled() {
+		app = app.route(REQUESTS_PATH, get(requests_handler));
+	}
+
+	let app = app
+		.fallback(fallback_handler)
 		.layer(SetResponseHeaderLayer::if_not_present(
 			header::SERVER,
 			HeaderValue::from_static("Sluggy"),
@@ -415,15 +1459,38 @@ pub async fn serve(config: Arc<ServerConfig>) -> Result<()> {
 						version = ?request.version(),
 					)
 				})
-				.on_response(|response: &Response<_>, latency: Duration, span: &Span| {
-					span.record("status_code", response.status().as_u16());
-
-					tracing::event!(
-						Level::INFO,
-						latency = %format_args!("{}μs", latency.as_micros()),
-						status = %response.status().as_u16(),
-						"on_response",
-					);
+				.on_response({
+					let config = config.clone();
+					let response_count = Arc::new(AtomicU64::new(0));
+					move |response: &Response<_>, latency: Duration, span: &Span| {
+						span.record("status_code", response.status().as_u16());
+
+						let is_slow = config
+							.slow_request_threshold
+							.map(|threshold| latency >= threshold)
+							.unwrap_or(false);
+
+						if is_slow {
+							// Slow requests are always logged, regardless of sampling.
+							tracing::event!(
+								Level::WARN,
+								latency = %format_args!("{}μs", latency.as_micros()),
+								status = %response.status().as_u16(),
+								"slow_response",
+							);
+						} else if config.log_sample_rate <= 1
+							|| response_count
+								.fetch_add(1, Ordering::Relaxed)
+								.is_multiple_of(config.log_sample_rate)
+						{
+							tracing::event!(
+								Level::INFO,
+								latency = %format_args!("{}μs", latency.as_micros()),
+								status = %response.status().as_u16(),
+								"on_response",
+							);
+						}
+					}
 				})
 				.on_failure(
 					|error: ServerErrorsFailureClass, latency: Duration, span: &Span| {
@@ -451,11 +1518,231 @@ pub async fn serve(config: Arc<ServerConfig>) -> Result<()> {
 		IoError(format!("Unable to bind to {address}")),
 	)?;
 
-	let server = axum::Server::from_tcp(listener)?
-		.serve(static_handler.into_make_service())
-		.with_graceful_shutdown(shutdown_signal());
+	#[cfg(feature = "tls")]
+	if let Some(tls) = &config.tls {
+		let rustls_config = rustls_config(tls, &config.host, config.port).await?;
+
+		let handle = Handle::new();
+		tokio::spawn({
+			let handle = handle.clone();
+			async move {
+				shutdown_signal().await;
+				handle.graceful_shutdown(None);
+			}
+		});
+
+		let http_config = TlsHttpConfig::new()
+			.http1_keep_alive(config.http.keep_alive)
+			.http1_only(!config.http.http2)
+			.http2_keep_alive_interval(config.http.http2_keep_alive_interval)
+			.http2_keep_alive_timeout(config.http.http2_keep_alive_timeout)
+			.build();
+
+		let acceptor = RustlsAcceptor::new(rustls_config).acceptor(LimitedAcceptor {
+			permits: config
+				.http
+				.max_connections
+				.map(|max_connections| Arc::new(Semaphore::new(max_connections))),
+		});
+
+		return map_err!(
+			axum_server::from_tcp(listener)
+				.acceptor(acceptor)
+				.handle(handle)
+				.http_config(http_config)
+				.serve(app.into_make_service())
+				.await,
+			IoError("server error"),
+		);
+	}
+
+	let http = &config.http;
+	match http.max_connections {
+		Some(max_connections) => {
+			map_err!(
+				listener.set_nonblocking(true),
+				IoError(format!("Unable to listen on {address}")),
+			)?;
+			let listener = map_err!(
+				TokioTcpListener::from_std(listener),
+				IoError(format!("Unable to listen on {address}")),
+			)?;
+
+			let incoming = accept::from_stream(limited_incoming(listener, max_connections));
+			let server = configure_builder(axum::Server::builder(incoming), http)
+				.serve(app.into_make_service())
+				.with_graceful_shutdown(shutdown_signal());
+
+			Ok(server.await?)
+		}
+		None => {
+			let server = configure_builder(axum::Server::from_tcp(listener)?, http)
+				.serve(app.into_make_service())
+				.with_graceful_shutdown(shutdown_signal());
+
+			Ok(server.await?)
+		}
+	}
+}
+
+#[inline]
+fn configure_builder<I>(builder: Builder<I>, http: &HttpConfig) -> Builder<I> {
+	builder
+		.http1_keepalive(http.keep_alive)
+		.http1_only(!http.http2)
+		.http2_keep_alive_interval(http.http2_keep_alive_interval)
+		.http2_keep_alive_timeout(http.http2_keep_alive_timeout)
+}
+
+/// Accepts connections while there are fewer than `max_connections` of them open.
+#[inline]
+fn limited_incoming(
+	listener: TokioTcpListener,
+	max_connections: usize,
+) -> impl Stream<Item = io::Result<LimitedConnection>> {
+	let permits = Arc::new(Semaphore::new(max_connections));
+
+	futures::stream::unfold((listener, permits), |(listener, permits)| async move {
+		let permit = permits.clone().acquire_owned().await.ok()?;
+
+		loop {
+			match listener.accept().await {
+				Ok((stream, _)) => {
+					let connection = LimitedConnection {
+						stream,
+						_permit: Some(permit),
+					};
+					return Some((Ok(connection), (listener, permits)));
+				}
+				// Errors such as running out of file descriptors pass, so the server carries on
+				// after a pause instead of shutting down.
+				Err(error) => {
+					tracing::error!(%error, "Unable to accept connection");
+					tokio::time::sleep(Duration::from_secs(1)).await;
+				}
+			}
+		}
+	})
+}
+
+/// Holds each connection over https to one of the `max_connections` permits, if there's a limit.
+/// Connections past the limit are accepted, but wait for a permit before their TLS handshake.
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone)]
+struct LimitedAcceptor {
+	permits: Option<Arc<Semaphore>>,
+}
+
+#[cfg(feature = "tls")]
+impl<I, S> Accept<I, S> for LimitedAcceptor
+where
+	I: Send + 'static,
+	S: Send + 'static,
+{
+	type Stream = LimitedConnection<I>;
+	type Service = S;
+	type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+	#[inline]
+	fn accept(&self, stream: I, service: S) -> Self::Future {
+		let permits = self.permits.clone();
+		Box::pin(async move {
+			let permit = match permits {
+				Some(permits) => Some(permits.acquire_owned().await.map_err(io::Error::other)?),
+				None => None,
+			};
+
+			Ok((
+				LimitedConnection {
+					stream,
+					_permit: permit,
+				},
+				service,
+			))
+		})
+	}
+}
 
-	Ok(server.await?)
+/// Connection which holds one of the `max_connections` permits until it's closed.
+struct LimitedConnection<S = TokioTcpStream> {
+	stream: S,
+	_permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for LimitedConnection<S> {
+	#[inline]
+	fn poll_read(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+	}
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for LimitedConnection<S> {
+	#[inline]
+	fn poll_write(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+	}
+
+	#[inline]
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+	}
+
+	#[inline]
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+	}
+}
+
+#[cfg(feature = "tls")]
+#[cfg_attr(not(feature = "acme"), allow(unused_variables))]
+#[inline]
+async fn rustls_config(tls: &Tls, host: &str, port: u16) -> Result<RustlsConfig> {
+	match tls {
+		Tls::Files { cert, key } => map_err!(
+			RustlsConfig::from_pem_file(cert, key).await,
+			IoError(format!(
+				"failed to load certificate {} and key {}",
+				cert.display(),
+				key.display()
+			)),
+		),
+		Tls::SelfSigned => {
+			let mut subject_alt_names = vec!["localhost".to_string()];
+			if host != "localhost" {
+				subject_alt_names.push(host.to_string());
+			}
+
+			let (cert, key) = rcgen::generate_simple_self_signed(subject_alt_names)
+				.and_then(|cert| Ok((cert.serialize_pem()?, cert.serialize_private_key_pem())))
+				.map_err(|error| {
+					Error::Validation(format!(
+						"failed to generate a self-signed certificate: {error}"
+					))
+				})?;
+
+			map_err!(
+				RustlsConfig::from_pem(cert.into_bytes(), key.into_bytes()).await,
+				IoError("failed to load the self-signed certificate"),
+			)
+		}
+		#[cfg(feature = "acme")]
+		Tls::Acme(acme) => {
+			acme.serve_challenges(host, port)?;
+
+			let rustls_config = acme.rustls_config().await?;
+			tokio::spawn(acme.clone().renew(rustls_config.clone()));
+
+			Ok(rustls_config)
+		}
+	}
 }
 
 async fn shutdown_signal() {
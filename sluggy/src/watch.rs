@@ -18,6 +18,9 @@ where
 	I: Iterator<Item = PathBuf>,
 {
 	paths: I,
+	/// Files which are watched through the dirs they're in, so that they're still watched once
+	/// editors replace them.
+	files: Vec<PathBuf>,
 	timeout: Duration,
 	handler: H,
 }
@@ -30,23 +33,57 @@ where
 	pub fn new(paths: I, timeout: Duration, handler: H) -> Self {
 		Self {
 			paths,
+			files: vec![],
 			timeout,
 			handler,
 		}
 	}
 
+	/// Also watches `files`, which may be outside of the dirs which are watched.
+	pub fn with_files(mut self, files: Vec<PathBuf>) -> Self {
+		self.files = files;
+		self
+	}
+
 	pub async fn watch(mut self) -> Result<()> {
 		let (mut debouncer, mut rx) = create_debounced_watcher(self.timeout)?;
 
-		for path in self.paths.by_ref() {
+		let paths = self.paths.by_ref().collect::<Vec<_>>();
+		for path in &paths {
 			debouncer
 				.watcher()
 				.watch(path.as_ref(), RecursiveMode::Recursive)?;
 		}
 
+		let mut file_dirs = self
+			.files
+			.iter()
+			.filter_map(|file| file.parent().map(Path::to_path_buf))
+			.collect::<Vec<_>>();
+		file_dirs.sort();
+		file_dirs.dedup();
+		for dir in &file_dirs {
+			debouncer
+				.watcher()
+				.watch(dir.as_ref(), RecursiveMode::NonRecursive)?;
+		}
+
 		while let Some(res) = rx.next().await {
 			match res {
-				Ok(events) => {
+				Ok(mut events) => {
+					// The dirs of files are watched for the files alone.
+					events.retain(|event| {
+						self.files.contains(&event.path)
+							|| paths.iter().any(|path| event.path.starts_with(path))
+							|| !event
+								.path
+								.parent()
+								.is_some_and(|parent| file_dirs.iter().any(|dir| dir == parent))
+					});
+					if events.is_empty() {
+						continue;
+					}
+
 					if let Err(error) = self.handler.handle(events) {
 						tracing::error!(?error, "Watcher handler error");
 					}
@@ -1,83 +1,78 @@
 pub mod http {
-	use crate::{
-		error::{Error, Result},
-		map_err,
-	};
-	use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder, ZlibEncoder};
-	use axum::http::HeaderValue;
-	use serde_derive::Deserialize;
-	use tokio::io::AsyncReadExt;
+	pub use crate::encoding::ContentEncoding;
 
-	#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
-	#[serde(rename_all = "kebab-case")]
-	pub enum ContentEncoding {
-		Brotli,
-		Gzip,
-		Deflate,
-		Identity,
-	}
+	/// Path of the dev server's websocket, which notifies pages to reload after each build.
+	pub const LIVE_RELOAD_PATH: &str = "/_sluggy/livereload";
 
-	impl Default for ContentEncoding {
-		fn default() -> Self {
-			Self::Brotli
-		}
-	}
+	/// Path of the dev server's JSON list of the pages which changed in the last rebuild.
+	pub const CHANGES_PATH: &str = "/_sluggy/changes";
 
-	impl From<&str> for ContentEncoding {
-		#[inline]
-		fn from(value: &str) -> Self {
-			match value {
-				"br" => ContentEncoding::Brotli,
-				"gzip" => ContentEncoding::Gzip,
-				"deflate" => ContentEncoding::Deflate,
-				value => {
-					tracing::debug!("{value} is not a supported content encoding");
-					ContentEncoding::Identity
-				}
-			}
-		}
-	}
+	/// Path of the dev server's JSON list of the requests it last served, when they're recorded.
+	pub const REQUESTS_PATH: &str = "/_sluggy/requests";
 
-	impl ContentEncoding {
-		#[inline]
-		pub async fn read_to_end(&self, src: &[u8]) -> Result<Vec<u8>> {
-			let mut out_buf = vec![];
+	/// Directory of the page of each error status in `out_dir`, e.g. `_error/404/index.html`, which
+	/// is generated from `_error/404.md` in `content_dir`.
+	pub const ERROR_PAGES_DIR: &str = "_error";
 
-			map_err!(
-				match self {
-					Self::Brotli => BrotliEncoder::new(src).read_to_end(&mut out_buf).await,
-					Self::Gzip => GzipEncoder::new(src).read_to_end(&mut out_buf).await,
-					Self::Deflate => ZlibEncoder::new(src).read_to_end(&mut out_buf).await,
-					Self::Identity => {
-						// TODO This is unnecessary work. Should just be able to return the original bytes.
-						out_buf.extend(src);
-						Ok(0)
-					}
-				},
-				IoError("failed to encode source buffer"),
-			)?;
+	/// Name of the error page of statuses which don't have their own, e.g. `_error/_/index.html`.
+	pub const FALLBACK_ERROR_PAGE: &str = "_";
 
-			Ok(out_buf)
-		}
+	/// File of response headers for each path, in the format which Netlify and Cloudflare Pages
+	/// read from the root of the site.
+	pub const HEADERS_FILE: &str = "_headers";
+
+	/// Rules of a `_headers` file, each being a path pattern followed by its headers.
+	///
+	/// Paths are unindented, with their `Name: value` headers indented on the lines after them.
+	/// Empty lines and `#` comments are skipped.
+	pub fn parse_headers_file(source: &str) -> Vec<(String, Vec<(String, String)>)> {
+		let mut rules: Vec<(String, Vec<(String, String)>)> = vec![];
 
-		#[inline]
-		pub fn extension(&self) -> Option<&str> {
-			match self {
-				Self::Brotli => Some("br"),
-				Self::Gzip => Some("gz"),
-				Self::Deflate => Some("zl"),
-				Self::Identity => None,
+		for line in source.lines() {
+			let trimmed = line.trim();
+			if trimmed.is_empty() || trimmed.starts_with('#') {
+				continue;
+			}
+
+			if !line.starts_with(char::is_whitespace) {
+				rules.push((trimmed.to_string(), vec![]));
+			} else if let (Some((_, headers)), Some((name, value))) =
+				(rules.last_mut(), trimmed.split_once(':'))
+			{
+				headers.push((name.trim().to_string(), value.trim().to_string()));
 			}
 		}
 
-		#[inline]
-		pub fn to_header_value(&self) -> HeaderValue {
-			HeaderValue::from_static(match self {
-				Self::Brotli => "br",
-				Self::Gzip => "gzip",
-				Self::Deflate => "deflate",
-				Self::Identity => "identity",
+		rules
+	}
+
+	/// File of the redirects of the site, in the format which Netlify and Cloudflare Pages read
+	/// from the root of the site.
+	pub const REDIRECTS_FILE: &str = "_redirects";
+
+	/// Status of redirects which don't set one, and of the `aliases` of entries.
+	pub const DEFAULT_REDIRECT_STATUS: u16 = 301;
+
+	/// Rules of a `_redirects` file, each being the path which is redirected, where it's
+	/// redirected to and the status of the response.
+	///
+	/// Each line is `from to [status]`. Empty lines and `#` comments are skipped, as are lines
+	/// without a destination.
+	pub fn parse_redirects_file(source: &str) -> Vec<(String, String, u16)> {
+		source
+			.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty() && !line.starts_with('#'))
+			.filter_map(|line| {
+				let mut parts = line.split_whitespace();
+				let from = parts.next()?;
+				let to = parts.next()?;
+				let status = parts
+					.next()
+					.and_then(|status| status.trim_end_matches('!').parse().ok())
+					.unwrap_or(DEFAULT_REDIRECT_STATUS);
+				Some((from.to_string(), to.to_string(), status))
 			})
-		}
+			.collect()
 	}
 }
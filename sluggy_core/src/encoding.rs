@@ -0,0 +1,413 @@
+//! Content encodings of the compressed variants of files, as sluggy writes and serves them.
+//!
+//! Each file of `out_dir` which is worth compressing has a variant in each of
+//! [`ContentEncoding::COMPRESSED`]. The variants are at the file's path within
+//! `compressed_content_dir`, with the extension of the encoding appended, e.g.
+//! `out/.compressed/posts/index.html.br` for `out/posts/index.html`. Requests are served the
+//! variant of the encoding which [`negotiate`] picks from their `Accept-Encoding` header, or the
+//! file itself when there's none.
+
+use crate::{
+	err,
+	error::{Error, Result},
+	map_err,
+	utils::unlink_output,
+};
+use async_compression::tokio::bufread::{
+	BrotliDecoder, BrotliEncoder, GzipDecoder, GzipEncoder, ZlibDecoder, ZlibEncoder,
+};
+use http::HeaderValue;
+use serde_derive::Deserialize;
+use std::{
+	ffi::OsStr,
+	path::{Path, PathBuf},
+};
+use tokio::io::AsyncReadExt;
+
+/// Encoding of the content of a response, and of a compressed variant of a file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContentEncoding {
+	#[default]
+	Brotli,
+	Gzip,
+	Deflate,
+	Identity,
+}
+
+impl From<&str> for ContentEncoding {
+	/// The encoding of a token of `Accept-Encoding` or `Content-Encoding`. Unsupported encodings
+	/// are the identity.
+	#[inline]
+	fn from(value: &str) -> Self {
+		match value {
+			"br" => ContentEncoding::Brotli,
+			"gzip" => ContentEncoding::Gzip,
+			"deflate" => ContentEncoding::Deflate,
+			value => {
+				tracing::debug!("{value} is not a supported content encoding");
+				ContentEncoding::Identity
+			}
+		}
+	}
+}
+
+impl ContentEncoding {
+	/// Encodings which files are compressed in, in the order they're preferred.
+	pub const COMPRESSED: [Self; 3] = [Self::Brotli, Self::Gzip, Self::Deflate];
+
+	/// Encodes `src` in this encoding.
+	#[inline]
+	pub async fn read_to_end(&self, src: &[u8]) -> Result<Vec<u8>> {
+		let mut out_buf = vec![];
+
+		map_err!(
+			match self {
+				Self::Brotli => BrotliEncoder::new(src).read_to_end(&mut out_buf).await,
+				Self::Gzip => GzipEncoder::new(src).read_to_end(&mut out_buf).await,
+				Self::Deflate => ZlibEncoder::new(src).read_to_end(&mut out_buf).await,
+				Self::Identity => {
+					// TODO This is unnecessary work. Should just be able to return the original bytes.
+					out_buf.extend(src);
+					Ok(0)
+				}
+			},
+			IoError("failed to encode source buffer"),
+		)?;
+
+		Ok(out_buf)
+	}
+
+	/// Decodes `src`, which is in this encoding, back to the identity content.
+	#[inline]
+	pub async fn decode(&self, src: &[u8]) -> Result<Vec<u8>> {
+		let mut out_buf = vec![];
+
+		map_err!(
+			match self {
+				Self::Brotli => BrotliDecoder::new(src).read_to_end(&mut out_buf).await,
+				Self::Gzip => GzipDecoder::new(src).read_to_end(&mut out_buf).await,
+				Self::Deflate => ZlibDecoder::new(src).read_to_end(&mut out_buf).await,
+				Self::Identity => {
+					out_buf.extend(src);
+					Ok(0)
+				}
+			},
+			IoError("failed to decode source buffer"),
+		)?;
+
+		Ok(out_buf)
+	}
+
+	/// Extension which is appended to the names of variants in this encoding, e.g. `br`.
+	#[inline]
+	pub fn extension(&self) -> Option<&str> {
+		match self {
+			Self::Brotli => Some("br"),
+			Self::Gzip => Some("gz"),
+			Self::Deflate => Some("zl"),
+			Self::Identity => None,
+		}
+	}
+
+	#[inline]
+	pub fn to_header_value(&self) -> HeaderValue {
+		HeaderValue::from_static(match self {
+			Self::Brotli => "br",
+			Self::Gzip => "gzip",
+			Self::Deflate => "deflate",
+			Self::Identity => "identity",
+		})
+	}
+
+	/// `path` with the extension of this encoding appended, e.g. `index.html.br`, or `LICENSE.br`
+	/// for files without an extension.
+	#[inline]
+	pub fn variant_path(&self, path: &Path) -> PathBuf {
+		let Some(content_encoding_extension) = self.extension() else {
+			return path.to_path_buf();
+		};
+
+		let mut variant_path = path.as_os_str().to_os_string();
+		variant_path.push(format!(".{content_encoding_extension}"));
+		PathBuf::from(variant_path)
+	}
+}
+
+/// Picks the encoding of the response to a request with `accept_encoding`.
+///
+/// Encodings are ordered by their quality, keeping the client's order for those of equal quality,
+/// with `preferred` first whenever it's accepted. Encodings with a quality of 0, and requests
+/// without `Accept-Encoding`, get the identity content.
+#[inline]
+pub fn negotiate(accept_encoding: Option<&str>, preferred: &ContentEncoding) -> ContentEncoding {
+	let Some(accept_encoding) = accept_encoding else {
+		return ContentEncoding::Identity;
+	};
+
+	let mut algos = accept_encoding
+		.split(',')
+		.filter_map(
+			#[inline]
+			|v| {
+				let mut params = v.split(';');
+				let name = params.next()?.trim();
+				let quality = params
+					.find_map(|param| param.trim().strip_prefix("q="))
+					.map(|quality| quality.trim().parse::<f32>().unwrap_or(0.0))
+					.unwrap_or(1.0);
+
+				// Encodings with a quality of 0 are explicitly not acceptable.
+				if quality <= 0.0 {
+					return None;
+				}
+
+				match ContentEncoding::from(name) {
+					ContentEncoding::Identity => None,
+					content_encoding => Some((content_encoding, quality)),
+				}
+			},
+		)
+		.collect::<Vec<_>>();
+
+	// Stable sort so that encodings of equal quality keep the client's order.
+	algos.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+	let mut algos = algos
+		.into_iter()
+		.map(|(content_encoding, _)| content_encoding)
+		.collect::<Vec<_>>();
+
+	// Prefer config defined encoding
+	if let Some(pos) = algos.iter().position(|a| a == preferred) {
+		let algo = algos.remove(pos);
+		algos.insert(0, algo);
+	}
+
+	if !algos.is_empty() {
+		algos.swap_remove(0)
+	} else {
+		ContentEncoding::Identity
+	}
+}
+
+/// Path of the variant in `content_encoding` of `name`, a file within `out_dir`.
+/// `compressed_content_dir` is relative to `out_dir`.
+#[inline]
+pub fn compressed_path(
+	out_dir: &Path,
+	compressed_content_dir: &Path,
+	name: &Path,
+	content_encoding: &ContentEncoding,
+) -> PathBuf {
+	content_encoding.variant_path(&out_dir.join(compressed_content_dir).join(name))
+}
+
+/// The file which `path`, a file within `compressed_dir`, is a compressed variant of, relative to
+/// `out_dir`, and its encoding. `None` for paths which aren't compressed variants.
+#[inline]
+pub fn variant_of(compressed_dir: &Path, path: &Path) -> Option<(PathBuf, ContentEncoding)> {
+	let name = path.strip_prefix(compressed_dir).ok()?;
+	ContentEncoding::COMPRESSED
+		.into_iter()
+		.find(|encoding| name.extension().and_then(OsStr::to_str) == encoding.extension())
+		.map(|encoding| (name.with_extension(""), encoding))
+}
+
+/// The compressed variants of `name`, a file within `out_dir`, which have been written, in the
+/// order the encodings are preferred, e.g. to load them along with the file.
+#[inline]
+pub fn variants(
+	out_dir: &Path,
+	compressed_content_dir: &Path,
+	name: &Path,
+) -> Vec<(ContentEncoding, PathBuf)> {
+	ContentEncoding::COMPRESSED
+		.into_iter()
+		.map(|encoding| {
+			let path = compressed_path(out_dir, compressed_content_dir, name, &encoding);
+			(encoding, path)
+		})
+		.filter(|(_, path)| path.is_file())
+		.collect()
+}
+
+/// Writes `compressed`, a variant of a file, to `path`, creating the directories it's in.
+#[inline]
+pub async fn write_variant(path: &Path, compressed: &[u8]) -> Result<()> {
+	if let Some(parent) = path.parent() {
+		map_err!(
+			tokio::fs::create_dir_all(parent).await,
+			IoError(format!("failed to create dirs for {}", parent.display()))
+		)?;
+	}
+
+	unlink_output(path)?;
+	map_err!(
+		tokio::fs::write(path, compressed).await,
+		IoError(format!("failed to write to out file {}", path.display()))
+	)?;
+
+	Ok(())
+}
+
+/// Compresses `source`, the content of `name` within `out_dir`, in `content_encoding`, and writes
+/// it to where sluggy serves it from. Returns the path and size of the variant.
+///
+/// Unlike a build, the variant is written even when compressing `source` doesn't make it smaller.
+/// The identity encoding has no variant.
+#[inline]
+pub async fn compress_to_file(
+	source: &[u8],
+	out_dir: &Path,
+	compressed_content_dir: &Path,
+	name: &Path,
+	content_encoding: &ContentEncoding,
+) -> Result<(PathBuf, usize)> {
+	if *content_encoding == ContentEncoding::Identity {
+		return Err(err!(Validation(format!(
+			"{} has no compressed variant in the identity encoding",
+			name.display()
+		))));
+	}
+
+	let path = compressed_path(out_dir, compressed_content_dir, name, content_encoding);
+	let compressed = content_encoding.read_to_end(source).await?;
+	write_variant(&path, &compressed).await?;
+
+	Ok((path, compressed.len()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn negotiate_without_accept_encoding() {
+		assert_eq!(
+			negotiate(None, &ContentEncoding::Brotli),
+			ContentEncoding::Identity
+		);
+		assert_eq!(
+			negotiate(Some(""), &ContentEncoding::Brotli),
+			ContentEncoding::Identity
+		);
+		assert_eq!(
+			negotiate(Some("identity, zstd"), &ContentEncoding::Brotli),
+			ContentEncoding::Identity
+		);
+	}
+
+	#[test]
+	fn negotiate_by_quality() {
+		assert_eq!(
+			negotiate(Some("br;q=0.5, gzip;q=0.8"), &ContentEncoding::Identity),
+			ContentEncoding::Gzip
+		);
+		assert_eq!(
+			negotiate(
+				Some("deflate;q=0.2, gzip; q=0.1"),
+				&ContentEncoding::Identity
+			),
+			ContentEncoding::Deflate
+		);
+	}
+
+	#[test]
+	fn negotiate_skips_unacceptable() {
+		assert_eq!(
+			negotiate(Some("br;q=0, gzip"), &ContentEncoding::Brotli),
+			ContentEncoding::Gzip
+		);
+		assert_eq!(
+			negotiate(Some("br;q=0.0, gzip;q=0"), &ContentEncoding::Brotli),
+			ContentEncoding::Identity
+		);
+	}
+
+	#[test]
+	fn negotiate_ties_keep_the_client_order() {
+		assert_eq!(
+			negotiate(Some("gzip, deflate, br"), &ContentEncoding::Identity),
+			ContentEncoding::Gzip
+		);
+		assert_eq!(
+			negotiate(
+				Some("deflate;q=0.5, gzip;q=0.5"),
+				&ContentEncoding::Identity
+			),
+			ContentEncoding::Deflate
+		);
+	}
+
+	#[test]
+	fn negotiate_prefers_the_configured_encoding() {
+		assert_eq!(
+			negotiate(Some("gzip, br;q=0.1"), &ContentEncoding::Brotli),
+			ContentEncoding::Brotli
+		);
+		// The preferred encoding isn't picked when it isn't accepted.
+		assert_eq!(
+			negotiate(Some("gzip, deflate"), &ContentEncoding::Brotli),
+			ContentEncoding::Gzip
+		);
+	}
+
+	#[test]
+	fn variant_paths_round_trip() {
+		let compressed_dir = Path::new("out/.compressed");
+		for name in ["posts/index.html", "feed.xml", "LICENSE", "archive.tar.gz"] {
+			for encoding in ContentEncoding::COMPRESSED {
+				let path = encoding.variant_path(&compressed_dir.join(name));
+				assert_eq!(
+					variant_of(compressed_dir, &path),
+					Some((PathBuf::from(name), encoding.clone()))
+				);
+			}
+		}
+
+		assert_eq!(
+			ContentEncoding::Brotli.variant_path(Path::new("LICENSE")),
+			PathBuf::from("LICENSE.br")
+		);
+		assert_eq!(
+			ContentEncoding::Identity.variant_path(Path::new("index.html")),
+			PathBuf::from("index.html")
+		);
+	}
+
+	#[test]
+	fn variant_of_other_files() {
+		let compressed_dir = Path::new("out/.compressed");
+		assert_eq!(
+			variant_of(compressed_dir, Path::new("out/.compressed/index.html")),
+			None
+		);
+		assert_eq!(
+			variant_of(compressed_dir, Path::new("out/index.html.br")),
+			None
+		);
+	}
+
+	#[test]
+	fn compressed_paths() {
+		assert_eq!(
+			compressed_path(
+				Path::new("out"),
+				Path::new(".compressed"),
+				Path::new("posts/index.html"),
+				&ContentEncoding::Gzip,
+			),
+			PathBuf::from("out/.compressed/posts/index.html.gz")
+		);
+		assert_eq!(
+			compressed_path(
+				Path::new("out"),
+				Path::new(".compressed"),
+				Path::new("LICENSE"),
+				&ContentEncoding::Deflate,
+			),
+			PathBuf::from("out/.compressed/LICENSE.zl")
+		);
+	}
+}
@@ -1,15 +1,34 @@
 use json_pointer::Error as JsonPointerError;
+use miette::{Diagnostic, NamedSource, SourceSpan};
 use notify::Error as NotifyError;
 use regex::Error as RegexError;
 use serde_json::error::Error as SerdeJsonError;
-use std::{path::PathBuf, result::Result as StdResult};
+use std::{ops::Range, path::PathBuf, result::Result as StdResult};
 use thiserror::Error;
 use tokio::task::JoinError;
 
 pub type Result<T> = StdResult<T, Error>;
 
-#[derive(Error, Debug)]
+#[derive(Error, Diagnostic, Debug)]
 pub enum Error {
+	/// An error at a location in a file, which is reported with an annotated excerpt of the file.
+	#[error("{message}")]
+	#[diagnostic(code(sluggy::source))]
+	Source {
+		message: String,
+		#[source_code]
+		source_code: NamedSource,
+		#[label("{label}")]
+		span: SourceSpan,
+		label: String,
+	},
+	#[error("failed to render template {template}")]
+	#[diagnostic(code(sluggy::template))]
+	Template {
+		/// Path of the template file, or its name if it wasn't loaded from a file.
+		template: String,
+		source: tera::Error,
+	},
 	#[error("json pointer error")]
 	JsonPointer(#[from] JsonPointerError),
 	#[error(transparent)]
@@ -37,6 +56,15 @@ pub enum Error {
 		message: String,
 		source: toml::de::Error,
 	},
+	#[cfg(feature = "data-formats")]
+	#[error("{message}")]
+	YamlDeserializeError {
+		message: String,
+		source: serde_yaml::Error,
+	},
+	#[cfg(feature = "data-formats")]
+	#[error("{message}")]
+	CsvError { message: String, source: csv::Error },
 	#[error("{message}")]
 	IoError {
 		message: String,
@@ -53,14 +81,21 @@ pub enum Error {
 	TemplateRenderError(#[from] tera::Error),
 	#[error("{0}")]
 	Css(String),
+	#[error("{0}")]
+	ImageError(String),
+	#[error("{0}")]
+	MediaError(String),
+	#[cfg(feature = "css")]
 	#[error("css modules pattern parse")]
 	CssModulesPatternParse(#[from] lightningcss::css_modules::PatternParseError),
+	#[cfg(feature = "css")]
 	#[error("browserslist error")]
 	Browserslist(#[from] browserslist::Error),
 	#[error("datetime parse error")]
 	DateTimeParse(#[from] chrono::ParseError),
 	#[error("url parse error")]
 	UrlParse(#[from] url::ParseError),
+	#[cfg(feature = "http-loader")]
 	#[error("client request error")]
 	ClientRequest {
 		message: String,
@@ -68,7 +103,18 @@ pub enum Error {
 	},
 	#[error("file loader error for {path:?}: {message}")]
 	FileLoaderError { message: String, path: PathBuf },
+	#[error("panic while rendering {path:?}: {message}")]
+	RenderPanic { message: String, path: PathBuf },
+	#[error("{0} task(s) failed during generation")]
+	GenerateFailed(usize),
+	#[error("found {0} broken link(s)")]
+	BrokenLinks(usize),
+	#[error("{0} budget(s) exceeded")]
+	BudgetsExceeded(usize),
+	#[error("{0} template test(s) failed")]
+	TemplateTestsFailed(usize),
 	#[error("server error")]
+	#[cfg(feature = "server")]
 	Server(#[from] hyper::Error),
 	#[error("OTLP error")]
 	TraceOtlp(#[from] opentelemetry_api::trace::TraceError),
@@ -76,12 +122,143 @@ pub enum Error {
 	TraceSetGlobalDefault(#[from] tracing::subscriber::SetGlobalDefaultError),
 }
 
+impl Error {
+	/// An error at `span` of `source`, which is the content of the file or URL called `name`.
+	pub fn in_source(
+		message: impl Into<String>,
+		name: impl AsRef<str>,
+		source: impl Into<String>,
+		span: Range<usize>,
+		label: impl Into<String>,
+	) -> Self {
+		let source = source.into();
+		let start = span.start.min(source.len());
+		let len = span.end.min(source.len()).saturating_sub(start);
+
+		Self::Source {
+			message: message.into(),
+			source_code: NamedSource::new(name, source),
+			span: (start, len).into(),
+			label: label.into(),
+		}
+	}
+
+	/// An error at the 1-based `line` and `column` of `source`.
+	pub fn at_line(
+		message: impl Into<String>,
+		name: impl AsRef<str>,
+		source: impl Into<String>,
+		line: usize,
+		column: usize,
+		label: impl Into<String>,
+	) -> Self {
+		let source = source.into();
+		let offset = line_offset(&source, line, column);
+		Self::in_source(message, name, source, offset..offset, label)
+	}
+
+	/// Labels the location of a TOML error in `source`, if it was reported.
+	pub fn toml_in_source(
+		message: String,
+		name: impl AsRef<str>,
+		source: &str,
+		error: toml::de::Error,
+	) -> Self {
+		match error.span() {
+			Some(span) => Self::in_source(message, name, source, span, error.message()),
+			None => Self::TomlDeserializeError {
+				message,
+				source: error,
+			},
+		}
+	}
+
+	/// Labels the location of a JSON error in `source`, if it was reported.
+	pub fn json_in_source(
+		message: String,
+		name: impl AsRef<str>,
+		source: &str,
+		error: SerdeJsonError,
+	) -> Self {
+		if error.line() == 0 {
+			return Self::SerdeJsonError {
+				message,
+				source: error,
+			};
+		}
+
+		Self::at_line(
+			message,
+			name,
+			source,
+			error.line(),
+			error.column(),
+			without_location(&error.to_string()),
+		)
+	}
+
+	/// Labels the location of a YAML error in `source`, if it was reported.
+	#[cfg(feature = "data-formats")]
+	pub fn yaml_in_source(
+		message: String,
+		name: impl AsRef<str>,
+		source: &str,
+		error: serde_yaml::Error,
+	) -> Self {
+		match error.location() {
+			Some(location) => Self::in_source(
+				message,
+				name,
+				source,
+				location.index()..location.index(),
+				without_location(&error.to_string()),
+			),
+			None => Self::YamlDeserializeError {
+				message,
+				source: error,
+			},
+		}
+	}
+}
+
+/// Byte offset of the 1-based `line` and `column` in `source`.
+#[inline]
+pub(crate) fn line_offset(source: &str, line: usize, column: usize) -> usize {
+	let line_start = source
+		.split_inclusive('\n')
+		.take(line.saturating_sub(1))
+		.map(str::len)
+		.sum::<usize>();
+	let line_len = source[line_start..]
+		.split('\n')
+		.next()
+		.map(str::len)
+		.unwrap_or_default();
+
+	let mut offset = line_start + column.saturating_sub(1).min(line_len);
+	while !source.is_char_boundary(offset) {
+		offset -= 1;
+	}
+
+	offset
+}
+
+/// Errors of serde formats end with their location, which is redundant once it is labelled.
+#[inline]
+fn without_location(message: &str) -> String {
+	match message.rfind(" at line ") {
+		Some(index) => message[..index].to_string(),
+		None => message.to_string(),
+	}
+}
+
 impl From<minify_html_onepass::Error> for Error {
 	fn from(value: minify_html_onepass::Error) -> Self {
 		Self::MinifyHtmlError(value.error_type.message())
 	}
 }
 
+#[cfg(feature = "css")]
 impl<T: std::fmt::Display> From<lightningcss::error::Error<T>> for Error {
 	fn from(value: lightningcss::error::Error<T>) -> Self {
 		Self::Css(format!("{}", value))
@@ -0,0 +1,449 @@
+use super::{
+	config::ArchivesConfig,
+	content::Content,
+	pagination::{newest_first, paginate, Paginator, DEFAULT_PAGINATE_PATH},
+	sections::SectionHandle,
+	taxonomies::spawn_page,
+};
+use crate::{
+	err,
+	error::{Error, Result},
+};
+use chrono::Datelike;
+use serde_derive::Serialize;
+use serde_json::json;
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+use tokio::task::JoinSet;
+use tracing::instrument;
+
+/// The entries published in a year, or in a month of it.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Archive {
+	pub year: i32,
+	/// 1-based month, if this is the archive of a month.
+	pub month: Option<u32>,
+	/// URL of the archive page, if the archive's pages are rendered.
+	pub url: Option<String>,
+	/// Number of entries published in the year or month.
+	pub count: usize,
+	/// Paths of the entries, newest first.
+	pub entries: Vec<PathBuf>,
+	/// Archives of the months of a year, newest first.
+	pub months: Vec<Archive>,
+	/// File the archive's page is rendered to, relative to `out_dir`.
+	#[serde(skip)]
+	file_path: PathBuf,
+}
+
+/// Archives of each year with published entries, newest first, or none when `archives` isn't
+/// configured. Entries are archived by their published date in UTC, and protected entries aren't
+/// archived.
+#[inline]
+pub(crate) fn archives(content: &Content) -> Vec<Archive> {
+	let Some(archives_config) = &content.config.archives else {
+		return vec![];
+	};
+
+	let section_handles = archives_config
+		.sections
+		.iter()
+		.map(|section| SectionHandle::from(section.as_str()))
+		.collect::<Vec<_>>();
+
+	let mut dates = BTreeMap::<i32, BTreeMap<u32, Vec<PathBuf>>>::new();
+	for entry in content.entries.iter() {
+		let Some(published) = entry
+			.published
+			.filter(|_| entry.is_renderable && !entry.protected)
+		else {
+			continue;
+		};
+		// Pages aren't archived, just as they aren't in the lists of their sections.
+		let is_listed = content
+			.config
+			.kind(&entry.kind)
+			.is_some_and(|kind| kind.listed);
+		let is_in_sections = section_handles.is_empty()
+			|| entry
+				.section_handle
+				.as_ref()
+				.is_some_and(|section_handle| section_handles.contains(section_handle));
+		if !is_listed || !is_in_sections {
+			continue;
+		}
+
+		dates
+			.entry(published.year())
+			.or_default()
+			.entry(published.month())
+			.or_default()
+			.push(entry.key().clone());
+	}
+
+	dates
+		.into_iter()
+		.rev()
+		.map(|(year, months)| {
+			let months = months
+				.into_iter()
+				.rev()
+				.map(|(month, paths)| {
+					archive(content, archives_config, year, Some(month), paths, vec![])
+				})
+				.collect::<Vec<_>>();
+			let paths = months
+				.iter()
+				.flat_map(|month| month.entries.iter().cloned())
+				.collect();
+
+			archive(content, archives_config, year, None, paths, months)
+		})
+		.collect()
+}
+
+#[inline]
+fn archive(
+	content: &Content,
+	archives_config: &ArchivesConfig,
+	year: i32,
+	month: Option<u32>,
+	paths: Vec<PathBuf>,
+	months: Vec<Archive>,
+) -> Archive {
+	let pattern = match month {
+		Some(_) => &archives_config.month_path,
+		None => &archives_config.year_path,
+	};
+	let path = pattern
+		.replace("{year}", &format!("{year:04}"))
+		.replace("{month}", &format!("{:02}", month.unwrap_or_default()))
+		.trim_matches('/')
+		.to_string();
+
+	let is_rendered = match month {
+		Some(_) => archives_config.months,
+		None => archives_config.years,
+	};
+
+	Archive {
+		year,
+		month,
+		url: is_rendered.then(|| format!("{}{path}/", content.config.base_url)),
+		count: paths.len(),
+		entries: newest_first(content, paths.iter()),
+		months,
+		file_path: PathBuf::from(path),
+	}
+}
+
+/// Renders the page of each year and month with published entries, e.g. `/2024/` and
+/// `/2024/03/`.
+///
+/// Pages get the `archive`, with the archives of its `months` on year pages, and, when
+/// paginated, a `paginator` of its entries.
+#[instrument(skip_all)]
+pub(crate) fn generate_archives(
+	content: &Arc<Content>,
+	join_set: &mut JoinSet<Result<()>>,
+) -> Result<()> {
+	let Some(archives_config) = &content.config.archives else {
+		return Ok(());
+	};
+	check_paths(archives_config)?;
+
+	for year in archives(content) {
+		if archives_config.months {
+			for month in &year.months {
+				render_archive_pages(
+					content,
+					archives_config,
+					month,
+					&archives_config.month_template,
+					join_set,
+				)?;
+			}
+		}
+
+		if archives_config.years {
+			render_archive_pages(
+				content,
+				archives_config,
+				&year,
+				&archives_config.year_template,
+				join_set,
+			)?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Checks that the path of each rendered archive is its own, so that archives don't overwrite each
+/// other or the home page.
+#[inline]
+fn check_paths(archives_config: &ArchivesConfig) -> Result<()> {
+	let paths = [
+		(
+			archives_config.years,
+			"year_path",
+			&archives_config.year_path,
+			&["{year}"][..],
+		),
+		(
+			archives_config.months,
+			"month_path",
+			&archives_config.month_path,
+			&["{year}", "{month}"][..],
+		),
+	];
+	for (is_rendered, option, path, placeholders) in paths {
+		if !is_rendered {
+			continue;
+		}
+
+		if let Some(placeholder) = placeholders
+			.iter()
+			.find(|placeholder| !path.contains(**placeholder))
+		{
+			return Err(err!(Validation(format!(
+				"`archives.{option}` must contain `{placeholder}`, instead of \"{path}\""
+			))));
+		}
+	}
+
+	Ok(())
+}
+
+#[inline]
+fn render_archive_pages(
+	content: &Arc<Content>,
+	archives_config: &ArchivesConfig,
+	archive: &Archive,
+	template_name: &str,
+	join_set: &mut JoinSet<Result<()>>,
+) -> Result<()> {
+	for (file_path, paginator) in archive_pages(content, archives_config, archive)? {
+		spawn_page(
+			content,
+			file_path,
+			template_name.to_string(),
+			json!({ "archive": archive, "paginator": paginator }),
+			join_set,
+		)?;
+	}
+
+	Ok(())
+}
+
+/// Files of the pages of an archive, relative to `out_dir`, with their paginators when the
+/// archive's entries are paginated.
+#[inline]
+fn archive_pages(
+	content: &Content,
+	archives_config: &ArchivesConfig,
+	archive: &Archive,
+) -> Result<Vec<(PathBuf, Option<Paginator>)>> {
+	let paginate_path = archives_config
+		.paginate_path
+		.as_deref()
+		.unwrap_or(DEFAULT_PAGINATE_PATH);
+
+	Ok(match archives_config.paginate_by {
+		Some(paginate_by) => paginate(
+			&archive.entries,
+			paginate_by,
+			&archive.file_path,
+			paginate_path,
+			&content.config.base_url,
+		)?
+		.into_iter()
+		.map(|paginator| (paginator.file_path.clone(), Some(paginator)))
+		.collect(),
+		None => vec![(archive.file_path.join("index.html"), None)],
+	})
+}
+
+/// Files of the pages which [`generate_archives`] renders, relative to `out_dir`, so that they can
+/// be checked for conflicts before anything is written.
+#[inline]
+pub(crate) fn output_paths(content: &Content) -> Result<Vec<PathBuf>> {
+	let Some(archives_config) = &content.config.archives else {
+		return Ok(vec![]);
+	};
+	check_paths(archives_config)?;
+
+	let mut paths = vec![];
+	for year in archives(content) {
+		if archives_config.months {
+			for month in &year.months {
+				for (file_path, _) in archive_pages(content, archives_config, month)? {
+					paths.push(file_path);
+				}
+			}
+		}
+
+		if archives_config.years {
+			for (file_path, _) in archive_pages(content, archives_config, &year)? {
+				paths.push(file_path);
+			}
+		}
+	}
+
+	Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::generate::{
+		config::Config,
+		content::{Entry, FileType},
+	};
+	use chrono::{TimeZone, Utc};
+	use dashmap::DashMap;
+
+	/// Path, kind, and the year and month an entry is published in.
+	type TestEntry = (&'static str, &'static str, Option<(i32, u32)>);
+
+	fn content(archives_config: ArchivesConfig, entries: &[TestEntry]) -> Content {
+		let content = Content {
+			entries: DashMap::new(),
+			sections: DashMap::new(),
+			taxonomies: DashMap::new(),
+			config: Arc::new(Config {
+				base_url: "/".into(),
+				archives: Some(archives_config),
+				..Config::default()
+			}),
+		};
+		for (path, kind, published) in entries {
+			let path = PathBuf::from(path);
+			content.entries.insert(
+				path.clone(),
+				Entry {
+					slug: None,
+					layout: None,
+					kind: kind.to_string(),
+					url: format!("/{}/", path.display()),
+					external_url: None,
+					link: format!("/{}/", path.display()),
+					file_path: path.with_extension("md"),
+					path,
+					file_type: FileType::Markdown,
+					published: published.map(|(year, month)| {
+						Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap()
+					}),
+					updated: None,
+					section_handle: None,
+					is_renderable: true,
+					draft: false,
+					headers: BTreeMap::new(),
+					aliases: vec![],
+					password: None,
+					protected: false,
+					extra: Default::default(),
+				},
+			);
+		}
+		content
+	}
+
+	fn entries() -> Vec<TestEntry> {
+		vec![
+			("posts/a", "post", Some((2023, 12))),
+			("posts/b", "post", Some((2024, 3))),
+			("posts/c", "post", Some((2024, 3))),
+			("posts/d", "post", Some((2024, 1))),
+			("posts/undated", "post", None),
+			("about", "page", Some((2024, 1))),
+		]
+	}
+
+	#[test]
+	fn archives_of_years_and_months() {
+		let content = content(ArchivesConfig::default(), &entries());
+		let archives = archives(&content);
+
+		assert_eq!(
+			archives
+				.iter()
+				.map(|archive| archive.year)
+				.collect::<Vec<_>>(),
+			vec![2024, 2023]
+		);
+		let year = &archives[0];
+		assert_eq!(year.month, None);
+		assert_eq!(year.url.as_deref(), Some("/2024/"));
+		assert_eq!(year.count, 3);
+		assert_eq!(
+			year.months
+				.iter()
+				.map(|month| (month.month, month.count))
+				.collect::<Vec<_>>(),
+			vec![(Some(3), 2), (Some(1), 1)]
+		);
+
+		let month = &year.months[0];
+		assert_eq!(month.url.as_deref(), Some("/2024/03/"));
+		assert_eq!(month.file_path, PathBuf::from("2024/03"));
+		// Entries published at the same time are in the order of their paths.
+		assert_eq!(
+			month.entries,
+			vec![PathBuf::from("posts/b"), PathBuf::from("posts/c")]
+		);
+	}
+
+	#[test]
+	fn archive_pages() {
+		let content = content(
+			ArchivesConfig {
+				months: false,
+				year_path: "archive/{year}".into(),
+				paginate_by: Some(2),
+				..ArchivesConfig::default()
+			},
+			&entries(),
+		);
+
+		let archives = archives(&content);
+		// Archives of months which aren't rendered have no URL.
+		assert_eq!(archives[0].months[0].url, None);
+
+		assert_eq!(
+			output_paths(&content).unwrap(),
+			vec![
+				PathBuf::from("archive/2024/index.html"),
+				PathBuf::from("archive/2024/page/2/index.html"),
+				PathBuf::from("archive/2023/index.html"),
+			]
+		);
+	}
+
+	#[test]
+	fn archive_paths_must_be_their_own() {
+		for archives_config in [
+			ArchivesConfig {
+				year_path: "".into(),
+				..ArchivesConfig::default()
+			},
+			ArchivesConfig {
+				month_path: "{year}/months".into(),
+				..ArchivesConfig::default()
+			},
+		] {
+			let content = content(archives_config, &entries());
+			assert!(output_paths(&content).is_err());
+		}
+
+		// Paths of archives which aren't rendered aren't used.
+		let content = content(
+			ArchivesConfig {
+				years: false,
+				year_path: "".into(),
+				..ArchivesConfig::default()
+			},
+			&entries(),
+		);
+		assert!(output_paths(&content).is_ok());
+	}
+}
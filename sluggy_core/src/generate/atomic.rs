@@ -0,0 +1,335 @@
+use super::config::Config;
+use crate::{
+	err,
+	error::{Error, Result},
+	map_err,
+};
+use std::{
+	fs,
+	os::unix::fs::symlink,
+	path::{Path, PathBuf},
+};
+use tracing::instrument;
+
+/// The config of a build into a copy of `out_dir`, which is swapped into place once it succeeds,
+/// so that `out_dir` is never left half-written. The copy keeps the outputs which are the same as
+/// the last build, and anything else in `out_dir`.
+///
+/// Files of the copy are hard links to those of `out_dir`, so that staging doesn't copy their
+/// content. Outputs are unlinked before they're written (see
+/// [`unlink_output`](crate::utils::unlink_output)), which leaves the files of `out_dir` as they
+/// were.
+#[instrument(skip(config))]
+pub(crate) fn stage(config: &Config) -> Result<Config> {
+	let staging_dir = sibling_dir(&config.out_dir, "staging")?;
+	if staging_dir.exists() {
+		map_err!(
+			fs::remove_dir_all(&staging_dir),
+			IoError(format!("failed to remove {}", staging_dir.display())),
+		)?;
+	}
+
+	if config.out_dir.is_dir() {
+		link_dir(&config.out_dir, &staging_dir)?;
+	} else {
+		map_err!(
+			fs::create_dir_all(&staging_dir),
+			IoError(format!("failed to create {}", staging_dir.display())),
+		)?;
+	}
+
+	// A manifest within `out_dir` is swapped in along with the rest of the build.
+	let output_manifest = config.output_manifest.as_ref().map(|output_manifest| {
+		output_manifest
+			.strip_prefix(&config.out_dir)
+			.map_or_else(|_| output_manifest.clone(), |path| staging_dir.join(path))
+	});
+
+	Ok(Config {
+		out_dir: staging_dir,
+		output_manifest,
+		..config.clone()
+	})
+}
+
+/// Swaps the successful build of `staged` into `out_dir`, after which the last build is removed.
+///
+/// Where the filesystem supports it, the two dirs are exchanged in one step, so that `out_dir` is
+/// never missing. Otherwise `out_dir` is only missing between two renames, and is put back if the
+/// build can't be moved into its place.
+#[instrument(skip_all)]
+pub(crate) fn swap(config: &Config, staged: &Config) -> Result<()> {
+	if !config.out_dir.exists() {
+		return rename(&staged.out_dir, &config.out_dir);
+	}
+
+	if exchange(&staged.out_dir, &config.out_dir)? {
+		// The last build is where the staged build was.
+		remove_last_build(&staged.out_dir);
+		return Ok(());
+	}
+
+	let previous_dir = sibling_dir(&config.out_dir, "previous")?;
+	rename_into_place(&staged.out_dir, &config.out_dir, &previous_dir)?;
+	remove_last_build(&previous_dir);
+
+	Ok(())
+}
+
+/// Moves `out_dir` to `previous_dir`, and then `staging_dir` to `out_dir`. `out_dir` is moved back
+/// when `staging_dir` can't be moved.
+#[inline]
+fn rename_into_place(staging_dir: &Path, out_dir: &Path, previous_dir: &Path) -> Result<()> {
+	if previous_dir.exists() {
+		map_err!(
+			fs::remove_dir_all(previous_dir),
+			IoError(format!("failed to remove {}", previous_dir.display())),
+		)?;
+	}
+
+	rename(out_dir, previous_dir)?;
+	if let Err(error) = rename(staging_dir, out_dir) {
+		rename(previous_dir, out_dir)?;
+		return Err(error);
+	}
+
+	Ok(())
+}
+
+#[inline]
+fn rename(from: &Path, to: &Path) -> Result<()> {
+	map_err!(
+		fs::rename(from, to),
+		IoError(format!(
+			"failed to move {} to {}",
+			from.display(),
+			to.display()
+		)),
+	)
+}
+
+/// Exchanges the dirs at `a` and `b` in one step, returning whether the filesystem supports it.
+#[cfg(target_os = "linux")]
+#[inline]
+fn exchange(a: &Path, b: &Path) -> Result<bool> {
+	use std::{ffi::CString, io, os::unix::ffi::OsStrExt};
+
+	let c_path = |path: &Path| {
+		CString::new(path.as_os_str().as_bytes())
+			.map_err(|_| err!(Validation(format!("invalid path {}", path.display()))))
+	};
+	let (a_path, b_path) = (c_path(a)?, c_path(b)?);
+
+	// SAFETY: Both paths are NUL terminated strings, which outlive the call.
+	let result = unsafe {
+		libc::renameat2(
+			libc::AT_FDCWD,
+			a_path.as_ptr(),
+			libc::AT_FDCWD,
+			b_path.as_ptr(),
+			libc::RENAME_EXCHANGE,
+		)
+	};
+	if result == 0 {
+		return Ok(true);
+	}
+
+	let error = io::Error::last_os_error();
+	match error.raw_os_error() {
+		Some(libc::EINVAL | libc::ENOSYS | libc::EOPNOTSUPP) => Ok(false),
+		_ => Err(Error::IoError {
+			message: format!("failed to exchange {} and {}", a.display(), b.display()),
+			source: error,
+		}),
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+#[inline]
+fn exchange(_a: &Path, _b: &Path) -> Result<bool> {
+	Ok(false)
+}
+
+#[inline]
+fn remove_last_build(path: &Path) {
+	if let Err(error) = fs::remove_dir_all(path) {
+		tracing::warn!(%error, path = %path.display(), "Unable to remove the last build");
+	}
+}
+
+/// Removes the copy of `out_dir` of a failed build, leaving `out_dir` as it was.
+#[instrument(skip_all)]
+pub(crate) fn discard(staged: &Config) {
+	if let Err(error) = fs::remove_dir_all(&staged.out_dir) {
+		tracing::warn!(%error, path = %staged.out_dir.display(), "Unable to remove the failed build");
+	}
+}
+
+/// A hidden directory next to `out_dir`, e.g. `.out.staging`, so that it can be renamed into
+/// place.
+#[inline]
+fn sibling_dir(out_dir: &Path, suffix: &str) -> Result<PathBuf> {
+	let name = out_dir
+		.file_name()
+		.ok_or_else(|| err!(Validation("atomic builds need out_dir to have a name")))?;
+	Ok(out_dir.with_file_name(format!(".{}.{suffix}", name.to_string_lossy())))
+}
+
+/// Recreates the dirs of `from` at `to`, with hard links to its files. Symlinks are recreated
+/// rather than followed, so that what they point at isn't copied into the build.
+#[inline]
+fn link_dir(from: &Path, to: &Path) -> Result<()> {
+	map_err!(
+		fs::create_dir_all(to),
+		IoError(format!("failed to create {}", to.display())),
+	)?;
+
+	for entry in map_err!(
+		fs::read_dir(from),
+		IoError(format!("failed to read dir {}", from.display())),
+	)? {
+		let entry = map_err!(entry, IoError("dir entry failed"))?;
+		let path = entry.path();
+		let to_path = to.join(entry.file_name());
+		let file_type = map_err!(
+			entry.file_type(),
+			IoError(format!("failed to read the type of {}", path.display())),
+		)?;
+
+		if file_type.is_symlink() {
+			let target = map_err!(
+				fs::read_link(&path),
+				IoError(format!("failed to read link {}", path.display())),
+			)?;
+			map_err!(
+				symlink(target, &to_path),
+				IoError(format!("failed to link {}", to_path.display())),
+			)?;
+		} else if file_type.is_dir() {
+			link_dir(&path, &to_path)?;
+		} else {
+			map_err!(
+				fs::hard_link(&path, &to_path),
+				IoError(format!("failed to link {}", path.display())),
+			)?;
+		}
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::unlink_output;
+	use std::os::unix::fs::MetadataExt;
+	use tempfile::TempDir;
+
+	/// An `out_dir` in a temporary dir, which also holds its staged and previous builds, and is
+	/// removed when the returned `TempDir` is dropped.
+	fn out_dir() -> (TempDir, PathBuf) {
+		let temp_dir = tempfile::tempdir().unwrap();
+		let dir = temp_dir.path().join("out");
+		fs::create_dir_all(dir.join("posts")).unwrap();
+		fs::write(dir.join("index.html"), "old").unwrap();
+		fs::write(dir.join("posts/index.html"), "post").unwrap();
+		(temp_dir, dir)
+	}
+
+	#[test]
+	fn stage_links_files() {
+		let (_temp_dir, out_dir) = out_dir();
+		symlink("../index.html", out_dir.join("posts/home.html")).unwrap();
+		let config = Config {
+			out_dir: out_dir.clone(),
+			..Config::default()
+		};
+
+		let staged = stage(&config).unwrap();
+
+		let inode = |path: &Path| fs::metadata(path).unwrap().ino();
+		assert_eq!(
+			inode(&staged.out_dir.join("posts/index.html")),
+			inode(&out_dir.join("posts/index.html"))
+		);
+		let link = staged.out_dir.join("posts/home.html");
+		assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+		assert_eq!(fs::read_link(&link).unwrap(), Path::new("../index.html"));
+
+		discard(&staged);
+		assert!(!staged.out_dir.exists());
+	}
+
+	#[test]
+	fn staged_writes_leave_out_dir() {
+		let (_temp_dir, out_dir) = out_dir();
+		let config = Config {
+			out_dir: out_dir.clone(),
+			..Config::default()
+		};
+
+		let staged = stage(&config).unwrap();
+		let staged_file = staged.out_dir.join("index.html");
+		unlink_output(&staged_file).unwrap();
+		fs::write(&staged_file, "new").unwrap();
+
+		assert_eq!(
+			fs::read_to_string(out_dir.join("index.html")).unwrap(),
+			"old"
+		);
+
+		swap(&config, &staged).unwrap();
+
+		assert_eq!(
+			fs::read_to_string(out_dir.join("index.html")).unwrap(),
+			"new"
+		);
+		assert_eq!(
+			fs::read_to_string(out_dir.join("posts/index.html")).unwrap(),
+			"post"
+		);
+		assert!(!staged.out_dir.exists());
+		assert!(!sibling_dir(&out_dir, "previous").unwrap().exists());
+	}
+
+	#[test]
+	fn out_dir_is_restored_when_the_build_can_not_be_moved() {
+		let (_temp_dir, out_dir) = out_dir();
+		let previous_dir = sibling_dir(&out_dir, "previous").unwrap();
+		let missing_dir = sibling_dir(&out_dir, "missing").unwrap();
+
+		assert!(rename_into_place(&missing_dir, &out_dir, &previous_dir).is_err());
+
+		assert_eq!(
+			fs::read_to_string(out_dir.join("index.html")).unwrap(),
+			"old"
+		);
+		assert!(!previous_dir.exists());
+	}
+
+	#[test]
+	fn rename_into_place_moves_the_build() {
+		let (_temp_dir, out_dir) = out_dir();
+		let config = Config {
+			out_dir: out_dir.clone(),
+			..Config::default()
+		};
+		let staged = stage(&config).unwrap();
+		let staged_file = staged.out_dir.join("index.html");
+		unlink_output(&staged_file).unwrap();
+		fs::write(&staged_file, "new").unwrap();
+
+		let previous_dir = sibling_dir(&out_dir, "previous").unwrap();
+		rename_into_place(&staged.out_dir, &out_dir, &previous_dir).unwrap();
+
+		assert_eq!(
+			fs::read_to_string(out_dir.join("index.html")).unwrap(),
+			"new"
+		);
+		assert_eq!(
+			fs::read_to_string(previous_dir.join("index.html")).unwrap(),
+			"old"
+		);
+		assert!(!staged.out_dir.exists());
+	}
+}
@@ -0,0 +1,378 @@
+#[cfg(feature = "http-loader")]
+use super::http_cache::{self, is_allowed_url};
+use super::{
+	config::{BlogrollConfig, Config},
+	content::Content,
+	data::{read_data, resolve_data_path},
+	process_output,
+};
+use crate::{
+	error::{Error, Result},
+	lazyfn::LazyFn,
+	map_err,
+	utils::{escape_xml, unlink_output, LockResultExt},
+};
+#[cfg(feature = "http-loader")]
+use regex::Regex;
+#[cfg(feature = "http-loader")]
+use reqwest::Url;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, fmt::Write, fs, sync::RwLock};
+use tokio::task::JoinSet;
+use tracing::instrument;
+
+/// Feeds of the blogroll, as they were loaded for the tree being generated.
+static BLOGROLL: LazyFn<RwLock<Vec<BlogrollFeed>>> = LazyFn::new(Default::default);
+
+#[cfg(feature = "http-loader")]
+static TITLE_RE: LazyFn<Regex> =
+	LazyFn::new(|| Regex::new(r"(?s)<title[^>]*>(.*?)</title>").unwrap());
+
+#[cfg(feature = "http-loader")]
+static LINK_RE: LazyFn<Regex> =
+	LazyFn::new(|| Regex::new(r"(?s)<link(\s[^>]*)?>(?:\s*([^<\s]+)\s*</link>)?").unwrap());
+
+#[cfg(feature = "http-loader")]
+static ATTRIBUTE_RE: LazyFn<Regex> =
+	LazyFn::new(|| Regex::new(r#"\s([\w:-]+)\s*=\s*["']([^"']*)["']"#).unwrap());
+
+/// A feed of the blogroll, as it's listed in `blogroll.data`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BlogrollFeed {
+	/// URL of the feed.
+	pub url: String,
+	pub title: Option<String>,
+	/// URL of the site which the feed is of.
+	pub site_url: Option<String>,
+	pub description: Option<String>,
+	/// Feeds are grouped by category in the OPML file.
+	pub category: Option<String>,
+}
+
+/// Loads the feeds of `blogroll.data`, and fetches the titles and site URLs of those which don't
+/// set them when `fetch_titles` is enabled.
+#[instrument(skip_all)]
+pub(crate) async fn load_blogroll(config: &Config) -> Result<()> {
+	let Some(blogroll_config) = &config.blogroll else {
+		BLOGROLL.write().acquire().clear();
+		return Ok(());
+	};
+
+	let path = resolve_data_path(&blogroll_config.data, config)?;
+	// Either a list of feeds, or a table with one, like `[[feeds]]` in TOML.
+	let feeds = match read_data(&path, None)? {
+		Value::Object(mut table) => table.remove("feeds").unwrap_or_default(),
+		feeds => feeds,
+	};
+	let mut feeds: Vec<BlogrollFeed> = map_err!(
+		serde_json::from_value(feeds),
+		SerdeJsonError(format!("failed to read the blogroll of {}", path.display())),
+	)?;
+
+	// Fields which are empty strings aren't set.
+	for feed in &mut feeds {
+		for field in [
+			&mut feed.title,
+			&mut feed.site_url,
+			&mut feed.description,
+			&mut feed.category,
+		] {
+			if field.as_deref().is_some_and(str::is_empty) {
+				*field = None;
+			}
+		}
+	}
+
+	if blogroll_config.fetch_titles {
+		fetch_titles(&mut feeds, config).await;
+	}
+
+	*BLOGROLL.write().acquire() = feeds;
+
+	Ok(())
+}
+
+/// The feeds of the blogroll, in the order they're listed.
+#[inline]
+pub(crate) fn blogroll() -> Vec<BlogrollFeed> {
+	BLOGROLL.read().acquire().clone()
+}
+
+/// Writes the OPML file of the blogroll to `out_dir`.
+#[instrument(skip_all)]
+pub(crate) fn generate_opml(content: &Content, join_set: &mut JoinSet<Result<()>>) -> Result<()> {
+	let config = &content.config;
+	let Some(blogroll_config) = &config.blogroll else {
+		return Ok(());
+	};
+
+	let opml_path = config.out_dir.join(&blogroll_config.opml_path);
+	if let Some(parent) = opml_path.parent() {
+		map_err!(
+			fs::create_dir_all(parent),
+			IoError(format!("failed to create dirs for {}", parent.display())),
+		)?;
+	}
+
+	let mut opml = render_opml(blogroll_config, &blogroll()).into_bytes();
+	config
+		.hooks
+		.run_before_write_output(&opml_path, &mut opml)?;
+
+	unlink_output(&opml_path)?;
+	map_err!(
+		fs::write(&opml_path, opml),
+		IoError(format!("failed to write {}", opml_path.display())),
+	)?;
+
+	process_output(&opml_path, join_set, config.clone())
+}
+
+/// Renders an OPML 2.0 subscription list of `feeds`, with an outline of each category.
+#[inline]
+fn render_opml(blogroll_config: &BlogrollConfig, feeds: &[BlogrollFeed]) -> String {
+	let mut opml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+	opml.push_str(r#"<opml version="2.0"><head>"#);
+	let _ = write!(
+		opml,
+		"<title>{}</title></head><body>",
+		escape_xml(&blogroll_config.title)
+	);
+
+	let mut categories = BTreeMap::<&str, Vec<&BlogrollFeed>>::new();
+	for feed in feeds {
+		match &feed.category {
+			Some(category) => categories.entry(category).or_default().push(feed),
+			None => push_outline(&mut opml, feed),
+		}
+	}
+	for (category, feeds) in categories {
+		let category = escape_xml(category);
+		let _ = write!(opml, r#"<outline text="{category}" title="{category}">"#);
+		for feed in feeds {
+			push_outline(&mut opml, feed);
+		}
+		opml.push_str("</outline>");
+	}
+
+	opml.push_str("</body></opml>");
+	opml
+}
+
+#[inline]
+fn push_outline(opml: &mut String, feed: &BlogrollFeed) {
+	let title = escape_xml(feed.title.as_deref().unwrap_or(&feed.url));
+	let _ = write!(
+		opml,
+		r#"<outline type="rss" text="{title}" title="{title}" xmlUrl="{}""#,
+		escape_xml(&feed.url)
+	);
+	if let Some(site_url) = &feed.site_url {
+		let _ = write!(opml, r#" htmlUrl="{}""#, escape_xml(site_url));
+	}
+	if let Some(description) = &feed.description {
+		let _ = write!(opml, r#" description="{}""#, escape_xml(description));
+	}
+	opml.push_str("/>");
+}
+
+/// Fills in the titles and site URLs of feeds which don't set them, from the feeds themselves.
+/// Feeds are requested and cached like those of `fetch`, so redirects off `fetch.allowed_domains`
+/// aren't followed either. Feeds which can't be fetched are logged and left as they are, as they're
+/// on other sites.
+#[cfg(feature = "http-loader")]
+#[instrument(skip_all)]
+async fn fetch_titles(feeds: &mut [BlogrollFeed], config: &Config) {
+	let client = match http_cache::client(config) {
+		Ok(client) => client,
+		Err(error) => {
+			tracing::warn!(%error, "Unable to fetch blogroll feeds");
+			return;
+		}
+	};
+
+	for feed in feeds {
+		if feed.title.is_some() && feed.site_url.is_some() {
+			continue;
+		}
+
+		let url = match Url::parse(&feed.url) {
+			Ok(url) if is_allowed_url(&url, config) => url,
+			Ok(_) => {
+				tracing::warn!(
+					url = feed.url,
+					"Blogroll feed isn't on one of `fetch.allowed_domains`"
+				);
+				continue;
+			}
+			Err(error) => {
+				tracing::warn!(url = feed.url, %error, "Invalid blogroll feed URL");
+				continue;
+			}
+		};
+
+		let body = match client.get(url).build() {
+			Ok(request) => http_cache::execute(&client, request, config).await,
+			Err(error) => {
+				tracing::warn!(url = feed.url, %error, "Unable to request blogroll feed");
+				continue;
+			}
+		};
+		match body {
+			Ok(body) => {
+				let (title, site_url) = feed_title(&body);
+				feed.title = feed.title.take().or(title);
+				feed.site_url = feed.site_url.take().or(site_url);
+			}
+			Err(error) => {
+				tracing::warn!(url = feed.url, %error, "Unable to fetch blogroll feed");
+			}
+		}
+	}
+}
+
+#[cfg(not(feature = "http-loader"))]
+#[inline]
+async fn fetch_titles(_feeds: &mut [BlogrollFeed], _config: &Config) {
+	tracing::warn!(
+		"Blogroll titles aren't fetched, sluggy was built without the `http-loader` feature"
+	);
+}
+
+/// Title and site URL of an RSS or Atom feed, which are the first `title` and `link` of the
+/// document, before those of its items.
+#[cfg(feature = "http-loader")]
+#[inline]
+fn feed_title(feed: &str) -> (Option<String>, Option<String>) {
+	let title = TITLE_RE
+		.captures(feed)
+		.map(|captures| unescape_xml(captures[1].trim()))
+		.filter(|title| !title.is_empty());
+
+	// RSS has the URL as the text of `link`, and Atom as the `href` of a `link` which isn't to
+	// the feed itself.
+	let site_url = LINK_RE.captures_iter(feed).find_map(|captures| {
+		if let Some(link) = captures.get(2) {
+			return Some(unescape_xml(link.as_str()));
+		}
+
+		let attributes = captures.get(1).map_or("", |attributes| attributes.as_str());
+		match attribute(attributes, "rel").as_deref() {
+			None | Some("alternate") => attribute(attributes, "href"),
+			_ => None,
+		}
+	});
+
+	(title, site_url)
+}
+
+/// Value of the attribute `name` of a tag, from its `attributes`.
+#[cfg(feature = "http-loader")]
+#[inline]
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+	ATTRIBUTE_RE
+		.captures_iter(attributes)
+		.find(|captures| captures[1].eq_ignore_ascii_case(name))
+		.map(|captures| unescape_xml(&captures[2]))
+}
+
+/// Text of XML, without `CDATA` and with the predefined entities replaced.
+#[cfg(feature = "http-loader")]
+#[inline]
+fn unescape_xml(text: &str) -> String {
+	let text = text
+		.strip_prefix("<![CDATA[")
+		.and_then(|text| text.strip_suffix("]]>"))
+		.unwrap_or(text);
+
+	text.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&apos;", "'")
+		.replace("&#39;", "'")
+		.replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn feed(url: &str, title: Option<&str>, category: Option<&str>) -> BlogrollFeed {
+		BlogrollFeed {
+			url: url.into(),
+			title: title.map(str::to_string),
+			site_url: None,
+			description: None,
+			category: category.map(str::to_string),
+		}
+	}
+
+	fn blogroll_config() -> BlogrollConfig {
+		BlogrollConfig {
+			data: "blogroll.toml".into(),
+			opml_path: "blogroll.opml".into(),
+			title: "Friends & Co".into(),
+			fetch_titles: false,
+		}
+	}
+
+	#[test]
+	fn render_opml_groups_feeds_by_category() {
+		let feeds = vec![
+			feed("https://b.example/feed.xml", Some("B"), Some("Rust")),
+			feed("https://a.example/feed.xml", None, None),
+			feed("https://c.example/feed.xml", Some("C"), Some("Art")),
+		];
+		assert_eq!(
+			render_opml(&blogroll_config(), &feeds),
+			concat!(
+				r#"<?xml version="1.0" encoding="UTF-8"?><opml version="2.0"><head>"#,
+				"<title>Friends &amp; Co</title></head><body>",
+				r#"<outline type="rss" text="https://a.example/feed.xml" "#,
+				r#"title="https://a.example/feed.xml" xmlUrl="https://a.example/feed.xml"/>"#,
+				r#"<outline text="Art" title="Art">"#,
+				r#"<outline type="rss" text="C" title="C" xmlUrl="https://c.example/feed.xml"/>"#,
+				"</outline>",
+				r#"<outline text="Rust" title="Rust">"#,
+				r#"<outline type="rss" text="B" title="B" xmlUrl="https://b.example/feed.xml"/>"#,
+				"</outline></body></opml>",
+			)
+		);
+	}
+
+	#[test]
+	fn render_opml_escapes_attributes() {
+		let mut feed = feed("https://a.example/?a=1&b=2", Some("\"A\" <a>"), None);
+		feed.site_url = Some("https://a.example/".into());
+		feed.description = Some("Tom & Jerry".into());
+		let opml = render_opml(&blogroll_config(), &[feed]);
+		assert!(opml.contains(r#"text="&quot;A&quot; &lt;a&gt;""#));
+		assert!(opml.contains(r#"xmlUrl="https://a.example/?a=1&amp;b=2""#));
+		assert!(opml.contains(r#"htmlUrl="https://a.example/""#));
+		assert!(opml.contains(r#"description="Tom &amp; Jerry""#));
+	}
+
+	#[cfg(feature = "http-loader")]
+	#[test]
+	fn feed_title_of_rss() {
+		let rss = r#"<rss><channel><title>A &amp; B</title><link>https://a.example/</link>
+			<item><title>Post</title><link>https://a.example/post/</link></item></channel></rss>"#;
+		assert_eq!(
+			feed_title(rss),
+			(Some("A & B".into()), Some("https://a.example/".into()))
+		);
+	}
+
+	#[cfg(feature = "http-loader")]
+	#[test]
+	fn feed_title_of_atom() {
+		let atom = r#"<feed><title><![CDATA[A]]></title>
+			<link rel="self" href="https://a.example/atom.xml"/>
+			<link href="https://a.example/"/></feed>"#;
+		assert_eq!(
+			feed_title(atom),
+			(Some("A".into()), Some("https://a.example/".into()))
+		);
+	}
+}
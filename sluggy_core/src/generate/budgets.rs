@@ -0,0 +1,194 @@
+use super::{
+	compressed_path,
+	config::Config,
+	html::links::{base_path, find_pages, page_resources, page_url, resolve_file},
+};
+use crate::{
+	encoding::ContentEncoding,
+	err,
+	error::{Error, Result},
+	map_err,
+};
+use std::{
+	collections::BTreeSet,
+	fs,
+	path::{Path, PathBuf},
+};
+use tracing::instrument;
+
+/// A limit of `budgets` which the generated site is over.
+#[derive(Debug)]
+struct ExceededBudget {
+	budget: &'static str,
+	/// Page which is over the budget, relative to `out_dir`, unless it's a budget of the site.
+	page: Option<PathBuf>,
+	actual: u64,
+	limit: u64,
+}
+
+/// Checks the generated site against the configured budgets, logging each which is exceeded.
+/// Exceeding a budget fails the build in strict mode.
+#[instrument(skip(config))]
+pub(crate) fn check_budgets(config: &Config) -> Result<()> {
+	let budgets = &config.budgets;
+	if budgets.max_pages.is_none()
+		&& budgets.max_page_weight.is_none()
+		&& budgets.max_compressed_page_size.is_none()
+		&& budgets.max_total_size.is_none()
+	{
+		return Ok(());
+	}
+
+	let compressed_content_dir = config.out_dir.join(&config.compressed_content_dir);
+	let mut pages = vec![];
+	find_pages(&config.out_dir, &compressed_content_dir, &mut pages)?;
+	pages.sort();
+
+	let mut exceeded = vec![];
+
+	if let Some(max_pages) = budgets.max_pages {
+		if pages.len() > max_pages {
+			exceeded.push(ExceededBudget {
+				budget: "max_pages",
+				page: None,
+				actual: pages.len() as u64,
+				limit: max_pages as u64,
+			});
+		}
+	}
+
+	if let Some(max_total_size) = budgets.max_total_size {
+		let total_size = dir_size(&config.out_dir, &compressed_content_dir)?;
+		tracing::info!(total_size, "Size of generated site");
+		if total_size > max_total_size {
+			exceeded.push(ExceededBudget {
+				budget: "max_total_size",
+				page: None,
+				actual: total_size,
+				limit: max_total_size,
+			});
+		}
+	}
+
+	let base_path = base_path(&config.base_url);
+	for page in &pages {
+		let name = map_err!(
+			page.strip_prefix(&config.out_dir).map(Path::to_path_buf),
+			StripPathPrefix("failed to strip out dir prefix"),
+		)?;
+
+		if let Some(max_page_weight) = budgets.max_page_weight {
+			let weight = page_weight(page, &name, &base_path, config)?;
+			if weight > max_page_weight {
+				exceeded.push(ExceededBudget {
+					budget: "max_page_weight",
+					page: Some(name.clone()),
+					actual: weight,
+					limit: max_page_weight,
+				});
+			}
+		}
+
+		if let Some(max_compressed_page_size) = budgets.max_compressed_page_size {
+			let compressed_size = ContentEncoding::COMPRESSED
+				.iter()
+				.filter_map(|encoding| {
+					fs::metadata(compressed_path(&name, encoding, config))
+						.map(|metadata| metadata.len())
+						.ok()
+				})
+				.min();
+			// Pages which aren't compressed, e.g. when `compress_content` is disabled, are checked
+			// as they are.
+			let compressed_size = match compressed_size {
+				Some(compressed_size) => compressed_size,
+				None => file_size(page)?,
+			};
+			if compressed_size > max_compressed_page_size {
+				exceeded.push(ExceededBudget {
+					budget: "max_compressed_page_size",
+					page: Some(name),
+					actual: compressed_size,
+					limit: max_compressed_page_size,
+				});
+			}
+		}
+	}
+
+	for budget in &exceeded {
+		match &budget.page {
+			Some(page) => tracing::warn!(
+				budget = budget.budget,
+				page = %page.display(),
+				actual = budget.actual,
+				limit = budget.limit,
+				"Budget exceeded"
+			),
+			None => tracing::warn!(
+				budget = budget.budget,
+				actual = budget.actual,
+				limit = budget.limit,
+				"Budget exceeded"
+			),
+		}
+	}
+
+	if budgets.strict && !exceeded.is_empty() {
+		return Err(err!(BudgetsExceeded(exceeded.len())));
+	}
+
+	Ok(())
+}
+
+/// Size of `page`, and of each of the files of `out_dir` which it loads, counting each once.
+#[inline]
+fn page_weight(page: &Path, name: &Path, base_path: &str, config: &Config) -> Result<u64> {
+	let html = map_err!(
+		fs::read(page),
+		IoError(format!("failed to read {}", page.display())),
+	)?;
+	let page_url = page_url(name, base_path)?;
+
+	let resources = page_resources(&html, name)?
+		.iter()
+		.filter_map(|link| resolve_file(link, &page_url, base_path, config))
+		.filter(|path| path != page)
+		.collect::<BTreeSet<_>>();
+
+	let mut weight = html.len() as u64;
+	for resource in resources {
+		weight += file_size(&resource)?;
+	}
+
+	Ok(weight)
+}
+
+#[inline]
+fn dir_size(dir: &Path, compressed_content_dir: &Path) -> Result<u64> {
+	let mut size = 0;
+	for entry in map_err!(
+		fs::read_dir(dir),
+		IoError(format!("failed to read dir {}", dir.display())),
+	)? {
+		let path = map_err!(entry, IoError("dir entry failed"))?.path();
+
+		if path.is_dir() {
+			if path != compressed_content_dir {
+				size += dir_size(&path, compressed_content_dir)?;
+			}
+		} else {
+			size += file_size(&path)?;
+		}
+	}
+
+	Ok(size)
+}
+
+#[inline]
+fn file_size(path: &Path) -> Result<u64> {
+	Ok(map_err!(
+		fs::metadata(path),
+		IoError(format!("failed to read metadata of {}", path.display())),
+	)?
+	.len())
+}
@@ -0,0 +1,190 @@
+use super::config::Config;
+use crate::{
+	error::{Error, Result},
+	lazyfn::LazyFn,
+	map_err,
+	utils::hex_digest,
+};
+use dashmap::DashMap;
+use serde_json::json;
+use std::{
+	collections::BTreeMap,
+	fs,
+	path::{Path, PathBuf},
+};
+use tracing::instrument;
+
+/// Hashes of the outputs of the last build are kept in this file under `cache_dir`.
+const BUILD_CACHE_FILE: &str = "build.json";
+
+/// Output file -> hash of the content it was written with.
+static HASHES: LazyFn<DashMap<PathBuf, String>> = LazyFn::new(DashMap::new);
+
+/// Loads the hashes of the last build, unless they're already loaded by an earlier build of this
+/// process.
+#[instrument(skip(config))]
+pub(crate) fn load(config: &Config) -> Result<()> {
+	if !config.build_cache || !HASHES.is_empty() {
+		return Ok(());
+	}
+
+	let path = config.cache_dir.join(BUILD_CACHE_FILE);
+	let Ok(source) = fs::read_to_string(&path) else {
+		return Ok(());
+	};
+
+	match serde_json::from_str::<BTreeMap<PathBuf, String>>(&source) {
+		Ok(hashes) => {
+			for (out_file, hash) in hashes {
+				HASHES.insert(out_file, hash);
+			}
+		}
+		Err(error) => {
+			tracing::warn!(%error, path = %path.display(), "Ignoring unreadable build cache");
+		}
+	}
+
+	Ok(())
+}
+
+/// Writes the hashes of this build for the next one.
+#[instrument(skip(config))]
+pub(crate) fn save(config: &Config) -> Result<()> {
+	if !config.build_cache {
+		return Ok(());
+	}
+
+	let hashes = HASHES
+		.iter()
+		.map(|hash| (hash.key().clone(), hash.value().clone()))
+		.collect::<BTreeMap<_, _>>();
+	let json = map_err!(
+		serde_json::to_vec(&hashes),
+		SerdeJsonError("failed to serialize build cache"),
+	)?;
+
+	map_err!(
+		fs::create_dir_all(&config.cache_dir),
+		IoError(format!(
+			"failed to create dirs for {}",
+			config.cache_dir.display()
+		)),
+	)?;
+	let path = config.cache_dir.join(BUILD_CACHE_FILE);
+	map_err!(
+		fs::write(&path, json),
+		IoError(format!("failed to write build cache {}", path.display())),
+	)?;
+
+	Ok(())
+}
+
+/// Forgets the hashes of this build, so that a failed build doesn't leave outputs which were
+/// never processed looking up to date.
+#[inline]
+pub(crate) fn reset() {
+	HASHES.clear();
+}
+
+/// Records the hash of `content` for `out_file`. Returns `true` when it's the same as the last
+/// build and `out_file` still exists, in which case it doesn't need to be written, postprocessed
+/// or compressed again.
+///
+/// Settings which change how outputs are processed are part of the hash, so that changing them
+/// processes everything again.
+#[inline]
+pub(crate) fn is_unchanged(out_file: &Path, content: &[u8], config: &Config) -> bool {
+	if !config.build_cache {
+		return false;
+	}
+
+	let settings = json!({
+		"compress_content": config.compress_content,
+		"compressed_content_dir": config.compressed_content_dir,
+		"compression": config.compression,
+		"postprocess": config.postprocess,
+	});
+	let hash = hex_digest(format!("{}{settings}", hex_digest(content)));
+
+	let previous = HASHES.insert(out_file.to_path_buf(), hash.clone());
+	previous.as_deref() == Some(hash.as_str()) && out_file.exists()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config(dir: &Path) -> Config {
+		Config {
+			build_cache: true,
+			cache_dir: dir.join("cache"),
+			..Config::default()
+		}
+	}
+
+	#[test]
+	fn unchanged_outputs() {
+		let temp_dir = tempfile::tempdir().unwrap();
+		let dir = temp_dir.path();
+		let config = config(dir);
+		let out_file = dir.join("index.html");
+		fs::write(&out_file, "page").unwrap();
+
+		assert!(!is_unchanged(&out_file, b"page", &config));
+		assert!(is_unchanged(&out_file, b"page", &config));
+		assert!(!is_unchanged(&out_file, b"changed", &config));
+		assert!(is_unchanged(&out_file, b"changed", &config));
+
+		// Outputs which were removed are written again.
+		fs::remove_file(&out_file).unwrap();
+		assert!(!is_unchanged(&out_file, b"changed", &config));
+	}
+
+	#[test]
+	fn changed_settings() {
+		let temp_dir = tempfile::tempdir().unwrap();
+		let dir = temp_dir.path();
+		let config = config(dir);
+		let out_file = dir.join("index.html");
+		fs::write(&out_file, "page").unwrap();
+
+		assert!(!is_unchanged(&out_file, b"page", &config));
+		let config = Config {
+			compress_content: !config.compress_content,
+			..config
+		};
+		assert!(!is_unchanged(&out_file, b"page", &config));
+	}
+
+	#[test]
+	fn disabled() {
+		let temp_dir = tempfile::tempdir().unwrap();
+		let dir = temp_dir.path();
+		let config = Config {
+			build_cache: false,
+			..config(dir)
+		};
+		let out_file = dir.join("index.html");
+		fs::write(&out_file, "page").unwrap();
+
+		assert!(!is_unchanged(&out_file, b"page", &config));
+		assert!(!is_unchanged(&out_file, b"page", &config));
+
+		save(&config).unwrap();
+		assert!(!config.cache_dir.exists());
+	}
+
+	#[test]
+	fn saved_hashes() {
+		let temp_dir = tempfile::tempdir().unwrap();
+		let dir = temp_dir.path();
+		let config = config(dir);
+		let out_file = dir.join("index.html");
+		is_unchanged(&out_file, b"page", &config);
+
+		save(&config).unwrap();
+		let source = fs::read_to_string(config.cache_dir.join(BUILD_CACHE_FILE)).unwrap();
+		let hashes = serde_json::from_str::<BTreeMap<PathBuf, String>>(&source).unwrap();
+		assert_eq!(hashes.get(&out_file), HASHES.get(&out_file).as_deref());
+	}
+}
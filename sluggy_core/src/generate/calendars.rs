@@ -0,0 +1,307 @@
+use super::{
+	config::CalendarConfig,
+	content::{Content, Entry},
+	process_output,
+	sections::SectionHandle,
+};
+use crate::{
+	err,
+	error::{Error, Result},
+	map_err,
+	utils::{hex_digest, unlink_output},
+};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use std::{collections::HashSet, fs};
+use tokio::task::JoinSet;
+use toml::Value;
+use tracing::instrument;
+
+/// Content lines longer than this many octets are folded.
+const MAX_LINE_OCTETS: usize = 75;
+
+/// Writes each of the configured iCalendar files to `out_dir`.
+#[instrument(skip_all)]
+pub(crate) fn generate_calendars(
+	content: &Content,
+	join_set: &mut JoinSet<Result<()>>,
+) -> Result<()> {
+	let config = &content.config;
+
+	for calendar_config in &config.calendars {
+		let calendar_path = config.out_dir.join(&calendar_config.path);
+		if let Some(parent) = calendar_path.parent() {
+			map_err!(
+				fs::create_dir_all(parent),
+				IoError(format!("failed to create dirs for {}", parent.display())),
+			)?;
+		}
+
+		let entries = calendar_entries(content, calendar_config);
+		let mut calendar = render_calendar(calendar_config, &entries)?.into_bytes();
+		config
+			.hooks
+			.run_before_write_output(&calendar_path, &mut calendar)?;
+
+		unlink_output(&calendar_path)?;
+		map_err!(
+			fs::write(&calendar_path, calendar),
+			IoError(format!(
+				"failed to write calendar {}",
+				calendar_path.display()
+			)),
+		)?;
+
+		process_output(&calendar_path, join_set, config.clone())?;
+	}
+
+	Ok(())
+}
+
+/// Entries within any of the calendar's sections or taxonomy terms, ordered by path so that the
+/// output is stable between builds.
+#[inline]
+fn calendar_entries(content: &Content, calendar_config: &CalendarConfig) -> Vec<Entry> {
+	let section_handles = calendar_config
+		.sections
+		.iter()
+		.map(|section| SectionHandle::from(section.as_str()))
+		.collect::<Vec<_>>();
+
+	let mut term_paths = HashSet::new();
+	for (taxonomy, terms) in &calendar_config.taxonomies {
+		if let Some(taxonomy) = content.taxonomies.get(taxonomy) {
+			for term in terms {
+				if let Some(paths) = taxonomy.get(term) {
+					term_paths.extend(paths.iter().cloned());
+				}
+			}
+		}
+	}
+
+	let mut entries = content
+		.entries
+		.iter()
+		// The content of protected entries would be readable in the calendar.
+		.filter(|entry| !entry.protected)
+		.filter(|entry| {
+			term_paths.contains(entry.key())
+				|| entry
+					.section_handle
+					.as_ref()
+					.map(|section_handle| section_handles.contains(section_handle))
+					.unwrap_or(false)
+		})
+		.map(|entry| entry.value().clone())
+		.collect::<Vec<_>>();
+
+	entries.sort_by(|a, b| a.path.cmp(&b.path));
+	entries
+}
+
+/// Renders an RFC 5545 calendar with an event for each entry which has a start date.
+#[instrument(level = "debug", skip(entries))]
+fn render_calendar(calendar_config: &CalendarConfig, entries: &[Entry]) -> Result<String> {
+	let mut ics = String::new();
+	push_line(&mut ics, "BEGIN:VCALENDAR");
+	push_line(&mut ics, "VERSION:2.0");
+	push_line(&mut ics, "PRODID:-//sluggy//sluggy//EN");
+	push_line(&mut ics, "CALSCALE:GREGORIAN");
+	push_line(
+		&mut ics,
+		&format!("X-WR-CALNAME:{}", escape_text(&calendar_config.name)),
+	);
+
+	for entry in entries {
+		let Some(start) = entry.extra.get(&calendar_config.start_field) else {
+			continue;
+		};
+		let start = EventTime::try_from_value(start, entry, &calendar_config.start_field)?;
+		let end = entry
+			.extra
+			.get(&calendar_config.end_field)
+			.map(|end| EventTime::try_from_value(end, entry, &calendar_config.end_field))
+			.transpose()?;
+
+		// Stamped with the dates of entries rather than the time of the build, so that calendars
+		// only change along with their entries.
+		let stamp = entry
+			.updated
+			.or(entry.published)
+			.unwrap_or_else(|| start.to_utc());
+
+		push_line(&mut ics, "BEGIN:VEVENT");
+		push_line(&mut ics, &format!("UID:{}@sluggy", hex_digest(&entry.url)));
+		push_line(&mut ics, &format!("DTSTAMP:{}", format_utc(&stamp)));
+		push_line(&mut ics, &start.to_property("DTSTART"));
+		if let Some(end) = end {
+			push_line(&mut ics, &end.to_property("DTEND"));
+		}
+		push_line(
+			&mut ics,
+			&format!("SUMMARY:{}", escape_text(&entry.title())),
+		);
+		if let Some(description) = entry.extra.get("description").and_then(Value::as_str) {
+			push_line(
+				&mut ics,
+				&format!("DESCRIPTION:{}", escape_text(description)),
+			);
+		}
+		if let Some(location) = entry
+			.extra
+			.get(&calendar_config.location_field)
+			.and_then(Value::as_str)
+		{
+			push_line(&mut ics, &format!("LOCATION:{}", escape_text(location)));
+		}
+		push_line(&mut ics, &format!("URL:{}", entry.url));
+		push_line(&mut ics, "END:VEVENT");
+	}
+
+	push_line(&mut ics, "END:VCALENDAR");
+
+	Ok(ics)
+}
+
+/// Start or end of an event.
+#[derive(Debug)]
+enum EventTime {
+	/// A date-time with an offset, which is converted to UTC.
+	Utc(DateTime<Utc>),
+	/// A date-time without an offset, which is in the local time of whoever is viewing it.
+	Floating(NaiveDateTime),
+	/// An all-day event.
+	Date(NaiveDate),
+}
+
+impl EventTime {
+	/// Parses a TOML date-time, or a string in the same formats.
+	#[inline]
+	fn try_from_value(value: &Value, entry: &Entry, field: &str) -> Result<Self> {
+		let value = match value {
+			Value::Datetime(datetime) => datetime.to_string(),
+			Value::String(value) => value.clone(),
+			_ => {
+				return Err(err!(Validation(format!(
+					"`{field}` of {} must be a date or date-time",
+					entry.path.display()
+				))));
+			}
+		};
+
+		if let Ok(datetime) = DateTime::parse_from_rfc3339(&value) {
+			Ok(Self::Utc(datetime.with_timezone(&Utc)))
+		} else if let Ok(datetime) = NaiveDateTime::parse_from_str(&value, "%Y-%m-%dT%H:%M:%S%.f")
+			.or_else(|_| NaiveDateTime::parse_from_str(&value, "%Y-%m-%d %H:%M:%S%.f"))
+		{
+			Ok(Self::Floating(datetime))
+		} else if let Ok(date) = NaiveDate::parse_from_str(&value, "%Y-%m-%d") {
+			Ok(Self::Date(date))
+		} else {
+			Err(err!(Validation(format!(
+				"failed to parse `{field}` of {}: \"{value}\"",
+				entry.path.display()
+			))))
+		}
+	}
+
+	/// The time in UTC, where floating times and dates are taken to be in UTC.
+	#[inline]
+	fn to_utc(&self) -> DateTime<Utc> {
+		match self {
+			Self::Utc(datetime) => *datetime,
+			Self::Floating(datetime) => datetime.and_utc(),
+			Self::Date(date) => date.and_time(NaiveTime::MIN).and_utc(),
+		}
+	}
+
+	#[inline]
+	fn to_property(&self, name: &str) -> String {
+		match self {
+			Self::Utc(datetime) => format!("{name}:{}", format_utc(datetime)),
+			Self::Floating(datetime) => format!("{name}:{}", datetime.format("%Y%m%dT%H%M%S")),
+			Self::Date(date) => format!("{name};VALUE=DATE:{}", date.format("%Y%m%d")),
+		}
+	}
+}
+
+#[inline]
+fn format_utc(datetime: &DateTime<Utc>) -> String {
+	datetime.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escapes a TEXT property value.
+#[inline]
+fn escape_text(value: &str) -> String {
+	let mut escaped = String::with_capacity(value.len());
+	for c in value.chars() {
+		match c {
+			'\\' => escaped.push_str("\\\\"),
+			';' => escaped.push_str("\\;"),
+			',' => escaped.push_str("\\,"),
+			'\n' => escaped.push_str("\\n"),
+			'\r' => {}
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+/// Appends a content line, folding it into lines of at most 75 octets without splitting any
+/// characters.
+#[inline]
+fn push_line(ics: &mut String, line: &str) {
+	let mut line_octets = 0;
+	for c in line.chars() {
+		if line_octets + c.len_utf8() > MAX_LINE_OCTETS {
+			ics.push_str("\r\n ");
+			// The leading space of a continuation line counts towards its length.
+			line_octets = 1;
+		}
+		ics.push(c);
+		line_octets += c.len_utf8();
+	}
+	ics.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::generate::content::FileType;
+	use std::{collections::BTreeMap, path::PathBuf};
+
+	#[test]
+	fn undated_events_are_stamped_with_their_start() {
+		let calendar_config: CalendarConfig = toml::from_str(
+			r#"path = "events.ics"
+name = "Events""#,
+		)
+		.unwrap();
+		let mut extra = toml::Table::new();
+		extra.insert("start".into(), "2024-05-01".into());
+		let entry = Entry {
+			slug: None,
+			layout: None,
+			kind: "post".into(),
+			url: "/events/meetup/".into(),
+			external_url: None,
+			link: "/events/meetup/".into(),
+			path: PathBuf::from("events/meetup"),
+			file_path: PathBuf::from("events/meetup.md"),
+			file_type: FileType::Markdown,
+			published: None,
+			updated: None,
+			section_handle: None,
+			is_renderable: true,
+			draft: false,
+			headers: BTreeMap::new(),
+			aliases: vec![],
+			password: None,
+			protected: false,
+			extra,
+		};
+
+		let calendar = render_calendar(&calendar_config, &[entry]).unwrap();
+		assert!(calendar.contains("DTSTAMP:20240501T000000Z\r\n"));
+		assert!(calendar.contains("DTSTART;VALUE=DATE:20240501\r\n"));
+	}
+}
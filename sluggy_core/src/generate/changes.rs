@@ -0,0 +1,313 @@
+use super::config::{Config, ReportChanges};
+use crate::lazyfn::LazyFn;
+use dashmap::{DashMap, DashSet};
+use serde_derive::Serialize;
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+use tracing::instrument;
+
+/// Texts with more words than this which differ aren't diffed, only counted.
+const MAX_DIFF_WORDS: usize = 2000;
+
+/// Hunks of the summary of each changed output.
+const MAX_SUMMARY_HUNKS: usize = 5;
+
+/// Words of each side of a hunk of the summary.
+const MAX_HUNK_WORDS: usize = 12;
+
+/// Output file -> how it changed, since the changes were last taken.
+static CHANGES: LazyFn<DashMap<PathBuf, OutputChange>> = LazyFn::new(DashMap::new);
+
+/// Output files which were written, since they were last taken. Unlike `CHANGES`, these include
+/// assets, and are recorded whether or not changes are reported.
+static WRITTEN: LazyFn<DashSet<PathBuf>> = LazyFn::new(DashSet::new);
+
+/// An output whose content is different to what was in `out_dir` before it was rendered.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputChange {
+	/// Path of the output within `out_dir`.
+	pub path: PathBuf,
+	/// Whether there was no output before.
+	pub created: bool,
+	/// Number of words of text which were added, not counting markup.
+	pub added: usize,
+	/// Number of words of text which were removed, not counting markup.
+	pub removed: usize,
+	/// Some of the changed text, e.g. `-old words +new words`, when diffs are reported.
+	pub summary: Vec<String>,
+}
+
+/// Compares `content` with what's in `out_file`, and records how it changed, when changes are
+/// reported. This is expected to be called before `content` is written.
+#[instrument(level = "trace", skip(content, config))]
+#[inline]
+pub(crate) fn record(out_file: &Path, content: &[u8], config: &Config) {
+	if config.report_changes == ReportChanges::Off {
+		return;
+	}
+
+	let previous = fs::read(out_file).ok();
+	if previous.as_deref() == Some(content) {
+		return;
+	}
+
+	let previous_text = previous
+		.as_deref()
+		.map(String::from_utf8_lossy)
+		.unwrap_or_default();
+	let text = String::from_utf8_lossy(content);
+	let (added, removed, summary) = diff_words(&text_words(&previous_text), &text_words(&text));
+
+	let path = out_file
+		.strip_prefix(&config.out_dir)
+		.unwrap_or(out_file)
+		.to_path_buf();
+	CHANGES.insert(
+		out_file.to_path_buf(),
+		OutputChange {
+			path,
+			created: previous.is_none(),
+			added,
+			removed,
+			summary: if config.report_changes == ReportChanges::Diff {
+				summary
+			} else {
+				vec![]
+			},
+		},
+	);
+}
+
+/// Forgets the recorded changes, at the start of a build.
+#[inline]
+pub(crate) fn clear() {
+	CHANGES.clear();
+}
+
+/// Records that `out_file` was written, and so may be cached with its old content.
+#[inline]
+pub(crate) fn record_written(out_file: &Path) {
+	WRITTEN.insert(out_file.to_path_buf());
+}
+
+/// The output files which were written since they were last taken, sorted.
+#[inline]
+pub(crate) fn take_written() -> Vec<PathBuf> {
+	let mut written = WRITTEN.iter().map(|path| path.clone()).collect::<Vec<_>>();
+	WRITTEN.clear();
+	written.sort();
+	written
+}
+
+/// Moves the written files within `from` to `to`, once a build into a copy of `out_dir` has been
+/// swapped into place.
+#[inline]
+pub(crate) fn rebase_written(from: &Path, to: &Path) {
+	for path in take_written() {
+		let path = path
+			.strip_prefix(from)
+			.map_or_else(|_| path.clone(), |path| to.join(path));
+		WRITTEN.insert(path);
+	}
+}
+
+/// The outputs which changed since the changes were last taken, sorted by path.
+#[inline]
+pub(crate) fn take() -> Vec<OutputChange> {
+	let mut changes = CHANGES
+		.iter()
+		.map(|change| change.value().clone())
+		.collect::<Vec<_>>();
+	CHANGES.clear();
+	changes.sort_by(|a, b| a.path.cmp(&b.path));
+	changes
+}
+
+/// The words of the text of `content`, leaving out tags, and the content of scripts and styles.
+#[inline]
+fn text_words(content: &str) -> Vec<&str> {
+	// Tags are matched in lowercase, which leaves the offsets of the content as they are.
+	let lowercase = content.to_ascii_lowercase();
+	let mut words = vec![];
+	let mut offset = 0;
+	while let Some(start) = content[offset..].find('<') {
+		words.extend(content[offset..offset + start].split_whitespace());
+		offset += start;
+
+		let rest = &lowercase[offset..];
+		let end_tag = if rest.starts_with("<script") {
+			"</script>"
+		} else if rest.starts_with("<style") {
+			"</style>"
+		} else {
+			">"
+		};
+		offset = match rest.find(end_tag) {
+			Some(end) => offset + end + end_tag.len(),
+			None => content.len(),
+		};
+	}
+	words.extend(content[offset..].split_whitespace());
+	words
+}
+
+/// Counts of the added and removed words, and hunks of the changes.
+#[inline]
+fn diff_words(previous: &[&str], current: &[&str]) -> (usize, usize, Vec<String>) {
+	// Only the middle of the texts, after the words they start and end with, is diffed.
+	let prefix = previous
+		.iter()
+		.zip(current)
+		.take_while(|(a, b)| a == b)
+		.count();
+	let suffix = previous[prefix..]
+		.iter()
+		.rev()
+		.zip(current[prefix..].iter().rev())
+		.take_while(|(a, b)| a == b)
+		.count();
+	let previous = &previous[prefix..previous.len() - suffix];
+	let current = &current[prefix..current.len() - suffix];
+
+	if previous.len() + current.len() > MAX_DIFF_WORDS {
+		let hunk = hunk(previous, current);
+		return (current.len(), previous.len(), vec![hunk]);
+	}
+
+	// Longest common subsequence of the words, from the end so that it can be walked forwards.
+	let width = current.len() + 1;
+	let mut lengths = vec![0usize; (previous.len() + 1) * width];
+	for i in (0..previous.len()).rev() {
+		for j in (0..current.len()).rev() {
+			lengths[i * width + j] = if previous[i] == current[j] {
+				lengths[(i + 1) * width + j + 1] + 1
+			} else {
+				lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+			};
+		}
+	}
+
+	let (mut added, mut removed) = (0, 0);
+	let mut hunks = vec![];
+	let (mut hunk_removed, mut hunk_added) = (vec![], vec![]);
+	let (mut i, mut j) = (0, 0);
+	while i < previous.len() || j < current.len() {
+		if i < previous.len() && j < current.len() && previous[i] == current[j] {
+			if !hunk_removed.is_empty() || !hunk_added.is_empty() {
+				hunks.push(hunk(&hunk_removed, &hunk_added));
+				hunk_removed.clear();
+				hunk_added.clear();
+			}
+			i += 1;
+			j += 1;
+		} else if j < current.len()
+			&& (i == previous.len() || lengths[i * width + j + 1] >= lengths[(i + 1) * width + j])
+		{
+			hunk_added.push(current[j]);
+			added += 1;
+			j += 1;
+		} else {
+			hunk_removed.push(previous[i]);
+			removed += 1;
+			i += 1;
+		}
+	}
+	if !hunk_removed.is_empty() || !hunk_added.is_empty() {
+		hunks.push(hunk(&hunk_removed, &hunk_added));
+	}
+
+	hunks.truncate(MAX_SUMMARY_HUNKS);
+	(added, removed, hunks)
+}
+
+#[inline]
+fn hunk(removed: &[&str], added: &[&str]) -> String {
+	let side = |sign: char, words: &[&str]| {
+		if words.is_empty() {
+			return None;
+		}
+		let mut side = format!(
+			"{sign}{}",
+			words[..words.len().min(MAX_HUNK_WORDS)].join(" ")
+		);
+		if words.len() > MAX_HUNK_WORDS {
+			side.push_str(" \u{2026}");
+		}
+		Some(side)
+	};
+
+	[side('-', removed), side('+', added)]
+		.into_iter()
+		.flatten()
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn words(text: &str) -> Vec<&str> {
+		text.split_whitespace().collect()
+	}
+
+	#[test]
+	fn words_of_html() {
+		assert_eq!(
+			text_words(
+				"<p>Some <em>text</em></p><script>let a = 1;</script><STYLE>p {}</STYLE> end"
+			),
+			vec!["Some", "text", "end"]
+		);
+	}
+
+	#[test]
+	fn unchanged_words() {
+		assert_eq!(
+			diff_words(&words("the same"), &words("the same")),
+			(0, 0, vec![])
+		);
+	}
+
+	#[test]
+	fn changed_words() {
+		let (added, removed, hunks) = diff_words(
+			&words("the quick brown fox jumps over the dog"),
+			&words("the slow brown fox jumps over the lazy dog"),
+		);
+		assert_eq!((added, removed), (2, 1));
+		assert_eq!(hunks, vec!["-quick +slow", "+lazy"]);
+	}
+
+	#[test]
+	fn added_and_removed_words() {
+		assert_eq!(
+			diff_words(&[], &words("new page")),
+			(2, 0, vec!["+new page".to_string()])
+		);
+		assert_eq!(
+			diff_words(&words("old page"), &[]),
+			(0, 2, vec!["-old page".to_string()])
+		);
+	}
+
+	#[test]
+	fn long_hunks_are_shortened() {
+		let current = (0..20).map(|n| n.to_string()).collect::<Vec<_>>();
+		let current = current.iter().map(String::as_str).collect::<Vec<_>>();
+		let (added, _, hunks) = diff_words(&[], &current);
+		assert_eq!(added, 20);
+		assert_eq!(hunks, vec!["+0 1 2 3 4 5 6 7 8 9 10 11 \u{2026}"]);
+	}
+
+	#[test]
+	fn hunks_are_limited() {
+		let previous = words("a 1 b 2 c 3 d 4 e 5 f 6 g");
+		let current = words("a x b x c x d x e x f x g");
+		let (added, removed, hunks) = diff_words(&previous, &current);
+		assert_eq!((added, removed), (6, 6));
+		assert_eq!(hunks.len(), MAX_SUMMARY_HUNKS);
+	}
+}
@@ -1,23 +1,1021 @@
-use std::path::PathBuf;
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	time::Duration,
+};
 
-use serde_derive::Serialize;
+use super::hooks::Hooks;
+pub use super::sections::SortBy;
+use crate::{
+	common::http::DEFAULT_REDIRECT_STATUS,
+	error::{Error, Result},
+	map_err,
+	utils::{cached_regex, is_compressed_content},
+};
+use serde_derive::{Deserialize, Serialize};
 use toml::Value;
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct Config {
 	pub content_dir: PathBuf,
+	pub content_files: ContentFilesConfig,
 	pub compress_content: bool,
 	pub compressed_content_dir: PathBuf,
+	pub compression: CompressionConfig,
+	pub postprocess: PostprocessConfig,
 	pub css_dir: PathBuf,
+	/// Append inline source maps to bundled stylesheets, which point back to the files of
+	/// `css_dir`.
+	pub css_source_maps: bool,
+	/// Scripts which are bundled into `out_dir`, like the stylesheets of `css_dir`.
+	pub js_dir: PathBuf,
+	pub js: JsConfig,
+	/// Add hashes of their content to the names of stylesheets, scripts and assets, so that they
+	/// can be cached indefinitely.
+	pub fingerprint: FingerprintConfig,
 	pub template_dir: PathBuf,
 	pub assets_dir: PathBuf,
 	pub data_dir: PathBuf,
+	/// Downloaded and intermediate files which are kept between builds.
+	pub cache_dir: PathBuf,
+	/// How long a build waits for another process to finish writing to `out_dir`, before failing.
+	pub lock_timeout: Duration,
+	/// Template test cases which are run by `sluggy test`.
+	pub tests_dir: PathBuf,
+	/// Extra `.sublime-syntax` definitions, and binary `.packdump` packs of syntaxes, for
+	/// highlighting code, which are merged with the default syntaxes.
+	pub syntaxes_dir: PathBuf,
 	pub out_dir: PathBuf,
 	pub processed_images_dir: PathBuf,
+	pub images: ImagesConfig,
+	pub media: MediaConfig,
+	pub diagrams: DiagramsConfig,
+	/// Extensions and options of markdown rendering.
+	pub markdown: MarkdownConfig,
+	/// Allowlist of the `sanitize` filter.
+	pub sanitize: SanitizeConfig,
+	pub fetch: FetchConfig,
 	/// Always has a trailing slash
 	pub base_url: String,
 	pub minify: bool,
-	pub taxonomies: Vec<String>,
+	/// Log entries which fail to render and carry on generating the rest of the site, instead of
+	/// aborting on the first failure.
+	pub keep_going: bool,
+	/// Inject a script into HTML pages which reloads them after each build. Only set while serving.
+	pub live_reload: bool,
+	/// Whether the site is being served, rather than built. Only set while serving.
+	pub serve_mode: bool,
+	/// Environment variables which templates can read with `env`.
+	pub template_env: Vec<String>,
+	/// Module script which is loaded in the head of HTML pages, the HMR client of the asset dev
+	/// server. Only set while serving.
+	pub dev_client_script: Option<String>,
+	/// Generate entries which are drafts or are published in the future.
+	pub drafts: bool,
+	/// Check that the internal links of generated pages resolve to generated files, logging a
+	/// warning for each that doesn't.
+	pub check_links: bool,
+	/// Fail the build when links are broken, instead of only logging them.
+	pub deny_broken_links: bool,
+	/// Limits of the size of the generated site, which are checked after each build.
+	pub budgets: BudgetsConfig,
+	/// Write a `sitemap.xml` of the pages of each tree.
+	pub sitemap: bool,
+	/// Skip writing, postprocessing and compressing outputs which are the same as the last build,
+	/// by hashes kept in `cache_dir`.
+	pub build_cache: bool,
+	/// File which a manifest of every file the build emitted is written to, with the file each is
+	/// generated from, its hash, and its size before and after compression.
+	pub output_manifest: Option<PathBuf>,
+	/// Generate into a copy of `out_dir`, which replaces it once the build succeeds, so that a
+	/// failed build never leaves it half-written. Rebuilds of only the CSS or assets while
+	/// watching are atomic as well.
+	pub atomic_builds: bool,
+	/// What happens to files of `out_dir` which the build didn't emit.
+	pub stale_outputs: StaleOutputs,
+	/// Patterns of files within `out_dir` which are never stale, such as those written by
+	/// postprocess commands or hooks, e.g. `pagefind/**`.
+	pub keep_outputs: Vec<String>,
+	/// Record which rendered pages changed content, to be reported after each rebuild. Only set
+	/// while watching.
+	pub report_changes: ReportChanges,
+	/// Text transformations of the content of markdown and notebook entries.
+	pub transforms: Vec<TransformConfig>,
+	/// Redirects which are written to the `_redirects` file, as well as the `aliases` of entries.
+	pub redirects: Vec<RedirectConfig>,
+	/// Also write a page which redirects with a meta refresh at the path of each redirect, for
+	/// hosts which don't read `_redirects`. Pages of redirects which are gone are removed.
+	pub redirect_pages: bool,
+	/// Callbacks of library users, which are run while generating.
+	#[serde(skip)]
+	pub hooks: Hooks,
+	/// Entries whose render context is written to `cache_dir`, for debugging templates.
+	pub dump_context: Option<DumpContext>,
+	/// Slug pattern of entries in sections which don't set their own `slug_pattern`.
+	pub slug_pattern: Option<String>,
+	/// Extract the slug from date prefixed file names, like `2024-01-02-foo.md`, when no slug
+	/// pattern is configured.
+	pub date_slug: bool,
+	/// Order of the entries of sections which don't set their own `sort_by`.
+	pub section_sort_by: SortBy,
+	/// What happens when entries from two files would be written to the same page.
+	pub url_conflicts: UrlConflicts,
+	/// Taxonomies keyed on the frontmatter field which holds their terms, e.g. `tags`.
+	pub taxonomies: BTreeMap<String, TaxonomyConfig>,
+	/// Pages of the entries published in each year and month, e.g. `/2024/` and `/2024/03/`.
+	pub archives: Option<ArchivesConfig>,
+	/// Kinds of entries, keyed on their name, which set the defaults of the entries of that
+	/// `kind`. `post` and `page` don't have to be configured.
+	pub kinds: BTreeMap<String, KindConfig>,
+	pub feeds: Vec<FeedConfig>,
+	pub calendars: Vec<CalendarConfig>,
+	/// Feeds of other sites which are listed in an OPML file and by the `blogroll` function.
+	pub blogroll: Option<BlogrollConfig>,
+	pub structured_data: Vec<StructuredDataConfig>,
+	/// Versions of the content which are each generated into their own tree, e.g. `/v1/`.
+	pub versions: Vec<Version>,
+	/// Name of the version being generated, if this is a versioned tree.
+	pub current_version: Option<String>,
+	/// Base URL which canonical links of versioned trees point to.
+	pub canonical_base_url: Option<String>,
 	#[serde(flatten)]
 	pub extra: Option<Value>,
 }
+
+/// How much is recorded about the pages which change content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportChanges {
+	#[default]
+	Off,
+	/// The paths of the pages, with counts of the words which were added and removed.
+	Files,
+	/// As well as some of the text which changed.
+	Diff,
+}
+
+/// What happens to files of `out_dir` which no longer correspond to anything, such as the pages of
+/// renamed entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StaleOutputs {
+	/// They're left as they are.
+	#[default]
+	Keep,
+	/// They're logged after each build.
+	Report,
+	/// They're deleted after each build, along with their compressed variants.
+	Remove,
+}
+
+/// What happens when entries from two files, like `about.md` and `about/index.md`, would be
+/// written to the same page.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UrlConflicts {
+	/// Fail before anything is written.
+	#[default]
+	Error,
+	/// Keep the entry whose file path sorts first, and warn about the other.
+	Warn,
+}
+
+/// Entries whose render context is dumped.
+#[derive(Debug, Clone, Serialize)]
+pub enum DumpContext {
+	All,
+	/// Path of the entry relative to the content dir, e.g. `blog/hello`.
+	Entry(PathBuf),
+}
+
+/// Directory of the tree which the latest version is also generated into.
+pub const LATEST_VERSION_DIR: &str = "latest";
+
+impl Config {
+	/// Config for generating `version` into the `dir_name` tree under `out_dir`.
+	pub fn for_version(&self, version: &Version, dir_name: &str) -> Self {
+		let latest_base_url = self
+			.versions
+			.iter()
+			.any(|version| version.is_latest)
+			.then(|| format!("{}{LATEST_VERSION_DIR}/", self.base_url));
+
+		Self {
+			content_dir: version.content_dir.clone(),
+			out_dir: self.out_dir.join(dir_name),
+			// Compressed variants are looked up relative to the root of `out_dir`.
+			compressed_content_dir: PathBuf::from("..")
+				.join(&self.compressed_content_dir)
+				.join(dir_name),
+			base_url: format!("{}{dir_name}/", self.base_url),
+			current_version: Some(version.name.clone()),
+			canonical_base_url: latest_base_url,
+			..self.clone()
+		}
+	}
+
+	/// The kind called `name`, with the defaults of `post` and `page` filled in, if it's one of
+	/// them or is configured.
+	pub fn kind(&self, name: &str) -> Option<Kind> {
+		let kind_config = self.kinds.get(name);
+		let (dated, listed, feed) = match name {
+			POST_KIND => (true, true, true),
+			PAGE_KIND => (false, false, false),
+			_ if kind_config.is_some() => (true, true, false),
+			_ => return None,
+		};
+		let kind_config = kind_config.cloned().unwrap_or_default();
+
+		Some(Kind {
+			name: name.to_string(),
+			layout: kind_config.layout,
+			slug_pattern: kind_config.slug_pattern,
+			dated: kind_config.dated.unwrap_or(dated),
+			listed: kind_config.listed.unwrap_or(listed),
+			feed: kind_config.feed.unwrap_or(feed),
+		})
+	}
+
+	/// Whether `path` is within the current working directory or one of the configured project
+	/// directories.
+	pub fn is_project_path(&self, path: &Path) -> bool {
+		let is_in_current_dir = std::env::current_dir()
+			.and_then(|current_dir| current_dir.canonicalize())
+			.map(|current_dir| path.starts_with(current_dir))
+			.unwrap_or(false);
+
+		is_in_current_dir
+			|| [
+				&self.content_dir,
+				&self.assets_dir,
+				&self.data_dir,
+				&self.template_dir,
+				&self.css_dir,
+				&self.js_dir,
+			]
+			.iter()
+			.any(|dir| path.starts_with(dir))
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImagesConfig {
+	/// Rotate and flip processed images according to their EXIF orientation.
+	pub auto_orient: bool,
+	/// Include GPS coordinates in the metadata exposed to templates.
+	///
+	/// Processed images never carry over the metadata of their source image.
+	pub expose_gps: bool,
+	/// Variants which every image passed to `gallery()` is processed into, keyed on name, e.g.
+	/// `thumbnail` and `full`.
+	pub gallery: BTreeMap<String, ImageVariant>,
+	/// Named arguments for `image(preset = "...")`, e.g. `thumbnail`.
+	pub presets: BTreeMap<String, ImagePreset>,
+	pub rewrite: ImageRewriteConfig,
+}
+
+/// Processing of the `<img>` tags of rendered pages, so that images are optimized without calling
+/// `image()`.
+///
+/// Images with a `process` attribute, e.g. `<img src="photo.jpg" process="content">`, are
+/// processed with the preset it names, or with `preset` when it's empty. Sources are resolved
+/// relative to the entry, or to `assets_dir` when they start with `@/`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageRewriteConfig {
+	/// Process every local image with `preset`, not only those with a `process` attribute.
+	pub all: bool,
+	pub preset: Option<String>,
+	/// Presets which each image is processed into for its `srcset`.
+	pub srcset: Vec<String>,
+}
+
+impl Default for ImagesConfig {
+	fn default() -> Self {
+		Self {
+			auto_orient: true,
+			expose_gps: false,
+			gallery: BTreeMap::new(),
+			presets: BTreeMap::new(),
+			rewrite: ImageRewriteConfig::default(),
+		}
+	}
+}
+
+/// Arguments for processing an image, in the same shape as the `format` and `operations` arguments
+/// of `image()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageVariant {
+	pub format: Value,
+	pub operations: Value,
+}
+
+/// Operations, and optionally a format, which `image()` applies when given the preset's name.
+///
+/// Operations passed to `image()` alongside the preset are applied after the preset's, and a
+/// `format` passed to it takes precedence.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImagePreset {
+	pub format: Option<Value>,
+	#[serde(default = "ImagePreset::default_operations")]
+	pub operations: Value,
+}
+
+impl ImagePreset {
+	fn default_operations() -> Value {
+		Value::Array(vec![])
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TaxonomyConfig {
+	/// Generate a page listing the terms at `/{taxonomy}/`, and a page of each term's entries at
+	/// `/{taxonomy}/{term}/`.
+	pub render: bool,
+	/// Defaults to `{taxonomy}/list.html`.
+	pub list_template: Option<String>,
+	/// Defaults to `{taxonomy}/single.html`.
+	pub term_template: Option<String>,
+	/// Number of entries on each page of a term. Term pages aren't paginated when unset.
+	pub paginate_by: Option<usize>,
+	/// Path segment of term pages after the first, e.g. `/tags/rust/page/2/`. Defaults to `page`.
+	pub paginate_path: Option<String>,
+	/// Write a feed of each term's entries to `/{taxonomy}/{term}/feed.xml`.
+	pub feed: bool,
+	/// Maximum number of entries of each term's feed, newest first.
+	pub feed_limit: Option<usize>,
+}
+
+impl Default for TaxonomyConfig {
+	fn default() -> Self {
+		Self {
+			render: true,
+			list_template: None,
+			term_template: None,
+			paginate_by: None,
+			paginate_path: None,
+			feed: false,
+			feed_limit: None,
+		}
+	}
+}
+
+impl TaxonomyConfig {
+	/// Config of a taxonomy whose terms are collected, but which has no pages or feeds.
+	pub fn collect_only() -> Self {
+		Self {
+			render: false,
+			..Self::default()
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchivesConfig {
+	/// Handles of sections whose entries are archived. Every listed, dated entry is when empty.
+	pub sections: Vec<String>,
+	/// Generate a page of the entries of each year.
+	pub years: bool,
+	/// Generate a page of the entries of each month.
+	pub months: bool,
+	/// Path of year pages, which must contain `{year}`. Defaults to `{year}`.
+	pub year_path: String,
+	/// Path of month pages, which must contain `{year}` and the 2-digit `{month}`. Defaults to
+	/// `{year}/{month}`.
+	pub month_path: String,
+	/// Defaults to `archives/year.html`.
+	pub year_template: String,
+	/// Defaults to `archives/month.html`.
+	pub month_template: String,
+	/// Number of entries on each page of an archive. Archives aren't paginated when unset.
+	pub paginate_by: Option<usize>,
+	/// Path segment of archive pages after the first, e.g. `/2024/page/2/`. Defaults to `page`.
+	pub paginate_path: Option<String>,
+}
+
+impl Default for ArchivesConfig {
+	fn default() -> Self {
+		Self {
+			sections: vec![],
+			years: true,
+			months: true,
+			year_path: "{year}".into(),
+			month_path: "{year}/{month}".into(),
+			year_template: "archives/year.html".into(),
+			month_template: "archives/month.html".into(),
+			paginate_by: None,
+			paginate_path: None,
+		}
+	}
+}
+
+/// Entries of sections, and dated entries outside of them, are posts unless they set another kind.
+pub const POST_KIND: &str = "post";
+
+/// Other entries are pages.
+pub const PAGE_KIND: &str = "page";
+
+/// Defaults of the entries of a kind. Whichever are unset are taken from `post` and `page` for
+/// those kinds, and otherwise entries of the kind are dated and listed but aren't in feeds.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KindConfig {
+	/// Template of entries of the kind which don't set their own `layout`.
+	pub layout: Option<String>,
+	/// Slug pattern of entries of the kind in sections which don't set their own `slug_pattern`.
+	pub slug_pattern: Option<String>,
+	/// Read the published date of entries from their file names, e.g. `2024-01-02-foo.md`.
+	pub dated: Option<bool>,
+	/// Keep entries in the lists of their sections' entries.
+	pub listed: Option<bool>,
+	/// Include dated entries in feeds.
+	pub feed: Option<bool>,
+}
+
+/// A kind, with its defaults resolved.
+#[derive(Debug, Clone, Serialize)]
+pub struct Kind {
+	pub name: String,
+	pub layout: Option<String>,
+	pub slug_pattern: Option<String>,
+	pub dated: bool,
+	pub listed: bool,
+	pub feed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedConfig {
+	/// Output path of the feed, relative to `out_dir`, e.g. `posts/feed.xml`.
+	pub path: PathBuf,
+	pub title: String,
+	pub description: Option<String>,
+	pub language: Option<String>,
+	/// Handle of the section whose entries are included. Entries from every section are included
+	/// when unset.
+	pub section: Option<String>,
+	/// Maximum number of entries, newest first.
+	pub limit: Option<usize>,
+	pub podcast: Option<PodcastConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PodcastConfig {
+	/// Frontmatter field with the path of an episode's audio file, resolved relative to the entry.
+	/// Entries without it are left out of the feed.
+	#[serde(default = "PodcastConfig::default_audio_field")]
+	pub audio_field: String,
+	/// Frontmatter field with an episode's chapters, e.g.
+	/// `chapters = [{ start = 0, title = "Intro" }]`. Chapters are written to JSON files next to
+	/// the feed.
+	pub chapters_field: Option<String>,
+	pub author: Option<String>,
+	pub owner_name: Option<String>,
+	pub owner_email: Option<String>,
+	/// URL of the podcast's cover art.
+	pub image: Option<String>,
+	#[serde(default)]
+	pub categories: Vec<String>,
+	#[serde(default)]
+	pub explicit: bool,
+	/// Either `episodic` or `serial`.
+	#[serde(rename = "type")]
+	pub show_type: Option<String>,
+}
+
+impl PodcastConfig {
+	fn default_audio_field() -> String {
+		"audio".into()
+	}
+}
+
+/// A version of the content, exposed to templates as `site.versions` for version switchers.
+#[derive(Debug, Clone, Serialize)]
+pub struct Version {
+	/// Directory name of the version's tree, e.g. `v1`.
+	pub name: String,
+	pub label: String,
+	pub url: String,
+	pub is_latest: bool,
+	#[serde(skip)]
+	pub content_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StructuredDataConfig {
+	#[serde(rename = "type")]
+	pub schema_type: SchemaType,
+	/// Handles of sections whose entries are given this schema, unless their `structured_data`
+	/// frontmatter field selects other types.
+	#[serde(default)]
+	pub sections: Vec<String>,
+	/// Frontmatter fields which schema properties are read from, overriding the defaults, e.g.
+	/// `{ author = "written_by" }`.
+	#[serde(default)]
+	pub fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SchemaType {
+	Article,
+	BreadcrumbList,
+	Event,
+	Product,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalendarConfig {
+	/// Output path of the calendar, relative to `out_dir`, e.g. `events.ics`.
+	pub path: PathBuf,
+	pub name: String,
+	/// Handles of sections whose entries are included.
+	#[serde(default)]
+	pub sections: Vec<String>,
+	/// Taxonomy terms whose entries are included, e.g. `{ tags = ["meetup"] }`.
+	#[serde(default)]
+	pub taxonomies: BTreeMap<String, Vec<String>>,
+	/// Frontmatter field with the start of an event. Entries without it are left out of the
+	/// calendar.
+	#[serde(default = "CalendarConfig::default_start_field")]
+	pub start_field: String,
+	#[serde(default = "CalendarConfig::default_end_field")]
+	pub end_field: String,
+	#[serde(default = "CalendarConfig::default_location_field")]
+	pub location_field: String,
+}
+
+impl CalendarConfig {
+	fn default_start_field() -> String {
+		"start".into()
+	}
+
+	fn default_end_field() -> String {
+		"end".into()
+	}
+
+	fn default_location_field() -> String {
+		"location".into()
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlogrollConfig {
+	/// Data file with the feeds, in any format `load_data` reads. Each feed has a `url`, and can
+	/// set its `title`, `site_url`, `description` and `category`.
+	pub data: String,
+	/// Output path of the OPML file, relative to `out_dir`.
+	#[serde(default = "BlogrollConfig::default_opml_path")]
+	pub opml_path: PathBuf,
+	/// Title of the OPML file.
+	#[serde(default = "BlogrollConfig::default_title")]
+	pub title: String,
+	/// Fetch the feeds which don't set a `title` or `site_url` for them while generating.
+	#[serde(default)]
+	pub fetch_titles: bool,
+}
+
+impl BlogrollConfig {
+	fn default_opml_path() -> PathBuf {
+		"blogroll.opml".into()
+	}
+
+	fn default_title() -> String {
+		"Blogroll".into()
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MarkdownConfig {
+	/// Curl quotes, and turn `--` and `...` into dashes and ellipses.
+	pub smart_punctuation: bool,
+	pub strikethrough: bool,
+	/// `- [ ]` and `- [x]` list items as checkboxes.
+	pub tasklists: bool,
+	/// Link URLs and email addresses which aren't in links.
+	pub autolink: bool,
+	pub footnotes: bool,
+	pub tables: bool,
+	/// Render raw HTML of the markdown, instead of replacing it with a comment.
+	pub unsafe_html: bool,
+	/// Prefix of the ids given to headings. Headings aren't given ids when it's not set.
+	pub header_id_prefix: Option<String>,
+	/// What happens to relative links to the files of other entries, like `./other-post.md`.
+	pub source_links: SourceLinks,
+}
+
+impl Default for MarkdownConfig {
+	fn default() -> Self {
+		Self {
+			smart_punctuation: false,
+			strikethrough: false,
+			tasklists: false,
+			autolink: false,
+			footnotes: true,
+			tables: true,
+			unsafe_html: true,
+			header_id_prefix: Some(String::new()),
+			source_links: SourceLinks::default(),
+		}
+	}
+}
+
+/// Relative links from markdown to the `.md` and `.ipynb` files of other entries are rewritten to
+/// the URLs of their pages, so that the content can be browsed in editors and on forges as well.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceLinks {
+	/// Leave the links as they are.
+	Off,
+	/// Warn about links to files which aren't entries, and leave them as they are.
+	#[default]
+	Warn,
+	/// Fail to render the entry when a link is to a file which isn't an entry.
+	Error,
+}
+
+/// What the `sanitize` filter keeps of HTML. Anything which is unset keeps ammonia's defaults, which
+/// leave out scripts, styles and event handlers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SanitizeConfig {
+	/// Tags which are kept, instead of the defaults.
+	pub tags: Option<Vec<String>>,
+	/// Attributes which are kept of each tag, as well as the defaults, e.g.
+	/// `{ img = ["loading"] }`.
+	pub tag_attributes: BTreeMap<String, Vec<String>>,
+	/// Attributes which are kept of any tag, as well as the defaults.
+	pub generic_attributes: Vec<String>,
+	/// Schemes of the URLs of links and images which are kept, instead of the defaults.
+	pub url_schemes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiagramsConfig {
+	/// Render diagrams at build time. When disabled, diagrams are left to be rendered client-side.
+	pub enabled: bool,
+	/// Commands which diagram sources are piped through, keyed on the language of the fenced code
+	/// block. Each command must write an SVG to stdout.
+	pub renderers: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for DiagramsConfig {
+	fn default() -> Self {
+		let command = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect();
+
+		Self {
+			enabled: false,
+			renderers: BTreeMap::from([
+				(
+					"mermaid".into(),
+					command(&[
+						"mmdc",
+						"--input",
+						"-",
+						"--output",
+						"-",
+						"--outputFormat",
+						"svg",
+					]),
+				),
+				("dot".into(), command(&["dot", "-Tsvg"])),
+			]),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JsConfig {
+	/// Command which bundles a script. The path of the script is appended to it, and it must write
+	/// the bundle to stdout.
+	pub bundler: Vec<String>,
+	/// Arguments which are appended to the command when `minify` is enabled.
+	pub minify_args: Vec<String>,
+}
+
+/// Limits of the generated site. Sizes are in bytes, and limits which aren't set aren't checked.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BudgetsConfig {
+	/// Most HTML pages which are generated.
+	pub max_pages: Option<usize>,
+	/// Largest size of an HTML page, along with the stylesheets, scripts, images and other files
+	/// of `out_dir` it loads.
+	pub max_page_weight: Option<u64>,
+	/// Largest compressed size of an HTML page, using the smallest of its compressed variants.
+	pub max_compressed_page_size: Option<u64>,
+	/// Largest total size of `out_dir`, not counting compressed variants.
+	pub max_total_size: Option<u64>,
+	/// Fail the build when a budget is exceeded, instead of only logging it.
+	pub strict: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FingerprintConfig {
+	pub enabled: bool,
+	/// Globs of the files of `assets_dir` which are fingerprinted, relative to `out_dir`, e.g.
+	/// `assets/fonts/**`. Bundled stylesheets and scripts are always fingerprinted. The `url()`s of
+	/// stylesheets, and the `@/` references of pages, point at the fingerprinted names.
+	pub assets: Vec<String>,
+}
+
+impl Default for JsConfig {
+	fn default() -> Self {
+		Self {
+			bundler: vec!["esbuild".into(), "--bundle".into(), "--format=esm".into()],
+			minify_args: vec!["--minify".into()],
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaConfig {
+	/// Path to an `ffmpeg` binary, which is required to generate poster images for videos.
+	pub ffmpeg: Option<PathBuf>,
+	/// Offset, in seconds, of the frame used as a video's poster image.
+	pub poster_offset: f64,
+}
+
+impl Default for MediaConfig {
+	fn default() -> Self {
+		Self {
+			ffmpeg: None,
+			poster_offset: 1.0,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FetchConfig {
+	/// Domains which `fetch` may request. Subdomains of these domains are also allowed.
+	pub allowed_domains: Vec<String>,
+	/// Seconds for which responses are cached between builds.
+	pub cache_max_age: u64,
+}
+
+impl Default for FetchConfig {
+	fn default() -> Self {
+		Self {
+			allowed_domains: Vec::default(),
+			cache_max_age: 3600,
+		}
+	}
+}
+
+/// Which files of `content_dir` are rendered, and which are copied as they are.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContentFilesConfig {
+	/// Files larger than this many bytes are copied with a warning, instead of being rendered.
+	pub max_size: u64,
+	/// Globs of paths, relative to `content_dir`, of files which are always rendered.
+	pub render: Vec<String>,
+	/// Globs of paths, relative to `content_dir`, of files which are always copied, e.g.
+	/// `downloads/**`.
+	pub copy: Vec<String>,
+}
+
+impl Default for ContentFilesConfig {
+	fn default() -> Self {
+		Self {
+			max_size: 10 * 1024 * 1024,
+			render: vec![],
+			copy: vec![],
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+	/// Files smaller than this many bytes are not compressed.
+	pub min_size: u64,
+	/// Extensions of files which are never compressed.
+	pub skip_extensions: Vec<String>,
+	/// Patterns matched against the output path (relative to `out_dir`) of files which are never
+	/// compressed.
+	pub skip_patterns: Vec<String>,
+	/// Compressed variants larger than this ratio of the original size are discarded.
+	pub max_ratio: f64,
+}
+
+impl Default for CompressionConfig {
+	fn default() -> Self {
+		Self {
+			min_size: 512,
+			skip_extensions: [
+				"gz", "br", "zst", "zip", "bz2", "xz", "7z", "rar", "woff", "woff2",
+			]
+			.into_iter()
+			.map(String::from)
+			.collect(),
+			skip_patterns: vec![],
+			max_ratio: 0.95,
+		}
+	}
+}
+
+/// External commands which generated files are piped through before they're compressed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostprocessConfig {
+	pub commands: Vec<PostprocessCommand>,
+	/// Maximum number of commands which run at the same time. Defaults to the number of CPUs.
+	pub concurrency: usize,
+	pub on_failure: PostprocessFailure,
+}
+
+impl Default for PostprocessConfig {
+	fn default() -> Self {
+		Self {
+			commands: vec![],
+			concurrency: std::thread::available_parallelism().map_or(1, usize::from),
+			on_failure: PostprocessFailure::default(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostprocessCommand {
+	/// Glob matched against the output path (relative to `out_dir`) of generated files, e.g.
+	/// `**/*.svg`.
+	pub pattern: String,
+	/// Command which the file is piped through. It reads the file from stdin, and the file is
+	/// replaced with what it writes to stdout.
+	pub command: Vec<String>,
+}
+
+/// What happens when a postprocess command fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostprocessFailure {
+	/// Fail the build.
+	#[default]
+	Fail,
+	/// Log a warning and keep the file as it was generated.
+	Warn,
+}
+
+/// A path of the site which redirects elsewhere.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedirectConfig {
+	/// Path relative to `base_url`. Paths ending in `*` redirect everything under them, with the
+	/// rest of the path replacing `:splat` in `to`.
+	pub from: String,
+	/// Path relative to `base_url`, or an absolute URL.
+	pub to: String,
+	/// One of 301, 302, 307 or 308.
+	#[serde(default = "default_redirect_status")]
+	pub status: u16,
+}
+
+#[inline]
+fn default_redirect_status() -> u16 {
+	DEFAULT_REDIRECT_STATUS
+}
+
+/// Plain-text tweaks of entry content, such as replacements and typographic quotes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransformConfig {
+	/// Handles of the sections whose entries are transformed. All entries are transformed when
+	/// empty.
+	pub sections: Vec<String>,
+	pub stage: TransformStage,
+	/// Replacements, which are applied in order.
+	pub replace: Vec<Replacement>,
+	/// Curl straight quotes, and turn `--` and `---` into en and em dashes and `...` into an
+	/// ellipsis.
+	pub typography: bool,
+	/// Abbreviations which are wrapped in `<abbr>` with their expansion as the title, e.g.
+	/// `{ HTML = "HyperText Markup Language" }`.
+	pub abbreviations: BTreeMap<String, String>,
+}
+
+/// When a transform is applied to an entry's content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransformStage {
+	/// To the text of the rendered HTML, leaving tags, attributes and the contents of `code`,
+	/// `pre`, `script` and `style` as they are.
+	#[default]
+	Html,
+	/// To the markdown source, before it's rendered. Only `replace` applies at this stage, as
+	/// typography and abbreviations would change the markup of the source.
+	Markdown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replacement {
+	pub find: String,
+	/// Replaces each match. Regex replacements can refer to groups, e.g. `$1`.
+	pub with: String,
+	/// Whether `find` is a regex, rather than a plain string.
+	#[serde(default)]
+	pub regex: bool,
+}
+
+impl CompressionConfig {
+	/// Whether the file at `path`, with the contents of `buf`, is worth compressing.
+	///
+	/// Content is sniffed for the signatures of already-compressed formats, so that files with
+	/// unknown or misleading extensions are not compressed again.
+	pub fn should_compress(&self, path: &Path, buf: &[u8]) -> Result<bool> {
+		if (buf.len() as u64) < self.min_size {
+			return Ok(false);
+		}
+
+		if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+			if self
+				.skip_extensions
+				.iter()
+				.any(|skip| skip.eq_ignore_ascii_case(extension))
+			{
+				return Ok(false);
+			}
+		}
+
+		let path_str = path.to_string_lossy();
+		for pattern in &self.skip_patterns {
+			let pattern_re = map_err!(
+				cached_regex(pattern),
+				RegexError(format!(
+					"failed to parse compression skip pattern \"{pattern}\""
+				)),
+			)?;
+
+			if pattern_re.is_match(&path_str) {
+				return Ok(false);
+			}
+		}
+
+		Ok(!is_compressed_content(buf))
+	}
+
+	/// Whether a compressed variant is small enough, relative to the original, to be kept.
+	#[inline]
+	pub fn is_worthwhile(&self, original_len: usize, compressed_len: usize) -> bool {
+		original_len > 0 && (compressed_len as f64 / original_len as f64) <= self.max_ratio
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn builtin_kinds() {
+		let config = Config::default();
+
+		let post = config.kind(POST_KIND).unwrap();
+		assert!(post.dated && post.listed && post.feed);
+		assert_eq!(post.layout, None);
+
+		let page = config.kind(PAGE_KIND).unwrap();
+		assert!(!page.dated && !page.listed && !page.feed);
+
+		assert!(config.kind("note").is_none());
+	}
+
+	#[test]
+	fn configured_kinds() {
+		let config = Config {
+			kinds: BTreeMap::from([
+				(
+					"note".to_string(),
+					KindConfig {
+						layout: Some("note.html".into()),
+						..KindConfig::default()
+					},
+				),
+				(
+					PAGE_KIND.to_string(),
+					KindConfig {
+						listed: Some(true),
+						slug_pattern: Some("{slug}".into()),
+						..KindConfig::default()
+					},
+				),
+			]),
+			..Config::default()
+		};
+
+		// Other kinds are dated and listed, but left out of feeds, unless they're configured to be.
+		let note = config.kind("note").unwrap();
+		assert_eq!(note.name, "note");
+		assert_eq!(note.layout.as_deref(), Some("note.html"));
+		assert!(note.dated && note.listed && !note.feed);
+
+		// The defaults of pages are only overridden where they're configured.
+		let page = config.kind(PAGE_KIND).unwrap();
+		assert!(!page.dated && page.listed && !page.feed);
+		assert_eq!(page.slug_pattern.as_deref(), Some("{slug}"));
+	}
+}
@@ -0,0 +1,204 @@
+use super::{format_markdown_node, markdown_options};
+use crate::{
+	error::Result,
+	generate::{config::Config, diagrams::render_diagrams, source_links::rewrite_source_links},
+};
+use comrak::{
+	nodes::{AstNode, ListType, NodeValue},
+	Anchorizer, Arena,
+};
+use serde_derive::Serialize;
+use std::path::Path;
+use tracing::instrument;
+
+/// A top-level block of a markdown entry, which is passed to templates by `content_ast`.
+///
+/// Each block has the `html` it renders to, so that templates can render the blocks they pick
+/// out without rendering the rest.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum AstBlock {
+	Heading {
+		level: u8,
+		text: String,
+		/// Anchor of the heading, as it is in the rendered content.
+		id: String,
+		html: String,
+	},
+	Paragraph {
+		text: String,
+		/// Images within the paragraph.
+		images: Vec<AstImage>,
+		html: String,
+	},
+	/// A paragraph with nothing but an image.
+	Image(AstImage),
+	CodeBlock {
+		/// The first word of the info string, e.g. `rust` of ```` ```rust ````.
+		lang: Option<String>,
+		info: String,
+		code: String,
+		html: String,
+	},
+	List {
+		ordered: bool,
+		/// Text of each of the items.
+		items: Vec<String>,
+		html: String,
+	},
+	BlockQuote {
+		text: String,
+		html: String,
+	},
+	Html {
+		html: String,
+	},
+	ThematicBreak {
+		html: String,
+	},
+	/// Tables, footnotes and other blocks which only have their HTML.
+	Other {
+		html: String,
+	},
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct AstImage {
+	url: String,
+	title: String,
+	alt: String,
+	html: String,
+}
+
+/// Parses markdown into its top-level blocks. Diagrams are rendered as they are for the content,
+/// so they are `html` blocks.
+#[instrument(level = "trace", skip(markdown, config))]
+#[inline]
+pub(crate) fn markdown_ast(markdown: &str, path: &Path, config: &Config) -> Result<Vec<AstBlock>> {
+	let options = markdown_options(config);
+	let arena = Arena::new();
+	let root = comrak::parse_document(&arena, markdown, &options);
+	render_diagrams(root, config)?;
+	rewrite_source_links(root, path, config)?;
+
+	// Anchors are deduplicated across the document in the same way as when it's rendered.
+	let mut anchorizer = Anchorizer::new();
+
+	let mut blocks = vec![];
+	for node in root.children() {
+		let html = format_markdown_node(node, &options, path)?;
+
+		let block = match &node.data.borrow().value {
+			NodeValue::Heading(heading) => {
+				let text = node_text(node);
+				AstBlock::Heading {
+					level: heading.level,
+					id: anchorizer.anchorize(text.clone()),
+					text,
+					html,
+				}
+			}
+			NodeValue::Paragraph => match only_image(node) {
+				Some(image) => AstBlock::Image(ast_image(image, html)),
+				None => AstBlock::Paragraph {
+					text: node_text(node),
+					images: node
+						.descendants()
+						.filter(|child| matches!(child.data.borrow().value, NodeValue::Image(_)))
+						.map(|image| {
+							let image_html = format_markdown_node(image, &options, path)?;
+							Ok(ast_image(image, image_html))
+						})
+						.collect::<Result<_>>()?,
+					html,
+				},
+			},
+			NodeValue::CodeBlock(code_block) => AstBlock::CodeBlock {
+				lang: code_block
+					.info
+					.split_whitespace()
+					.next()
+					.map(str::to_string),
+				info: code_block.info.clone(),
+				code: code_block.literal.clone(),
+				html,
+			},
+			NodeValue::List(list) => AstBlock::List {
+				ordered: list.list_type == ListType::Ordered,
+				items: node.children().map(node_text).collect(),
+				html,
+			},
+			NodeValue::BlockQuote => AstBlock::BlockQuote {
+				text: node_text(node),
+				html,
+			},
+			NodeValue::HtmlBlock(_) => AstBlock::Html { html },
+			NodeValue::ThematicBreak => AstBlock::ThematicBreak { html },
+			_ => AstBlock::Other { html },
+		};
+
+		blocks.push(block);
+	}
+
+	Ok(blocks)
+}
+
+/// The plain text of `node` and its children.
+#[inline]
+fn node_text<'a>(node: &'a AstNode<'a>) -> String {
+	let mut text = String::new();
+	for child in node.descendants() {
+		match &child.data.borrow().value {
+			NodeValue::Text(literal) => text.push_str(literal),
+			NodeValue::Code(code) => text.push_str(&code.literal),
+			NodeValue::SoftBreak => text.push(' '),
+			NodeValue::LineBreak => text.push('\n'),
+			// Separate the text of consecutive blocks, e.g. of list items.
+			NodeValue::Paragraph if !text.is_empty() => text.push('\n'),
+			_ => {}
+		}
+	}
+	text
+}
+
+#[inline]
+fn only_image<'a>(paragraph: &'a AstNode<'a>) -> Option<&'a AstNode<'a>> {
+	let mut children = paragraph.children();
+	match (children.next(), children.next()) {
+		(Some(child), None) if matches!(child.data.borrow().value, NodeValue::Image(_)) => {
+			Some(child)
+		}
+		_ => None,
+	}
+}
+
+/// URLs of the links of markdown, in the order they appear.
+#[instrument(level = "trace", skip(markdown, config))]
+#[inline]
+pub(crate) fn markdown_links(markdown: &str, config: &Config) -> Vec<String> {
+	let options = markdown_options(config);
+	let arena = Arena::new();
+	let root = comrak::parse_document(&arena, markdown, &options);
+
+	root.descendants()
+		.filter_map(|node| match &node.data.borrow().value {
+			NodeValue::Link(link) => Some(link.url.clone()),
+			_ => None,
+		})
+		.collect()
+}
+
+#[inline]
+fn ast_image<'a>(image: &'a AstNode<'a>, html: String) -> AstImage {
+	let (url, title) = match &image.data.borrow().value {
+		NodeValue::Image(link) => (link.url.clone(), link.title.clone()),
+		_ => Default::default(),
+	};
+
+	AstImage {
+		url,
+		title,
+		alt: node_text(image),
+		html,
+	}
+}
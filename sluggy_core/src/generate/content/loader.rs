@@ -1,23 +1,28 @@
-use super::{FileType, HTML_EXT, MARKDOWN_EXT};
+use super::{replaces_conflicting, FileType, HTML_EXT, MARKDOWN_EXT, NOTEBOOK_EXT};
 use crate::{
 	err,
 	error::{Error, Error::FileLoaderError, Result},
 	generate::{
-		config::Config,
+		config::{Config, Kind, PAGE_KIND, POST_KIND},
 		content::FRONTMATTER_MARKER,
+		data::{read_data, without_nulls, DataFormat},
+		notebook,
+		postprocess::glob_pattern,
 		sections::{Section, SectionHandle, SectionMetadata},
 	},
 	map_err,
-	utils::{await_joinset, is_renderable},
+	utils::{await_joinset, cached_regex, is_renderable},
 };
 use chrono::{DateTime, NaiveDate, Utc};
-use dashmap::DashMap;
+use dashmap::{mapref::entry::Entry as MapEntry, DashMap};
 use http::{HeaderMap, Method};
 use regex::Regex;
+#[cfg(feature = "http-loader")]
 use reqwest::{Client, Url};
 use serde_derive::{Deserialize, Serialize};
 use std::{
-	fs::{File as FsFile, ReadDir},
+	collections::BTreeMap,
+	fs::{self, File as FsFile, ReadDir},
 	io::{self, BufRead},
 	path::{Path, PathBuf},
 	str::FromStr,
@@ -25,7 +30,7 @@ use std::{
 };
 use tokio::task::JoinSet;
 use toml::{Table, Value};
-use tracing::instrument;
+use tracing::{debug, instrument};
 
 pub const DEFAULT_SLUG_PATTERN: &str = r#"^(?P<slug>.*)"#;
 pub const DEFAULT_DATE_SLUG_PATTERN: &str = r#"^(\d{4})-(\d{2})-(\d{2})-(?P<slug>.*)"#;
@@ -42,6 +47,7 @@ pub struct EntryData {
 	pub published: Option<DateTime<Utc>>,
 	pub updated: Option<DateTime<Utc>>,
 	pub section_handle: Option<SectionHandle>,
+	pub kind: Kind,
 	pub frontmatter: Frontmatter,
 }
 
@@ -70,7 +76,7 @@ impl ContentLoader {
 	pub fn new(config: Arc<Config>) -> Arc<Self> {
 		let taxonomies = DashMap::new();
 
-		for taxonomy in &config.taxonomies {
+		for taxonomy in config.taxonomies.keys() {
 			taxonomies.insert(taxonomy.clone(), DashMap::new());
 		}
 
@@ -91,6 +97,18 @@ impl ContentLoader {
 			.load_recursive(self.config.content_dir.clone(), &mut join_set)?;
 		await_joinset(join_set).await?;
 
+		// Entries are added to their sections as they finish loading, so they're sorted to keep
+		// the order the same between builds.
+		for mut section in self.sections.iter_mut() {
+			let sort_by = section.sort_by.unwrap_or(self.config.section_sort_by);
+			section
+				.entries
+				.sort_by(|a, b| match (self.entries.get(a), self.entries.get(b)) {
+					(Some(a), Some(b)) => sort_by.compare(&a, &b),
+					_ => a.cmp(b),
+				});
+		}
+
 		// Load taxonomies
 		if !self.taxonomies.is_empty() {
 			for entry in &self.entries {
@@ -149,10 +167,19 @@ impl ContentLoader {
 		let (section, section_metadata) =
 			match std::fs::read_to_string(&current.join(MANIFEST_FILE)) {
 				Ok(manifest_content) => {
-					let section_metadata: SectionMetadata = map_err!(
-						toml::from_str(&manifest_content),
-						TomlDeserializeError("failed to parse section manifest content"),
-					)?;
+					let section_metadata: SectionMetadata = toml::from_str(&manifest_content)
+						.map_err(|error| {
+							let manifest_path = current.join(MANIFEST_FILE);
+							Error::toml_in_source(
+								format!(
+									"failed to parse section manifest {}",
+									manifest_path.display()
+								),
+								manifest_path.display().to_string(),
+								&manifest_content,
+								error,
+							)
+						})?;
 
 					let prefix = map_err!(
 						current
@@ -243,18 +270,43 @@ impl ContentLoader {
 				.await?
 			};
 
-		if let Some(section_handle) = section_handle {
+		if entry.is_draft() && !self.config.drafts {
+			debug!("skipping draft {}", entry.path.display());
+			return Ok(());
+		}
+
+		let path = entry.path.clone();
+		let listed = entry.kind.listed;
+		match self.entries.entry(path.clone()) {
+			MapEntry::Occupied(mut existing) => {
+				if replaces_conflicting(
+					&path,
+					&existing.get().fs_meta.path(),
+					&entry.fs_meta.path(),
+					&self.config,
+				)? {
+					existing.insert(entry);
+				}
+
+				// The path is already in its section.
+				return Ok(());
+			}
+			MapEntry::Vacant(vacant) => {
+				vacant.insert(entry);
+			}
+		}
+
+		// Entries of kinds which aren't listed, like pages, are left out of their section's entries.
+		if let Some(section_handle) = section_handle.filter(|_| listed) {
 			let mut section =
 				self.sections
 					.get_mut(&section_handle)
 					.ok_or(err!(NotFound(format!(
 						"Section not found {section_handle:?}"
 					))))?;
-			section.entries.push(entry.path.clone());
+			section.entries.push(path);
 		}
 
-		self.entries.insert(entry.path.clone(), entry);
-
 		Ok(())
 	}
 }
@@ -263,8 +315,30 @@ impl ContentLoader {
 pub struct Frontmatter {
 	#[serde(default)]
 	pub layout: Option<String>,
+	/// Kind of the entry, e.g. `post` or `page`, which its defaults are taken from. Defaults to
+	/// the `kind` of its section.
+	#[serde(default)]
+	pub kind: Option<String>,
 	#[serde(default)]
 	pub published_at: Option<String>,
+	/// Excludes the entry from generation unless `drafts` is enabled.
+	#[serde(default)]
+	pub draft: bool,
+	/// Response headers of the entry's page, e.g. `headers = { "X-Robots-Tag" = "noindex" }`.
+	#[serde(default)]
+	pub headers: BTreeMap<String, String>,
+	/// Paths of the site, relative to `base_url`, which redirect to the entry's page, e.g. where it
+	/// used to be.
+	#[serde(default)]
+	pub aliases: Vec<String>,
+	/// Link-blog entries point list pages and feeds at this URL, while their own page is still
+	/// generated at the permalink.
+	#[serde(default)]
+	pub external_url: Option<String>,
+	/// The rendered page is encrypted with this, and can only be read in the browser after
+	/// entering it.
+	#[serde(default, skip_serializing)]
+	pub password: Option<String>,
 	#[serde(default)]
 	pub load: Option<DashMap<String, DataLoader>>,
 	#[serde(default)]
@@ -314,7 +388,9 @@ pub enum GenerateFromSelector {
 pub enum DataLoader {
 	/// Load data from an HTTP request
 	Request(DataRequest),
-	/// Load data from a file. Relative to the current working dir if not absolute.
+	/// Load data from a JSON, TOML, YAML or CSV file, by its extension. CSV files are loaded as an
+	/// array of objects, keyed by the header row. Relative to the current working dir if not
+	/// absolute, or to `data_dir` when it starts with `@/`.
 	File(PathBuf),
 	/// Command?
 	Command(Vec<String>),
@@ -324,6 +400,7 @@ impl DataLoader {
 	#[inline]
 	async fn load(self, config: &Arc<Config>) -> Result<Value> {
 		Ok(match self {
+			#[cfg(feature = "http-loader")]
 			Self::Request(request) => {
 				let client = Client::new();
 				let mut builder = client
@@ -339,22 +416,40 @@ impl DataLoader {
 					ClientRequest("failed to build data loader request"),
 				)?;
 
+				// Data loader responses aren't cached, as requests may have credentials and are
+				// expected to return the latest data on each build.
+				let url = request.url().to_string();
 				let response = map_err!(
 					map_err!(
 						client.execute(request).await,
 						ClientRequest("failed to execute data loader request"),
 					)?
 					.error_for_status(),
-					ClientRequest("request failed"),
+					ClientRequest("data loader request failed"),
 				)?;
-
-				let value: Value = map_err!(
-					response.json().await,
-					ClientRequest("failed to parse JSON response"),
+				let body = map_err!(
+					response.text().await,
+					ClientRequest("failed to read data loader response"),
 				)?;
 
+				let value: Value = serde_json::from_str(&body).map_err(|error| {
+					Error::json_in_source(
+						"failed to parse JSON response".into(),
+						&url,
+						&body,
+						error,
+					)
+				})?;
+
 				value
 			}
+			#[cfg(not(feature = "http-loader"))]
+			Self::Request(request) => {
+				return Err(err!(Validation(format!(
+					"cannot load {}: sluggy was built without the `http-loader` feature",
+					request.url
+				))));
+			}
 			Self::File(filename) => {
 				let path = if filename.starts_with("@/") {
 					// We've already tested that the path starts with "@/", so this is safe.
@@ -363,38 +458,20 @@ impl DataLoader {
 					filename
 				};
 
-				let extension = path
-					.extension()
-					.ok_or(FileLoaderError {
-						message: "unable to determine file type".into(),
-						path: path.clone(),
-					})?
-					.to_string_lossy();
-
-				let data: toml::Value = match extension.as_ref() {
-					"toml" => map_err!(
-						toml::from_str(&map_err!(
-							std::fs::read_to_string(&path),
-							IoError(format!("failed to read file {path:?}")),
-						)?),
-						TomlDeserializeError("failed to parse TOML file"),
-					)?,
-					"json" => map_err!(
-						serde_json::from_str(&map_err!(
-							std::fs::read_to_string(&path),
-							IoError(format!("failed to read file {path:?}")),
-						)?),
-						SerdeJsonError("failed to parse JSON file"),
-					)?,
-					_ => {
-						return Err(FileLoaderError {
-							message: "unsupported file type".into(),
-							path,
-						});
-					}
-				};
+				if DataFormat::from_path(&path).is_none() {
+					return Err(FileLoaderError {
+						message:
+							"unsupported file type, expected .json, .toml, .yaml, .yml or .csv"
+								.into(),
+						path,
+					});
+				}
 
-				data
+				let data = without_nulls(read_data(&path, None)?);
+				map_err!(
+					toml::Value::try_from(data),
+					TomlSerializeError(format!("failed to convert data file {path:?}")),
+				)?
 			}
 			Self::Command(_command_parts) => {
 				todo!()
@@ -444,6 +521,8 @@ struct EntryConfig {
 	path: PathBuf,
 	prefix: PathBuf,
 	section: Option<(Section, SectionMetadata)>,
+	/// Slug pattern of the entry's kind, which is used when its section has none.
+	kind_slug_pattern: Option<String>,
 	config: Arc<Config>,
 }
 
@@ -452,7 +531,7 @@ impl TryFrom<EntryConfig> for File {
 
 	fn try_from(entry_config: EntryConfig) -> std::result::Result<Self, Self::Error> {
 		let file_type = FileType::from(entry_config.path.extension());
-		let is_renderable = is_renderable(&entry_config.path);
+		let is_renderable = classify_renderable(&entry_config.path, &entry_config.config)?;
 		let filename = entry_config
 			.path
 			.file_name()
@@ -472,6 +551,64 @@ impl TryFrom<EntryConfig> for File {
 	}
 }
 
+/// Whether the file at `path` is rendered, rather than copied as it is. Files are rendered by their
+/// mime type, unless they're too large or aren't UTF-8 text, or they match the globs of
+/// `content_files`.
+#[inline]
+fn classify_renderable(path: &Path, config: &Config) -> Result<bool> {
+	let content_files = &config.content_files;
+	let relative_path = path.strip_prefix(&config.content_dir).unwrap_or(path);
+
+	if matches_any(&content_files.copy, relative_path)? {
+		return Ok(false);
+	}
+	if matches_any(&content_files.render, relative_path)? {
+		return Ok(true);
+	}
+	if !is_renderable(path) {
+		return Ok(false);
+	}
+
+	let size = map_err!(
+		fs::metadata(path),
+		IoError(format!("failed to read metadata of {}", path.display())),
+	)?
+	.len();
+	if size > content_files.max_size {
+		tracing::warn!(path = %path.display(), size, "Copying file which is too large to render");
+		return Ok(false);
+	}
+
+	let contents = map_err!(
+		fs::read(path),
+		IoError(format!("failed to read file {}", path.display())),
+	)?;
+	// NUL bytes are valid UTF-8, but only binary files have them.
+	if std::str::from_utf8(&contents).is_err() || contents.contains(&0) {
+		tracing::warn!(path = %path.display(), "Copying file which isn't UTF-8 text");
+		return Ok(false);
+	}
+
+	Ok(true)
+}
+
+#[inline]
+fn matches_any(globs: &[String], path: &Path) -> Result<bool> {
+	let path = path.to_string_lossy();
+	for glob in globs {
+		let glob_re = map_err!(
+			cached_regex(&glob_pattern(glob)),
+			RegexError(format!("failed to parse content files pattern \"{glob}\"")),
+		)?;
+
+		if glob_re.is_match(&path) {
+			return Ok(true);
+		}
+	}
+
+	Ok(false)
+}
+
 #[derive(Clone, Debug)]
 pub struct Dir {
 	path: PathBuf,
@@ -561,18 +698,41 @@ fn extract_path_components(
 			.to_str()
 			.unwrap();
 
-		// TODO need to just try configured slug pattern, default datetime pattern and then default slug pattern
-		let slug_pattern = match &entry_config.section {
-			Some((_, section_metadata)) => section_metadata
-				.slug_pattern
-				.clone()
-				.unwrap_or(DEFAULT_SLUG_PATTERN.into()),
-			None => DEFAULT_SLUG_PATTERN.into(),
+		// The section's pattern takes precedence over the kind's pattern, and then the global
+		// pattern. When none are set, the date pattern is tried before falling back to the default
+		// pattern.
+		let configured_pattern = entry_config
+			.section
+			.as_ref()
+			.and_then(|(_, section_metadata)| section_metadata.slug_pattern.clone())
+			.or_else(|| entry_config.kind_slug_pattern.clone())
+			.or_else(|| entry_config.config.slug_pattern.clone());
+
+		let (slug_pattern, filename_re) = match configured_pattern {
+			Some(slug_pattern) => {
+				let filename_re = map_err!(
+					Regex::from_str(&slug_pattern),
+					RegexError("failed to parse slug pattern"),
+				)?;
+				(slug_pattern, filename_re)
+			}
+			None => {
+				let date_slug_re = map_err!(
+					Regex::from_str(DEFAULT_DATE_SLUG_PATTERN),
+					RegexError("failed to parse date slug pattern"),
+				)?;
+
+				if entry_config.config.date_slug && date_slug_re.is_match(file_stem) {
+					(DEFAULT_DATE_SLUG_PATTERN.to_string(), date_slug_re)
+				} else {
+					let filename_re = map_err!(
+						Regex::from_str(DEFAULT_SLUG_PATTERN),
+						RegexError("failed to parse slug pattern"),
+					)?;
+					(DEFAULT_SLUG_PATTERN.to_string(), filename_re)
+				}
+			}
 		};
-		let filename_re = map_err!(
-			Regex::from_str(&slug_pattern),
-			RegexError("failed to parse slug pattern"),
-		)?;
 
 		match filename_re.captures(file_stem) {
 			Some(captures) => {
@@ -684,7 +844,9 @@ impl TryFrom<EntryConfig> for EntryFsMeta {
 		let filename = entry_config.path.file_name().unwrap().to_str().unwrap();
 
 		let index_re = map_err!(
-			Regex::from_str(&format!(r#"^index\.({HTML_EXT}|{MARKDOWN_EXT})$"#)),
+			Regex::from_str(&format!(
+				r#"^index\.({HTML_EXT}|{MARKDOWN_EXT}|{NOTEBOOK_EXT})$"#
+			)),
 			RegexError("failed to parse regex string"),
 		)?;
 		if index_re.captures(filename).is_some() {
@@ -695,12 +857,57 @@ impl TryFrom<EntryConfig> for EntryFsMeta {
 	}
 }
 
+/// Labels the location of a frontmatter error in the entry's file. The frontmatter starts on the
+/// line after the first marker.
+#[inline]
+fn frontmatter_error(file_path: &Path, frontmatter: &str, error: toml::de::Error) -> Error {
+	let message = format!("Failed to parse header for {}", file_path.display());
+
+	match (error.span(), std::fs::read_to_string(file_path)) {
+		(Some(span), Ok(source)) => {
+			let before = &frontmatter[..span.start.min(frontmatter.len())];
+			let line = before.matches('\n').count() + 1;
+			let column = before.len() - before.rfind('\n').map_or(0, |index| index + 1) + 1;
+
+			Error::at_line(
+				message,
+				file_path.display().to_string(),
+				source,
+				line + 1,
+				column,
+				error.message(),
+			)
+		}
+		_ => Error::TomlDeserializeError {
+			message,
+			source: error,
+		},
+	}
+}
+
 impl EntryData {
+	/// Entries of sections are of the section's kind, or are posts, when they don't set one.
+	/// `section_kind` is `None` outside of sections.
 	#[inline]
-	async fn load_from(fs_meta: EntryFsMeta, config: Arc<Config>) -> Result<Self> {
+	async fn load_from(
+		fs_meta: EntryFsMeta,
+		section_kind: Option<&str>,
+		config: Arc<Config>,
+	) -> Result<Self> {
 		let file_path = fs_meta.path();
 
-		let mut frontmatter = {
+		let mut frontmatter = if fs_meta.file_type().is_notebook() {
+			match notebook::read_frontmatter(&file_path)? {
+				Some(frontmatter) => map_err!(
+					serde_json::from_value(frontmatter),
+					SerdeJsonError(format!(
+						"Failed to parse notebook metadata for {}",
+						file_path.display()
+					)),
+				)?,
+				None => Frontmatter::default(),
+			}
+		} else {
 			let file = map_err!(
 				FsFile::open(&file_path),
 				IoError(format!("failed to open file {}", file_path.display())),
@@ -710,7 +917,10 @@ impl EntryData {
 				let mut in_frontmatter = false;
 				let mut frontmatter = String::default();
 				for (line_index, line) in io::BufReader::new(file).lines().enumerate() {
-					let line = map_err!(line, IoError)?;
+					let mut line = map_err!(line, IoError)?;
+					if line_index == 0 {
+						line = strip_bom(line);
+					}
 					if line.starts_with(FRONTMATTER_MARKER) && (line_index == 0 || in_frontmatter) {
 						if !in_frontmatter {
 							in_frontmatter = true;
@@ -727,13 +937,8 @@ impl EntryData {
 				if frontmatter.is_empty() {
 					Frontmatter::default()
 				} else {
-					map_err!(
-						toml::from_str(&frontmatter),
-						TomlDeserializeError(format!(
-							"Failed to parse header for {}",
-							file_path.display()
-						)),
-					)?
+					toml::from_str(&frontmatter)
+						.map_err(|error| frontmatter_error(&file_path, &frontmatter, error))?
 				}
 			} else {
 				Frontmatter::default()
@@ -746,21 +951,50 @@ impl EntryData {
 			}
 		}
 
+		let kind_name = match (&frontmatter.kind, section_kind) {
+			(Some(kind), _) => kind.as_str(),
+			(None, Some(section_kind)) => section_kind,
+			(None, None) if fs_meta.published().is_some() => POST_KIND,
+			(None, None) => PAGE_KIND,
+		};
+		let kind = config.kind(kind_name).ok_or_else(|| {
+			err!(Validation(format!(
+				"{} is of an unknown kind \"{kind_name}\"",
+				file_path.display()
+			)))
+		})?;
+
 		let published = match &frontmatter.published_at {
 			Some(published_at) => Some(datetime_from_str(published_at)?),
-			None => fs_meta.published(),
+			None if kind.dated => fs_meta.published(),
+			None => None,
 		};
 
+		if frontmatter.layout.is_none() && fs_meta.file_type().is_rendered_to_html() {
+			frontmatter.layout = kind.layout.clone();
+		}
+
 		Ok(Self {
 			path: fs_meta.url().clone(),
 			fs_meta,
 			published,
 			updated: published, // TODO why i even have this?
 			section_handle: None,
+			kind,
 			frontmatter,
 		})
 	}
 
+	/// Whether the entry is marked as a draft or is scheduled to be published in the future.
+	#[inline]
+	pub(crate) fn is_draft(&self) -> bool {
+		self.frontmatter.draft
+			|| self
+				.published
+				.map(|published| published > Utc::now())
+				.unwrap_or(false)
+	}
+
 	#[inline]
 	pub async fn open(
 		path: PathBuf,
@@ -769,16 +1003,47 @@ impl EntryData {
 		config: Arc<Config>,
 	) -> Result<Self> {
 		let section_handle = section.as_ref().map(|section| section.0.handle.clone());
+		let section_kind = section.as_ref().map(|(_, section_metadata)| {
+			section_metadata
+				.kind
+				.clone()
+				.unwrap_or_else(|| POST_KIND.to_string())
+		});
+		let has_slug_pattern = section
+			.as_ref()
+			.is_some_and(|(_, section_metadata)| section_metadata.slug_pattern.is_some());
 
 		let entry_config = EntryConfig {
-			path,
-			prefix,
-			section,
+			path: path.clone(),
+			prefix: prefix.clone(),
+			section: section.clone(),
+			kind_slug_pattern: None,
 			config: config.clone(),
 		};
 
 		let entry_fs_meta = EntryFsMeta::try_from(entry_config)?;
-		let mut entry = EntryData::load_from(entry_fs_meta, config.clone()).await?;
+		let mut entry =
+			EntryData::load_from(entry_fs_meta, section_kind.as_deref(), config.clone()).await?;
+
+		// The kind is only known once the frontmatter is read, so the URL is worked out again
+		// with the kind's pattern.
+		let kind_slug_pattern = entry
+			.kind
+			.slug_pattern
+			.clone()
+			.filter(|_| !has_slug_pattern);
+		if kind_slug_pattern.is_some() {
+			let entry_config = EntryConfig {
+				path,
+				prefix,
+				section,
+				kind_slug_pattern,
+				config: config.clone(),
+			};
+
+			entry.fs_meta = EntryFsMeta::try_from(entry_config)?;
+			entry.path = entry.fs_meta.url().clone();
+		}
 
 		if entry.fs_meta.is_renderable() {
 			entry.section_handle = section_handle;
@@ -788,6 +1053,15 @@ impl EntryData {
 	}
 }
 
+/// Strips the byte order mark which some editors start UTF-8 files with.
+#[inline]
+pub(crate) fn strip_bom(line: String) -> String {
+	match line.strip_prefix('\u{feff}') {
+		Some(line) => line.to_string(),
+		None => line,
+	}
+}
+
 fn datetime_from_str(value: &str) -> Result<DateTime<Utc>> {
 	Ok(DateTime::from_utc(
 		NaiveDate::parse_from_str(value, "%Y-%m-%d")?
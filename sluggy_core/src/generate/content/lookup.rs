@@ -0,0 +1,248 @@
+use super::Content;
+use crate::{
+	err,
+	error::{Error, Result},
+};
+use std::{
+	collections::{BTreeSet, HashMap},
+	path::{Path, PathBuf},
+};
+use tracing::instrument;
+
+/// Maximum number of near-matches suggested when nothing matches a query.
+const MAX_NEAR_MATCHES: usize = 5;
+
+/// Resolves entries by path, slug, URL or content file path.
+///
+/// Paths and URLs match with or without their extensions, leading and trailing slashes, and
+/// `index` file names.
+#[derive(Debug, Default)]
+pub(crate) struct EntryLookup {
+	aliases: HashMap<String, BTreeSet<PathBuf>>,
+	slugs: HashMap<String, BTreeSet<PathBuf>>,
+	base_url: String,
+}
+
+impl EntryLookup {
+	#[instrument(level = "debug", skip_all)]
+	pub(crate) fn new(content: &Content) -> Self {
+		let mut lookup = Self {
+			base_url: content.config.base_url.clone(),
+			..Self::default()
+		};
+
+		for entry in content.entries.iter() {
+			let path = entry.key();
+			let entry = entry.value();
+
+			let mut aliases = vec![path.to_string_lossy().to_string(), entry.url.clone()];
+			if let Ok(file_path) = entry.file_path.strip_prefix(&content.config.content_dir) {
+				aliases.push(file_path.to_string_lossy().to_string());
+			}
+
+			for alias in aliases {
+				let alias = lookup.normalize(&alias);
+				for alias in [without_extension(&alias), alias] {
+					lookup
+						.aliases
+						.entry(alias)
+						.or_default()
+						.insert(path.clone());
+				}
+			}
+
+			if let Some(slug) = &entry.slug {
+				lookup
+					.slugs
+					.entry(slug.clone())
+					.or_default()
+					.insert(path.clone());
+			}
+		}
+
+		lookup
+	}
+
+	/// Path of the entry matching `query`, which is tried as an exact path first, then as any of
+	/// the aliases of an entry, then as a slug.
+	#[instrument(level = "trace", skip(self, content))]
+	pub(crate) fn resolve(&self, query: &str, content: &Content) -> Result<PathBuf> {
+		if content.entries.contains_key(Path::new(query)) {
+			return Ok(PathBuf::from(query));
+		}
+
+		let normalized = self.normalize(query);
+		let matches = self
+			.aliases
+			.get(&normalized)
+			.or_else(|| self.aliases.get(&without_extension(&normalized)))
+			.or_else(|| self.slugs.get(query.trim_matches('/')));
+
+		match matches {
+			Some(paths) if paths.len() == 1 => Ok(paths.iter().next().unwrap().clone()),
+			Some(paths) => Err(err!(Validation(format!(
+				"\"{query}\" matches more than one entry: {}",
+				join_paths(paths.iter())
+			)))),
+			None => {
+				let near_matches = self.near_matches(&normalized);
+				if near_matches.is_empty() {
+					Err(err!(NotFound(format!("no entry found for \"{query}\""))))
+				} else {
+					Err(err!(NotFound(format!(
+						"no entry found for \"{query}\", did you mean {}?",
+						join_paths(near_matches.iter())
+					))))
+				}
+			}
+		}
+	}
+
+	/// Strips the base URL, surrounding slashes and a trailing `index` file name component.
+	#[inline]
+	fn normalize(&self, query: &str) -> String {
+		let query = query.strip_prefix(&self.base_url).unwrap_or(query);
+		let query = query.trim_matches('/');
+		let query = ["index.html", "index"]
+			.iter()
+			.find_map(|index| {
+				if query == *index {
+					Some("")
+				} else {
+					query.strip_suffix(index)?.strip_suffix('/')
+				}
+			})
+			.unwrap_or(query);
+		query.trim_end_matches('/').to_string()
+	}
+
+	/// Entries with an alias or slug within a small edit distance of `query`, closest first.
+	#[inline]
+	fn near_matches(&self, query: &str) -> Vec<PathBuf> {
+		let max_distance = (query.chars().count() / 3).max(2);
+
+		let mut candidates = self
+			.aliases
+			.iter()
+			.chain(self.slugs.iter())
+			.filter_map(|(alias, paths)| {
+				let distance = edit_distance(query, alias);
+				(distance <= max_distance).then_some((distance, paths))
+			})
+			.collect::<Vec<_>>();
+		candidates.sort_by_key(|(distance, _)| *distance);
+
+		let mut near_matches = vec![];
+		for (_, paths) in candidates {
+			for path in paths {
+				if !near_matches.contains(path) {
+					near_matches.push(path.clone());
+				}
+			}
+		}
+		near_matches.truncate(MAX_NEAR_MATCHES);
+
+		near_matches
+	}
+}
+
+#[inline]
+fn without_extension(path: &str) -> String {
+	Path::new(path)
+		.with_extension("")
+		.to_string_lossy()
+		.to_string()
+}
+
+#[inline]
+fn join_paths<'p>(paths: impl Iterator<Item = &'p PathBuf>) -> String {
+	paths
+		.map(|path| format!("\"{}\"", path.display()))
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+/// Levenshtein distance between `a` and `b`.
+#[inline]
+fn edit_distance(a: &str, b: &str) -> usize {
+	let b = b.chars().collect::<Vec<_>>();
+	let mut distances = (0..=b.len()).collect::<Vec<_>>();
+
+	for (i, a_char) in a.chars().enumerate() {
+		let mut previous = distances[0];
+		distances[0] = i + 1;
+		for (j, b_char) in b.iter().enumerate() {
+			let substitution = previous + usize::from(a_char != *b_char);
+			previous = distances[j + 1];
+			distances[j + 1] = substitution.min(distances[j] + 1).min(distances[j + 1] + 1);
+		}
+	}
+
+	distances[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn lookup() -> EntryLookup {
+		EntryLookup {
+			base_url: "https://example.com".to_string(),
+			..EntryLookup::default()
+		}
+	}
+
+	#[test]
+	fn normalize_strips_base_url_and_slashes() {
+		let lookup = lookup();
+		assert_eq!(lookup.normalize("https://example.com/posts/a/"), "posts/a");
+		assert_eq!(lookup.normalize("/posts/a"), "posts/a");
+		assert_eq!(lookup.normalize("https://example.com/"), "");
+	}
+
+	#[test]
+	fn normalize_strips_index_components() {
+		let lookup = lookup();
+		assert_eq!(lookup.normalize("/posts/a/index.html"), "posts/a");
+		assert_eq!(lookup.normalize("posts/a/index"), "posts/a");
+		assert_eq!(lookup.normalize("https://example.com/index.html"), "");
+		assert_eq!(lookup.normalize("index"), "");
+	}
+
+	#[test]
+	fn normalize_keeps_names_ending_in_index() {
+		let lookup = lookup();
+		assert_eq!(lookup.normalize("posts/reindex"), "posts/reindex");
+		assert_eq!(lookup.normalize("/reindex/"), "reindex");
+		assert_eq!(lookup.normalize("posts/myindex.html"), "posts/myindex.html");
+	}
+
+	#[test]
+	fn near_matches_are_closest_first() {
+		let mut lookup = lookup();
+		for (alias, path) in [
+			("posts/hello", "a.md"),
+			("posts/help", "b.md"),
+			("about", "c.md"),
+		] {
+			lookup
+				.aliases
+				.entry(alias.to_string())
+				.or_default()
+				.insert(PathBuf::from(path));
+		}
+
+		assert_eq!(
+			lookup.near_matches("posts/helloo"),
+			vec![PathBuf::from("a.md"), PathBuf::from("b.md")]
+		);
+		assert!(lookup.near_matches("contact").is_empty());
+	}
+
+	#[test]
+	fn edit_distance_counts_edits() {
+		assert_eq!(edit_distance("kitten", "sitting"), 3);
+		assert_eq!(edit_distance("", "abc"), 3);
+		assert_eq!(edit_distance("same", "same"), 0);
+	}
+}
@@ -1,16 +1,29 @@
-use self::loader::EntryData;
+use self::{
+	ast::{markdown_ast, markdown_links, AstBlock},
+	loader::{strip_bom, EntryData},
+};
 
+#[cfg(feature = "syntax-highlighting")]
+use super::syntect::SyntectAdapter;
 use super::{
-	config::Config,
-	sections::{Section, SectionHandle},
-	syntect::SyntectAdapter,
+	config::{Config, UrlConflicts},
+	diagrams::render_diagrams,
+	notebook::render_notebook,
+	sections::{Listing, Section, SectionHandle},
+	shortcodes::render_shortcodes,
+	source_links::rewrite_source_links,
+	transforms::{transform_html, transform_markdown},
 };
 use chrono::{serde::ts_seconds_option, DateTime, Utc};
-use comrak::{ComrakExtensionOptions, ComrakOptions, ComrakPlugins, ComrakRenderOptions};
+use comrak::{
+	nodes::AstNode, Arena, ComrakExtensionOptions, ComrakOptions, ComrakParseOptions,
+	ComrakPlugins, ComrakRenderOptions,
+};
 use dashmap::DashMap;
 use json_pointer::Resolve;
 
 use crate::{
+	common::http::ERROR_PAGES_DIR,
 	err,
 	error::{Error, Result},
 	map_err,
@@ -19,6 +32,7 @@ use regex::Regex;
 use serde_derive::Serialize;
 use serde_json::json;
 use std::{
+	collections::BTreeMap,
 	ffi::OsStr,
 	fs::File,
 	io::BufRead,
@@ -29,7 +43,9 @@ use std::{
 use toml::Table;
 use tracing::instrument;
 
+pub(crate) mod ast;
 pub(crate) mod loader;
+pub(crate) mod lookup;
 
 const FRONTMATTER_MARKER: &str = "+++";
 
@@ -37,6 +53,7 @@ const TEMPLATE_EXT: &str = "tpl";
 const MARKDOWN_EXT: &str = "md";
 const HTML_EXT: &str = "html";
 const XML_EXT: &str = "xml";
+const NOTEBOOK_EXT: &str = "ipynb";
 
 #[derive(Debug)]
 pub struct Content {
@@ -46,11 +63,49 @@ pub struct Content {
 	pub config: Arc<Config>,
 }
 
+impl Content {
+	/// Whether `entry` is left out of `listing` by its section, or a section its section is
+	/// within.
+	#[inline]
+	pub(crate) fn is_excluded_from(&self, entry: &Entry, listing: Listing) -> bool {
+		self.any_section_of(entry, |section| section.excludes(listing))
+	}
+
+	/// Whether search engines are kept from indexing the page of `entry` by its sections.
+	#[inline]
+	pub(crate) fn is_noindex(&self, entry: &Entry) -> bool {
+		self.any_section_of(entry, |section| section.noindex)
+	}
+
+	#[inline]
+	fn any_section_of(&self, entry: &Entry, f: impl Fn(&Section) -> bool) -> bool {
+		let Some(prefix) = entry
+			.section_handle
+			.as_ref()
+			.and_then(|handle| self.sections.get(handle))
+			.map(|section| section.prefix.clone())
+		else {
+			return false;
+		};
+
+		self.sections
+			.iter()
+			.any(|section| prefix.starts_with(&section.prefix) && f(section.value()))
+	}
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct Entry {
 	pub slug: Option<String>,
 	pub layout: Option<String>,
+	/// Name of the entry's kind, e.g. `post` or `page`.
+	pub kind: String,
+	/// Permalink of the entry's page.
 	pub url: String,
+	/// URL of the target of a link-blog entry.
+	pub external_url: Option<String>,
+	/// Where list pages and feeds link to, the `external_url` if it's set or otherwise the `url`.
+	pub link: String,
 	pub path: PathBuf,
 	pub file_path: PathBuf,
 	pub file_type: FileType,
@@ -60,6 +115,20 @@ pub struct Entry {
 	pub updated: Option<DateTime<Utc>>,
 	pub section_handle: Option<SectionHandle>,
 	pub is_renderable: bool,
+	/// Drafts and entries published in the future are only generated when `drafts` is enabled.
+	pub draft: bool,
+	/// Response headers of the entry's page, which are written to the `_headers` file.
+	pub headers: BTreeMap<String, String>,
+	/// Paths which redirect to the entry's page, which are written to the `_redirects` file.
+	pub aliases: Vec<String>,
+	/// Password the entry's page is encrypted with. It's never passed to templates.
+	#[serde(skip)]
+	pub password: Option<String>,
+	/// Whether the entry's page is encrypted. Protected entries are left out of feeds, sitemaps,
+	/// calendars, archives, taxonomy terms, `entries()` and `related()`, and `render_content` and
+	/// `content_ast` don't render them. Sections still list them, so that templates can leave
+	/// their content out of list pages.
+	pub protected: bool,
 	#[serde(default, flatten)]
 	pub extra: Table,
 }
@@ -80,22 +149,36 @@ impl Entry {
 		config: Arc<Config>,
 	) -> Result<Vec<Self>> {
 		let mut entries = vec![];
+		let draft = entry_data.is_draft();
 
 		// If we're not generating a entry from the generate_from field
 		match entry_data.frontmatter.generate_from {
 			None => {
 				let fs_meta = entry_data.fs_meta;
+				let url = format!("{}{}", config.base_url, fs_meta.url().to_string_lossy());
 				let parent = Self {
 					path: entry_data.path,
 					slug: fs_meta.slug(),
-					url: format!("{}{}", config.base_url, fs_meta.url().to_string_lossy()),
+					link: entry_data
+						.frontmatter
+						.external_url
+						.clone()
+						.unwrap_or_else(|| url.clone()),
+					external_url: entry_data.frontmatter.external_url,
+					url,
 					file_path: fs_meta.path(),
 					file_type: fs_meta.file_type(),
 					published: entry_data.published,
 					updated: entry_data.updated,
 					section_handle: entry_data.section_handle,
 					layout: entry_data.frontmatter.layout,
+					kind: entry_data.kind.name,
 					is_renderable: fs_meta.is_renderable(),
+					draft,
+					headers: entry_data.frontmatter.headers,
+					aliases: entry_data.frontmatter.aliases,
+					protected: entry_data.frontmatter.password.is_some(),
+					password: entry_data.frontmatter.password,
 					extra: entry_data.frontmatter.extra,
 				};
 
@@ -208,17 +291,31 @@ impl Entry {
 					path.set_file_name(&filename);
 					url.set_file_name(&filename);
 
+					let url = format!("{}{}", config.base_url, url.to_string_lossy());
 					let mut entry = Self {
 						path,
 						slug: fs_meta.slug(),
-						url: format!("{}{}", config.base_url, url.to_string_lossy()),
+						link: entry_data
+							.frontmatter
+							.external_url
+							.clone()
+							.unwrap_or_else(|| url.clone()),
+						external_url: entry_data.frontmatter.external_url.clone(),
+						url,
 						file_path: fs_meta.path(),
 						file_type: fs_meta.file_type(),
 						published: entry_data.published,
 						updated: entry_data.updated,
 						section_handle: entry_data.section_handle.clone(),
 						layout: entry_data.frontmatter.layout.clone(),
+						kind: entry_data.kind.name.clone(),
 						is_renderable: fs_meta.is_renderable(),
+						draft,
+						headers: entry_data.frontmatter.headers.clone(),
+						// Each generated entry would otherwise redirect the same paths.
+						aliases: vec![],
+						password: entry_data.frontmatter.password.clone(),
+						protected: entry_data.frontmatter.password.is_some(),
 						extra: entry_data.frontmatter.extra.clone(),
 					};
 
@@ -237,6 +334,24 @@ impl Entry {
 		Ok(entries)
 	}
 
+	/// Whether the entry is the page of an error status, which is served in place of missing pages
+	/// and such rather than at its own URL.
+	#[inline]
+	pub fn is_error_page(&self) -> bool {
+		self.path.starts_with(ERROR_PAGES_DIR)
+	}
+
+	/// The `title` from the frontmatter, falling back to the slug or path.
+	#[inline]
+	pub(crate) fn title(&self) -> String {
+		self.extra
+			.get("title")
+			.and_then(|value| value.as_str())
+			.map(String::from)
+			.or_else(|| self.slug.clone())
+			.unwrap_or_else(|| self.path.to_string_lossy().to_string())
+	}
+
 	#[inline]
 	#[instrument(level = "trace", skip(self))]
 	fn read_skip_frontmatter(&self) -> Result<String> {
@@ -251,7 +366,10 @@ impl Entry {
 		let mut in_header = false;
 		let mut content = String::default();
 		for (idx, line) in std::io::BufReader::new(file).lines().enumerate() {
-			let line = map_err!(line, IoError)?;
+			let mut line = map_err!(line, IoError)?;
+			if idx == 0 {
+				line = strip_bom(line);
+			}
 			if line.starts_with(FRONTMATTER_MARKER) && content.is_empty() {
 				if !in_header && idx == 0 {
 					in_header = true;
@@ -277,33 +395,57 @@ impl Entry {
 		self.read_skip_frontmatter()
 	}
 
-	#[instrument(level = "trace", skip(self))]
+	#[instrument(level = "trace", skip(self, config))]
 	#[inline]
-	pub fn generate(&self) -> Result<String> {
-		let options = ComrakOptions {
-			render: ComrakRenderOptions {
-				unsafe_: true, // Allow rendering of raw HTML
-				..ComrakRenderOptions::default()
-			},
-			extension: ComrakExtensionOptions {
-				header_ids: Some(String::new()),
-				footnotes: true,
-				table: true,
-				..ComrakExtensionOptions::default()
-			},
-			..ComrakOptions::default()
+	pub fn generate(&self, config: &Config) -> Result<String> {
+		let html = if self.file_type.is_notebook() {
+			render_notebook(&self.file_path, config)?
+		} else {
+			let markdown = transform_markdown(
+				self.read_skip_frontmatter()?,
+				self.section_handle.as_ref(),
+				config,
+			)?;
+			let markdown = render_shortcodes(markdown, self)?;
+			render_markdown(&markdown, &self.file_path, config)?
 		};
 
-		let mut plugins = ComrakPlugins::default();
-		let syntect_adapter = SyntectAdapter;
-		plugins.render.codefence_syntax_highlighter = Some(&syntect_adapter);
-		let content = comrak::markdown_to_html_with_plugins(
-			&self.read_skip_frontmatter()?,
-			&options,
-			&plugins,
-		);
+		transform_html(html, self.section_handle.as_ref(), config)
+	}
 
-		Ok(content)
+	/// Top-level blocks of the entry's markdown.
+	#[instrument(level = "trace", skip(self, config))]
+	#[inline]
+	pub(crate) fn ast(&self, config: &Config) -> Result<Vec<AstBlock>> {
+		if !self.file_type.is_markdown() {
+			return Err(err!(Validation(format!(
+				"{} is not a markdown entry",
+				self.path.display()
+			))));
+		}
+
+		let markdown = transform_markdown(
+			self.read_skip_frontmatter()?,
+			self.section_handle.as_ref(),
+			config,
+		)?;
+		markdown_ast(&markdown, &self.file_path, config)
+	}
+
+	/// URLs of the links of the entry's markdown. Other entries have none.
+	#[instrument(level = "trace", skip(self, config))]
+	#[inline]
+	pub(crate) fn links(&self, config: &Config) -> Result<Vec<String>> {
+		if !self.file_type.is_markdown() {
+			return Ok(vec![]);
+		}
+
+		let markdown = transform_markdown(
+			self.read_skip_frontmatter()?,
+			self.section_handle.as_ref(),
+			config,
+		)?;
+		Ok(markdown_links(&markdown, config))
 	}
 
 	pub(crate) async fn render_by_path(
@@ -317,8 +459,8 @@ impl Entry {
 				.find(|map_entry| map_entry.value().path == path)
 			{
 				Some(entry) => {
-					if entry.file_type.is_markdown() {
-						Some(entry.generate()?)
+					if entry.file_type.is_generated() {
+						Some(entry.generate(&content.config)?)
 					} else {
 						Some(entry.raw()?)
 					}
@@ -329,12 +471,122 @@ impl Entry {
 	}
 }
 
+/// Whether the entry of `file` replaces the entry at `path` of `existing_file`, as they'd be written
+/// to the same page. Conflicts are errors, unless `url_conflicts` only warns about them, in which
+/// case the entry whose file path sorts first is kept.
+#[inline]
+pub(crate) fn replaces_conflicting(
+	path: &Path,
+	existing_file: &Path,
+	file: &Path,
+	config: &Config,
+) -> Result<bool> {
+	let (first, second) = if file < existing_file {
+		(file, existing_file)
+	} else {
+		(existing_file, file)
+	};
+	let relative = |file: &Path| {
+		file.strip_prefix(&config.content_dir)
+			.unwrap_or(file)
+			.display()
+			.to_string()
+	};
+	let message = if first == second {
+		format!(
+			"{} has more than one entry at {}",
+			relative(first),
+			path.display()
+		)
+	} else {
+		format!(
+			"{} and {} are both entries at {}",
+			relative(first),
+			relative(second),
+			path.display()
+		)
+	};
+
+	match config.url_conflicts {
+		UrlConflicts::Error => Err(err!(Validation(message))),
+		UrlConflicts::Warn => {
+			tracing::warn!(kept = %relative(first), "{message}");
+			Ok(file < existing_file)
+		}
+	}
+}
+
+/// Renders markdown to HTML, highlighting code blocks, rendering diagrams and rewriting links to
+/// the files of other entries. `path` is the file the markdown is from.
+#[instrument(level = "trace", skip(markdown, config))]
+#[inline]
+pub(crate) fn render_markdown(markdown: &str, path: &Path, config: &Config) -> Result<String> {
+	let options = markdown_options(config);
+	let arena = Arena::new();
+	let root = comrak::parse_document(&arena, markdown, &options);
+	render_diagrams(root, config)?;
+	rewrite_source_links(root, path, config)?;
+
+	format_markdown_node(root, &options, path)
+}
+
+#[inline]
+fn markdown_options(config: &Config) -> ComrakOptions {
+	let markdown = &config.markdown;
+
+	ComrakOptions {
+		parse: ComrakParseOptions {
+			smart: markdown.smart_punctuation,
+			..ComrakParseOptions::default()
+		},
+		render: ComrakRenderOptions {
+			unsafe_: markdown.unsafe_html,
+			..ComrakRenderOptions::default()
+		},
+		extension: ComrakExtensionOptions {
+			header_ids: markdown.header_id_prefix.clone(),
+			footnotes: markdown.footnotes,
+			table: markdown.tables,
+			strikethrough: markdown.strikethrough,
+			tasklist: markdown.tasklists,
+			autolink: markdown.autolink,
+			..ComrakExtensionOptions::default()
+		},
+	}
+}
+
+/// Renders `node` and its children to HTML, highlighting code blocks.
+#[inline]
+fn format_markdown_node<'a>(
+	node: &'a AstNode<'a>,
+	options: &ComrakOptions,
+	path: &Path,
+) -> Result<String> {
+	#[cfg_attr(not(feature = "syntax-highlighting"), allow(unused_mut))]
+	let mut plugins = ComrakPlugins::default();
+	#[cfg(feature = "syntax-highlighting")]
+	let syntect_adapter = SyntectAdapter;
+	#[cfg(feature = "syntax-highlighting")]
+	{
+		plugins.render.codefence_syntax_highlighter = Some(&syntect_adapter);
+	}
+
+	let mut content = vec![];
+	map_err!(
+		comrak::format_html_with_plugins(node, options, &mut content, &plugins),
+		IoError(format!("failed to render {}", path.display())),
+	)?;
+
+	Ok(String::from_utf8_lossy(&content).to_string())
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum FileType {
 	Template,
 	Markdown,
 	Html,
 	Xml,
+	Notebook,
 	None,
 	Other(String),
 }
@@ -345,9 +597,20 @@ impl FileType {
 		*self == Self::Markdown
 	}
 
+	#[inline]
+	pub fn is_notebook(&self) -> bool {
+		*self == Self::Notebook
+	}
+
+	/// Whether the content is converted to HTML, rather than used as is.
+	#[inline]
+	pub fn is_generated(&self) -> bool {
+		self.is_markdown() || self.is_notebook()
+	}
+
 	#[inline]
 	pub fn is_rendered_to_html(&self) -> bool {
-		*self == Self::Markdown || *self == Self::Html
+		*self == Self::Markdown || *self == Self::Html || *self == Self::Notebook
 	}
 
 	#[inline]
@@ -363,6 +626,7 @@ impl AsRef<str> for FileType {
 			Self::Markdown => MARKDOWN_EXT,
 			Self::Html => HTML_EXT,
 			Self::Xml => XML_EXT,
+			Self::Notebook => NOTEBOOK_EXT,
 			Self::None => "",
 			Self::Other(other) => other,
 		}
@@ -376,6 +640,7 @@ impl<'ext> From<&'ext str> for FileType {
 			MARKDOWN_EXT => Self::Markdown,
 			HTML_EXT => Self::Html,
 			XML_EXT => Self::Xml,
+			NOTEBOOK_EXT => Self::Notebook,
 			"" => Self::None,
 			other => Self::Other(other.to_owned()),
 		}
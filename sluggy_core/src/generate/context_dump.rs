@@ -0,0 +1,96 @@
+use super::{
+	config::{Config, DumpContext},
+	content::Content,
+	pagination::Paginator,
+};
+use crate::{
+	error::{Error, Result},
+	map_err,
+};
+use serde_json::{json, Value};
+use std::{
+	collections::BTreeMap,
+	fs,
+	path::{Path, PathBuf},
+};
+use tracing::instrument;
+
+/// Directory of `cache_dir` which render contexts are written to.
+const CONTEXT_DIR: &str = "context";
+
+/// Whether the render context of the entry at `entry_path` should be written.
+#[inline]
+pub(crate) fn should_dump(entry_path: &Path, config: &Config) -> bool {
+	match &config.dump_context {
+		None => false,
+		Some(DumpContext::All) => true,
+		Some(DumpContext::Entry(path)) => {
+			entry_path == path || entry_path == path.with_extension("")
+		}
+	}
+}
+
+/// Writes the render context of a page of the entry to `{cache_dir}/context`, as JSON. Templates
+/// only get `context`, and the rest is what the template functions return for the entry, so that
+/// the fields which are available can be found without reading the source.
+#[instrument(level = "debug", skip(context, paginator, content))]
+pub(crate) fn dump_context(
+	entry_path: &Path,
+	file_path: &Path,
+	context: &Value,
+	paginator: Option<&Paginator>,
+	content: &Content,
+) -> Result<()> {
+	let config = &content.config;
+
+	let entry = content.entries.get(entry_path).map(|entry| entry.clone());
+	let section = entry
+		.as_ref()
+		.and_then(|entry| entry.section_handle.as_ref())
+		.and_then(|section_handle| content.sections.get(section_handle))
+		.map(|section| section.clone());
+	let taxonomies = content
+		.taxonomies
+		.iter()
+		.map(|taxonomy| {
+			let terms = taxonomy
+				.value()
+				.iter()
+				.map(|term| (term.key().clone(), term.value().clone()))
+				.collect::<BTreeMap<_, _>>();
+			(taxonomy.key().clone(), terms)
+		})
+		.collect::<BTreeMap<_, _>>();
+
+	let dump = json!({
+		"context": context,
+		"entry": entry,
+		"section": section,
+		"paginator": paginator,
+		"taxonomies": taxonomies,
+	});
+
+	let mut dump_path = config.cache_dir.join(CONTEXT_DIR);
+	if let Some(version) = &config.current_version {
+		dump_path.push(version);
+	}
+	let dump_path: PathBuf = dump_path.join(file_path).with_extension("json");
+
+	if let Some(parent) = dump_path.parent() {
+		map_err!(
+			fs::create_dir_all(parent),
+			IoError(format!("failed to create dirs for {}", parent.display())),
+		)?;
+	}
+
+	let dump = map_err!(
+		serde_json::to_string_pretty(&dump),
+		SerdeJsonError("failed to serialize render context"),
+	)?;
+	map_err!(
+		fs::write(&dump_path, dump),
+		IoError(format!("failed to write {}", dump_path.display())),
+	)?;
+
+	Ok(())
+}
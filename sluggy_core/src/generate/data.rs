@@ -0,0 +1,204 @@
+use super::config::Config;
+use crate::{
+	err,
+	error::{Error, Result},
+	map_err,
+};
+use serde_derive::Deserialize;
+#[cfg(feature = "data-formats")]
+use serde_json::Map;
+use serde_json::Value;
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+use tracing::instrument;
+
+/// Paths starting with this prefix are relative to `data_dir`.
+const DATA_DIR_PREFIX: &str = "@/";
+
+/// Formats which data files can be loaded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DataFormat {
+	Json,
+	Toml,
+	Yaml,
+	/// Loaded as an array of objects, keyed by the header row.
+	Csv,
+}
+
+impl DataFormat {
+	/// Guesses the format from the extension of `path`.
+	#[inline]
+	pub(crate) fn from_path(path: &Path) -> Option<Self> {
+		match path.extension()?.to_str()? {
+			"json" => Some(Self::Json),
+			"toml" => Some(Self::Toml),
+			"yaml" | "yml" => Some(Self::Yaml),
+			"csv" => Some(Self::Csv),
+			_ => None,
+		}
+	}
+}
+
+/// Resolves `path` relative to `data_dir` when it starts with `@/`, or the current directory
+/// otherwise. The path must be within the project.
+#[instrument(level = "debug", skip(config))]
+pub(crate) fn resolve_data_path(path: &str, config: &Config) -> Result<PathBuf> {
+	let path = match path.strip_prefix(DATA_DIR_PREFIX) {
+		Some(path) => config.data_dir.join(path),
+		None => {
+			let current_dir = map_err!(
+				std::env::current_dir(),
+				IoError("failed to get current dir"),
+			)?;
+			current_dir.join(path)
+		}
+	};
+
+	let path = map_err!(
+		path.canonicalize(),
+		IoError(format!("file not found: {}", path.display())),
+	)?;
+
+	if !config.is_project_path(&path) {
+		return Err(err!(Validation(format!(
+			"{} is outside of the project",
+			path.display()
+		))));
+	}
+
+	Ok(path)
+}
+
+/// Reads and parses the data file at `path`, guessing the format from its extension if it isn't
+/// given.
+#[instrument(level = "debug")]
+pub(crate) fn read_data(path: &Path, format: Option<DataFormat>) -> Result<Value> {
+	let format = format
+		.or_else(|| DataFormat::from_path(path))
+		.ok_or(err!(Validation(format!(
+			"unable to determine the data format of {}",
+			path.display()
+		))))?;
+
+	let source = map_err!(
+		fs::read_to_string(path),
+		IoError(format!("failed to read file {}", path.display())),
+	)?;
+
+	parse_data(&source, format, &path.display().to_string())
+}
+
+/// Parses `source` in the given format. `origin` is the file or URL it was read from, for errors.
+#[instrument(level = "trace", skip(source))]
+pub(crate) fn parse_data(source: &str, format: DataFormat, origin: &str) -> Result<Value> {
+	match format {
+		DataFormat::Json => serde_json::from_str(source).map_err(|error| {
+			Error::json_in_source(
+				format!("failed to parse JSON {origin}"),
+				origin,
+				source,
+				error,
+			)
+		}),
+		DataFormat::Toml => {
+			let value: toml::Value = toml::from_str(source).map_err(|error| {
+				Error::toml_in_source(
+					format!("failed to parse TOML {origin}"),
+					origin,
+					source,
+					error,
+				)
+			})?;
+			map_err!(
+				serde_json::to_value(value),
+				SerdeJsonError(format!("failed to convert TOML {origin}")),
+			)
+		}
+		#[cfg(feature = "data-formats")]
+		DataFormat::Yaml => serde_yaml::from_str(source).map_err(|error| {
+			Error::yaml_in_source(
+				format!("failed to parse YAML {origin}"),
+				origin,
+				source,
+				error,
+			)
+		}),
+		#[cfg(feature = "data-formats")]
+		DataFormat::Csv => {
+			let mut reader = csv::Reader::from_reader(source.as_bytes());
+			let headers = map_err!(
+				reader.headers(),
+				CsvError(format!("failed to read CSV headers of {origin}")),
+			)?
+			.clone();
+
+			let mut rows = vec![];
+			for record in reader.records() {
+				let record = record.map_err(|error| match error.position() {
+					Some(position) => Error::in_source(
+						format!("failed to parse CSV {origin}"),
+						origin,
+						source,
+						position.byte() as usize..position.byte() as usize,
+						error.to_string(),
+					),
+					None => Error::CsvError {
+						message: format!("failed to parse CSV {origin}"),
+						source: error,
+					},
+				})?;
+
+				let row = headers
+					.iter()
+					.zip(record.iter())
+					.map(|(header, value)| (header.to_string(), Value::String(value.to_string())))
+					.collect::<Map<_, _>>();
+				rows.push(Value::Object(row));
+			}
+
+			Ok(Value::Array(rows))
+		}
+		#[cfg(not(feature = "data-formats"))]
+		DataFormat::Yaml | DataFormat::Csv => Err(err!(Validation(format!(
+			"{origin} can't be loaded, as YAML and CSV require sluggy to be built with the \
+			 `data-formats` feature"
+		)))),
+	}
+}
+
+/// `value` without the fields which are `null` in YAML and JSON, which TOML has no value for, as
+/// though they were unset, like unset frontmatter fields. Elements of arrays which are `null` are
+/// kept, so that the indices of the elements after them don't shift, and fail to load as TOML.
+#[inline]
+pub(crate) fn without_nulls(value: Value) -> Value {
+	match value {
+		Value::Object(table) => Value::Object(
+			table
+				.into_iter()
+				.filter(|(_, value)| !value.is_null())
+				.map(|(key, value)| (key, without_nulls(value)))
+				.collect(),
+		),
+		Value::Array(values) => Value::Array(values.into_iter().map(without_nulls).collect()),
+		value => value,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn without_nulls_keeps_array_elements() {
+		let value = json!({ "title": null, "items": [1, null, { "name": "a", "url": null }] });
+
+		assert_eq!(
+			without_nulls(value),
+			json!({ "items": [1, null, { "name": "a" }] })
+		);
+	}
+}
@@ -0,0 +1,129 @@
+use super::config::Config;
+use crate::{
+	err,
+	error::{Error, Result},
+	map_err,
+	utils::{escape_xml, hex_digest},
+};
+use comrak::nodes::{AstNode, NodeHtmlBlock, NodeValue};
+use std::{
+	fs,
+	io::Write,
+	process::{Command, Stdio},
+};
+use tracing::instrument;
+
+/// Rendered diagrams are cached in this directory under `cache_dir`.
+const DIAGRAMS_CACHE_DIR: &str = "diagrams";
+
+/// Replaces fenced code blocks of diagram languages with their rendered SVG.
+///
+/// When build-time rendering is disabled, diagrams are wrapped in `<pre class="{lang}">` so that
+/// they can be rendered client-side instead, e.g. by mermaid.js.
+#[instrument(level = "debug", skip_all)]
+pub(crate) fn render_diagrams<'a>(root: &'a AstNode<'a>, config: &Config) -> Result<()> {
+	for node in root.descendants() {
+		let mut data = node.data.borrow_mut();
+		let NodeValue::CodeBlock(code_block) = &data.value else {
+			continue;
+		};
+
+		let lang = code_block
+			.info
+			.split_whitespace()
+			.next()
+			.unwrap_or_default();
+		let Some(command) = config.diagrams.renderers.get(lang) else {
+			continue;
+		};
+
+		let html = if config.diagrams.enabled {
+			render_diagram(lang, command, &code_block.literal, config)?
+		} else {
+			format!(
+				"<pre class=\"{lang}\">{}</pre>\n",
+				escape_xml(&code_block.literal)
+			)
+		};
+
+		data.value = NodeValue::HtmlBlock(NodeHtmlBlock {
+			block_type: 0,
+			literal: html,
+		});
+	}
+
+	Ok(())
+}
+
+/// Pipes the diagram source through the renderer, caching the SVG between builds.
+#[instrument(level = "debug", skip(source, config))]
+fn render_diagram(lang: &str, command: &[String], source: &str, config: &Config) -> Result<String> {
+	let cache_path = config
+		.cache_dir
+		.join(DIAGRAMS_CACHE_DIR)
+		.join(hex_digest(format!("{lang}{command:?}{source}")))
+		.with_extension("svg");
+
+	if let Ok(svg) = fs::read_to_string(&cache_path) {
+		return Ok(svg);
+	}
+
+	let Some((program, args)) = command.split_first() else {
+		return Err(err!(Validation(format!(
+			"diagram renderer for `{lang}` has no command"
+		))));
+	};
+
+	let mut child = map_err!(
+		Command::new(program)
+			.args(args)
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn(),
+		IoError(format!("failed to run diagram renderer {program}")),
+	)?;
+
+	if let Some(mut stdin) = child.stdin.take() {
+		map_err!(
+			stdin.write_all(source.as_bytes()),
+			IoError(format!("failed to write to diagram renderer {program}")),
+		)?;
+	}
+
+	let output = map_err!(
+		child.wait_with_output(),
+		IoError(format!("failed to run diagram renderer {program}")),
+	)?;
+
+	if !output.status.success() {
+		return Err(err!(Validation(format!(
+			"failed to render `{lang}` diagram: {}",
+			String::from_utf8_lossy(&output.stderr).trim()
+		))));
+	}
+
+	let svg = String::from_utf8_lossy(&output.stdout);
+	// Drop the XML declaration and doctype so that the SVG can be embedded in HTML.
+	let svg = match svg.find("<svg") {
+		Some(start) => svg[start..].trim_end().to_string(),
+		None => {
+			return Err(err!(Validation(format!(
+				"diagram renderer for `{lang}` did not output an SVG"
+			))));
+		}
+	};
+
+	if let Some(parent) = cache_path.parent() {
+		map_err!(
+			fs::create_dir_all(parent),
+			IoError(format!("failed to create dirs for {}", parent.display())),
+		)?;
+	}
+	map_err!(
+		fs::write(&cache_path, &svg),
+		IoError(format!("failed to write {}", cache_path.display())),
+	)?;
+
+	Ok(svg)
+}
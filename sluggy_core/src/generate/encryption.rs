@@ -0,0 +1,214 @@
+use super::content::Content;
+use crate::{
+	err,
+	error::{Error, Result},
+};
+#[cfg(feature = "encryption")]
+use crate::{lazyfn::LazyFn, utils::escape_xml};
+#[cfg(feature = "encryption")]
+use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit, Nonce};
+#[cfg(feature = "encryption")]
+use base64::prelude::*;
+#[cfg(feature = "encryption")]
+use dashmap::DashMap;
+#[cfg(feature = "encryption")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "encryption")]
+use sha2::{Digest, Sha256};
+use std::path::Path;
+#[cfg(feature = "encryption")]
+use std::{ffi::OsStr, path::PathBuf};
+use tracing::instrument;
+
+/// Iterations of PBKDF2-HMAC-SHA256 which the key is derived from the password with.
+#[cfg(feature = "encryption")]
+const PBKDF2_ITERATIONS: u32 = 310_000;
+
+#[cfg(feature = "encryption")]
+const SALT_LEN: usize = 16;
+
+/// Length of AES-GCM nonces.
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 12;
+
+/// Passwords of the protected entries of the tree being generated, keyed on their path.
+#[cfg(feature = "encryption")]
+static PASSWORDS: LazyFn<DashMap<PathBuf, String>> = LazyFn::new(DashMap::new);
+
+/// Collects the passwords of the entries of the tree, before they're rendered.
+#[cfg(feature = "encryption")]
+#[instrument(skip_all)]
+pub(crate) fn collect_passwords(content: &Content) -> Result<()> {
+	PASSWORDS.clear();
+
+	for entry in content.entries.iter() {
+		if let Some(password) = &entry.password {
+			PASSWORDS.insert(entry.key().clone(), password.clone());
+		}
+	}
+
+	Ok(())
+}
+
+/// Without the `encryption` feature, rendering protected entries as they are would publish them.
+#[cfg(not(feature = "encryption"))]
+#[instrument(skip_all)]
+pub(crate) fn collect_passwords(content: &Content) -> Result<()> {
+	match content
+		.entries
+		.iter()
+		.find(|entry| entry.password.is_some())
+	{
+		Some(entry) => Err(err!(Validation(format!(
+			"{} has a password, but sluggy was built without the `encryption` feature",
+			entry.key().display()
+		)))),
+		None => Ok(()),
+	}
+}
+
+/// Replaces the rendered HTML of protected entries with a page which decrypts it in the browser,
+/// once the password is entered. Anything else is returned as it is.
+#[cfg(feature = "encryption")]
+#[instrument(level = "debug", skip(output))]
+#[inline]
+pub(crate) fn protect_page(entry_path: &Path, out_file: &Path, output: Vec<u8>) -> Result<Vec<u8>> {
+	if out_file.extension() != Some(OsStr::new("html")) {
+		return Ok(output);
+	}
+
+	match PASSWORDS.get(entry_path) {
+		Some(password) => {
+			Ok(decrypt_page(&encrypt(entry_path, &output, password.value())?).into_bytes())
+		}
+		None => Ok(output),
+	}
+}
+
+#[cfg(not(feature = "encryption"))]
+#[inline]
+pub(crate) fn protect_page(
+	_entry_path: &Path,
+	_out_file: &Path,
+	output: Vec<u8>,
+) -> Result<Vec<u8>> {
+	Ok(output)
+}
+
+/// The salt, nonce and ciphertext of `html`, the page of `entry_path`, base64 encoded.
+///
+/// The salt and nonce are derived from the password, the path and the page, so that unchanged
+/// pages are encrypted to the same output on each build. Pages which change get a new salt, and so
+/// a new key, which keeps nonces from being reused with a key. They're keyed on the password, so
+/// that they don't give away whether a guess of the page is right.
+#[cfg(feature = "encryption")]
+#[inline]
+fn encrypt(entry_path: &Path, html: &[u8], password: &str) -> Result<String> {
+	let salt = derive(password, b"salt", entry_path, html)?;
+	let salt = &salt[..SALT_LEN];
+
+	let mut key = [0u8; 32];
+	pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, PBKDF2_ITERATIONS, &mut key);
+
+	let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+	let nonce = derive(password, b"nonce", entry_path, html)?;
+	let nonce = Nonce::from_slice(&nonce[..NONCE_LEN]);
+	let ciphertext = cipher
+		.encrypt(nonce, html)
+		.map_err(|_| err!(Validation("failed to encrypt page")))?;
+
+	let mut payload = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+	payload.extend_from_slice(salt);
+	payload.extend_from_slice(nonce);
+	payload.extend_from_slice(&ciphertext);
+
+	Ok(BASE64_STANDARD.encode(payload))
+}
+
+/// HMAC-SHA256, keyed on `password`, of what `purpose` is derived for, `entry_path` and the digest
+/// of `html`.
+#[cfg(feature = "encryption")]
+#[inline]
+fn derive(password: &str, purpose: &[u8], entry_path: &Path, html: &[u8]) -> Result<[u8; 32]> {
+	let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(password.as_bytes())
+		.map_err(|_| err!(Validation("failed to derive the encryption parameters")))?;
+	mac.update(purpose);
+	mac.update(&[0]);
+	mac.update(entry_path.to_string_lossy().as_bytes());
+	mac.update(&[0]);
+	mac.update(&Sha256::digest(html));
+
+	Ok(mac.finalize().into_bytes().into())
+}
+
+/// A password form which decrypts `payload` with the Web Crypto API, and replaces the document
+/// with the page.
+#[cfg(feature = "encryption")]
+#[inline]
+fn decrypt_page(payload: &str) -> String {
+	format!(
+		r#"<!DOCTYPE html><html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width,initial-scale=1"><meta name="robots" content="noindex"><title>Protected page</title></head><body><form id="unlock"><label for="password">This page is protected. Enter the password to view it.</label> <input id="password" type="password" autocomplete="current-password" required autofocus> <button>View</button><p id="error" hidden>Incorrect password.</p></form><script>(function(){{var payload="{payload}";document.getElementById("unlock").addEventListener("submit",function(event){{event.preventDefault();var data=Uint8Array.from(atob(payload),function(c){{return c.charCodeAt(0)}});var password=new TextEncoder().encode(document.getElementById("password").value);crypto.subtle.importKey("raw",password,"PBKDF2",false,["deriveKey"]).then(function(material){{return crypto.subtle.deriveKey({{name:"PBKDF2",salt:data.slice(0,{SALT_LEN}),iterations:{PBKDF2_ITERATIONS},hash:"SHA-256"}},material,{{name:"AES-GCM",length:256}},false,["decrypt"])}}).then(function(key){{return crypto.subtle.decrypt({{name:"AES-GCM",iv:data.slice({SALT_LEN},{iv_end})}},key,data.slice({iv_end}))}}).then(function(html){{document.open();document.write(new TextDecoder().decode(html));document.close()}},function(){{document.getElementById("error").hidden=false}})}})}})();</script></body></html>"#,
+		payload = escape_xml(payload),
+		iv_end = SALT_LEN + NONCE_LEN,
+	)
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+	use super::*;
+
+	fn decrypt(payload: &str, password: &str) -> Option<Vec<u8>> {
+		let payload = BASE64_STANDARD.decode(payload).ok()?;
+		let (salt, rest) = payload.split_at(SALT_LEN);
+		let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+		let mut key = [0u8; 32];
+		pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, PBKDF2_ITERATIONS, &mut key);
+		Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+			.decrypt(Nonce::from_slice(nonce), ciphertext)
+			.ok()
+	}
+
+	#[test]
+	fn encrypt_round_trip() {
+		let html = b"<p>secret</p>";
+		let payload = encrypt(Path::new("posts/secret"), html, "hunter2").unwrap();
+
+		assert_eq!(decrypt(&payload, "hunter2").as_deref(), Some(&html[..]));
+		assert_eq!(decrypt(&payload, "hunter3"), None);
+	}
+
+	#[test]
+	fn encrypt_is_stable_between_builds() {
+		let path = Path::new("posts/secret");
+		assert_eq!(
+			encrypt(path, b"<p>secret</p>", "hunter2").unwrap(),
+			encrypt(path, b"<p>secret</p>", "hunter2").unwrap()
+		);
+	}
+
+	#[test]
+	fn encrypt_salts_each_page() {
+		let salt = |path: &str, html: &[u8], password: &str| {
+			let payload = encrypt(Path::new(path), html, password).unwrap();
+			BASE64_STANDARD.decode(payload).unwrap()[..SALT_LEN + NONCE_LEN].to_vec()
+		};
+
+		let salted = salt("posts/a", b"<p>a</p>", "hunter2");
+		assert_ne!(salted, salt("posts/b", b"<p>a</p>", "hunter2"));
+		assert_ne!(salted, salt("posts/a", b"<p>b</p>", "hunter2"));
+		assert_ne!(salted, salt("posts/a", b"<p>a</p>", "hunter3"));
+	}
+
+	#[test]
+	fn protect_page_leaves_unprotected_pages() {
+		let output = b"<p>public</p>".to_vec();
+		let protected = protect_page(
+			Path::new("posts/public"),
+			Path::new("posts/public/index.html"),
+			output.clone(),
+		)
+		.unwrap();
+		assert_eq!(protected, output);
+	}
+}
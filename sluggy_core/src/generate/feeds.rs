@@ -0,0 +1,492 @@
+use super::{
+	config::{Config, FeedConfig, PodcastConfig},
+	content::{Content, Entry},
+	media::Media,
+	output_manifest,
+	paths::resolve_path,
+	process_output,
+	sections::{Listing, SectionHandle},
+};
+use crate::{
+	err,
+	error::{Error, Result},
+	map_err,
+	utils::{escape_xml, unlink_output},
+};
+use serde_json::json;
+use std::{
+	cmp::Reverse,
+	fmt::Write,
+	fs,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+use tokio::task::JoinSet;
+use toml::value::Array;
+use tracing::{debug, instrument};
+
+const CHAPTERS_DIR: &str = "chapters";
+
+/// Writes each of the configured feeds to `out_dir`.
+#[instrument(skip_all)]
+pub(crate) fn generate_feeds(content: &Content, join_set: &mut JoinSet<Result<()>>) -> Result<()> {
+	let config = &content.config;
+
+	for feed_config in &config.feeds {
+		let entries = feed_entries(content, feed_config);
+		write_feed(feed_config, &entries, config, join_set)?;
+	}
+
+	Ok(())
+}
+
+/// Writes a feed of `entries` to the path of `feed_config`.
+#[inline]
+pub(crate) fn write_feed(
+	feed_config: &FeedConfig,
+	entries: &[Entry],
+	config: &Arc<Config>,
+	join_set: &mut JoinSet<Result<()>>,
+) -> Result<()> {
+	let feed_path = config.out_dir.join(&feed_config.path);
+	let feed_dir = feed_path
+		.parent()
+		.map(Path::to_path_buf)
+		.unwrap_or_else(|| config.out_dir.clone());
+
+	map_err!(
+		fs::create_dir_all(&feed_dir),
+		IoError(format!("failed to create dirs for {}", feed_dir.display())),
+	)?;
+
+	let mut feed = render_feed(feed_config, entries, config)?.into_bytes();
+	config
+		.hooks
+		.run_before_write_output(&feed_path, &mut feed)?;
+
+	unlink_output(&feed_path)?;
+	map_err!(
+		fs::write(&feed_path, feed),
+		IoError(format!("failed to write feed {}", feed_path.display())),
+	)?;
+
+	// Items are only in podcast feeds when they have audio.
+	if let Some(podcast) = &feed_config.podcast {
+		for entry in entries
+			.iter()
+			.filter(|entry| entry.extra.contains_key(&podcast.audio_field))
+		{
+			write_chapters(entry, podcast, feed_config, config)?;
+		}
+	}
+
+	process_output(&feed_path, join_set, config.clone())?;
+
+	Ok(())
+}
+
+/// Dated entries of the feed's section whose kinds are in feeds, newest first. Entries of podcast
+/// feeds without audio are left out before the limit is applied.
+#[inline]
+fn feed_entries(content: &Content, feed_config: &FeedConfig) -> Vec<Entry> {
+	let section_handle = feed_config.section.as_deref().map(SectionHandle::from);
+
+	let mut entries = content
+		.entries
+		.iter()
+		.map(|entry| entry.value().clone())
+		.filter(|entry| entry.published.is_some() && is_in_feeds(entry, content))
+		.filter(|entry| {
+			section_handle.is_none() || entry.section_handle.as_ref() == section_handle.as_ref()
+		})
+		.filter(|entry| {
+			feed_config
+				.podcast
+				.as_ref()
+				.is_none_or(|podcast| entry.extra.contains_key(&podcast.audio_field))
+		})
+		.collect::<Vec<_>>();
+
+	entries.sort_by_key(|entry| Reverse(entry.published));
+
+	if let Some(limit) = feed_config.limit {
+		entries.truncate(limit);
+	}
+
+	entries
+}
+
+/// Whether the kind of `entry` has feeds, e.g. posts but not pages, and its sections don't leave
+/// it out of them. Protected entries aren't, as their content would be readable in feeds, and nor
+/// are error pages.
+#[inline]
+pub(crate) fn is_in_feeds(entry: &Entry, content: &Content) -> bool {
+	!entry.protected
+		&& !entry.is_error_page()
+		&& content
+			.config
+			.kind(&entry.kind)
+			.is_some_and(|kind| kind.feed)
+		&& !content.is_excluded_from(entry, Listing::Feeds)
+}
+
+/// Renders an RSS 2.0 feed of `entries`, in the order given, with iTunes and podcast namespace
+/// tags when the feed is a podcast. Nothing is written, so the chapters files which podcast items
+/// link to are only there once the feed is generated.
+#[instrument(level = "debug", skip(entries, config))]
+pub fn render_feed<'e>(
+	feed_config: &FeedConfig,
+	entries: impl IntoIterator<Item = &'e Entry>,
+	config: &Arc<Config>,
+) -> Result<String> {
+	let entries = entries.into_iter().collect::<Vec<_>>();
+
+	let feed_url = format!("{}{}", config.base_url, feed_config.path.to_string_lossy());
+
+	let mut xml = String::new();
+	xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+	xml.push_str(r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom""#);
+	if feed_config.podcast.is_some() {
+		xml.push_str(r#" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd""#);
+		xml.push_str(r#" xmlns:podcast="https://podcastindex.org/namespace/1.0""#);
+	}
+	xml.push_str("><channel>");
+
+	push_element(&mut xml, "title", &feed_config.title);
+	push_element(&mut xml, "link", &config.base_url);
+	push_element(
+		&mut xml,
+		"description",
+		feed_config.description.as_deref().unwrap_or_default(),
+	);
+	push_empty_element(
+		&mut xml,
+		"atom:link",
+		&[
+			("href", feed_url.as_str()),
+			("rel", "self"),
+			("type", "application/rss+xml"),
+		],
+	);
+	if let Some(language) = &feed_config.language {
+		push_element(&mut xml, "language", language);
+	}
+	if let Some(published) = entries.first().and_then(|entry| entry.published) {
+		push_element(&mut xml, "lastBuildDate", &published.to_rfc2822());
+	}
+
+	if let Some(podcast) = &feed_config.podcast {
+		push_podcast_channel(&mut xml, podcast);
+	}
+
+	for entry in entries {
+		let enclosure = match &feed_config.podcast {
+			Some(podcast) => match podcast_enclosure(entry, podcast, config)? {
+				Some(media) => Some(media),
+				None => {
+					debug!(
+						"skipping {} which has no `{}` field",
+						entry.path.display(),
+						podcast.audio_field
+					);
+					continue;
+				}
+			},
+			None => None,
+		};
+
+		xml.push_str("<item>");
+		push_element(&mut xml, "title", &entry.title());
+		push_element(&mut xml, "link", &entry.link);
+		xml.push_str(r#"<guid isPermaLink="true">"#);
+		xml.push_str(&escape_xml(&entry.url));
+		xml.push_str("</guid>");
+		if let Some(published) = entry.published {
+			push_element(&mut xml, "pubDate", &published.to_rfc2822());
+		}
+		if let Some(description) = entry
+			.extra
+			.get("description")
+			.and_then(|value| value.as_str())
+		{
+			push_element(&mut xml, "description", description);
+		}
+
+		if let (Some(podcast), Some(media)) = (&feed_config.podcast, enclosure) {
+			let length = media.size.to_string();
+			push_empty_element(
+				&mut xml,
+				"enclosure",
+				&[
+					("url", media.url.as_deref().unwrap_or_default()),
+					("length", length.as_str()),
+					("type", media.mime_type.as_str()),
+				],
+			);
+			if let Some(duration) = media.duration {
+				push_element(
+					&mut xml,
+					"itunes:duration",
+					&(duration.round() as u64).to_string(),
+				);
+			}
+
+			if let Some(chapters_url) = chapters_url(entry, podcast, feed_config, config) {
+				push_empty_element(
+					&mut xml,
+					"podcast:chapters",
+					&[
+						("url", chapters_url.as_str()),
+						("type", "application/json+chapters"),
+					],
+				);
+			}
+		}
+
+		xml.push_str("</item>");
+	}
+
+	xml.push_str("</channel></rss>");
+
+	Ok(xml)
+}
+
+#[inline]
+fn push_podcast_channel(xml: &mut String, podcast: &PodcastConfig) {
+	if let Some(author) = &podcast.author {
+		push_element(xml, "itunes:author", author);
+	}
+	if podcast.owner_name.is_some() || podcast.owner_email.is_some() {
+		xml.push_str("<itunes:owner>");
+		if let Some(owner_name) = &podcast.owner_name {
+			push_element(xml, "itunes:name", owner_name);
+		}
+		if let Some(owner_email) = &podcast.owner_email {
+			push_element(xml, "itunes:email", owner_email);
+		}
+		xml.push_str("</itunes:owner>");
+	}
+	if let Some(image) = &podcast.image {
+		push_empty_element(xml, "itunes:image", &[("href", image.as_str())]);
+	}
+	for category in &podcast.categories {
+		push_empty_element(xml, "itunes:category", &[("text", category.as_str())]);
+	}
+	push_element(
+		xml,
+		"itunes:explicit",
+		if podcast.explicit { "true" } else { "false" },
+	);
+	if let Some(show_type) = &podcast.show_type {
+		push_element(xml, "itunes:type", show_type);
+	}
+}
+
+/// Reads the audio file referenced by the entry's frontmatter.
+#[inline]
+fn podcast_enclosure(
+	entry: &Entry,
+	podcast: &PodcastConfig,
+	config: &Arc<Config>,
+) -> Result<Option<Media>> {
+	let Some(audio) = entry
+		.extra
+		.get(&podcast.audio_field)
+		.and_then(|value| value.as_str())
+	else {
+		return Ok(None);
+	};
+
+	let audio_path = resolve_path(audio, Some(&entry.file_path), config)?;
+	let media = Media::read(audio_path, config)?;
+
+	if media.url.is_none() {
+		return Err(err!(Validation(format!(
+			"audio file {audio} of {} must be in the assets or content dir",
+			entry.path.display()
+		))));
+	}
+
+	Ok(Some(media))
+}
+
+#[inline]
+fn entry_chapters<'e>(entry: &'e Entry, podcast: &PodcastConfig) -> Option<&'e Array> {
+	podcast
+		.chapters_field
+		.as_ref()
+		.and_then(|chapters_field| entry.extra.get(chapters_field))
+		.and_then(|value| value.as_array())
+}
+
+/// Path of the entry's chapters file relative to `out_dir`, next to the feed.
+#[inline]
+fn chapters_path(entry: &Entry, feed_config: &FeedConfig) -> PathBuf {
+	feed_config
+		.path
+		.parent()
+		.unwrap_or(Path::new(""))
+		.join(CHAPTERS_DIR)
+		.join(&entry.path)
+		.with_extension("json")
+}
+
+#[inline]
+fn chapters_url(
+	entry: &Entry,
+	podcast: &PodcastConfig,
+	feed_config: &FeedConfig,
+	config: &Config,
+) -> Option<String> {
+	entry_chapters(entry, podcast)?;
+
+	Some(format!(
+		"{}{}",
+		config.base_url,
+		chapters_path(entry, feed_config).to_string_lossy()
+	))
+}
+
+/// Writes the entry's chapters as a JSON chapters file, if it has any.
+#[inline]
+fn write_chapters(
+	entry: &Entry,
+	podcast: &PodcastConfig,
+	feed_config: &FeedConfig,
+	config: &Config,
+) -> Result<()> {
+	let Some(chapters) = entry_chapters(entry, podcast) else {
+		return Ok(());
+	};
+
+	let chapters = chapters
+		.iter()
+		.map(|chapter| {
+			let start = chapter
+				.get("start")
+				.and_then(|start| {
+					start
+						.as_float()
+						.or(start.as_integer().map(|start| start as f64))
+				})
+				.ok_or(err!(Validation(format!(
+					"chapters of {} must have a numeric `start`",
+					entry.path.display()
+				))))?;
+
+			let mut chapter_json = json!({ "startTime": start });
+			for (field, key) in [("title", "title"), ("url", "url"), ("image", "img")] {
+				if let Some(value) = chapter.get(field).and_then(|value| value.as_str()) {
+					chapter_json[key] = json!(value);
+				}
+			}
+
+			Ok(chapter_json)
+		})
+		.collect::<Result<Vec<_>>>()?;
+
+	let chapters_path = config.out_dir.join(chapters_path(entry, feed_config));
+
+	if let Some(parent) = chapters_path.parent() {
+		map_err!(
+			fs::create_dir_all(parent),
+			IoError(format!("failed to create dirs for {}", parent.display())),
+		)?;
+	}
+
+	let chapters_json = map_err!(
+		serde_json::to_string(&json!({ "version": "1.2.0", "chapters": chapters })),
+		SerdeJsonError("failed to serialize chapters"),
+	)?;
+
+	unlink_output(&chapters_path)?;
+	map_err!(
+		fs::write(&chapters_path, chapters_json),
+		IoError(format!("failed to write {}", chapters_path.display())),
+	)?;
+	output_manifest::record(&chapters_path, None);
+
+	Ok(())
+}
+
+#[inline]
+fn push_element(xml: &mut String, name: &str, value: &str) {
+	let _ = write!(xml, "<{name}>{}</{name}>", escape_xml(value));
+}
+
+#[inline]
+fn push_empty_element(xml: &mut String, name: &str, attributes: &[(&str, &str)]) {
+	let _ = write!(xml, "<{name}");
+	for (attribute, value) in attributes {
+		let _ = write!(xml, r#" {attribute}="{}""#, escape_xml(value));
+	}
+	xml.push_str("/>");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::generate::content::FileType;
+	use chrono::{TimeZone, Utc};
+	use dashmap::DashMap;
+	use std::collections::BTreeMap;
+
+	#[test]
+	fn podcast_entries_without_audio_are_left_out_before_the_limit() {
+		let content = Content {
+			entries: DashMap::new(),
+			sections: DashMap::new(),
+			taxonomies: DashMap::new(),
+			config: Arc::new(Config::default()),
+		};
+		for (day, path, audio) in [(3, "newest", false), (2, "b", true), (1, "c", true)] {
+			let path = PathBuf::from("posts").join(path);
+			let mut extra = toml::Table::new();
+			if audio {
+				extra.insert("audio".into(), "episode.mp3".into());
+			}
+			content.entries.insert(
+				path.clone(),
+				Entry {
+					slug: None,
+					layout: None,
+					kind: "post".into(),
+					url: format!("/{}/", path.display()),
+					external_url: None,
+					link: format!("/{}/", path.display()),
+					file_path: path.with_extension("md"),
+					path,
+					file_type: FileType::Markdown,
+					published: Some(Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap()),
+					updated: None,
+					section_handle: None,
+					is_renderable: true,
+					draft: false,
+					headers: BTreeMap::new(),
+					aliases: vec![],
+					password: None,
+					protected: false,
+					extra,
+				},
+			);
+		}
+
+		let feed_config = FeedConfig {
+			path: PathBuf::from("podcast.xml"),
+			title: "Podcast".into(),
+			description: None,
+			language: None,
+			section: None,
+			limit: Some(2),
+			podcast: Some(toml::from_str("").unwrap()),
+		};
+		let paths = feed_entries(&content, &feed_config)
+			.into_iter()
+			.map(|entry| entry.path)
+			.collect::<Vec<_>>();
+		assert_eq!(
+			paths,
+			vec![PathBuf::from("posts/b"), PathBuf::from("posts/c")]
+		);
+	}
+}
@@ -0,0 +1,262 @@
+use super::{
+	config::Config, html::links::base_path, output_manifest, paths::normalize_path,
+	postprocess::glob_pattern,
+};
+use crate::{
+	error::{Error, Result},
+	lazyfn::LazyFn,
+	map_err,
+	utils::{cached_regex, hex_digest, unlink_output},
+};
+use dashmap::DashMap;
+use regex::{Captures, Regex};
+use std::{
+	collections::BTreeMap,
+	fs,
+	path::{Path, PathBuf},
+};
+use tracing::instrument;
+
+/// Name of the manifest of the fingerprinted files of each tree, written to its `out_dir`.
+pub(crate) const MANIFEST_FILE: &str = "asset-manifest.json";
+
+/// Length of the hash which is added to the names of fingerprinted files.
+const HASH_LEN: usize = 8;
+
+/// `out_dir` of the tree, and the path of a file within it -> the fingerprinted path of the file.
+static MANIFEST: LazyFn<DashMap<(PathBuf, PathBuf), PathBuf>> = LazyFn::new(DashMap::new);
+
+static CSS_URL_RE: LazyFn<Regex> =
+	LazyFn::new(|| Regex::new(r#"url\(\s*(["']?)([^"')\s]+)(["']?)\s*\)"#).unwrap());
+
+/// The path, relative to `out_dir`, which `path` is written to. When fingerprinting is enabled, a
+/// hash of `content` is added before its extension, e.g. `css/app.3f9ab2c1.css`, and recorded in
+/// the manifest.
+#[instrument(level = "trace", skip(content, config))]
+#[inline]
+pub(crate) fn fingerprint(path: &Path, content: &[u8], config: &Config) -> PathBuf {
+	if !config.fingerprint.enabled {
+		return path.to_path_buf();
+	}
+
+	let hash = &hex_digest(content)[..HASH_LEN];
+	let file_name = match (
+		path.file_stem().map(|stem| stem.to_string_lossy()),
+		path.extension()
+			.map(|extension| extension.to_string_lossy()),
+	) {
+		(Some(stem), Some(extension)) => format!("{stem}.{hash}.{extension}"),
+		(Some(stem), None) => format!("{stem}.{hash}"),
+		_ => return path.to_path_buf(),
+	};
+	let fingerprinted = path.with_file_name(file_name);
+
+	MANIFEST.insert(
+		(config.out_dir.clone(), path.to_path_buf()),
+		fingerprinted.clone(),
+	);
+
+	fingerprinted
+}
+
+/// Whether the file at `path`, relative to `out_dir`, is copied from `assets_dir` with a
+/// fingerprint. Bundled stylesheets and scripts always are when fingerprinting is enabled.
+#[inline]
+pub(crate) fn is_fingerprinted_asset(path: &Path, config: &Config) -> Result<bool> {
+	if !config.fingerprint.enabled {
+		return Ok(false);
+	}
+
+	let path = path.to_string_lossy();
+	for pattern in &config.fingerprint.assets {
+		let pattern_re = map_err!(
+			cached_regex(&glob_pattern(pattern)),
+			RegexError(format!("failed to parse fingerprint pattern \"{pattern}\"")),
+		)?;
+
+		if pattern_re.is_match(&path) {
+			return Ok(true);
+		}
+	}
+
+	Ok(false)
+}
+
+/// The fingerprinted name of the file at `path`, relative to `out_dir`, if it has one.
+#[inline]
+pub(crate) fn resolve(path: &Path, config: &Config) -> PathBuf {
+	MANIFEST
+		.get(&(config.out_dir.clone(), path.to_path_buf()))
+		.map(|fingerprinted| fingerprinted.clone())
+		.unwrap_or_else(|| path.to_path_buf())
+}
+
+/// `css`, the stylesheet which is written to `out_file`, with the `url()`s of fingerprinted files
+/// pointed at their fingerprinted names, e.g. fonts of `assets/fonts/**`. URLs which are relative
+/// to the stylesheet, relative to the root, or start with `base_url` are rewritten. The files are
+/// expected to be copied before the stylesheets are bundled.
+#[inline]
+pub(crate) fn rewrite_css_urls(css: &str, out_file: &Path, config: &Config) -> String {
+	if !config.fingerprint.enabled {
+		return css.to_string();
+	}
+
+	let base_path = base_path(&config.base_url);
+	CSS_URL_RE
+		.replace_all(css, |captures: &Captures| {
+			let url = &captures[2];
+			match fingerprinted_url(url, out_file, &base_path, config) {
+				Some(url) => format!("url({}{url}{})", &captures[1], &captures[3]),
+				None => captures[0].to_string(),
+			}
+		})
+		.into_owned()
+}
+
+/// `url`, a reference of the file at `out_file`, with the fingerprinted name of the file it
+/// references, or `None` if the file isn't fingerprinted.
+#[inline]
+fn fingerprinted_url(
+	url: &str,
+	out_file: &Path,
+	base_path: &str,
+	config: &Config,
+) -> Option<String> {
+	let (url_path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+
+	let path = if let Some(path) = url_path.strip_prefix(&config.base_url) {
+		PathBuf::from(path)
+	} else if url_path.contains(':') || url_path.starts_with("//") || url_path.is_empty() {
+		return None;
+	} else if url_path.starts_with('/') {
+		PathBuf::from(url_path.strip_prefix(base_path)?)
+	} else {
+		out_file.parent().unwrap_or(Path::new("")).join(url_path)
+	};
+
+	let fingerprinted = MANIFEST.get(&(config.out_dir.clone(), normalize_path(&path)))?;
+	let file_name = fingerprinted.file_name()?.to_string_lossy();
+	let dir = url_path.rsplit_once('/').map(|(dir, _)| format!("{dir}/"));
+
+	Some(format!("{}{file_name}{suffix}", dir.unwrap_or_default()))
+}
+
+/// URL of the file at `path`, relative to `out_dir`, using its fingerprinted name if it has one.
+#[inline]
+pub(crate) fn asset_url(path: &str, config: &Config) -> String {
+	format!(
+		"{}{}",
+		config.base_url,
+		resolve(Path::new(path), config).display()
+	)
+}
+
+/// Forgets the fingerprinted files, at the start of a build.
+#[inline]
+pub(crate) fn clear() {
+	MANIFEST.clear();
+}
+
+/// Writes the fingerprinted paths of the files of the tree to its manifest, for hosts and tools
+/// which need to map the original names.
+#[instrument(skip(config))]
+pub(crate) fn write_manifest(config: &Config) -> Result<()> {
+	if !config.fingerprint.enabled {
+		return Ok(());
+	}
+
+	let manifest = MANIFEST
+		.iter()
+		.filter(|item| item.key().0 == config.out_dir)
+		.map(|item| (item.key().1.clone(), item.value().clone()))
+		.collect::<BTreeMap<_, _>>();
+
+	let json = map_err!(
+		serde_json::to_vec_pretty(&manifest),
+		SerdeJsonError("failed to serialize asset manifest"),
+	)?;
+	let manifest_path = config.out_dir.join(MANIFEST_FILE);
+	unlink_output(&manifest_path)?;
+	map_err!(
+		fs::write(&manifest_path, json),
+		IoError(format!("failed to write {MANIFEST_FILE}")),
+	)?;
+	output_manifest::record(&manifest_path, None);
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config(name: &str) -> Config {
+		let mut config = Config::default();
+		config.fingerprint.enabled = true;
+		config.base_url = "https://example.com/blog/".into();
+		config.out_dir = PathBuf::from(format!("out-{name}"));
+		config
+	}
+
+	#[test]
+	fn fingerprint_adds_hash() {
+		let config = config("hash");
+		let fingerprinted = fingerprint(Path::new("assets/fonts/inter.woff2"), b"font", &config);
+		assert_eq!(
+			fingerprinted,
+			PathBuf::from(format!(
+				"assets/fonts/inter.{}.woff2",
+				&hex_digest(b"font")[..HASH_LEN]
+			))
+		);
+		assert_eq!(
+			resolve(Path::new("assets/fonts/inter.woff2"), &config),
+			fingerprinted
+		);
+		assert_eq!(
+			resolve(Path::new("assets/other.woff2"), &config),
+			PathBuf::from("assets/other.woff2")
+		);
+	}
+
+	#[test]
+	fn rewrite_css_urls_of_fingerprinted_files() {
+		let config = config("css");
+		let fingerprinted = fingerprint(Path::new("assets/fonts/inter.woff2"), b"font", &config);
+		let name = fingerprinted.file_name().unwrap().to_string_lossy();
+
+		let css = concat!(
+			"@font-face{src:url(../assets/fonts/inter.woff2?v=1) format(\"woff2\")}",
+			"a{background:url('/blog/assets/fonts/inter.woff2')}",
+			"b{background:url(\"https://example.com/blog/assets/fonts/inter.woff2#x\")}",
+			"c{background:url(../assets/other.png)}",
+			"d{background:url(data:image/png;base64,AAAA)}",
+		);
+		assert_eq!(
+			rewrite_css_urls(css, Path::new("css/main.css"), &config),
+			format!(
+				concat!(
+					"@font-face{{src:url(../assets/fonts/{name}?v=1) format(\"woff2\")}}",
+					"a{{background:url('/blog/assets/fonts/{name}')}}",
+					"b{{background:url(\"https://example.com/blog/assets/fonts/{name}#x\")}}",
+					"c{{background:url(../assets/other.png)}}",
+					"d{{background:url(data:image/png;base64,AAAA)}}",
+				),
+				name = name
+			)
+		);
+	}
+
+	#[test]
+	fn rewrite_css_urls_disabled() {
+		let mut config = config("disabled");
+		fingerprint(Path::new("assets/a.woff2"), b"font", &config);
+		config.fingerprint.enabled = false;
+
+		let css = "a{src:url(../assets/a.woff2)}";
+		assert_eq!(
+			rewrite_css_urls(css, Path::new("css/main.css"), &config),
+			css
+		);
+	}
+}
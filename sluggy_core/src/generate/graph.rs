@@ -0,0 +1,408 @@
+use super::{
+	content::{lookup::EntryLookup, Content},
+	sections::SectionHandle,
+};
+use crate::{error::Result, utils::escape_xml};
+use serde_derive::Serialize;
+use std::{
+	collections::BTreeSet,
+	fmt::{self, Write},
+	path::{Path, PathBuf},
+};
+use tracing::instrument;
+use url::Url;
+
+/// The entries of the site, and the sections, taxonomy terms and other entries they're connected
+/// to.
+#[derive(Debug, Clone, Serialize)]
+pub struct ContentGraph {
+	pub entries: Vec<GraphEntry>,
+	pub sections: Vec<GraphSection>,
+	pub terms: Vec<GraphTerm>,
+	/// Links from the content of entries to other entries.
+	pub links: Vec<GraphLink>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphEntry {
+	pub path: PathBuf,
+	pub url: String,
+	pub title: String,
+	/// RFC 3339 date the entry was published at, if it has one.
+	pub published: Option<String>,
+	/// Handle of the entry's section. The root section is an empty handle.
+	pub section: Option<String>,
+	pub draft: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphSection {
+	pub handle: String,
+	pub title: Option<String>,
+	pub entries: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphTerm {
+	pub taxonomy: String,
+	pub term: String,
+	pub entries: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct GraphLink {
+	pub from: PathBuf,
+	pub to: PathBuf,
+}
+
+impl ContentGraph {
+	/// Collects the graph of `content`, resolving the links of each markdown entry in the same way
+	/// as the `entry` filter. Links which don't resolve to an entry are left out.
+	#[instrument(level = "debug", skip_all)]
+	pub(crate) fn new(content: &Content) -> Result<Self> {
+		let config = &content.config;
+
+		let mut entries = content
+			.entries
+			.iter()
+			.map(|entry| GraphEntry {
+				path: entry.key().clone(),
+				url: entry.url.clone(),
+				title: entry.title(),
+				published: entry.published.map(|published| published.to_rfc3339()),
+				section: entry.section_handle.as_ref().map(section_handle),
+				draft: entry.draft,
+			})
+			.collect::<Vec<_>>();
+		entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+		let mut sections = content
+			.sections
+			.iter()
+			.map(|section| GraphSection {
+				handle: section_handle(section.key()),
+				title: section.title.clone(),
+				entries: section.entries.clone(),
+			})
+			.collect::<Vec<_>>();
+		sections.sort_by(|a, b| a.handle.cmp(&b.handle));
+
+		let mut terms = content
+			.taxonomies
+			.iter()
+			.flat_map(|taxonomy| {
+				taxonomy
+					.value()
+					.iter()
+					.map(|term| GraphTerm {
+						taxonomy: taxonomy.key().clone(),
+						term: term.key().clone(),
+						entries: term.value().clone(),
+					})
+					.collect::<Vec<_>>()
+			})
+			.collect::<Vec<_>>();
+		terms.sort_by(|a, b| (&a.taxonomy, &a.term).cmp(&(&b.taxonomy, &b.term)));
+
+		let entry_lookup = EntryLookup::new(content);
+		let mut links = BTreeSet::new();
+		for entry in content.entries.iter() {
+			for link in entry.links(config)? {
+				let Some(query) = link_query(&link, &entry.url, &config.base_url) else {
+					continue;
+				};
+
+				if let Ok(to) = entry_lookup.resolve(&query, content) {
+					if &to != entry.key() {
+						links.insert(GraphLink {
+							from: entry.key().clone(),
+							to,
+						});
+					}
+				}
+			}
+		}
+
+		Ok(Self {
+			entries,
+			sections,
+			terms,
+			links: links.into_iter().collect(),
+		})
+	}
+
+	/// The graph as GraphML, with a node for each entry, section and term. Entries have edges to
+	/// their section, terms have edges to their entries, and links are edges between entries.
+	/// Nodes of terms are of the `term` kind, with the name of their `taxonomy`.
+	pub fn to_graphml(&self) -> String {
+		let mut graphml = String::new();
+		// Writing to a string can't fail.
+		let _ = self.write_graphml(&mut graphml);
+		graphml
+	}
+
+	#[inline]
+	fn write_graphml(&self, graphml: &mut String) -> fmt::Result {
+		writeln!(graphml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+		writeln!(
+			graphml,
+			r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+		)?;
+		for (id, target) in [
+			("kind", "node"),
+			("label", "node"),
+			("url", "node"),
+			("published", "node"),
+			("draft", "node"),
+			("taxonomy", "node"),
+			("kind", "edge"),
+		] {
+			let attr_type = if id == "draft" { "boolean" } else { "string" };
+			writeln!(
+				graphml,
+				r#"  <key id="{target}_{id}" for="{target}" attr.name="{id}" attr.type="{attr_type}"/>"#
+			)?;
+		}
+		writeln!(graphml, r#"  <graph id="content" edgedefault="directed">"#)?;
+
+		for entry in &self.entries {
+			let id = entry_id(&entry.path);
+			writeln!(graphml, r#"    <node id="{}">"#, escape_xml(&id))?;
+			write_data(graphml, "node_kind", "entry")?;
+			write_data(graphml, "node_label", &entry.title)?;
+			write_data(graphml, "node_url", &entry.url)?;
+			if let Some(published) = &entry.published {
+				write_data(graphml, "node_published", published)?;
+			}
+			write_data(graphml, "node_draft", &entry.draft.to_string())?;
+			writeln!(graphml, "    </node>")?;
+		}
+
+		for section in &self.sections {
+			let id = format!("section:{}", section.handle);
+			writeln!(graphml, r#"    <node id="{}">"#, escape_xml(&id))?;
+			write_data(graphml, "node_kind", "section")?;
+			write_data(
+				graphml,
+				"node_label",
+				section.title.as_deref().unwrap_or(&section.handle),
+			)?;
+			writeln!(graphml, "    </node>")?;
+		}
+
+		for term in &self.terms {
+			let id = term_id(term);
+			writeln!(graphml, r#"    <node id="{}">"#, escape_xml(&id))?;
+			write_data(graphml, "node_kind", "term")?;
+			write_data(graphml, "node_label", &term.term)?;
+			write_data(graphml, "node_taxonomy", &term.taxonomy)?;
+			writeln!(graphml, "    </node>")?;
+		}
+
+		for entry in &self.entries {
+			if let Some(section) = &entry.section {
+				write_edge(
+					graphml,
+					&entry_id(&entry.path),
+					&format!("section:{section}"),
+					"section",
+				)?;
+			}
+		}
+
+		for term in &self.terms {
+			for entry in &term.entries {
+				write_edge(graphml, &term_id(term), &entry_id(entry), "term")?;
+			}
+		}
+
+		for link in &self.links {
+			write_edge(graphml, &entry_id(&link.from), &entry_id(&link.to), "link")?;
+		}
+
+		writeln!(graphml, "  </graph>")?;
+		writeln!(graphml, "</graphml>")
+	}
+}
+
+/// Query of `link` for the entry lookup, if it may link to an entry. Links relative to the entry
+/// are resolved against its URL.
+#[inline]
+fn link_query(link: &str, entry_url: &str, base_url: &str) -> Option<String> {
+	let link = link.split(['#', '?']).next().unwrap_or_default();
+	if link.is_empty() {
+		return None;
+	}
+
+	if let Some(path) = link.strip_prefix("@/") {
+		return Some(path.to_string());
+	}
+	if link.starts_with('/') || link.starts_with(base_url) {
+		return Some(link.to_string());
+	}
+	if link.contains(':') {
+		// Other sites, and `mailto:` and such.
+		return None;
+	}
+
+	let entry_url = Url::parse(entry_url)
+		.or_else(|_| {
+			Url::parse(&format!(
+				"http://localhost/{}",
+				entry_url.trim_start_matches('/')
+			))
+		})
+		.ok()?;
+	// Pages are directories, so links are relative to the URL with a trailing slash, as they are
+	// in browsers.
+	let entry_url = if entry_url.path().ends_with('/') {
+		entry_url
+	} else {
+		Url::parse(&format!("{entry_url}/")).ok()?
+	};
+	entry_url.join(link).ok().map(|url| url.path().to_string())
+}
+
+#[inline]
+fn section_handle(handle: &SectionHandle) -> String {
+	match handle {
+		SectionHandle::Root => String::new(),
+		SectionHandle::Handle(handle) => handle.clone(),
+	}
+}
+
+#[inline]
+fn entry_id(path: &Path) -> String {
+	format!("entry:{}", path.display())
+}
+
+#[inline]
+fn term_id(term: &GraphTerm) -> String {
+	format!("term:{}/{}", term.taxonomy, term.term)
+}
+
+#[inline]
+fn write_data(graphml: &mut String, key: &str, value: &str) -> fmt::Result {
+	writeln!(
+		graphml,
+		r#"      <data key="{key}">{}</data>"#,
+		escape_xml(value)
+	)
+}
+
+#[inline]
+fn write_edge(graphml: &mut String, source: &str, target: &str, kind: &str) -> fmt::Result {
+	writeln!(
+		graphml,
+		r#"    <edge source="{}" target="{}"><data key="edge_kind">{kind}</data></edge>"#,
+		escape_xml(source),
+		escape_xml(target)
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn graph() -> ContentGraph {
+		ContentGraph {
+			entries: vec![
+				GraphEntry {
+					path: PathBuf::from("posts/a"),
+					url: "/posts/a/".into(),
+					title: "A & B".into(),
+					published: Some("2024-01-02T00:00:00+00:00".into()),
+					section: Some("posts".into()),
+					draft: false,
+				},
+				GraphEntry {
+					path: PathBuf::from("about"),
+					url: "/about/".into(),
+					title: "About".into(),
+					published: None,
+					section: None,
+					draft: true,
+				},
+			],
+			sections: vec![GraphSection {
+				handle: "posts".into(),
+				title: Some("Posts".into()),
+				entries: vec![PathBuf::from("posts/a")],
+			}],
+			terms: vec![GraphTerm {
+				taxonomy: "tags".into(),
+				term: "rust".into(),
+				entries: vec![PathBuf::from("posts/a")],
+			}],
+			links: vec![GraphLink {
+				from: PathBuf::from("about"),
+				to: PathBuf::from("posts/a"),
+			}],
+		}
+	}
+
+	#[test]
+	fn graphml_nodes() {
+		let graphml = graph().to_graphml();
+		assert!(graphml.contains(
+			r#"<node id="entry:posts/a">
+      <data key="node_kind">entry</data>
+      <data key="node_label">A &amp; B</data>"#
+		));
+		assert!(graphml.contains(r#"<data key="node_draft">true</data>"#));
+		assert!(graphml.contains(
+			r#"<node id="section:posts">
+      <data key="node_kind">section</data>
+      <data key="node_label">Posts</data>"#
+		));
+		assert!(graphml.contains(
+			r#"<node id="term:tags/rust">
+      <data key="node_kind">term</data>
+      <data key="node_label">rust</data>
+      <data key="node_taxonomy">tags</data>"#
+		));
+	}
+
+	#[test]
+	fn graphml_edges() {
+		let graphml = graph().to_graphml();
+		for (source, target, kind) in [
+			("entry:posts/a", "section:posts", "section"),
+			("term:tags/rust", "entry:posts/a", "term"),
+			("entry:about", "entry:posts/a", "link"),
+		] {
+			assert!(graphml.contains(&format!(
+				r#"<edge source="{source}" target="{target}"><data key="edge_kind">{kind}</data></edge>"#
+			)));
+		}
+		assert_eq!(graphml.matches("<edge ").count(), 3);
+	}
+
+	#[test]
+	fn link_queries() {
+		let base_url = "https://example.com/";
+		let entry_url = "https://example.com/posts/a/";
+		assert_eq!(
+			link_query("@/posts/b.md", entry_url, base_url).as_deref(),
+			Some("posts/b.md")
+		);
+		assert_eq!(
+			link_query("/about/#team", entry_url, base_url).as_deref(),
+			Some("/about/")
+		);
+		assert_eq!(
+			link_query("../b/?page=2", entry_url, base_url).as_deref(),
+			Some("/posts/b/")
+		);
+		assert_eq!(
+			link_query("https://example.com/about/", entry_url, base_url).as_deref(),
+			Some("https://example.com/about/")
+		);
+		assert_eq!(link_query("https://other.com/", entry_url, base_url), None);
+		assert_eq!(
+			link_query("mailto:a@example.com", entry_url, base_url),
+			None
+		);
+		assert_eq!(link_query("#top", entry_url, base_url), None);
+	}
+}
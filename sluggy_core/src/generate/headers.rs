@@ -0,0 +1,92 @@
+use super::{config::Config, content::Content, output_manifest, paths::url_path};
+use crate::{
+	common::http::HEADERS_FILE,
+	err,
+	error::{Error, Result},
+	lazyfn::LazyFn,
+	map_err,
+	utils::unlink_output,
+};
+use dashmap::DashMap;
+use http::{HeaderName, HeaderValue};
+use std::{collections::BTreeMap, fmt::Write, fs, str::FromStr};
+use tracing::instrument;
+
+const ROBOTS_HEADER: &str = "X-Robots-Tag";
+
+/// Response headers of each page across all of the trees, keyed on the path of the page's URL.
+static PAGE_HEADERS: LazyFn<DashMap<String, BTreeMap<String, String>>> = LazyFn::new(DashMap::new);
+
+#[inline]
+pub(crate) fn clear_headers() {
+	PAGE_HEADERS.clear();
+}
+
+/// Collects the `headers` of each of the entries of a tree. Entries of `noindex` sections are
+/// given an `X-Robots-Tag: noindex` header, unless they set their own.
+#[instrument(level = "debug", skip_all)]
+pub(crate) fn collect_headers(content: &Content) -> Result<()> {
+	for entry in content.entries.iter() {
+		let entry = entry.value();
+		let mut headers = entry.headers.clone();
+		if content.is_noindex(entry)
+			&& !headers
+				.keys()
+				.any(|name| name.eq_ignore_ascii_case(ROBOTS_HEADER))
+		{
+			headers.insert(ROBOTS_HEADER.into(), "noindex".into());
+		}
+		if headers.is_empty() {
+			continue;
+		}
+
+		for (name, value) in &headers {
+			if HeaderName::from_str(name).is_err() || HeaderValue::from_str(value).is_err() {
+				return Err(err!(Validation(format!(
+					"invalid header `{name}: {value}` of {}",
+					entry.path.display()
+				))));
+			}
+		}
+
+		PAGE_HEADERS.insert(url_path(&entry.url), headers);
+	}
+
+	Ok(())
+}
+
+/// Writes the collected headers to the `_headers` file at the root of `out_dir`, after the rules
+/// of `_headers` in `content_dir`, if there is one.
+#[instrument(level = "debug", skip_all)]
+pub(crate) fn write_headers_file(config: &Config) -> Result<()> {
+	if PAGE_HEADERS.is_empty() {
+		return Ok(());
+	}
+
+	let mut headers_file = fs::read_to_string(config.content_dir.join(HEADERS_FILE))
+		.map(|source| format!("{}\n", source.trim_end()))
+		.unwrap_or_default();
+
+	let page_headers = PAGE_HEADERS
+		.iter()
+		.map(|page| (page.key().clone(), page.value().clone()))
+		.collect::<BTreeMap<_, _>>();
+
+	for (path, headers) in page_headers {
+		headers_file.push_str(&path);
+		headers_file.push('\n');
+		for (name, value) in headers {
+			let _ = writeln!(headers_file, "  {name}: {value}");
+		}
+	}
+
+	let headers_path = config.out_dir.join(HEADERS_FILE);
+	unlink_output(&headers_path)?;
+	map_err!(
+		fs::write(&headers_path, headers_file),
+		IoError(format!("failed to write {}", headers_path.display())),
+	)?;
+	output_manifest::record(&headers_path, None);
+
+	Ok(())
+}
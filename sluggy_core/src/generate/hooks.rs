@@ -0,0 +1,132 @@
+use super::content::Content;
+use crate::error::Result;
+use serde_json::Value;
+use std::{fmt, path::Path, sync::Arc};
+
+pub type AfterContentLoadHook = dyn Fn(&mut Content) -> Result<()> + Send + Sync;
+pub type BeforeRenderEntryHook = dyn Fn(&Path, &mut Value) -> Result<()> + Send + Sync;
+pub type AfterRenderEntryHook = dyn Fn(&Path, &mut Vec<u8>) -> Result<()> + Send + Sync;
+pub type BeforeWriteOutputHook = dyn Fn(&Path, &mut Vec<u8>) -> Result<()> + Send + Sync;
+
+/// Callbacks which are run at points of the generation of each tree, for embedding sluggy as a
+/// library. Hooks run in the order they're added, and an error from any of them fails the entry
+/// or file it was run for.
+///
+/// ```ignore
+/// let config = Config {
+///     hooks: Hooks::new().before_render_entry(|entry_path, context| {
+///         context["build"] = json!(env!("CARGO_PKG_VERSION"));
+///         Ok(())
+///     }),
+///     ..Config::default()
+/// };
+/// ```
+#[derive(Clone, Default)]
+pub struct Hooks {
+	after_content_load: Vec<Arc<AfterContentLoadHook>>,
+	before_render_entry: Vec<Arc<BeforeRenderEntryHook>>,
+	after_render_entry: Vec<Arc<AfterRenderEntryHook>>,
+	before_write_output: Vec<Arc<BeforeWriteOutputHook>>,
+}
+
+impl Hooks {
+	#[inline]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Runs once the content of a tree is loaded, before anything is rendered. Entries, sections
+	/// and taxonomies can be changed, added or removed.
+	pub fn after_content_load(
+		mut self,
+		hook: impl Fn(&mut Content) -> Result<()> + Send + Sync + 'static,
+	) -> Self {
+		self.after_content_load.push(Arc::new(hook));
+		self
+	}
+
+	/// Runs with the path and template context of each entry before it's rendered, to add to or
+	/// change the context.
+	pub fn before_render_entry(
+		mut self,
+		hook: impl Fn(&Path, &mut Value) -> Result<()> + Send + Sync + 'static,
+	) -> Self {
+		self.before_render_entry.push(Arc::new(hook));
+		self
+	}
+
+	/// Runs with the rendered output of each entry, before it's minified. Pages which aren't backed
+	/// by an entry, such as taxonomy terms, are passed with the path of their file.
+	pub fn after_render_entry(
+		mut self,
+		hook: impl Fn(&Path, &mut Vec<u8>) -> Result<()> + Send + Sync + 'static,
+	) -> Self {
+		self.after_render_entry.push(Arc::new(hook));
+		self
+	}
+
+	/// Runs with the path in `out_dir` and the final content of each rendered page, feed,
+	/// calendar and sitemap before it's written. Static files are copied as they are.
+	pub fn before_write_output(
+		mut self,
+		hook: impl Fn(&Path, &mut Vec<u8>) -> Result<()> + Send + Sync + 'static,
+	) -> Self {
+		self.before_write_output.push(Arc::new(hook));
+		self
+	}
+
+	#[inline]
+	pub(crate) fn run_after_content_load(&self, content: &mut Content) -> Result<()> {
+		for hook in &self.after_content_load {
+			hook(content)?;
+		}
+		Ok(())
+	}
+
+	#[inline]
+	pub(crate) fn run_before_render_entry(
+		&self,
+		entry_path: &Path,
+		context: &mut Value,
+	) -> Result<()> {
+		for hook in &self.before_render_entry {
+			hook(entry_path, context)?;
+		}
+		Ok(())
+	}
+
+	#[inline]
+	pub(crate) fn run_after_render_entry(
+		&self,
+		entry_path: &Path,
+		output: &mut Vec<u8>,
+	) -> Result<()> {
+		for hook in &self.after_render_entry {
+			hook(entry_path, output)?;
+		}
+		Ok(())
+	}
+
+	#[inline]
+	pub(crate) fn run_before_write_output(
+		&self,
+		out_file: &Path,
+		output: &mut Vec<u8>,
+	) -> Result<()> {
+		for hook in &self.before_write_output {
+			hook(out_file, output)?;
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Debug for Hooks {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Hooks")
+			.field("after_content_load", &self.after_content_load.len())
+			.field("before_render_entry", &self.before_render_entry.len())
+			.field("after_render_entry", &self.after_render_entry.len())
+			.field("before_write_output", &self.before_write_output.len())
+			.finish()
+	}
+}
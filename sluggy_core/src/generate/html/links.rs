@@ -0,0 +1,260 @@
+use crate::{
+	err,
+	error::{Error, Result},
+	generate::config::Config,
+	map_err,
+};
+use lol_html::{element, HtmlRewriter, Settings};
+use std::{
+	ffi::OsStr,
+	fs,
+	path::{Path, PathBuf},
+};
+use tracing::instrument;
+use url::Url;
+
+/// Pages are given URLs on this host so that their links can be resolved with `Url::join`. Links
+/// which resolve to any other host are external.
+const PAGE_HOST: &str = "sluggy.invalid";
+
+/// Attributes of elements which link to other files.
+const LINK_ATTRIBUTES: [&str; 2] = ["href", "src"];
+
+/// What a link of a page resolves to.
+enum LinkTarget {
+	/// Another host.
+	External,
+	/// A path in `out_dir`, which may not exist.
+	Internal(PathBuf),
+	/// Outside of the site, or not a URL.
+	Invalid,
+}
+
+/// A link of a generated page which doesn't resolve to a generated file.
+#[derive(Debug)]
+pub(crate) struct BrokenLink {
+	/// Page with the link, relative to `out_dir`.
+	pub page: PathBuf,
+	pub link: String,
+}
+
+/// Checks the internal links of every HTML page in `out_dir`. Links resolve in the same way as
+/// the dev server serves them, either to a file or to a directory with an `index.html`.
+#[instrument(skip(config))]
+pub(crate) fn find_broken_links(config: &Config) -> Result<Vec<BrokenLink>> {
+	let base_path = base_path(&config.base_url);
+
+	let mut pages = vec![];
+	find_pages(
+		&config.out_dir,
+		&config.out_dir.join(&config.compressed_content_dir),
+		&mut pages,
+	)?;
+	pages.sort();
+
+	let mut broken_links = vec![];
+	for page in pages {
+		let html = map_err!(
+			fs::read(&page),
+			IoError(format!("failed to read {}", page.display())),
+		)?;
+		let page = map_err!(
+			page.strip_prefix(&config.out_dir).map(Path::to_path_buf),
+			StripPathPrefix("failed to strip out dir prefix"),
+		)?;
+
+		let page_url = page_url(&page, &base_path)?;
+
+		for link in page_links(&html, &page)? {
+			if !resolves(&link, &page_url, &base_path, config) {
+				broken_links.push(BrokenLink {
+					page: page.clone(),
+					link,
+				});
+			}
+		}
+	}
+
+	Ok(broken_links)
+}
+
+#[inline]
+pub(crate) fn find_pages(
+	dir: &Path,
+	compressed_content_dir: &Path,
+	pages: &mut Vec<PathBuf>,
+) -> Result<()> {
+	for entry in map_err!(
+		fs::read_dir(dir),
+		IoError(format!("failed to read dir {}", dir.display())),
+	)? {
+		let path = map_err!(entry, IoError("dir entry failed"))?.path();
+
+		if path.is_dir() {
+			if path != compressed_content_dir {
+				find_pages(&path, compressed_content_dir, pages)?;
+			}
+		} else if path.extension() == Some(OsStr::new("html")) {
+			pages.push(path);
+		}
+	}
+
+	Ok(())
+}
+
+/// URL of `page`, relative to `out_dir`, which its links are resolved against.
+#[inline]
+pub(crate) fn page_url(page: &Path, base_path: &str) -> Result<Url> {
+	Ok(Url::parse(&format!(
+		"http://{PAGE_HOST}{base_path}{}",
+		page.to_string_lossy()
+	))?)
+}
+
+#[inline]
+fn page_links(html: &[u8], page: &Path) -> Result<Vec<String>> {
+	links_of(html, page, "[href], [src]")
+}
+
+/// Links to the files which are loaded with `page`: its stylesheets, scripts, images, and such.
+#[inline]
+pub(crate) fn page_resources(html: &[u8], page: &Path) -> Result<Vec<String>> {
+	links_of(html, page, "link[href], [src]")
+}
+
+#[inline]
+fn links_of(html: &[u8], page: &Path, selector: &str) -> Result<Vec<String>> {
+	let mut links = vec![];
+
+	let mut rewriter = HtmlRewriter::new(
+		Settings {
+			element_content_handlers: vec![element!(selector, |el| {
+				for attribute in LINK_ATTRIBUTES {
+					if let Some(link) = el.get_attribute(attribute) {
+						links.push(link);
+					}
+				}
+				Ok(())
+			})],
+			..Settings::default()
+		},
+		|_: &[u8]| {},
+	);
+
+	rewriter
+		.write(html)
+		.and_then(|_| rewriter.end())
+		.map_err(|error| {
+			err!(Validation(format!(
+				"failed to parse {}: {error}",
+				page.display()
+			)))
+		})?;
+
+	Ok(links)
+}
+
+/// Whether `link` is external, or resolves to a file in `out_dir`.
+#[inline]
+fn resolves(link: &str, page_url: &Url, base_path: &str, config: &Config) -> bool {
+	let link = link.trim();
+	if link.is_empty() || link.starts_with('#') {
+		return true;
+	}
+
+	match link_target(link, page_url, base_path, config) {
+		LinkTarget::Internal(path) => path.is_file() || path.join("index.html").is_file(),
+		LinkTarget::Invalid => false,
+		LinkTarget::External => true,
+	}
+}
+
+/// The file in `out_dir` which the file at `link` is served from, if it exists.
+#[inline]
+pub(crate) fn resolve_file(
+	link: &str,
+	page_url: &Url,
+	base_path: &str,
+	config: &Config,
+) -> Option<PathBuf> {
+	let link = link.trim();
+	if link.is_empty() || link.starts_with('#') {
+		return None;
+	}
+
+	let LinkTarget::Internal(path) = link_target(link, page_url, base_path, config) else {
+		return None;
+	};
+	if path.is_file() {
+		Some(path)
+	} else {
+		Some(path.join("index.html")).filter(|path| path.is_file())
+	}
+}
+
+#[inline]
+fn link_target(link: &str, page_url: &Url, base_path: &str, config: &Config) -> LinkTarget {
+	// Links with the full URL of the site are internal, even when the base URL is absolute.
+	let link = match link.strip_prefix(&config.base_url) {
+		Some(path) if config.base_url.contains("://") => format!("{base_path}{path}"),
+		_ => link.to_string(),
+	};
+
+	let Ok(url) = page_url.join(&link) else {
+		return LinkTarget::Invalid;
+	};
+	if url.scheme() != "http" || url.host_str() != Some(PAGE_HOST) {
+		return LinkTarget::External;
+	}
+
+	let Some(path) = url.path().strip_prefix(base_path) else {
+		return LinkTarget::Invalid;
+	};
+
+	LinkTarget::Internal(config.out_dir.join(percent_decode(path)))
+}
+
+/// Path of the base URL, with leading and trailing slashes.
+#[inline]
+pub(crate) fn base_path(base_url: &str) -> String {
+	let path = match Url::parse(base_url) {
+		Ok(url) => url.path().to_string(),
+		Err(_) => base_url.to_string(),
+	};
+
+	let path = path.trim_matches('/');
+	if path.is_empty() {
+		"/".into()
+	} else {
+		format!("/{path}/")
+	}
+}
+
+#[inline]
+fn percent_decode(path: &str) -> String {
+	let bytes = path.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+
+	let mut index = 0;
+	while index < bytes.len() {
+		let byte = match (bytes[index], bytes.get(index + 1..index + 3)) {
+			(b'%', Some(hex)) => std::str::from_utf8(hex)
+				.ok()
+				.and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+			_ => None,
+		};
+
+		match byte {
+			Some(byte) => {
+				decoded.push(byte);
+				index += 3;
+			}
+			None => {
+				decoded.push(bytes[index]);
+				index += 1;
+			}
+		}
+	}
+
+	String::from_utf8_lossy(&decoded).to_string()
+}
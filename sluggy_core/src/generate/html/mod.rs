@@ -1,2 +1,3 @@
+pub(crate) mod links;
 pub(crate) mod minifier;
 pub(crate) mod rewriter;
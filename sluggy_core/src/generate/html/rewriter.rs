@@ -1,19 +1,48 @@
-use crate::generate::config::Config;
+use crate::{
+	common::http::LIVE_RELOAD_PATH,
+	generate::{config::Config, fingerprint::asset_url},
+	utils::escape_xml,
+};
+#[cfg(feature = "images")]
+use crate::{
+	error::Result as SluggyResult,
+	generate::{
+		images::{current_processor, ImageProcessor, ImageSource},
+		Image,
+	},
+};
 use dashmap::DashMap;
 use lol_html::{
 	element,
 	html_content::{ContentType, Element},
 	HtmlRewriter, OutputSink, Settings,
 };
+#[cfg(feature = "images")]
+use std::sync::Arc;
 use std::{
+	collections::BTreeSet,
 	error::Error,
 	io::{ErrorKind, Write},
-	path::PathBuf,
+	path::{Path, PathBuf},
 };
 use tracing::instrument;
 
 type HandlerResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+/// Attributes of other elements which reference `@/` files, such as preloaded fonts, which are
+/// pointed at `base_url` like stylesheets and scripts.
+const ASSET_ATTRIBUTES: [(&str, &str); 7] = [
+	("link[href]", "href"),
+	("img[src]", "src"),
+	("source[src]", "src"),
+	("video[src]", "src"),
+	("video[poster]", "poster"),
+	("audio[src]", "src"),
+	("track[src]", "src"),
+];
 type ContentMap = &'static DashMap<PathBuf, String>;
+/// Embedded content -> entries which embed it.
+type EmbeddedByMap = &'static DashMap<PathBuf, BTreeSet<PathBuf>>;
 
 struct Sink<'b> {
 	buf: &'b mut Vec<u8>,
@@ -32,18 +61,62 @@ pub(crate) struct Rewriter<'c: 'h, 'h> {
 
 impl<'c, 'h> Rewriter<'c, 'h> {
 	#[inline]
-	pub(crate) fn new(config: &'c Config, buf: &'c mut Vec<u8>, content_map: ContentMap) -> Self {
+	pub(crate) fn new(
+		config: &'c Config,
+		buf: &'c mut Vec<u8>,
+		content_map: ContentMap,
+		embedded_by: EmbeddedByMap,
+		entry_path: &'c Path,
+		head_html: Option<&'c str>,
+	) -> Self {
+		let mut element_content_handlers = vec![
+			// Rewrite insecure hyperlinks
+			element!(
+				"link[rel=\"stylesheet\"]",
+				make_rewrite_link_stylesheet(config, content_map, embedded_by, entry_path)
+			),
+			element!(
+				"script[src]",
+				make_rewrite_script(config, content_map, embedded_by, entry_path)
+			),
+			element!("a", make_rewrite_anchor_href(config)),
+		];
+
+		if let Some(head_html) = head_html {
+			element_content_handlers.push(element!("head", make_append_head(head_html)));
+		}
+
+		#[cfg(feature = "images")]
+		if let Some(image_processor) = current_processor() {
+			element_content_handlers.push(element!(
+				"img",
+				make_rewrite_img(config, image_processor, entry_path)
+			));
+		}
+
+		if let Some(dev_client_script) = &config.dev_client_script {
+			element_content_handlers.push(element!(
+				"head",
+				make_append_dev_client_script(dev_client_script)
+			));
+		}
+
+		// After the images, which resize `@/` images rather than pointing at them.
+		for (selector, attribute) in ASSET_ATTRIBUTES {
+			element_content_handlers.push(element!(
+				selector,
+				make_rewrite_asset_attribute(config, attribute)
+			));
+		}
+
+		if config.live_reload {
+			element_content_handlers.push(element!("body", append_live_reload_script));
+		}
+
 		Rewriter {
 			rewriter: HtmlRewriter::new(
 				Settings {
-					element_content_handlers: vec![
-						// Rewrite insecure hyperlinks
-						element!(
-							"link[rel=\"stylesheet\"]",
-							make_rewrite_link_stylesheet(config, content_map)
-						),
-						element!("a", make_rewrite_anchor_href(config)),
-					],
+					element_content_handlers,
 					..Settings::default()
 				},
 				Sink { buf },
@@ -52,12 +125,53 @@ impl<'c, 'h> Rewriter<'c, 'h> {
 	}
 }
 
-#[instrument(level = "trace", skip(config, content_map))]
+/// Appends generated markup, such as JSON-LD, to the end of the head.
+#[instrument(level = "trace", skip(head_html))]
 #[inline]
-fn make_rewrite_link_stylesheet(
-	config: &Config,
-	content_map: ContentMap,
+fn make_append_head(head_html: &str) -> impl FnMut(&mut Element) -> HandlerResult + '_ {
+	|el| {
+		el.append(head_html, ContentType::Html);
+		Ok(())
+	}
+}
+
+/// Loads the client of the asset dev server, which replaces modules as they change.
+#[inline]
+fn make_append_dev_client_script(
+	dev_client_script: &str,
 ) -> impl FnMut(&mut Element) -> HandlerResult + '_ {
+	|el| {
+		el.append(
+			&format!(
+				r#"<script type="module" src="{}"></script>"#,
+				escape_xml(dev_client_script)
+			),
+			ContentType::Html,
+		);
+		Ok(())
+	}
+}
+
+/// Reloads the page whenever the dev server finishes a build, reconnecting if the server restarts.
+#[inline]
+fn append_live_reload_script(el: &mut Element) -> HandlerResult {
+	el.append(
+		&format!(
+			r#"<script>(function(){{function connect(){{var socket=new WebSocket((location.protocol==="https:"?"wss://":"ws://")+location.host+"{LIVE_RELOAD_PATH}");socket.onmessage=function(){{location.reload()}};socket.onclose=function(){{setTimeout(connect,1000)}}}}connect()}})();</script>"#
+		),
+		ContentType::Html,
+	);
+	Ok(())
+}
+
+#[instrument(level = "trace", skip(config, content_map, embedded_by))]
+#[inline]
+fn make_rewrite_link_stylesheet<'c>(
+	config: &'c Config,
+	content_map: ContentMap,
+	embedded_by: EmbeddedByMap,
+	entry_path: &'c Path,
+) -> impl FnMut(&mut Element) -> HandlerResult + 'c {
 	|el| {
 		let embed = el.get_attribute("embed");
 		let href = el.get_attribute("href");
@@ -71,6 +185,13 @@ fn make_rewrite_link_stylesheet(
 								&format!("<style>{}</style>", css.value()),
 								ContentType::Html,
 							);
+
+							// Recorded so that only these entries are rendered again when the CSS
+							// changes.
+							embedded_by
+								.entry(PathBuf::from(path))
+								.or_default()
+								.insert(entry_path.to_path_buf());
 						}
 						None => {
 							tracing::warn!("css not found: {path}");
@@ -80,9 +201,7 @@ fn make_rewrite_link_stylesheet(
 			}
 			(Some(href), None) => {
 				if let Some(path) = href.strip_prefix("@/") {
-					if let Err(error) =
-						el.set_attribute("href", &format!("{}{path}", config.base_url))
-					{
+					if let Err(error) = el.set_attribute("href", &asset_url(path, config)) {
 						tracing::warn!(?error, "rewrite link css failure");
 					}
 				}
@@ -94,6 +213,166 @@ fn make_rewrite_link_stylesheet(
 	}
 }
 
+/// Inlines bundled scripts with an `embed` attribute, like stylesheets, and points the rest of the
+/// `@/` scripts at `base_url`.
+#[instrument(level = "trace", skip(config, content_map, embedded_by))]
+#[inline]
+fn make_rewrite_script<'c>(
+	config: &'c Config,
+	content_map: ContentMap,
+	embedded_by: EmbeddedByMap,
+	entry_path: &'c Path,
+) -> impl FnMut(&mut Element) -> HandlerResult + 'c {
+	|el| {
+		let Some(path) = el
+			.get_attribute("src")
+			.and_then(|src| src.strip_prefix("@/").map(str::to_string))
+		else {
+			return Ok(());
+		};
+
+		if el.get_attribute("embed").is_none() {
+			if let Err(error) = el.set_attribute("src", &asset_url(&path, config)) {
+				tracing::warn!(?error, "rewrite script src failure");
+			}
+			return Ok(());
+		}
+
+		match content_map.get(&PathBuf::from(&path)) {
+			Some(js) => {
+				let type_attribute = el
+					.get_attribute("type")
+					.map(|script_type| format!(r#" type="{}""#, escape_xml(&script_type)))
+					.unwrap_or_default();
+				// Closing tags within the script would end it early.
+				let js = js.value().replace("</script", "<\\/script");
+				el.replace(
+					&format!("<script{type_attribute}>{js}</script>"),
+					ContentType::Html,
+				);
+
+				embedded_by
+					.entry(PathBuf::from(&path))
+					.or_default()
+					.insert(entry_path.to_path_buf());
+			}
+			None => {
+				tracing::warn!("script not found: {path}");
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Processes images with a `process` attribute, or every local image when `images.rewrite.all` is
+/// set, and points them at the processed image.
+#[cfg(feature = "images")]
+#[instrument(level = "trace", skip(config, image_processor))]
+#[inline]
+fn make_rewrite_img<'c>(
+	config: &'c Config,
+	image_processor: Arc<ImageProcessor>,
+	entry_path: &'c Path,
+) -> impl FnMut(&mut Element) -> HandlerResult + 'c {
+	move |el| {
+		let rewrite = &config.images.rewrite;
+		let process = el.get_attribute("process");
+		let Some(src) = el.get_attribute("src") else {
+			return Ok(());
+		};
+
+		let is_local = !(src.contains("://")
+			|| src.starts_with("//")
+			|| src.starts_with('/')
+			|| src.starts_with("data:"));
+		if process.is_none() && !(rewrite.all && is_local) {
+			return Ok(());
+		}
+		el.remove_attribute("process");
+
+		let Some(preset) = process
+			.clone()
+			.filter(|preset| !preset.is_empty())
+			.or_else(|| rewrite.preset.clone())
+		else {
+			return Err(format!(
+				"no preset to process image {src} with, set `images.rewrite.preset`"
+			)
+			.into());
+		};
+
+		match process_img(&src, &preset, entry_path, &image_processor, config) {
+			Ok((image, srcset)) => {
+				el.set_attribute("src", &image.url)?;
+				el.set_attribute("width", &image.width.to_string())?;
+				el.set_attribute("height", &image.height.to_string())?;
+				if !srcset.is_empty() {
+					el.set_attribute("srcset", &srcset.join(", "))?;
+				}
+			}
+			// Images which were only picked up by `all` are left as they are.
+			Err(error) if process.is_none() => {
+				tracing::warn!(%error, src, "Unable to process image");
+			}
+			Err(error) => return Err(error.into()),
+		}
+
+		Ok(())
+	}
+}
+
+/// The processed image, and the candidates of its `srcset`.
+#[cfg(feature = "images")]
+#[inline]
+fn process_img(
+	src: &str,
+	preset: &str,
+	entry_path: &Path,
+	image_processor: &ImageProcessor,
+	config: &Config,
+) -> SluggyResult<(Image, Vec<String>)> {
+	let source = match src.strip_prefix("@/") {
+		Some(asset) => ImageSource::resolve(asset, None, config)?,
+		None => ImageSource::resolve(src, Some(entry_path), config)?,
+	};
+
+	let image = image_processor.process_preset(source.clone(), preset)?;
+	let srcset = config
+		.images
+		.rewrite
+		.srcset
+		.iter()
+		.map(|preset| {
+			image_processor
+				.process_preset(source.clone(), preset)
+				.map(|image| format!("{} {}w", image.url, image.width))
+		})
+		.collect::<SluggyResult<_>>()?;
+
+	Ok((image, srcset))
+}
+
+/// Points `@/` references of `attribute` at `base_url`, with the fingerprinted names of the files.
+#[inline]
+fn make_rewrite_asset_attribute<'c>(
+	config: &'c Config,
+	attribute: &'c str,
+) -> impl FnMut(&mut Element) -> HandlerResult + 'c {
+	move |el| {
+		if let Some(path) = el
+			.get_attribute(attribute)
+			.and_then(|value| value.strip_prefix("@/").map(str::to_string))
+		{
+			if let Err(error) = el.set_attribute(attribute, &asset_url(&path, config)) {
+				tracing::warn!(?error, attribute, "rewrite asset reference failure");
+			}
+		}
+
+		Ok(())
+	}
+}
+
 #[instrument(level = "trace", skip(config))]
 #[inline]
 fn make_rewrite_anchor_href(config: &Config) -> impl FnMut(&mut Element) -> HandlerResult + '_ {
@@ -102,8 +381,7 @@ fn make_rewrite_anchor_href(config: &Config) -> impl FnMut(&mut Element) -> Hand
 
 		if let Some(href) = &href {
 			if let Some(path) = href.strip_prefix("@/") {
-				if let Err(error) = el.set_attribute("href", &format!("{}{path}", config.base_url))
-				{
+				if let Err(error) = el.set_attribute("href", &asset_url(path, config)) {
 					tracing::warn!(?error, "rewrite anchor href failure");
 				}
 			}
@@ -0,0 +1,217 @@
+use super::config::Config;
+use crate::{
+	error::{Error, Result},
+	map_err,
+	utils::hex_digest,
+};
+use reqwest::{
+	header::{AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION},
+	redirect, Client, Request, Url,
+};
+use std::{fs, path::Path};
+use tracing::{debug, instrument};
+
+/// Responses are cached in this directory under `cache_dir`.
+const HTTP_CACHE_DIR: &str = "http";
+
+/// Redirects which are followed before a request fails.
+const MAX_REDIRECTS: usize = 10;
+
+/// Client of requests to `fetch.allowed_domains`. Redirects are only followed to URLs which are
+/// allowed too, so that an allowed site can't redirect a request to any other.
+#[inline]
+pub(crate) fn client(config: &Config) -> Result<Client> {
+	let allowed_domains = config.fetch.allowed_domains.clone();
+	let policy = redirect::Policy::custom(move |attempt| {
+		if attempt.previous().len() >= MAX_REDIRECTS {
+			attempt.error("too many redirects")
+		} else if is_allowed(attempt.url(), &allowed_domains) {
+			attempt.follow()
+		} else {
+			let error = format!(
+				"redirected to {}, which is not on one of `fetch.allowed_domains`",
+				attempt.url()
+			);
+			attempt.error(error)
+		}
+	});
+
+	map_err!(
+		Client::builder().redirect(policy).build(),
+		ClientRequest("failed to build the fetch client"),
+	)
+}
+
+/// Executes `request`, reusing the response body from a previous build if it was cached within
+/// `fetch.cache_max_age` seconds.
+///
+/// Requests with credentials, such as an `Authorization` header, are never cached, as the cache
+/// isn't private.
+#[instrument(level = "debug", skip(client, config))]
+pub(crate) async fn execute(client: &Client, request: Request, config: &Config) -> Result<String> {
+	let is_cacheable = ![AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE]
+		.iter()
+		.any(|header| request.headers().contains_key(header));
+	let cache_path = config
+		.cache_dir
+		.join(HTTP_CACHE_DIR)
+		.join(hex_digest(cache_key(&request)));
+
+	if is_cacheable && is_fresh(&cache_path, config.fetch.cache_max_age) {
+		if let Ok(body) = fs::read_to_string(&cache_path) {
+			debug!("using cached response for {}", request.url());
+			return Ok(body);
+		}
+	}
+
+	let response = map_err!(
+		map_err!(
+			client.execute(request).await,
+			ClientRequest("failed to execute request"),
+		)?
+		.error_for_status(),
+		ClientRequest("request failed"),
+	)?;
+
+	let body = map_err!(
+		response.text().await,
+		ClientRequest("failed to read response body"),
+	)?;
+
+	if !is_cacheable {
+		return Ok(body);
+	}
+
+	if let Some(parent) = cache_path.parent() {
+		map_err!(
+			fs::create_dir_all(parent),
+			IoError(format!("failed to create dirs for {}", parent.display())),
+		)?;
+	}
+	map_err!(
+		fs::write(&cache_path, &body),
+		IoError(format!("failed to write {}", cache_path.display())),
+	)?;
+
+	Ok(body)
+}
+
+/// Whether the host of `url` is one of `fetch.allowed_domains`, or a subdomain of one.
+#[inline]
+pub(crate) fn is_allowed_url(url: &Url, config: &Config) -> bool {
+	is_allowed(url, &config.fetch.allowed_domains)
+}
+
+/// Hosts are compared case-insensitively, and without the trailing dot of fully qualified names.
+#[inline]
+fn is_allowed(url: &Url, allowed_domains: &[String]) -> bool {
+	let Some(host) = url.host_str() else {
+		return false;
+	};
+	let host = host.trim_end_matches('.').to_ascii_lowercase();
+
+	matches!(url.scheme(), "http" | "https")
+		&& allowed_domains.iter().any(|domain| {
+			let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+			host == domain
+				|| host
+					.strip_suffix(domain.as_str())
+					.is_some_and(|subdomain| subdomain.ends_with('.'))
+		})
+}
+
+/// The method, URL, headers and body of `request`.
+#[inline]
+fn cache_key(request: &Request) -> String {
+	let mut headers = request
+		.headers()
+		.iter()
+		.map(|(name, value)| format!("{name}: {}", String::from_utf8_lossy(value.as_bytes())))
+		.collect::<Vec<_>>();
+	headers.sort();
+
+	let body = request
+		.body()
+		.and_then(|body| body.as_bytes())
+		.map(String::from_utf8_lossy)
+		.unwrap_or_default();
+
+	format!(
+		"{} {}\n{}\n{body}",
+		request.method(),
+		request.url(),
+		headers.join("\n")
+	)
+}
+
+#[inline]
+fn is_fresh(path: &Path, max_age: u64) -> bool {
+	fs::metadata(path)
+		.and_then(|metadata| metadata.modified())
+		.ok()
+		.and_then(|modified| modified.elapsed().ok())
+		.map(|age| age.as_secs() < max_age)
+		.unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::generate::config::FetchConfig;
+	use reqwest::Method;
+
+	fn config(allowed_domains: &[&str]) -> Config {
+		Config {
+			fetch: FetchConfig {
+				allowed_domains: allowed_domains.iter().map(|d| d.to_string()).collect(),
+				..FetchConfig::default()
+			},
+			..Config::default()
+		}
+	}
+
+	fn url(url: &str) -> Url {
+		Url::parse(url).unwrap()
+	}
+
+	#[test]
+	fn allows_domains_and_their_subdomains() {
+		let config = config(&["example.com"]);
+		assert!(is_allowed_url(
+			&url("https://example.com/feed.xml"),
+			&config
+		));
+		assert!(is_allowed_url(&url("http://api.example.com/"), &config));
+		assert!(!is_allowed_url(&url("https://notexample.com/"), &config));
+		assert!(!is_allowed_url(
+			&url("https://example.com.evil.test/"),
+			&config
+		));
+		assert!(!is_allowed_url(&url("ftp://example.com/"), &config));
+	}
+
+	#[test]
+	fn allowed_domains_are_case_insensitive() {
+		let config = config(&["Example.COM."]);
+		assert!(is_allowed_url(&url("https://EXAMPLE.com/"), &config));
+		assert!(is_allowed_url(&url("https://www.example.com./"), &config));
+	}
+
+	#[test]
+	fn cache_key_ignores_header_order() {
+		let client = Client::new();
+		let a = client
+			.request(Method::GET, "https://example.com/")
+			.header("a", "1")
+			.header("b", "2")
+			.build()
+			.unwrap();
+		let b = client
+			.request(Method::GET, "https://example.com/")
+			.header("b", "2")
+			.header("a", "1")
+			.build()
+			.unwrap();
+		assert_eq!(cache_key(&a), cache_key(&b));
+	}
+}
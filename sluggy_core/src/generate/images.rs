@@ -0,0 +1,608 @@
+use super::{config::Config, output_manifest, paths::resolve_path, Image};
+use crate::{
+	err,
+	error::{Error, Result},
+	lazyfn::LazyFn,
+	map_err,
+	utils::{hex_digest, unlink_output, LockResultExt},
+};
+use dashmap::DashMap;
+use exif::{Exif, In, Tag, Value as ExifValue};
+use imageless::{ImageOutputFormat, Operation};
+use serde_derive::{Deserialize, Serialize};
+#[cfg(feature = "http-loader")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+	collections::BTreeMap,
+	ffi::OsString,
+	fs::{self, File as FsFile},
+	io::{BufReader, BufWriter},
+	path::{Path, PathBuf},
+	sync::{Arc, RwLock},
+};
+use tokio::{
+	runtime::Handle,
+	sync::OnceCell,
+	task::{block_in_place, spawn_blocking},
+};
+use tracing::{debug, instrument};
+use url::Url;
+
+/// Remote images are downloaded into this directory under `cache_dir`.
+const REMOTE_IMAGES_CACHE_DIR: &str = "images";
+
+/// Sources which are rotated or flipped by their EXIF orientation are written to this directory
+/// under `cache_dir` while they're processed.
+const ORIENTED_IMAGES_CACHE_DIR: &str = "images/oriented";
+
+/// Processor of the tree being generated, which the HTML rewriter shares with the template
+/// functions so that images are only processed once.
+static CURRENT_PROCESSOR: LazyFn<RwLock<Option<Arc<ImageProcessor>>>> =
+	LazyFn::new(|| RwLock::new(None));
+
+/// Count of the downloads started by this process, which makes the name of each download's
+/// temporary file unique.
+#[cfg(feature = "http-loader")]
+static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+
+#[inline]
+pub(crate) fn set_current_processor(image_processor: Option<Arc<ImageProcessor>>) {
+	*CURRENT_PROCESSOR.write().acquire() = image_processor;
+}
+
+#[inline]
+pub(crate) fn current_processor() -> Option<Arc<ImageProcessor>> {
+	CURRENT_PROCESSOR.read().acquire().clone()
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum ImageSource {
+	/// An image within the project.
+	File(PathBuf),
+	/// An image which is downloaded and cached before processing.
+	Remote(Url),
+}
+
+impl ImageSource {
+	/// Resolves the source of an image.
+	///
+	/// - URLs are downloaded and cached.
+	/// - Absolute paths are used as is, but must be within the project.
+	/// - Relative paths are resolved against the directory of `relative_to` when it is given, which
+	///   allows images to be co-located with content, e.g. `relative_to = entry.file_path`.
+	/// - Otherwise relative paths are resolved against `assets_dir`.
+	pub(crate) fn resolve(
+		source: &str,
+		relative_to: Option<&Path>,
+		config: &Config,
+	) -> Result<Self> {
+		if source.starts_with("http://") || source.starts_with("https://") {
+			return Ok(Self::Remote(Url::parse(source)?));
+		}
+
+		Ok(Self::File(resolve_path(source, relative_to, config)?))
+	}
+
+	/// Resolves every image in the directory `dir`, sorted by file name.
+	///
+	/// `dir` is resolved in the same way as local image sources.
+	pub(crate) fn resolve_dir(
+		dir: &str,
+		relative_to: Option<&Path>,
+		config: &Config,
+	) -> Result<Vec<Self>> {
+		let dir = resolve_path(dir, relative_to, config)?;
+
+		let read_dir = map_err!(
+			fs::read_dir(&dir),
+			IoError(format!("failed to read directory {}", dir.display())),
+		)?;
+
+		let mut paths = vec![];
+		for dir_entry in read_dir {
+			let path = map_err!(
+				dir_entry,
+				IoError(format!("failed to read directory {}", dir.display())),
+			)?
+			.path();
+
+			if path.is_file() && is_raster_image(&path) {
+				paths.push(path);
+			}
+		}
+		paths.sort();
+
+		Ok(paths.into_iter().map(Self::File).collect())
+	}
+
+	#[inline]
+	fn file_name(&self) -> Option<OsString> {
+		match self {
+			Self::File(path) => path.file_name().map(|file_name| file_name.to_os_string()),
+			Self::Remote(url) => Path::new(url.path())
+				.file_name()
+				.map(|file_name| file_name.to_os_string()),
+		}
+	}
+
+	/// Stable identifier for the source, used when hashing output file names.
+	#[inline]
+	fn key(&self) -> String {
+		match self {
+			Self::File(path) => std::env::current_dir()
+				.ok()
+				.and_then(|current_dir| path.strip_prefix(current_dir).ok())
+				.unwrap_or(path)
+				.to_string_lossy()
+				.to_string(),
+			Self::Remote(url) => url.to_string(),
+		}
+	}
+
+	#[inline]
+	fn original_path(&self) -> PathBuf {
+		match self {
+			Self::File(path) => path.clone(),
+			Self::Remote(url) => PathBuf::from(url.as_str()),
+		}
+	}
+
+	/// Path to the source image on disk, downloading it first if it is remote.
+	#[instrument(level = "debug", skip(config))]
+	fn local_path(&self, config: &Config) -> Result<PathBuf> {
+		match self {
+			Self::File(path) => Ok(path.clone()),
+			Self::Remote(url) => {
+				let mut cache_path = config
+					.cache_dir
+					.join(REMOTE_IMAGES_CACHE_DIR)
+					.join(hex_digest(url.as_str()));
+				if let Some(extension) = Path::new(url.path()).extension() {
+					cache_path.set_extension(extension);
+				}
+
+				if cache_path.exists() {
+					return Ok(cache_path);
+				}
+
+				if let Some(parent) = cache_path.parent() {
+					map_err!(
+						fs::create_dir_all(parent),
+						IoError(format!("failed to create dirs for {}", parent.display())),
+					)?;
+				}
+
+				download(url, &cache_path)?;
+
+				Ok(cache_path)
+			}
+		}
+	}
+}
+
+/// Downloads `url` to `path`, through a temporary file so that a partial download is never cached.
+/// Each download has its own temporary file, as the same image may be downloaded by concurrent
+/// builds or template functions, and whichever finishes last is kept.
+#[cfg(feature = "http-loader")]
+#[instrument(level = "debug")]
+fn download(url: &Url, path: &Path) -> Result<()> {
+	let response = map_err!(
+		reqwest::blocking::get(url.clone()).and_then(|response| response.error_for_status()),
+		ClientRequest(format!("failed to download image {url}")),
+	)?;
+
+	let bytes = map_err!(
+		response.bytes(),
+		ClientRequest(format!("failed to read image {url}")),
+	)?;
+
+	let partial_path = path.with_extension(format!(
+		"{}-{}.part",
+		std::process::id(),
+		DOWNLOADS.fetch_add(1, Ordering::Relaxed)
+	));
+	map_err!(
+		fs::write(&partial_path, &bytes),
+		IoError(format!("failed to write {}", partial_path.display())),
+	)?;
+	map_err!(
+		fs::rename(&partial_path, path),
+		IoError(format!("failed to write {}", path.display())),
+	)?;
+
+	Ok(())
+}
+
+#[cfg(not(feature = "http-loader"))]
+#[inline]
+fn download(url: &Url, _path: &Path) -> Result<()> {
+	Err(err!(ImageError(format!(
+		"downloading {url} requires sluggy to be built with the `http-loader` feature"
+	))))
+}
+
+/// Whether `path` looks like an image which can be processed. SVGs are skipped.
+#[inline]
+fn is_raster_image(path: &Path) -> bool {
+	mime_guess::from_path(path)
+		.first_raw()
+		.map(|mime| mime.starts_with("image/") && mime != "image/svg+xml")
+		.unwrap_or(false)
+}
+
+/// An image in a gallery, processed into each of the configured gallery variants.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct GalleryImage {
+	pub file_name: String,
+	pub variants: BTreeMap<String, Image>,
+	pub metadata: Option<ImageMetadata>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ImageProcessor {
+	config: Arc<Config>,
+	/// Images processed during this build, keyed on their output path, so that each variant is only
+	/// processed once regardless of how many times it is requested.
+	processed: DashMap<PathBuf, Arc<OnceCell<Image>>>,
+}
+
+impl ImageProcessor {
+	pub(crate) fn new(config: Arc<Config>) -> Self {
+		Self {
+			config,
+			processed: DashMap::new(),
+		}
+	}
+
+	#[instrument(level = "debug", skip(self, out_format, operations))]
+	pub(crate) fn process(
+		&self,
+		source: ImageSource,
+		out_format: ImageOutputFormat,
+		operations: Vec<Operation>,
+	) -> Result<Image> {
+		let file_name = source.file_name().ok_or(err!(ImageError(format!(
+			"could not get filename from {source:?}"
+		))))?;
+
+		let operations_json = map_err!(
+			serde_json::to_string(&operations),
+			SerdeJsonError("could not serialize image operations"),
+		)?;
+
+		let out_dir = self
+			.config
+			.out_dir
+			.join(&self.config.processed_images_dir)
+			.join(file_name);
+
+		let full_out_path = out_dir
+			.join(hex_digest(format!("{}{operations_json}", source.key())))
+			.with_extension(out_format.extension());
+
+		map_err!(
+			fs::create_dir_all(&out_dir),
+			IoError(format!("could not create directory {}", out_dir.display())),
+		)?;
+		output_manifest::record(&full_out_path, None);
+
+		let image_cell = self
+			.processed
+			.entry(full_out_path.clone())
+			.or_default()
+			.clone();
+
+		let config = self.config.clone();
+
+		// Decoding and encoding is CPU bound, so it is moved off of the render task's thread.
+		let image = block_in_place(|| {
+			Handle::current().block_on(image_cell.get_or_try_init(|| async move {
+				spawn_blocking(move || {
+					process_image(&config, source, full_out_path, out_format, operations)
+				})
+				.await?
+			}))
+		})?;
+
+		Ok(image.clone())
+	}
+
+	/// Format and operations of the preset configured in `images.presets` as `name`.
+	#[instrument(level = "debug", skip(self))]
+	pub(crate) fn preset(&self, name: &str) -> Result<(Option<ImageOutputFormat>, Vec<Operation>)> {
+		let preset = self.config.images.presets.get(name).ok_or_else(|| {
+			err!(ImageError(format!(
+				"no image preset `{name}` is configured in `images.presets`"
+			)))
+		})?;
+
+		let out_format = preset
+			.format
+			.clone()
+			.map(|format| {
+				format.try_into().map_err(|error| {
+					err!(ImageError(format!(
+						"invalid format for image preset `{name}`: {error}"
+					)))
+				})
+			})
+			.transpose()?;
+		let operations = preset.operations.clone().try_into().map_err(|error| {
+			err!(ImageError(format!(
+				"invalid operations for image preset `{name}`: {error}"
+			)))
+		})?;
+
+		Ok((out_format, operations))
+	}
+
+	/// Processes `source` with the preset `name`, which must have a format.
+	#[instrument(level = "debug", skip(self))]
+	pub(crate) fn process_preset(&self, source: ImageSource, name: &str) -> Result<Image> {
+		let (out_format, operations) = self.preset(name)?;
+		let out_format = out_format.ok_or_else(|| {
+			err!(ImageError(format!(
+				"image preset `{name}` needs a `format` to process `<img>` tags"
+			)))
+		})?;
+
+		self.process(source, out_format, operations)
+	}
+
+	/// Processes each of `sources` into every variant configured in `images.gallery`.
+	#[instrument(level = "debug", skip(self))]
+	pub(crate) fn gallery(&self, sources: Vec<ImageSource>) -> Result<Vec<GalleryImage>> {
+		if self.config.images.gallery.is_empty() {
+			return Err(err!(ImageError(
+				"no gallery variants are configured in `images.gallery`".into()
+			)));
+		}
+
+		let mut gallery = vec![];
+		for source in sources {
+			let file_name = source
+				.file_name()
+				.map(|file_name| file_name.to_string_lossy().to_string())
+				.unwrap_or_default();
+
+			let mut variants = BTreeMap::new();
+			for (name, variant) in &self.config.images.gallery {
+				let out_format: ImageOutputFormat =
+					variant.format.clone().try_into().map_err(|error| {
+						err!(ImageError(format!(
+							"invalid format for gallery variant `{name}`: {error}"
+						)))
+					})?;
+				let operations: Vec<Operation> =
+					variant.operations.clone().try_into().map_err(|error| {
+						err!(ImageError(format!(
+							"invalid operations for gallery variant `{name}`: {error}"
+						)))
+					})?;
+
+				let image = self.process(source.clone(), out_format, operations)?;
+				variants.insert(name.clone(), image);
+			}
+
+			let metadata = variants
+				.values()
+				.next()
+				.and_then(|image| image.metadata.clone());
+
+			gallery.push(GalleryImage {
+				file_name,
+				variants,
+				metadata,
+			});
+		}
+
+		Ok(gallery)
+	}
+}
+
+#[inline]
+fn process_image(
+	config: &Arc<Config>,
+	source: ImageSource,
+	full_out_path: PathBuf,
+	out_format: ImageOutputFormat,
+	operations: Vec<Operation>,
+) -> Result<Image> {
+	let local_path = source.local_path(config)?;
+	let exif = read_exif(&local_path);
+
+	let orientation = exif
+		.as_ref()
+		.filter(|_| config.images.auto_orient)
+		.and_then(|exif| exif.get_field(Tag::Orientation, In::PRIMARY))
+		.and_then(|field| field.value.get_uint(0))
+		.filter(|orientation| (2..=8).contains(orientation));
+
+	// Encoded images don't carry over any of the source metadata, so the orientation has to be
+	// applied to the pixels. It's applied before the operations, so that they resize and crop the
+	// image as it's displayed rather than as it's stored.
+	let oriented_path = match orientation {
+		Some(orientation) => Some(orient(config, &local_path, orientation, &full_out_path)?),
+		None => None,
+	};
+	let image = imageless::process_file(
+		oriented_path.clone().unwrap_or_else(|| local_path.clone()),
+		operations,
+	)
+	.map_err(|error| err!(ImageError(format!("failed to process image: {error}"))));
+	if let Some(oriented_path) = &oriented_path {
+		let _ = fs::remove_file(oriented_path);
+	}
+	let image = image?;
+
+	unlink_output(&full_out_path)?;
+	let out_file = map_err!(
+		FsFile::create(&full_out_path),
+		IoError(format!("could not create {}", full_out_path.display())),
+	)?;
+	let mut out_buf = BufWriter::new(out_file);
+	image
+		.write_to(&mut out_buf, out_format)
+		.map_err(|error| err!(ImageError(format!("unable to write image: {error}"))))?;
+
+	Ok(Image {
+		metadata: exif.map(|exif| ImageMetadata::from_exif(&exif, config.images.expose_gps)),
+		..Image::new(
+			config,
+			image.width(),
+			image.height(),
+			full_out_path,
+			source.original_path(),
+		)
+	})
+}
+
+/// Decodes the image at `path`, rotates and flips it by its EXIF `orientation`, and writes it
+/// losslessly to the cache, to be processed in place of the source. The path is unique to
+/// `full_out_path`, as each output is only processed once at a time.
+#[inline]
+fn orient(config: &Config, path: &Path, orientation: u32, full_out_path: &Path) -> Result<PathBuf> {
+	let image = image::open(path).map_err(|error| {
+		err!(ImageError(format!(
+			"failed to decode image {}: {error}",
+			path.display()
+		)))
+	})?;
+
+	let image = match orientation {
+		2 => image.fliph(),
+		3 => image.rotate180(),
+		4 => image.flipv(),
+		5 => image.rotate90().fliph(),
+		6 => image.rotate90(),
+		7 => image.rotate270().fliph(),
+		8 => image.rotate270(),
+		_ => image,
+	};
+
+	let oriented_dir = config.cache_dir.join(ORIENTED_IMAGES_CACHE_DIR);
+	map_err!(
+		fs::create_dir_all(&oriented_dir),
+		IoError(format!(
+			"could not create directory {}",
+			oriented_dir.display()
+		)),
+	)?;
+	let oriented_path = oriented_dir.join(format!(
+		"{}.png",
+		hex_digest(full_out_path.to_string_lossy().as_bytes())
+	));
+	image
+		.save_with_format(&oriented_path, image::ImageFormat::Png)
+		.map_err(|error| {
+			err!(ImageError(format!(
+				"unable to write oriented image {}: {error}",
+				oriented_path.display()
+			)))
+		})?;
+
+	Ok(oriented_path)
+}
+
+/// Metadata read from the EXIF data of a source image.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageMetadata {
+	/// When the photo was taken, formatted as `YYYY-MM-DD HH:MM:SS`.
+	pub taken_at: Option<String>,
+	pub camera_make: Option<String>,
+	pub camera_model: Option<String>,
+	pub lens_model: Option<String>,
+	pub exposure_time: Option<String>,
+	pub f_number: Option<String>,
+	pub iso: Option<u32>,
+	pub focal_length: Option<String>,
+	/// Only included when `images.expose_gps` is enabled.
+	pub gps: Option<GpsCoordinates>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GpsCoordinates {
+	pub latitude: f64,
+	pub longitude: f64,
+}
+
+impl ImageMetadata {
+	fn from_exif(exif: &Exif, expose_gps: bool) -> Self {
+		let gps = if expose_gps {
+			gps_coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S").and_then(|latitude| {
+				gps_coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W").map(
+					|longitude| GpsCoordinates {
+						latitude,
+						longitude,
+					},
+				)
+			})
+		} else {
+			None
+		};
+
+		Self {
+			taken_at: exif
+				.get_field(Tag::DateTimeOriginal, In::PRIMARY)
+				.map(|field| field.display_value().to_string()),
+			camera_make: ascii_field(exif, Tag::Make),
+			camera_model: ascii_field(exif, Tag::Model),
+			lens_model: ascii_field(exif, Tag::LensModel),
+			exposure_time: display_field(exif, Tag::ExposureTime),
+			f_number: display_field(exif, Tag::FNumber),
+			iso: exif
+				.get_field(Tag::PhotographicSensitivity, In::PRIMARY)
+				.and_then(|field| field.value.get_uint(0)),
+			focal_length: display_field(exif, Tag::FocalLength),
+			gps,
+		}
+	}
+}
+
+/// Reads the EXIF data of an image, if the format supports it and it has any.
+#[inline]
+fn read_exif(path: &Path) -> Option<Exif> {
+	let file = FsFile::open(path).ok()?;
+	match exif::Reader::new().read_from_container(&mut BufReader::new(file)) {
+		Ok(exif) => Some(exif),
+		Err(error) => {
+			debug!("no exif data for {}: {error}", path.display());
+			None
+		}
+	}
+}
+
+#[inline]
+fn ascii_field(exif: &Exif, tag: Tag) -> Option<String> {
+	match &exif.get_field(tag, In::PRIMARY)?.value {
+		ExifValue::Ascii(values) => values
+			.first()
+			.map(|value| String::from_utf8_lossy(value).trim().to_string())
+			.filter(|value| !value.is_empty()),
+		_ => None,
+	}
+}
+
+#[inline]
+fn display_field(exif: &Exif, tag: Tag) -> Option<String> {
+	exif.get_field(tag, In::PRIMARY)
+		.map(|field| field.display_value().with_unit(exif).to_string())
+}
+
+/// Converts a degrees, minutes and seconds coordinate to decimal degrees.
+#[inline]
+fn gps_coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative_ref: &str) -> Option<f64> {
+	let ExifValue::Rational(values) = &exif.get_field(tag, In::PRIMARY)?.value else {
+		return None;
+	};
+	let [degrees, minutes, seconds] = values.as_slice() else {
+		return None;
+	};
+
+	let coordinate = degrees.to_f64() + minutes.to_f64() / 60.0 + seconds.to_f64() / 3600.0;
+	let is_negative = ascii_field(exif, ref_tag)
+		.map(|value| value.eq_ignore_ascii_case(negative_ref))
+		.unwrap_or(false);
+
+	Some(if is_negative { -coordinate } else { coordinate })
+}
@@ -0,0 +1,158 @@
+use super::{config::Config, paths::resolve_path};
+use crate::{
+	err,
+	error::{Error, Result},
+	map_err,
+};
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	str::FromStr,
+};
+use tracing::instrument;
+
+/// Starts a named region in a source file, e.g. `// ANCHOR: setup`.
+const ANCHOR_START: &str = "ANCHOR:";
+/// Ends a named region in a source file, e.g. `// ANCHOR_END: setup`.
+const ANCHOR_END: &str = "ANCHOR_END:";
+
+/// An inclusive, 1-based range of lines, written as `10-20`, `10-`, `-20` or `10`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LineRange {
+	start: usize,
+	end: Option<usize>,
+}
+
+impl FromStr for LineRange {
+	type Err = Error;
+
+	fn from_str(range: &str) -> Result<Self> {
+		let parse = |line: &str| -> Result<Option<usize>> {
+			let line = line.trim();
+			if line.is_empty() {
+				return Ok(None);
+			}
+
+			match line.parse::<usize>() {
+				Ok(0) | Err(_) => Err(err!(Validation(format!("invalid line range \"{range}\"")))),
+				Ok(line) => Ok(Some(line)),
+			}
+		};
+
+		let (start, end) = match range.split_once('-') {
+			Some((start, end)) => (parse(start)?, parse(end)?),
+			None => {
+				let line = parse(range)?;
+				(line, line)
+			}
+		};
+
+		Ok(Self {
+			start: start.unwrap_or(1),
+			end,
+		})
+	}
+}
+
+/// Reads the source file at `path`, relative to the project root unless `relative_to` is given.
+#[instrument(level = "debug", skip(config))]
+pub(crate) fn read_source(
+	path: &str,
+	relative_to: Option<&Path>,
+	config: &Config,
+) -> Result<(PathBuf, String)> {
+	let path = match relative_to {
+		Some(_) => resolve_path(path, relative_to, config)?,
+		None => {
+			let current_dir = map_err!(
+				std::env::current_dir(),
+				IoError("failed to get current dir"),
+			)?;
+			let path = current_dir.join(path);
+			let path = map_err!(
+				path.canonicalize(),
+				IoError(format!("file not found: {}", path.display())),
+			)?;
+
+			if !config.is_project_path(&path) {
+				return Err(err!(Validation(format!(
+					"{} is outside of the project",
+					path.display()
+				))));
+			}
+
+			path
+		}
+	};
+
+	let source = map_err!(
+		fs::read_to_string(&path),
+		IoError(format!("failed to read {}", path.display())),
+	)?;
+
+	Ok((path, source))
+}
+
+/// Extracts the lines of `source` within the `anchor` region, then within `lines` of that.
+///
+/// Anchor comments within the region are removed and the region is dedented.
+#[instrument(level = "debug", skip(source))]
+pub(crate) fn extract_region(
+	source: &str,
+	lines: Option<LineRange>,
+	anchor: Option<&str>,
+) -> Result<String> {
+	let mut region = source.lines().collect::<Vec<_>>();
+
+	if let Some(anchor) = anchor {
+		let start = region
+			.iter()
+			.position(|line| anchor_name(line, ANCHOR_START) == Some(anchor))
+			.ok_or(err!(Validation(format!("anchor `{anchor}` not found"))))?;
+		let end = region[start..]
+			.iter()
+			.position(|line| anchor_name(line, ANCHOR_END) == Some(anchor))
+			.map(|end| start + end)
+			.unwrap_or(region.len());
+
+		region = region[start + 1..end].to_vec();
+	}
+
+	if let Some(LineRange { start, end }) = lines {
+		let end = end.unwrap_or(region.len()).min(region.len());
+		if start > end {
+			return Err(err!(Validation(format!(
+				"line range {start}-{end} is out of bounds"
+			))));
+		}
+
+		region = region[start - 1..end].to_vec();
+	}
+
+	region.retain(|line| {
+		anchor_name(line, ANCHOR_START).is_none() && anchor_name(line, ANCHOR_END).is_none()
+	});
+
+	let indent = region
+		.iter()
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| line.len() - line.trim_start().len())
+		.min()
+		.unwrap_or(0);
+
+	let mut code = region
+		.iter()
+		.map(|line| line.get(indent..).unwrap_or_else(|| line.trim_start()))
+		.collect::<Vec<_>>()
+		.join("\n");
+	code.push('\n');
+
+	Ok(code)
+}
+
+/// Name of the anchor on `line` if it is an anchor comment of the given kind.
+#[inline]
+fn anchor_name<'l>(line: &'l str, kind: &str) -> Option<&'l str> {
+	let (_, rest) = line.split_once(kind)?;
+	rest.split_whitespace().next()
+}
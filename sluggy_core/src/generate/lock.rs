@@ -0,0 +1,164 @@
+use super::config::Config;
+use crate::{
+	err,
+	error::{Error, Result},
+	map_err,
+	utils::hex_digest,
+};
+use std::{
+	fs::{self, File, OpenOptions, TryLockError},
+	io::Write,
+	path::Path,
+	time::{Duration, Instant},
+};
+use tracing::instrument;
+
+/// Directory under `cache_dir` of the locks of the builds which write to each `out_dir`, so that
+/// they're never published with the site. Each lock holds the id of the process which has it.
+const LOCK_DIR: &str = "locks";
+
+/// How often a held lock is tried again.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An advisory lock on `out_dir`, so that builds of separate processes, such as `sluggy generate`
+/// while `sluggy serve` is rebuilding, don't interleave their writes. The lock is released when
+/// this is dropped.
+#[derive(Debug)]
+pub(crate) struct BuildLock {
+	_file: File,
+}
+
+/// Locks `out_dir`, waiting for up to `lock_timeout` for another process to release it.
+#[instrument(skip(config))]
+pub(crate) async fn lock_out_dir(config: &Config) -> Result<BuildLock> {
+	let lock_dir = config.cache_dir.join(LOCK_DIR);
+	map_err!(
+		fs::create_dir_all(&lock_dir),
+		IoError(format!("failed to create lock dir {}", lock_dir.display())),
+	)?;
+
+	let path = lock_dir.join(lock_file_name(&config.out_dir)?);
+	let mut file = map_err!(
+		OpenOptions::new()
+			.create(true)
+			.truncate(false)
+			.read(true)
+			.write(true)
+			.open(&path),
+		IoError(format!("failed to open lock file {}", path.display())),
+	)?;
+
+	let started = Instant::now();
+	let mut waiting = false;
+	loop {
+		match file.try_lock() {
+			Ok(()) => break,
+			Err(TryLockError::WouldBlock) => {
+				if started.elapsed() >= config.lock_timeout {
+					let holder = fs::read_to_string(&path)
+						.ok()
+						.filter(|pid| !pid.trim().is_empty())
+						.map(|pid| format!(" by process {}", pid.trim()))
+						.unwrap_or_default();
+					return Err(err!(Validation(format!(
+						"{} is locked{holder}, another sluggy process is writing to it",
+						config.out_dir.display()
+					))));
+				}
+
+				if !waiting {
+					tracing::warn!(
+						out_dir = %config.out_dir.display(),
+						"Waiting for another sluggy process to finish writing"
+					);
+					waiting = true;
+				}
+				tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+			}
+			Err(TryLockError::Error(error)) => {
+				return Err(Error::IoError {
+					message: format!("failed to lock {}", path.display()),
+					source: error,
+				});
+			}
+		}
+	}
+
+	// Only used to name the holder of the lock, so failing to write it doesn't matter.
+	let _ = file
+		.set_len(0)
+		.and_then(|_| write!(file, "{}", std::process::id()));
+
+	Ok(BuildLock { _file: file })
+}
+
+/// Name of the lock file of `out_dir`, from a digest of its canonical path. `out_dir` may not
+/// exist yet, or be swapped out by a staged build, so its parent is canonicalized instead.
+#[inline]
+fn lock_file_name(out_dir: &Path) -> Result<String> {
+	let out_dir = map_err!(
+		std::path::absolute(out_dir),
+		IoError(format!("failed to resolve {}", out_dir.display())),
+	)?;
+	let out_dir = match (out_dir.parent(), out_dir.file_name()) {
+		(Some(parent), Some(name)) => parent
+			.canonicalize()
+			.map(|parent| parent.join(name))
+			.unwrap_or(out_dir),
+		_ => out_dir,
+	};
+
+	Ok(format!(
+		"build-{}.lock",
+		&hex_digest(out_dir.as_os_str().as_encoded_bytes())[..16]
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config(root: &Path, out_dir: &str) -> Config {
+		Config {
+			out_dir: root.join(out_dir),
+			cache_dir: root.join(".cache"),
+			lock_timeout: Duration::from_millis(200),
+			..Config::default()
+		}
+	}
+
+	#[tokio::test]
+	async fn lock_times_out_while_held() {
+		let root = tempfile::tempdir().unwrap();
+		let config = config(root.path(), "public");
+
+		let lock = lock_out_dir(&config).await.unwrap();
+		assert!(lock_out_dir(&config).await.is_err());
+
+		drop(lock);
+		assert!(lock_out_dir(&config).await.is_ok());
+	}
+
+	#[tokio::test]
+	async fn each_out_dir_has_its_own_lock() {
+		let root = tempfile::tempdir().unwrap();
+
+		let _lock = lock_out_dir(&config(root.path(), "public")).await.unwrap();
+		assert!(lock_out_dir(&config(root.path(), "preview")).await.is_ok());
+	}
+
+	#[test]
+	fn lock_file_name_is_the_same_for_equivalent_paths() {
+		let root = tempfile::tempdir().unwrap();
+		fs::create_dir(root.path().join("site")).unwrap();
+
+		assert_eq!(
+			lock_file_name(&root.path().join("site/public")).unwrap(),
+			lock_file_name(&root.path().join("site/../site/public")).unwrap()
+		);
+		assert_ne!(
+			lock_file_name(&root.path().join("site/public")).unwrap(),
+			lock_file_name(&root.path().join("site/preview")).unwrap()
+		);
+	}
+}
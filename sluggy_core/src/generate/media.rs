@@ -0,0 +1,366 @@
+#[cfg(feature = "media")]
+use super::output_manifest;
+use super::{config::Config, Image};
+#[cfg(feature = "media")]
+use crate::{err, utils::hex_digest};
+use crate::{
+	error::{Error, Result},
+	map_err,
+};
+use serde_derive::Serialize;
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+#[cfg(feature = "media")]
+use std::{fs::File as FsFile, process::Command};
+#[cfg(feature = "media")]
+use symphonia::{
+	core::{formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint},
+	default::get_probe,
+};
+use tracing::instrument;
+
+/// MIME types which are read with the MP4 parser, which also exposes video dimensions.
+#[cfg(feature = "media")]
+const MP4_MIME_TYPES: [&str; 5] = [
+	"video/mp4",
+	"video/quicktime",
+	"audio/mp4",
+	"audio/m4a",
+	"audio/x-m4a",
+];
+
+/// Metadata of a video or audio file.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Media {
+	pub path: PathBuf,
+	/// URL of the file in the generated site, if it is copied from `assets_dir` or `content_dir`.
+	pub url: Option<String>,
+	pub mime_type: String,
+	/// Size of the file in bytes.
+	pub size: u64,
+	/// Duration in seconds.
+	pub duration: Option<f64>,
+	pub width: Option<u32>,
+	pub height: Option<u32>,
+	pub sample_rate: Option<u32>,
+	pub channels: Option<usize>,
+	pub poster: Option<Image>,
+}
+
+impl Media {
+	/// Reads the metadata of the media file at `path`. Without the `media` feature, only its size
+	/// and type are known.
+	#[instrument(level = "debug", skip(config))]
+	pub(crate) fn read(path: PathBuf, config: &Arc<Config>) -> Result<Self> {
+		let size = map_err!(
+			fs::metadata(&path),
+			IoError(format!("failed to read metadata of {}", path.display())),
+		)?
+		.len();
+
+		let mime_type = mime_guess::from_path(&path)
+			.first_raw()
+			.unwrap_or("application/octet-stream")
+			.to_string();
+
+		#[cfg_attr(not(feature = "media"), allow(unused_mut))]
+		let mut media = Self {
+			url: asset_url(&path, config),
+			path,
+			mime_type,
+			size,
+			duration: None,
+			width: None,
+			height: None,
+			sample_rate: None,
+			channels: None,
+			poster: None,
+		};
+
+		#[cfg(feature = "media")]
+		media.probe()?;
+
+		Ok(media)
+	}
+
+	/// Reads the duration, dimensions and audio format of the file.
+	#[cfg(feature = "media")]
+	#[inline]
+	fn probe(&mut self) -> Result<()> {
+		if MP4_MIME_TYPES.contains(&self.mime_type.as_str()) {
+			self.read_mp4()
+		} else if self.mime_type.starts_with("audio/") || self.mime_type.starts_with("video/") {
+			self.read_with_symphonia()
+		} else {
+			Err(err!(MediaError(format!(
+				"unsupported media type {} for {}",
+				self.mime_type,
+				self.path.display()
+			))))
+		}
+	}
+
+	#[cfg(feature = "media")]
+	#[inline]
+	fn read_mp4(&mut self) -> Result<()> {
+		let file = map_err!(
+			FsFile::open(&self.path),
+			IoError(format!("failed to open {}", self.path.display())),
+		)?;
+
+		let reader = mp4::read_mp4(file).map_err(|error| {
+			err!(MediaError(format!(
+				"failed to read {}: {error}",
+				self.path.display()
+			)))
+		})?;
+
+		self.duration = Some(reader.duration().as_secs_f64());
+
+		for track in reader.tracks().values() {
+			match track.track_type() {
+				Ok(mp4::TrackType::Video) if self.width.is_none() => {
+					self.width = Some(track.width() as u32);
+					self.height = Some(track.height() as u32);
+				}
+				Ok(mp4::TrackType::Audio) if self.sample_rate.is_none() => {
+					self.sample_rate = track
+						.sample_freq_index()
+						.ok()
+						.map(|sample_freq_index| sample_freq_index.freq());
+				}
+				_ => {}
+			}
+		}
+
+		Ok(())
+	}
+
+	#[cfg(feature = "media")]
+	#[inline]
+	fn read_with_symphonia(&mut self) -> Result<()> {
+		let file = map_err!(
+			FsFile::open(&self.path),
+			IoError(format!("failed to open {}", self.path.display())),
+		)?;
+		let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+		let mut hint = Hint::new();
+		if let Some(extension) = self
+			.path
+			.extension()
+			.and_then(|extension| extension.to_str())
+		{
+			hint.with_extension(extension);
+		}
+
+		let probed = get_probe()
+			.format(
+				&hint,
+				stream,
+				&FormatOptions::default(),
+				&MetadataOptions::default(),
+			)
+			.map_err(|error| {
+				err!(MediaError(format!(
+					"failed to read {}: {error}",
+					self.path.display()
+				)))
+			})?;
+
+		let Some(track) = probed.format.default_track() else {
+			return Err(err!(MediaError(format!(
+				"no tracks found in {}",
+				self.path.display()
+			))));
+		};
+
+		let params = &track.codec_params;
+		self.sample_rate = params.sample_rate;
+		self.channels = params.channels.map(|channels| channels.count());
+		self.duration = match (params.time_base, params.n_frames) {
+			(Some(time_base), Some(n_frames)) => {
+				let time = time_base.calc_time(n_frames);
+				Some(time.seconds as f64 + time.frac)
+			}
+			(None, Some(n_frames)) => params
+				.sample_rate
+				.map(|sample_rate| n_frames as f64 / sample_rate as f64),
+			_ => None,
+		};
+
+		Ok(())
+	}
+
+	/// Extracts a frame from the video with `ffmpeg` as its poster image.
+	#[cfg(feature = "media")]
+	#[instrument(level = "debug", skip(self, config))]
+	pub(crate) fn generate_poster(&mut self, config: &Arc<Config>) -> Result<()> {
+		let Some(ffmpeg) = &config.media.ffmpeg else {
+			return Err(err!(MediaError(
+				"generating poster images requires `media.ffmpeg` to be configured".into()
+			)));
+		};
+
+		let (Some(width), Some(height)) = (self.width, self.height) else {
+			return Err(err!(MediaError(format!(
+				"{} has no video track",
+				self.path.display()
+			))));
+		};
+
+		let poster_path = self.poster_path(config)?;
+
+		if !poster_path.exists() {
+			let out_dir = poster_path.parent().unwrap();
+			map_err!(
+				fs::create_dir_all(out_dir),
+				IoError(format!("could not create directory {}", out_dir.display())),
+			)?;
+
+			let output = map_err!(
+				Command::new(ffmpeg)
+					.arg("-y")
+					.args(["-loglevel", "error"])
+					.args(["-ss", &config.media.poster_offset.to_string()])
+					.arg("-i")
+					.arg(&self.path)
+					.args(["-frames:v", "1"])
+					.arg(&poster_path)
+					.output(),
+				IoError(format!("failed to run {}", ffmpeg.display())),
+			)?;
+
+			if !output.status.success() {
+				return Err(err!(MediaError(format!(
+					"failed to generate poster for {}: {}",
+					self.path.display(),
+					String::from_utf8_lossy(&output.stderr).trim()
+				))));
+			}
+		}
+		// Posters which are already generated are outputs of this build too, so that they aren't
+		// removed as stale.
+		output_manifest::record(&poster_path, Some(&self.path));
+
+		self.poster = Some(Image::new(
+			config,
+			width,
+			height,
+			poster_path,
+			self.path.clone(),
+		));
+
+		Ok(())
+	}
+
+	/// Path of the poster image within `processed_images_dir`, which is named after the video
+	/// and the offset of its frame.
+	#[cfg(feature = "media")]
+	#[inline]
+	fn poster_path(&self, config: &Config) -> Result<PathBuf> {
+		let file_name = self.path.file_name().ok_or(err!(MediaError(format!(
+			"could not get filename from {}",
+			self.path.display()
+		))))?;
+
+		Ok(config
+			.out_dir
+			.join(&config.processed_images_dir)
+			.join(file_name)
+			.join(hex_digest(format!(
+				"{}{}",
+				self.path.display(),
+				config.media.poster_offset
+			)))
+			.with_extension("jpg"))
+	}
+}
+
+/// URL of a file which is copied into the generated site as is.
+#[inline]
+fn asset_url(path: &Path, config: &Config) -> Option<String> {
+	if let Ok(relative_path) = path.strip_prefix(&config.assets_dir) {
+		let assets_dir_name = config.assets_dir.file_name()?.to_string_lossy();
+		Some(format!(
+			"{}{assets_dir_name}/{}",
+			config.base_url,
+			relative_path.to_string_lossy()
+		))
+	} else {
+		path.strip_prefix(&config.content_dir)
+			.ok()
+			.map(|relative_path| format!("{}{}", config.base_url, relative_path.to_string_lossy()))
+	}
+}
+
+#[cfg(all(test, feature = "media"))]
+mod tests {
+	use super::*;
+	use crate::generate::config::MediaConfig;
+	use std::os::unix::fs::PermissionsExt;
+
+	fn video(path: &Path) -> Media {
+		Media {
+			path: path.to_path_buf(),
+			url: None,
+			mime_type: "video/mp4".into(),
+			size: 0,
+			duration: Some(1.0),
+			width: Some(640),
+			height: Some(360),
+			sample_rate: None,
+			channels: None,
+			poster: None,
+		}
+	}
+
+	#[test]
+	fn posters_are_outputs_of_the_build() {
+		let temp_dir = tempfile::tempdir().unwrap();
+		let dir = temp_dir.path();
+
+		// Stands in for ffmpeg, writing an empty poster to its last argument.
+		let ffmpeg = dir.join("ffmpeg");
+		fs::write(
+			&ffmpeg,
+			"#!/bin/sh\nfor arg; do last=$arg; done\n: > \"$last\"\n",
+		)
+		.unwrap();
+		fs::set_permissions(&ffmpeg, fs::Permissions::from_mode(0o755)).unwrap();
+		let config = Arc::new(Config {
+			out_dir: dir.join("out"),
+			media: MediaConfig {
+				ffmpeg: Some(ffmpeg),
+				..MediaConfig::default()
+			},
+			..Config::default()
+		});
+
+		let generated = dir.join("generated.mp4");
+		let mut media = video(&generated);
+		media.generate_poster(&config).unwrap();
+		let poster_path = media.poster_path(&config).unwrap();
+		assert!(poster_path.exists());
+		assert!(output_manifest::is_recorded(&poster_path));
+
+		// Posters which already exist aren't generated again, but are still recorded.
+		let config = Arc::new(Config {
+			media: MediaConfig {
+				ffmpeg: Some(dir.join("missing")),
+				..MediaConfig::default()
+			},
+			..(*config).clone()
+		});
+		let cached = dir.join("cached.mp4");
+		let mut media = video(&cached);
+		let poster_path = media.poster_path(&config).unwrap();
+		fs::create_dir_all(poster_path.parent().unwrap()).unwrap();
+		fs::write(&poster_path, "").unwrap();
+		media.generate_poster(&config).unwrap();
+		assert!(output_manifest::is_recorded(&poster_path));
+	}
+}
@@ -1,45 +1,102 @@
+mod archives;
+mod atomic;
+mod blogroll;
+mod budgets;
+mod build_cache;
+mod calendars;
+mod changes;
 pub mod config;
 pub mod content;
+mod context_dump;
+mod data;
+mod diagrams;
+mod encryption;
+mod feeds;
+mod fingerprint;
+mod graph;
+mod headers;
+pub mod hooks;
 mod html;
+#[cfg(feature = "http-loader")]
+mod http_cache;
+#[cfg(feature = "images")]
+mod images;
+mod include;
+mod lock;
+mod media;
+mod notebook;
+mod on_demand;
+mod output_manifest;
+mod pagination;
+mod paths;
+mod postprocess;
+mod redirects;
+mod scripts;
 mod sections;
+mod shortcodes;
+mod sitemap;
+mod source_links;
+mod structured_data;
 mod syntect;
+mod taxonomies;
 mod template;
+mod transforms;
+
+pub use changes::OutputChange;
+pub use feeds::render_feed;
+pub use graph::{ContentGraph, GraphEntry, GraphLink, GraphSection, GraphTerm};
+pub use hooks::Hooks;
+pub use on_demand::OnDemand;
+pub use sitemap::render_sitemap;
+pub use template::testing::TemplateTestResult;
 
 use self::{
-	config::Config,
+	config::{Config, UrlConflicts, LATEST_VERSION_DIR},
 	content::{Content, FileType},
 };
 use crate::{
-	common::http::ContentEncoding,
+	encoding::{self, ContentEncoding},
 	err,
 	error::{Error, Result},
 	lazyfn::LazyFn,
 	map_err,
-	utils::await_joinset,
+	utils::{await_joinset, await_joinset_all, catch_panic, catch_panic_async, unlink_output},
 };
-use content::{loader::ContentLoader, Entry};
-use dashmap::DashMap;
-use html::{minifier::minify_html, rewriter::Rewriter};
-use itertools::Itertools;
+#[cfg(feature = "css")]
+use base64::prelude::*;
+use content::{loader::ContentLoader, replaces_conflicting, Entry};
+use dashmap::{mapref::entry::Entry as MapEntry, DashMap};
+use html::{links, minifier::minify_html, rewriter::Rewriter};
+#[cfg(feature = "images")]
+use images::ImageMetadata;
+#[cfg(feature = "css")]
 use lightningcss::{
 	bundler::{Bundler, FileProvider},
 	css_modules::{Config as CssModulesConfig, Pattern},
 	stylesheet::{ParserFlags, ParserOptions, PrinterOptions},
 	targets::Browsers,
 };
+use pagination::Paginator;
+#[cfg(feature = "css")]
+use parcel_sourcemap::SourceMap;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use serde_derive::{Deserialize, Serialize};
 use serde_json::json;
 use std::{
+	collections::{BTreeSet, HashMap},
 	ffi::OsStr,
 	fs::{self, File},
+	future::Future,
 	io::Write,
 	path::{Path, PathBuf},
 	sync::Arc,
 };
 use tokio::{
 	fs::File as TokioFile,
-	io::{AsyncReadExt, AsyncWriteExt, BufReader},
-	task::JoinSet,
+	io::{AsyncReadExt, BufReader},
+	runtime::Handle,
+	sync::watch,
+	task::{block_in_place, JoinSet},
 };
 use tracing::instrument;
 
@@ -47,119 +104,367 @@ const ONCE_OFF_TEMPLATE_NAME_PREFIX: &str = "___once_off_";
 
 static EMBEDDABLE_CONTENT: LazyFn<DashMap<PathBuf, String>> = LazyFn::new(DashMap::new);
 
+/// Entries which embed each of `EMBEDDABLE_CONTENT`, as of the last time they were rendered.
+static EMBEDDED_BY: LazyFn<DashMap<PathBuf, BTreeSet<PathBuf>>> = LazyFn::new(DashMap::new);
+
+/// CSS as each tree last bundled it, by the base URL of the tree, as `EMBEDDABLE_CONTENT` only
+/// holds the CSS of the tree which was bundled last.
+static TREE_CSS: LazyFn<DashMap<String, HashMap<PathBuf, String>>> = LazyFn::new(DashMap::new);
+
+/// Static files which have been copied, by their paths within `out_dir`. Each is processed once
+/// the sender of its receiver is dropped.
+static PROCESSING_ASSETS: LazyFn<DashMap<PathBuf, watch::Receiver<()>>> = LazyFn::new(DashMap::new);
+
 #[derive(Debug)]
 pub struct Generator {
 	pub config: Arc<Config>,
 }
 
 impl Generator {
+	/// Generates the site, followed by each of the versioned trees.
 	#[instrument(skip(config))]
 	pub async fn generate(config: Arc<Config>) -> Result<()> {
+		let _lock = lock::lock_out_dir(&config).await?;
+
+		EMBEDDED_BY.clear();
+		TREE_CSS.clear();
+		PROCESSING_ASSETS.clear();
+		template::clear_fragments();
+		changes::clear();
+		fingerprint::clear();
+		headers::clear_headers();
+		redirects::clear_redirects();
+		output_manifest::clear();
+		build_cache::load(&config)?;
+		output_manifest::check_stale_outputs(&config)?;
+
+		// Links and budgets are checked before a staged build is swapped into place, so that a build
+		// which fails them isn't served.
+		Self::staged(&config, |config| async move {
+			Self::build(config.clone()).await?;
+
+			if config.check_links {
+				let broken_links = links::find_broken_links(&config)?;
+				for broken_link in &broken_links {
+					tracing::warn!(
+						page = %broken_link.page.display(),
+						link = %broken_link.link,
+						"Broken link"
+					);
+				}
+
+				if config.deny_broken_links && !broken_links.is_empty() {
+					return Err(err!(BrokenLinks(broken_links.len())));
+				}
+			}
+
+			budgets::check_budgets(&config)
+		})
+		.await
+	}
+
+	/// Runs `build` with a config of a copy of `out_dir` with atomic builds, which is swapped into
+	/// place once `build` succeeds, and discarded otherwise. Without atomic builds, `build` writes to
+	/// `out_dir` itself.
+	#[inline]
+	async fn staged<T, F>(config: &Arc<Config>, build: impl FnOnce(Arc<Config>) -> F) -> Result<T>
+	where
+		F: Future<Output = Result<T>>,
+	{
+		if !config.atomic_builds {
+			return build(config.clone()).await;
+		}
+
+		let staged = Arc::new(atomic::stage(config)?);
+		match build(staged.clone()).await {
+			Ok(value) => {
+				if let Err(error) = atomic::swap(config, &staged) {
+					atomic::discard(&staged);
+					return Err(error);
+				}
+				changes::rebase_written(&staged.out_dir, &config.out_dir);
+				Ok(value)
+			}
+			Err(error) => {
+				atomic::discard(&staged);
+				Err(error)
+			}
+		}
+	}
+
+	/// Generates each of the trees into the `out_dir` of `config`, and writes the files of the whole
+	/// site.
+	#[inline]
+	async fn build(config: Arc<Config>) -> Result<()> {
+		// Trees are generated one after the other, as the template engine is shared.
+		for tree_config in trees(&config) {
+			if let Err(error) = Self::generate_tree(tree_config, None).await {
+				build_cache::reset();
+				return Err(error);
+			}
+		}
+
+		headers::write_headers_file(&config)?;
+		redirects::write_redirects_file(&config)?;
+		redirects::write_redirect_pages(&config)?;
+		build_cache::save(&config)?;
+		template::remove_unused_fragments(&config)?;
+		output_manifest::clean_stale_outputs(&config)?;
+		output_manifest::write_manifest(&config)?;
+
+		Ok(())
+	}
+
+	/// Bundles the CSS again and renders only the entries which embed CSS that has changed, for
+	/// when nothing but files of `css_dir` have changed.
+	#[instrument(skip(config))]
+	pub async fn regenerate_css(config: Arc<Config>) -> Result<()> {
+		let _lock = lock::lock_out_dir(&config).await?;
+
+		changes::clear();
+
+		Self::staged(&config, Self::rebundle_css).await
+	}
+
+	/// Each tree is bundled and then rendered before the next, so that its entries embed its own
+	/// CSS, and its CSS is compared with what it bundled before.
+	#[inline]
+	async fn rebundle_css(config: Arc<Config>) -> Result<()> {
+		for tree_config in trees(&config) {
+			let previous_css = TREE_CSS
+				.remove(&tree_config.base_url)
+				.map(|(_, css)| css)
+				.unwrap_or_default();
+
+			let generator = Generator {
+				config: tree_config.clone(),
+			};
+
+			let mut join_set = JoinSet::new();
+			generator.bundle_css(&mut join_set)?;
+			await_joinset(join_set).await?;
+
+			let entry_paths = TREE_CSS
+				.get(&tree_config.base_url)
+				.map(|tree_css| {
+					tree_css
+						.iter()
+						.filter(|(out_file, css)| previous_css.get(*out_file) != Some(*css))
+						.filter_map(|(out_file, _)| {
+							EMBEDDED_BY.get(out_file).map(|paths| paths.clone())
+						})
+						.flatten()
+						.collect::<BTreeSet<_>>()
+				})
+				.unwrap_or_default();
+
+			if !entry_paths.is_empty() {
+				Self::generate_tree(tree_config, Some(&entry_paths)).await?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Generates the tree of `config`. When `only` is set, just those entries are rendered, as the
+	/// CSS and static files are expected to be up to date.
+	#[instrument(skip(config, only))]
+	async fn generate_tree(config: Arc<Config>, only: Option<&BTreeSet<PathBuf>>) -> Result<()> {
 		let generator = Arc::new(Generator {
 			config: config.clone(),
 		});
 
+		syntect::load_syntaxes(&config)?;
+
 		let content_loader = ContentLoader::new(config.clone());
 
 		content_loader.load().await?;
 
-		// We need css transpiled first so that it can be embedded if required
-		let mut join_set = JoinSet::new();
-		generator.bundle_css(&mut join_set)?;
-		await_joinset(join_set).await?;
-
-		let mut join_set = JoinSet::new();
-		generator.copy_static_files(&mut join_set).await?;
+		// Entries are collected, and every page is checked for conflicts, before anything is
+		// written.
+		let entries = loaded_entries(&content_loader, &config)?;
 
-		let entries: DashMap<PathBuf, Entry> = content_loader
-			.entries
-			.clone() // TODO Don't like this clone yo
-			.into_iter()
-			.map(|(_path, entry)| {
-				Entry::try_from_entry_data(entry, &content_loader.taxonomies, config.clone()).map(
-					|entries| {
-						entries
-							.into_iter()
-							.map(|entry| (entry.path.clone(), entry))
-							.collect::<Vec<_>>()
-					},
-				)
-			})
-			.flatten_ok()
-			.collect::<Result<_>>()?;
-
-		let content = Arc::new(Content {
+		let mut content = Content {
 			entries,
 			sections: content_loader.sections.clone(), // TODO this is slow
 			taxonomies: content_loader.taxonomies.clone(), // TODO this is slow
 			config: config.clone(),
-		});
+		};
+		config.hooks.run_after_content_load(&mut content)?;
+		let content = Arc::new(content);
+
+		check_output_conflicts(&content)?;
+
+		let mut join_set = JoinSet::new();
+		if only.is_none() {
+			// Static files are copied before the css is bundled, so that its `url()`s can point at
+			// the fingerprinted names of the files. They're postprocessed and compressed while
+			// entries are rendered.
+			generator.copy_static_files(&mut join_set).await?;
+
+			// We need css transpiled and scripts bundled first so that they can be embedded if
+			// required
+			let mut css_join_set = JoinSet::new();
+			generator.bundle_css(&mut css_join_set)?;
+			generator.bundle_js(&mut css_join_set)?;
+			await_joinset(css_join_set).await?;
+
+			fingerprint::write_manifest(&config)?;
+		}
+
+		if only.is_none() {
+			headers::collect_headers(&content)?;
+			redirects::collect_redirects(&content)?;
+		}
+		encryption::collect_passwords(&content)?;
+		source_links::collect_source_urls(&content)?;
+		blogroll::load_blogroll(&config).await?;
 
 		template::setup_template_engine(&content)?;
 
+		// Converting markdown and notebooks to HTML is the bulk of a build, so it's done up front
+		// on the rayon pool rather than one entry at a time. Rayon's threads enter the runtime, as
+		// template functions and shortcodes block on it, e.g. to fetch URLs and process images.
+		let handle = Handle::current();
+		let mut generated_html = block_in_place(|| {
+			content
+				.entries
+				.par_iter()
+				.filter(|entry| only.is_none_or(|only| only.contains(entry.key())))
+				.filter(|entry| {
+					entry.is_renderable && entry.layout.is_none() && entry.file_type.is_generated()
+				})
+				.map(|entry| {
+					let _guard = handle.enter();
+					let html = catch_panic(entry.key(), || entry.generate(&config));
+					(entry.key().clone(), html)
+				})
+				.collect::<HashMap<_, _>>()
+		});
+
+		let mut errors = vec![];
 		for entry in content.entries.iter() {
 			let entry_path = entry.key().clone();
 			let entry = entry.value();
 
+			if let Some(only) = only {
+				if !only.contains(&entry_path) {
+					continue;
+				}
+			}
+
 			if entry.is_renderable {
-				let mut file_path = entry_path.clone();
-				if entry.file_type.is_rendered_to_html() {
-					let is_index = entry_path
-						.components()
-						.last()
-						.unwrap()
-						.as_os_str()
-						.to_string_lossy()
-						.starts_with("index");
-
-					if !is_index {
-						file_path = entry_path.join("index");
+				let file_path = entry_file_path(&entry_path, entry);
+
+				// If the file is markdown or a notebook, but has no layout, then its html has
+				// already been generated. Otherwise just return raw
+				let template = entry_template(entry, || match generated_html.remove(&entry_path) {
+					Some(html) => html,
+					None => catch_panic(&entry_path, || entry.raw()),
+				});
+				let (template_name, template_raw) = match template {
+					Ok(template) => template,
+					Err(error) if config.keep_going => {
+						errors.push(error);
+						continue;
 					}
+					Err(error) => return Err(error),
+				};
 
-					file_path.set_extension(FileType::Html.as_ref());
-				} else if !entry.file_type.is_template() {
-					file_path.set_extension(entry.file_type.as_ref());
-				} else {
-					file_path.set_extension("");
-				}
+				let head_html = match entry_head_html(entry, &file_path, &content) {
+					Ok(head_html) => head_html,
+					Err(error) if config.keep_going => {
+						errors.push(error);
+						continue;
+					}
+					Err(error) => return Err(error),
+				};
 
-				let (template_name, template_raw) = if let Some(layout) = &entry.layout {
-					(layout.clone(), None)
-				} else {
-					// If the file is markdown, but has no layout, then we generate it's html.
-					let html = if entry.file_type.is_markdown() {
-						entry.generate()?
-					} else {
-						// Otherwise just return raw
-						entry.raw()?
-					};
-					(
-						format!("{}{}", ONCE_OFF_TEMPLATE_NAME_PREFIX, entry.path.display()),
-						Some(html),
-					)
+				let pages = match entry_pages(&entry_path, file_path, &content) {
+					Ok(pages) => pages,
+					Err(error) if config.keep_going => {
+						errors.push(error);
+						continue;
+					}
+					Err(error) => return Err(error),
 				};
 
-				generator.dirs_exists(&file_path)?;
-				join_set.spawn(render_entry(
-					file_path,
-					entry_path,
-					template_name,
-					template_raw,
-					config.clone(),
-				));
+				for (file_path, paginator) in pages {
+					generator.dirs_exists(&file_path)?;
+					output_manifest::record(
+						&config.out_dir.join(&file_path),
+						Some(&entry.file_path),
+					);
+
+					if context_dump::should_dump(&entry_path, &config) {
+						let context = entry_context(&entry_path, paginator.as_ref(), &config);
+						context_dump::dump_context(
+							&entry_path,
+							&file_path,
+							&context,
+							paginator.as_ref(),
+							&content,
+						)?;
+					}
+					join_set.spawn(catch_panic_async(
+						entry_path.clone(),
+						render_entry(
+							file_path,
+							entry_path.clone(),
+							template_name.clone(),
+							template_raw.clone(),
+							head_html.clone(),
+							paginator,
+							config.clone(),
+						),
+					));
+				}
 			} else {
 				// Just copy the file to the out dir.
+				let out_file = config.out_dir.join(&entry_path);
+				unlink_output(&out_file)?;
 				map_err!(
-					fs::copy(&entry.file_path, config.out_dir.join(&entry_path)),
+					fs::copy(&entry.file_path, &out_file),
 					IoError(format!("Failed to copy file {}", entry_path.display())),
 				)?;
+				output_manifest::record(&out_file, Some(&entry.file_path));
+				changes::record_written(&out_file);
 			}
 		}
 
-		await_joinset(join_set).await?;
+		if only.is_none() {
+			for result in [
+				feeds::generate_feeds(&content, &mut join_set),
+				calendars::generate_calendars(&content, &mut join_set),
+				blogroll::generate_opml(&content, &mut join_set),
+				taxonomies::generate_taxonomies(&content, &mut join_set),
+				archives::generate_archives(&content, &mut join_set),
+				sitemap::generate_sitemap(&content, &mut join_set),
+			] {
+				match result {
+					Ok(_) => {}
+					Err(error) if config.keep_going => errors.push(error),
+					Err(error) => return Err(error),
+				}
+			}
+		}
+
+		if config.keep_going {
+			errors.extend(await_joinset_all(join_set).await);
+		} else {
+			await_joinset(join_set).await?;
+		}
 
 		template::unset_template_engine();
 
+		if !errors.is_empty() {
+			for error in &errors {
+				tracing::error!(%error, "Generation failure");
+			}
+
+			return Err(err!(GenerateFailed(errors.len())));
+		}
+
 		Ok(())
 	}
 
@@ -209,34 +514,6 @@ impl Generator {
 				return Ok(());
 			}
 
-			let file_provider = FileProvider::new();
-
-			let parser_options = ParserOptions {
-				filename: file.to_string_lossy().to_string(),
-				css_modules: Some(CssModulesConfig {
-					pattern: Pattern::parse("[local]")?,
-					dashed_idents: false,
-				}),
-				source_index: 0,
-				error_recovery: false,
-				warnings: None,
-				flags: ParserFlags::NESTING | ParserFlags::CUSTOM_MEDIA,
-			};
-			let mut bundler = Bundler::new(&file_provider, None, parser_options);
-			let out = bundler.bundle(file)?;
-
-			let printer_options = PrinterOptions {
-				minify: self.config.minify,
-				source_map: None,
-				project_root: None,
-				// TODO make this a config option
-				targets: Browsers::from_browserslist(["> 0.2% and not dead"])?.into(),
-				analyze_dependencies: None,
-				pseudo_classes: None,
-			};
-
-			let css = out.to_css(printer_options)?.code;
-
 			let css_dir_name = self
 				.config
 				.css_dir
@@ -248,10 +525,22 @@ impl Generator {
 				StripPathPrefix("failed to strip css dir prefix"),
 			)?;
 
-			let to_path = self.config.out_dir.join(css_dir_name).join(to_file);
+			let out_file = PathBuf::from(css_dir_name).join(to_file);
+			let css = fingerprint::rewrite_css_urls(
+				&transform_css(file, &self.config)?,
+				&out_file,
+				&self.config,
+			);
+			let to_path = self.config.out_dir.join(fingerprint::fingerprint(
+				&out_file,
+				css.as_bytes(),
+				&self.config,
+			));
 
 			create_dir_all(&self.config.out_dir, to_path.parent().unwrap())?;
+			output_manifest::record(&to_path, Some(file));
 
+			unlink_output(&to_path)?;
 			let mut file = map_err!(
 				File::create(&to_path),
 				IoError(format!("Failed to create {}", to_file.display())),
@@ -262,54 +551,236 @@ impl Generator {
 				IoError(format!("Failed to write css to {}", to_file.display())),
 			)?;
 
-			EMBEDDABLE_CONTENT.insert(PathBuf::from(&css_dir_name).join(to_file), css);
+			TREE_CSS
+				.entry(self.config.base_url.clone())
+				.or_default()
+				.insert(out_file.clone(), css.clone());
+			EMBEDDABLE_CONTENT.insert(out_file, css);
+
+			process_output(&to_path, join_set, self.config.clone())?;
 
-			if self.config.compress_content {
-				apply_compression(&to_path, join_set, self.config.clone())?;
+			Ok(())
+		})?;
+
+		Ok(())
+	}
+
+	/// Bundles each of the scripts of `js_dir` into a `.js` file of the same name. Scripts prefixed
+	/// with `_` are only bundled where they're imported. Scripts are bundled concurrently.
+	#[instrument(skip(self))]
+	fn bundle_js(&self, join_set: &mut JoinSet<Result<()>>) -> Result<()> {
+		// Unlike the css dir, the js dir is optional.
+		if !self.config.js_dir.is_dir() {
+			return Ok(());
+		}
+
+		Self::recursive_process(&self.config.js_dir, &mut |file| {
+			if !scripts::is_entry_script(file) {
+				return Ok(());
 			}
 
+			let file = file.to_path_buf();
+			let config = self.config.clone();
+			join_set.spawn(async move {
+				let js = scripts::bundle_script(&file, &config).await?;
+
+				let js_dir_name = config
+					.js_dir
+					.file_name()
+					.ok_or(err!(Validation("Invalid js dir")))?;
+
+				let to_file = map_err!(
+					file.strip_prefix(&config.js_dir),
+					StripPathPrefix("failed to strip js dir prefix"),
+				)?
+				.with_extension("js");
+
+				let out_file = PathBuf::from(js_dir_name).join(&to_file);
+				let to_path = config.out_dir.join(fingerprint::fingerprint(
+					&out_file,
+					js.as_bytes(),
+					&config,
+				));
+
+				create_dir_all(&config.out_dir, to_path.parent().unwrap())?;
+				output_manifest::record(&to_path, Some(&file));
+
+				unlink_output(&to_path)?;
+				map_err!(
+					tokio::fs::write(&to_path, js.as_bytes()).await,
+					IoError(format!("Failed to write js to {}", to_file.display())),
+				)?;
+
+				EMBEDDABLE_CONTENT.insert(out_file, js);
+
+				let mut output_join_set = JoinSet::new();
+				process_output(&to_path, &mut output_join_set, config.clone())?;
+				await_joinset(output_join_set).await?;
+
+				Ok(())
+			});
+
 			Ok(())
 		})?;
 
 		Ok(())
 	}
 
-	#[instrument(skip_all)]
-	async fn copy_static_files(&self, join_set: &mut JoinSet<Result<()>>) -> Result<()> {
-		Self::recursive_process(&self.config.assets_dir, &mut |file| {
-			let to_file = map_err!(
-				file.strip_prefix(&self.config.assets_dir),
-				StripPathPrefix("failed to strip assets dir prefix"),
-			)?;
+	/// The pages which changed content since the start of the last build, when `report_changes`
+	/// is enabled. Changes are only returned once.
+	pub fn take_changes() -> Vec<OutputChange> {
+		changes::take()
+	}
 
-			let to_path = self
-				.config
-				.out_dir
-				.join(
-					self.config
-						.assets_dir
-						.file_name()
-						.ok_or(err!(Validation("Invalid asset dir")))?,
-				)
-				.join(to_file);
+	/// The files which were written to `out_dir`, or the `out_dir` of a versioned tree, since the
+	/// last time they were taken. Pages and assets which were the same as the last build aren't
+	/// written when `build_cache` is enabled. Files are only returned once.
+	pub fn take_written() -> Vec<PathBuf> {
+		changes::take_written()
+	}
 
-			create_dir_all(&self.config.out_dir, to_path.parent().unwrap())?;
+	/// Loads the entries of the content dir without rendering anything, e.g. to pass a filtered
+	/// selection of them to `render_feed` or `render_sitemap`. Entries are sorted by path.
+	#[instrument(skip(config))]
+	pub async fn load_entries(config: Arc<Config>) -> Result<Vec<Entry>> {
+		let content_loader = ContentLoader::new(config.clone());
+		content_loader.load().await?;
 
-			map_err!(
-				fs::copy(file, &to_path),
-				IoError(format!("failed to copy to {}", to_path.display())),
-			)?;
+		let mut entries = loaded_entries(&content_loader, &config)?
+			.into_iter()
+			.map(|(_path, entry)| entry)
+			.collect::<Vec<_>>();
+		entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+		Ok(entries)
+	}
+
+	/// Loads the content of the site without rendering anything, and collects the graph of its
+	/// entries, sections, taxonomy terms and links between entries.
+	#[instrument(skip(config))]
+	pub async fn content_graph(config: Arc<Config>) -> Result<ContentGraph> {
+		let content_loader = ContentLoader::new(config.clone());
+		content_loader.load().await?;
+
+		let mut content = Content {
+			entries: loaded_entries(&content_loader, &config)?,
+			sections: content_loader.sections.clone(),
+			taxonomies: content_loader.taxonomies.clone(),
+			config: config.clone(),
+		};
+		config.hooks.run_after_content_load(&mut content)?;
+
+		ContentGraph::new(&content)
+	}
 
-			if self.config.compress_content {
-				apply_compression(&to_path, join_set, self.config.clone())?;
+	/// Runs the template test cases of `tests_dir` against `template_dir`, without generating the
+	/// site. Snapshots are written again when `update_snapshots` is set.
+	#[instrument(skip(config))]
+	pub fn test_templates(
+		config: Arc<Config>,
+		update_snapshots: bool,
+	) -> Result<Vec<TemplateTestResult>> {
+		syntect::load_syntaxes(&config)?;
+		template::testing::run_template_tests(config, update_snapshots)
+	}
+
+	/// Copies changed files of `assets_dir` into each of the trees without regenerating the site,
+	/// returning the paths of the copies relative to `out_dir`.
+	#[instrument(skip(config))]
+	pub async fn copy_assets(config: Arc<Config>, files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+		let _lock = lock::lock_out_dir(&config).await?;
+
+		Self::staged(&config, |config| async move {
+			let mut copied = vec![];
+			let mut join_set = JoinSet::new();
+			for tree_config in trees(&config) {
+				let generator = Generator {
+					config: tree_config,
+				};
+
+				for file in files {
+					let to_path = generator.copy_static_file(file, &mut join_set)?;
+					copied.push(map_err!(
+						to_path.strip_prefix(&config.out_dir).map(Path::to_path_buf),
+						StripPathPrefix("failed to strip out dir prefix"),
+					)?);
+				}
 			}
+			await_joinset(join_set).await?;
+
+			Ok(copied)
+		})
+		.await
+	}
 
+	#[instrument(skip_all)]
+	async fn copy_static_files(&self, join_set: &mut JoinSet<Result<()>>) -> Result<()> {
+		Self::recursive_process(&self.config.assets_dir, &mut |file| {
+			self.copy_static_file(file, join_set)?;
 			Ok(())
 		})?;
 
 		Ok(())
 	}
 
+	#[inline]
+	fn copy_static_file(&self, file: &Path, join_set: &mut JoinSet<Result<()>>) -> Result<PathBuf> {
+		let to_file = map_err!(
+			file.strip_prefix(&self.config.assets_dir),
+			StripPathPrefix("failed to strip assets dir prefix"),
+		)?;
+
+		let mut out_file = PathBuf::from(
+			self.config
+				.assets_dir
+				.file_name()
+				.ok_or(err!(Validation("Invalid asset dir")))?,
+		)
+		.join(to_file);
+
+		let is_fingerprinted = fingerprint::is_fingerprinted_asset(&out_file, &self.config)?;
+		let content = if self.config.build_cache || is_fingerprinted {
+			Some(map_err!(
+				fs::read(file),
+				IoError(format!("failed to read {}", file.display())),
+			)?)
+		} else {
+			None
+		};
+
+		if let Some(content) = content.as_ref().filter(|_| is_fingerprinted) {
+			out_file = fingerprint::fingerprint(&out_file, content, &self.config);
+		}
+		let to_path = self.config.out_dir.join(out_file);
+
+		create_dir_all(&self.config.out_dir, to_path.parent().unwrap())?;
+		output_manifest::record(&to_path, Some(file));
+
+		if let Some(content) = &content {
+			if build_cache::is_unchanged(&to_path, content, &self.config) {
+				return Ok(to_path);
+			}
+		}
+
+		unlink_output(&to_path)?;
+		map_err!(
+			fs::copy(file, &to_path),
+			IoError(format!("failed to copy to {}", to_path.display())),
+		)?;
+
+		let (processed, processing) = watch::channel(());
+		PROCESSING_ASSETS.insert(to_path.clone(), processing);
+		let mut process_join_set = JoinSet::new();
+		process_output(&to_path, &mut process_join_set, self.config.clone())?;
+		join_set.spawn(async move {
+			let result = await_joinset(process_join_set).await;
+			drop(processed);
+			result
+		});
+
+		Ok(to_path)
+	}
+
 	fn recursive_process<F>(path: &Path, f: &mut F) -> Result<()>
 	where
 		F: FnMut(&Path) -> Result<()>,
@@ -328,50 +799,319 @@ impl Generator {
 	}
 }
 
-#[instrument(level = "info", skip(template_raw, config))]
+/// Config of the site's tree, followed by each of the versioned trees.
+#[inline]
+fn trees(config: &Arc<Config>) -> Vec<Arc<Config>> {
+	let mut trees = vec![config.clone()];
+	for version in &config.versions {
+		trees.push(Arc::new(config.for_version(version, &version.name)));
+
+		if version.is_latest {
+			trees.push(Arc::new(config.for_version(version, LATEST_VERSION_DIR)));
+		}
+	}
+
+	trees
+}
+
+#[instrument(level = "info", skip(template_raw, paginator, config))]
 #[inline]
 async fn render_entry(
 	file_path: PathBuf,
 	entry_path: PathBuf,
 	template_name: String,
 	template_raw: Option<String>,
+	head_html: Option<String>,
+	paginator: Option<Paginator>,
 	config: Arc<Config>,
 ) -> Result<()> {
 	if let Some(template_raw) = &template_raw {
 		template::add_once_off_template(&template_name, template_raw)?;
 	}
 
-	let out_file = render_template(
+	let mut context = entry_context(&entry_path, paginator.as_ref(), &config);
+	config
+		.hooks
+		.run_before_render_entry(&entry_path, &mut context)?;
+
+	let Some(out_file) = render_template(
 		&file_path,
+		&entry_path,
 		&template_name,
-		json!({ // TODO use an actual struct man wtf is wrong with you?
-			"entry_path": entry_path,
-			"site": *config.clone(),
-			"base": &config.base_url,
-		}),
+		context,
 		&config.out_dir,
+		head_html.as_deref(),
 		&config,
-	)?;
+	)?
+	else {
+		return Ok(());
+	};
 
-	if config.compress_content {
-		let mut join_set = JoinSet::<Result<()>>::new();
-		apply_compression(&out_file, &mut join_set, config.clone())?;
-		await_joinset(join_set).await?;
+	let mut join_set = JoinSet::<Result<()>>::new();
+	process_output(&out_file, &mut join_set, config.clone())?;
+	await_joinset(join_set).await?;
+
+	Ok(())
+}
+
+/// Path within `out_dir` which an entry is rendered to, unless its section is paginated.
+#[inline]
+fn entry_file_path(entry_path: &Path, entry: &Entry) -> PathBuf {
+	let mut file_path = entry_path.to_path_buf();
+	if entry.file_type.is_rendered_to_html() {
+		let is_index = entry_path
+			.components()
+			.next_back()
+			.unwrap()
+			.as_os_str()
+			.to_string_lossy()
+			.starts_with("index");
+
+		if !is_index {
+			file_path = entry_path.join("index");
+		}
+
+		file_path.set_extension(FileType::Html.as_ref());
+	} else if !entry.file_type.is_template() {
+		file_path.set_extension(entry.file_type.as_ref());
+	} else {
+		file_path.set_extension("");
+	}
+
+	file_path
+}
+
+/// Name of the template which an entry is rendered with. Entries without a layout are their own
+/// once-off template, and `html` is only called for them.
+#[inline]
+fn entry_template(
+	entry: &Entry,
+	html: impl FnOnce() -> Result<String>,
+) -> Result<(String, Option<String>)> {
+	match &entry.layout {
+		Some(layout) => Ok((layout.clone(), None)),
+		None => Ok((
+			format!("{}{}", ONCE_OFF_TEMPLATE_NAME_PREFIX, entry.path.display()),
+			Some(html()?),
+		)),
+	}
+}
+
+/// The canonical link and JSON-LD which are added to the head of an entry's HTML pages.
+#[inline]
+fn entry_head_html(entry: &Entry, file_path: &Path, content: &Content) -> Result<Option<String>> {
+	if file_path.extension() != Some(OsStr::new("html")) {
+		return Ok(None);
+	}
+
+	let config = &content.config;
+	let json_ld = structured_data::render_json_ld(entry, content)?;
+
+	let canonical_link = config
+		.canonical_base_url
+		.as_ref()
+		.map(|canonical_base_url| {
+			let relative_url = entry
+				.url
+				.strip_prefix(&config.base_url)
+				.unwrap_or(&entry.url);
+			format!(r#"<link rel="canonical" href="{canonical_base_url}{relative_url}">"#)
+		});
+
+	Ok(match (canonical_link, json_ld) {
+		(Some(canonical_link), Some(json_ld)) => Some(canonical_link + &json_ld),
+		(canonical_link, json_ld) => canonical_link.or(json_ld),
+	})
+}
+
+/// Pages which an entry is rendered to, with their page of the section when it's paginated.
+#[inline]
+fn entry_pages(
+	entry_path: &Path,
+	file_path: PathBuf,
+	content: &Content,
+) -> Result<Vec<(PathBuf, Option<Paginator>)>> {
+	Ok(match pagination::section_pages(content, entry_path)? {
+		Some(pages) => pages
+			.into_iter()
+			.map(|paginator| (paginator.file_path.clone(), Some(paginator)))
+			.collect(),
+		None => vec![(file_path, None)],
+	})
+}
+
+/// Data which the template of an entry is rendered with.
+#[inline]
+fn entry_context(
+	entry_path: &Path,
+	paginator: Option<&Paginator>,
+	config: &Config,
+) -> serde_json::Value {
+	json!({ // TODO use an actual struct man wtf is wrong with you?
+		"entry_path": entry_path,
+		"paginator": paginator,
+		"site": config,
+		"base": &config.base_url,
+	})
+}
+
+/// Entries of the loaded content, keyed on their paths. Entries generated from data can conflict
+/// with others, which is checked as they're collected.
+#[inline]
+fn loaded_entries(
+	content_loader: &ContentLoader,
+	config: &Arc<Config>,
+) -> Result<DashMap<PathBuf, Entry>> {
+	let entries = DashMap::<PathBuf, Entry>::new();
+	// TODO Don't like this clone yo
+	for (_path, entry_data) in content_loader.entries.clone() {
+		for entry in
+			Entry::try_from_entry_data(entry_data, &content_loader.taxonomies, config.clone())?
+		{
+			match entries.entry(entry.path.clone()) {
+				MapEntry::Occupied(mut existing) => {
+					if replaces_conflicting(
+						&entry.path,
+						&existing.get().file_path,
+						&entry.file_path,
+						config,
+					)? {
+						existing.insert(entry);
+					}
+				}
+				MapEntry::Vacant(vacant) => {
+					vacant.insert(entry);
+				}
+			}
+		}
+	}
+
+	Ok(entries)
+}
+
+/// Checks that the pages of entries, including the pages they're paginated to, and the taxonomy and
+/// archive pages are each written to a file of their own. Conflicts are errors, unless
+/// `url_conflicts` only warns about them.
+#[inline]
+fn check_output_conflicts(content: &Content) -> Result<()> {
+	let config = &content.config;
+	let relative = |file: &Path| {
+		file.strip_prefix(&config.content_dir)
+			.unwrap_or(file)
+			.display()
+			.to_string()
+	};
+
+	let mut outputs = HashMap::<PathBuf, String>::new();
+	let mut add_output = |file_path: PathBuf, source: String| -> Result<()> {
+		let Some(existing) = outputs.get(&file_path) else {
+			outputs.insert(file_path, source);
+			return Ok(());
+		};
+
+		let message = format!(
+			"{existing} and {source} are both written to {}",
+			file_path.display()
+		);
+		match config.url_conflicts {
+			UrlConflicts::Error => Err(err!(Validation(message))),
+			UrlConflicts::Warn => {
+				tracing::warn!("{message}");
+				Ok(())
+			}
+		}
+	};
+
+	// Sorted, so that conflicts are reported the same way on every build.
+	let mut entry_paths = content
+		.entries
+		.iter()
+		.map(|entry| entry.key().clone())
+		.collect::<Vec<_>>();
+	entry_paths.sort();
+	for entry_path in entry_paths {
+		let Some(entry) = content.entries.get(&entry_path) else {
+			continue;
+		};
+		let source = relative(&entry.file_path);
+		if !entry.is_renderable {
+			add_output(entry_path, source)?;
+			continue;
+		}
+
+		let file_path = entry_file_path(&entry_path, &entry);
+		for (file_path, _) in entry_pages(&entry_path, file_path, content)? {
+			add_output(file_path, source.clone())?;
+		}
+	}
+
+	for file_path in taxonomies::output_paths(content)? {
+		add_output(file_path, "a taxonomy page".into())?;
+	}
+	for file_path in archives::output_paths(content)? {
+		add_output(file_path, "an archive page".into())?;
 	}
 
 	Ok(())
 }
 
+/// Renders a page which isn't backed by an entry, such as the list of a taxonomy's terms.
+#[instrument(level = "info", skip(data, config))]
+#[inline]
+async fn render_page(
+	file_path: PathBuf,
+	template_name: String,
+	mut data: serde_json::Value,
+	config: Arc<Config>,
+) -> Result<()> {
+	data["site"] = json!(*config);
+	data["base"] = json!(config.base_url);
+
+	let Some(out_file) = render_template(
+		&file_path,
+		&file_path,
+		&template_name,
+		data,
+		&config.out_dir,
+		None,
+		&config,
+	)?
+	else {
+		return Ok(());
+	};
+
+	let mut join_set = JoinSet::<Result<()>>::new();
+	process_output(&out_file, &mut join_set, config.clone())?;
+	await_joinset(join_set).await?;
+
+	Ok(())
+}
+
+/// Renders the template to `file_path` in `out_dir`. Returns `None` when the output is the same as
+/// the last build, so the file was left as it is.
 #[instrument(level = "debug", skip(data))]
 #[inline]
 fn render_template(
 	file_path: &Path,
+	entry_path: &Path,
 	template: &str,
 	data: serde_json::Value,
 	out_dir: &Path,
+	head_html: Option<&str>,
 	config: &Config,
-) -> Result<PathBuf> {
+) -> Result<Option<PathBuf>> {
 	let out_file = out_dir.join(file_path);
+	let buf = render_output(&out_file, entry_path, template, data, head_html, config)?;
+	changes::record(&out_file, &buf, config);
+	output_manifest::record(&out_file, None);
+
+	// Templates can read any entry, so they're always rendered, but unchanged output isn't
+	// written and processed again.
+	if build_cache::is_unchanged(&out_file, &buf, config) {
+		return Ok(None);
+	}
+
+	unlink_output(&out_file)?;
 	let mut file = map_err!(
 		File::create(&out_file),
 		IoError(format!(
@@ -379,25 +1119,119 @@ fn render_template(
 			out_file.display()
 		))
 	)?;
+	map_err!(
+		file.write(&buf),
+		IoError("failed to write rendered template to file")
+	)?;
+
+	Ok(Some(out_file))
+}
 
+/// Renders the template of `out_file` as it's written, rewritten, minified and protected.
+#[inline]
+fn render_output(
+	out_file: &Path,
+	entry_path: &Path,
+	template: &str,
+	data: serde_json::Value,
+	head_html: Option<&str>,
+	config: &Config,
+) -> Result<Vec<u8>> {
 	let mut buf = vec![];
 
-	let mut rewriter = Rewriter::new(config, &mut buf, &EMBEDDABLE_CONTENT);
+	let mut rewriter = Rewriter::new(
+		config,
+		&mut buf,
+		&EMBEDDABLE_CONTENT,
+		&EMBEDDED_BY,
+		entry_path,
+		head_html,
+	);
 	template::render_template(template, data, &mut rewriter)?;
 	drop(rewriter); // Drop this so we can exclusively borrow buf.
 
+	config.hooks.run_after_render_entry(entry_path, &mut buf)?;
+
 	let buf = if out_file.extension() == Some(OsStr::new("html")) && config.minify {
-		minify_html(&mut buf)?
+		minify_html(&mut buf)?.to_vec()
 	} else {
-		&buf[..]
+		buf
 	};
+	let mut buf = encryption::protect_page(entry_path, out_file, buf)?;
+	config.hooks.run_before_write_output(out_file, &mut buf)?;
 
-	map_err!(
-		file.write(buf),
-		IoError("failed to write rendered template to file")
-	)?;
+	Ok(buf)
+}
+
+/// Runs the postprocess commands which match a generated file, and then compresses it when
+/// compression is enabled.
+#[instrument(level = "debug", skip(join_set))]
+#[inline]
+fn process_output(
+	path: &Path,
+	join_set: &mut JoinSet<Result<()>>,
+	config: Arc<Config>,
+) -> Result<()> {
+	output_manifest::record(path, None);
+	changes::record_written(path);
+
+	let relative_path = path.strip_prefix(&config.out_dir).unwrap_or(path);
+	let commands = postprocess::matching_commands(relative_path, &config)?
+		.into_iter()
+		.cloned()
+		.collect::<Vec<_>>();
+
+	if commands.is_empty() {
+		if config.compress_content {
+			apply_compression(path, join_set, config)?;
+		}
+		return Ok(());
+	}
+
+	let path = path.to_path_buf();
+	join_set.spawn(async move {
+		postprocess::postprocess_file(&path, &commands, &config).await?;
+
+		if config.compress_content {
+			let mut compress_join_set = JoinSet::new();
+			apply_compression(&path, &mut compress_join_set, config.clone())?;
+			await_joinset(compress_join_set).await?;
+		}
 
-	Ok(out_file)
+		Ok(())
+	});
+
+	Ok(())
+}
+
+/// Waits for the static file at `path`, within `out_dir`, to be postprocessed and compressed, if
+/// it's still being processed.
+#[inline]
+pub(crate) fn wait_for_asset(path: &Path) {
+	let Some(mut processing) = PROCESSING_ASSETS
+		.get(path)
+		.map(|processing| processing.clone())
+	else {
+		return;
+	};
+
+	// Only fails once the asset is processed, as nothing is sent.
+	block_in_place(|| Handle::current().block_on(processing.changed())).ok();
+}
+
+/// Path of the variant in `content_encoding` of `name`, a file within `out_dir`.
+#[inline]
+pub(crate) fn compressed_path(
+	name: &Path,
+	content_encoding: &ContentEncoding,
+	config: &Config,
+) -> PathBuf {
+	encoding::compressed_path(
+		&config.out_dir,
+		&config.compressed_content_dir,
+		name,
+		content_encoding,
+	)
 }
 
 #[instrument(level = "debug", skip(join_set))]
@@ -459,43 +1293,30 @@ async fn compress_file(
 		IoError(format!("failed to read file {file_path:?}"))
 	)?;
 
-	let out_buf = content_encoding.read_to_end(&buffer[..]).await?;
+	let file_path = compressed_path(&name, &content_encoding, &config);
 
-	let mut file_path = config
-		.out_dir
-		.join(&config.compressed_content_dir)
-		.join(&name);
-	let mut extension = file_path.extension().unwrap_or_default().to_os_string();
-	if let Some(content_encoding_extension) = content_encoding.extension() {
-		extension.push(format!(".{content_encoding_extension}"));
-	}
-	file_path.set_extension(extension);
-
-	let mut file_path_components = file_path.components().peekable();
-	let mut parent_dir = vec![];
-	while let Some(component) = file_path_components.next() {
-		match file_path_components.peek() {
-			Some(_) => parent_dir.push(component),
-			None => break,
+	let out_buf = if config.compression.should_compress(&name, &buffer)? {
+		let out_buf = content_encoding.read_to_end(&buffer[..]).await?;
+		if config
+			.compression
+			.is_worthwhile(buffer.len(), out_buf.len())
+		{
+			Some(out_buf)
+		} else {
+			None
 		}
-	}
-	let parent_dir = PathBuf::from_iter(parent_dir);
-	create_dir_all(&config.out_dir, &parent_dir)?;
-
-	let mut out_file = map_err!(
-		TokioFile::create(&file_path).await,
-		IoError(format!("failed to create out file {}", file_path.display()))
-	)?;
+	} else {
+		None
+	};
 
-	map_err!(
-		out_file.write_all(&out_buf[..]).await,
-		IoError(format!(
-			"failed to write to out file {}",
-			file_path.display()
-		))
-	)?;
+	let Some(out_buf) = out_buf else {
+		tracing::debug!(?name, ?content_encoding, "skipping compression");
+		// Remove any variant left over from a previous build so that it isn't served.
+		let _ = tokio::fs::remove_file(&file_path).await;
+		return Ok(());
+	};
 
-	Ok(())
+	encoding::write_variant(&file_path, &out_buf).await
 }
 
 #[instrument(level = "debug")]
@@ -509,6 +1330,86 @@ fn create_dir_all(out_dir: &Path, dir: &Path) -> Result<()> {
 	Ok(())
 }
 
+/// Bundles the imports of the stylesheet at `file` and lowers it for the supported browsers.
+#[cfg(feature = "css")]
+#[inline]
+fn transform_css(file: &Path, config: &Config) -> Result<String> {
+	let file_provider = FileProvider::new();
+
+	let parser_options = ParserOptions {
+		filename: file.to_string_lossy().to_string(),
+		css_modules: Some(CssModulesConfig {
+			pattern: Pattern::parse("[local]")?,
+			dashed_idents: false,
+		}),
+		source_index: 0,
+		error_recovery: false,
+		warnings: None,
+		flags: ParserFlags::NESTING | ParserFlags::CUSTOM_MEDIA,
+	};
+	let mut bundler = Bundler::new(&file_provider, None, parser_options);
+	let out = bundler.bundle(file)?;
+
+	// Sources are relative to the css dir.
+	let mut source_map = config
+		.css_source_maps
+		.then(|| SourceMap::new(&config.css_dir.to_string_lossy()));
+
+	let printer_options = PrinterOptions {
+		minify: config.minify,
+		source_map: source_map.as_mut(),
+		project_root: None,
+		// TODO make this a config option
+		targets: Browsers::from_browserslist(["> 0.2% and not dead"])?.into(),
+		analyze_dependencies: None,
+		pseudo_classes: None,
+	};
+
+	let mut css = out.to_css(printer_options)?.code;
+	if let Some(source_map) = &mut source_map {
+		css.push_str(&inline_source_map(source_map, config)?);
+	}
+
+	Ok(css)
+}
+
+/// A `sourceMappingURL` comment with the source map as a data URL, so that it also works for
+/// embedded stylesheets. The sources are included, so they don't need to be served.
+#[cfg(feature = "css")]
+#[inline]
+fn inline_source_map(source_map: &mut SourceMap, config: &Config) -> Result<String> {
+	let sources = source_map.get_sources().clone();
+	for (index, source) in sources.iter().enumerate() {
+		let file = config.css_dir.join(source);
+		let content = map_err!(
+			fs::read_to_string(&file),
+			IoError(format!("failed to read {}", file.display())),
+		)?;
+		source_map
+			.set_source_content(index, &content)
+			.map_err(|error| err!(Css(format!("failed to add source {source}: {error}"))))?;
+	}
+
+	let json = source_map
+		.to_json(None)
+		.map_err(|error| err!(Css(format!("failed to write source map: {error}"))))?;
+
+	Ok(format!(
+		"\n/*# sourceMappingURL=data:application/json;base64,{} */\n",
+		BASE64_STANDARD.encode(json)
+	))
+}
+
+/// Without the `css` feature, stylesheets are copied as they are.
+#[cfg(not(feature = "css"))]
+#[inline]
+fn transform_css(file: &Path, _config: &Config) -> Result<String> {
+	map_err!(
+		fs::read_to_string(file),
+		IoError(format!("failed to read {}", file.display())),
+	)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Image {
 	pub width: u32,
@@ -516,6 +1417,10 @@ pub struct Image {
 	pub path: PathBuf,
 	pub url: String,
 	pub mime_type: String,
+	/// EXIF metadata of the source image, if it has any.
+	#[cfg(feature = "images")]
+	#[serde(default)]
+	pub metadata: Option<ImageMetadata>,
 	original_path: PathBuf,
 }
 
@@ -542,7 +1447,103 @@ impl Image {
 				.unwrap()
 				.to_string(),
 			path,
+			#[cfg(feature = "images")]
+			metadata: None,
 			original_path,
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use config::TaxonomyConfig;
+	use std::collections::BTreeMap;
+
+	fn content(config: Config, entries: &[&str]) -> Content {
+		let content = Content {
+			entries: DashMap::new(),
+			sections: DashMap::new(),
+			taxonomies: DashMap::new(),
+			config: Arc::new(config),
+		};
+		for path in entries {
+			let path = PathBuf::from(path);
+			content.entries.insert(
+				path.clone(),
+				Entry {
+					slug: None,
+					layout: None,
+					kind: "page".into(),
+					url: format!("/{}/", path.display()),
+					external_url: None,
+					link: format!("/{}/", path.display()),
+					file_path: path.with_extension("md"),
+					path,
+					file_type: FileType::Markdown,
+					published: None,
+					updated: None,
+					section_handle: None,
+					is_renderable: true,
+					draft: false,
+					headers: BTreeMap::new(),
+					aliases: vec![],
+					password: None,
+					protected: false,
+					extra: Default::default(),
+				},
+			);
+		}
+		content
+	}
+
+	fn tagged(config: Config, entries: &[&str]) -> Content {
+		let content = content(config, entries);
+		let tags = DashMap::new();
+		tags.insert("rust".to_string(), vec![PathBuf::from("posts/a")]);
+		content.taxonomies.insert("tags".to_string(), tags);
+		content
+	}
+
+	fn tags_config() -> Config {
+		Config {
+			taxonomies: BTreeMap::from([("tags".to_string(), TaxonomyConfig::default())]),
+			..Config::default()
+		}
+	}
+
+	#[test]
+	fn entries_at_taxonomy_pages_conflict() {
+		let content = tagged(tags_config(), &["posts/a", "tags/rust"]);
+		let error = check_output_conflicts(&content).unwrap_err();
+		assert!(error.to_string().contains("tags/rust/index.html"));
+
+		let content = tagged(tags_config(), &["posts/a", "tags"]);
+		assert!(check_output_conflicts(&content).is_err());
+	}
+
+	#[test]
+	fn separate_outputs_do_not_conflict() {
+		let content = tagged(tags_config(), &["posts/a", "about"]);
+		assert!(check_output_conflicts(&content).is_ok());
+	}
+
+	#[test]
+	fn conflicts_only_warn_when_configured() {
+		let config = Config {
+			url_conflicts: UrlConflicts::Warn,
+			..tags_config()
+		};
+		let content = tagged(config, &["posts/a", "tags/rust"]);
+		assert!(check_output_conflicts(&content).is_ok());
+	}
+
+	#[test]
+	fn copied_files_conflict_with_pages() {
+		let content = tagged(tags_config(), &["posts/a", "tags/index.html"]);
+		if let Some(mut entry) = content.entries.get_mut(Path::new("tags/index.html")) {
+			entry.is_renderable = false;
+		}
+		assert!(check_output_conflicts(&content).is_err());
+	}
+}
@@ -0,0 +1,296 @@
+use super::{config::Config, content::render_markdown, syntect::highlight_code_block};
+use crate::{
+	error::{Error, Result},
+	map_err,
+	utils::escape_xml,
+};
+use regex::Regex;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, fmt::Write, fs, path::Path};
+use tracing::instrument;
+
+/// Notebook metadata field which holds the frontmatter of the entry.
+const FRONTMATTER_FIELD: &str = "sluggy";
+
+/// Output mime types in order of preference. Each output is rendered with the first type it has.
+const OUTPUT_MIME_TYPES: [&str; 7] = [
+	"text/html",
+	"image/svg+xml",
+	"image/png",
+	"image/jpeg",
+	"image/gif",
+	"text/markdown",
+	"text/plain",
+];
+
+/// Matches ANSI colour codes, which are used in error tracebacks.
+const ANSI_ESCAPE_PATTERN: &str = r"\x1b\[[0-9;]*[A-Za-z]";
+
+#[derive(Debug, Deserialize)]
+struct Notebook {
+	#[serde(default)]
+	metadata: NotebookMetadata,
+	#[serde(default)]
+	cells: Vec<Cell>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct NotebookMetadata {
+	kernelspec: Option<KernelSpec>,
+	language_info: Option<LanguageInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KernelSpec {
+	language: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LanguageInfo {
+	name: Option<String>,
+	file_extension: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cell_type", rename_all = "lowercase")]
+enum Cell {
+	Markdown {
+		source: MultilineString,
+		#[serde(default)]
+		attachments: BTreeMap<String, BTreeMap<String, Value>>,
+	},
+	Code {
+		source: MultilineString,
+		#[serde(default)]
+		outputs: Vec<Output>,
+	},
+	Raw {
+		source: MultilineString,
+		#[serde(default)]
+		metadata: RawCellMetadata,
+	},
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawCellMetadata {
+	format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "output_type", rename_all = "snake_case")]
+enum Output {
+	Stream {
+		name: String,
+		text: MultilineString,
+	},
+	DisplayData {
+		data: BTreeMap<String, Value>,
+	},
+	ExecuteResult {
+		data: BTreeMap<String, Value>,
+	},
+	Error {
+		ename: String,
+		evalue: String,
+		#[serde(default)]
+		traceback: Vec<String>,
+	},
+}
+
+/// Notebooks store text either as a single string or as a list of lines.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MultilineString {
+	Single(String),
+	Lines(Vec<String>),
+}
+
+impl MultilineString {
+	#[inline]
+	fn text(&self) -> String {
+		match self {
+			Self::Single(text) => text.clone(),
+			Self::Lines(lines) => lines.concat(),
+		}
+	}
+}
+
+/// Reads the frontmatter of a notebook from the `sluggy` field of its metadata.
+#[instrument(level = "debug")]
+pub(crate) fn read_frontmatter(path: &Path) -> Result<Option<Value>> {
+	let notebook = read_notebook_json(path)?;
+
+	Ok(notebook
+		.pointer(&format!("/metadata/{FRONTMATTER_FIELD}"))
+		.cloned())
+}
+
+/// Converts the cells of a notebook to HTML.
+///
+/// Markdown cells are rendered the same way as markdown content, code cells are highlighted in the
+/// language of the notebook's kernel and their outputs are embedded after them.
+#[instrument(level = "debug", skip(config))]
+pub(crate) fn render_notebook(path: &Path, config: &Config) -> Result<String> {
+	let notebook = map_err!(
+		serde_json::from_value::<Notebook>(read_notebook_json(path)?),
+		SerdeJsonError(format!("invalid notebook {}", path.display())),
+	)?;
+
+	let language_info = notebook.metadata.language_info.as_ref();
+	let lang = language_info
+		.and_then(|language_info| language_info.name.as_deref())
+		.or_else(|| {
+			notebook
+				.metadata
+				.kernelspec
+				.as_ref()
+				.and_then(|kernelspec| kernelspec.language.as_deref())
+		});
+	let extension = language_info
+		.and_then(|language_info| language_info.file_extension.as_deref())
+		.map(|extension| extension.trim_start_matches('.'));
+
+	let ansi_escape_re = map_err!(
+		Regex::new(ANSI_ESCAPE_PATTERN),
+		RegexError("failed to parse regex pattern"),
+	)?;
+
+	let mut html = String::new();
+	for cell in &notebook.cells {
+		match cell {
+			Cell::Markdown {
+				source,
+				attachments,
+			} => {
+				let mut markdown = source.text();
+				for (name, bundle) in attachments {
+					if let Some((mime_type, data)) = bundle.iter().find(|(mime_type, _)| {
+						mime_type.starts_with("image/") && *mime_type != "image/svg+xml"
+					}) {
+						markdown = markdown.replace(
+							&format!("attachment:{name}"),
+							&data_uri(mime_type, &bundle_text(data)),
+						);
+					}
+				}
+
+				let _ = write!(
+					html,
+					r#"<div class="notebook-cell notebook-markdown">{}</div>"#,
+					render_markdown(&markdown, path, config)?
+				);
+			}
+			Cell::Code { source, outputs } => {
+				html.push_str(r#"<div class="notebook-cell notebook-code">"#);
+				let _ = write!(
+					html,
+					r#"<div class="notebook-input">{}</div>"#,
+					highlight_code_block(lang, extension, &source.text())
+				);
+
+				for output in outputs {
+					html.push_str(&render_output(output, &ansi_escape_re, path, config)?);
+				}
+
+				html.push_str("</div>");
+			}
+			Cell::Raw { source, metadata } => {
+				// Raw cells are only meant for the formats they target.
+				if matches!(metadata.format.as_deref(), Some("text/html")) {
+					html.push_str(&source.text());
+				}
+			}
+		}
+	}
+
+	Ok(html)
+}
+
+#[inline]
+fn render_output(
+	output: &Output,
+	ansi_escape_re: &Regex,
+	path: &Path,
+	config: &Config,
+) -> Result<String> {
+	let output = match output {
+		Output::Stream { name, text } => format!(
+			r#"<pre class="notebook-stream notebook-{name}">{}</pre>"#,
+			escape_xml(&text.text())
+		),
+		Output::DisplayData { data } | Output::ExecuteResult { data } => {
+			let Some((mime_type, value)) = OUTPUT_MIME_TYPES
+				.iter()
+				.find_map(|mime_type| data.get(*mime_type).map(|value| (*mime_type, value)))
+			else {
+				return Ok(String::new());
+			};
+
+			let text = bundle_text(value);
+			match mime_type {
+				"text/html" | "image/svg+xml" => text,
+				"text/markdown" => render_markdown(&text, path, config)?,
+				"text/plain" => format!("<pre>{}</pre>", escape_xml(&text)),
+				mime_type => format!(r#"<img src="{}" alt="">"#, data_uri(mime_type, &text)),
+			}
+		}
+		Output::Error {
+			ename,
+			evalue,
+			traceback,
+		} => {
+			let traceback = if traceback.is_empty() {
+				format!("{ename}: {evalue}")
+			} else {
+				ansi_escape_re
+					.replace_all(&traceback.join("\n"), "")
+					.to_string()
+			};
+
+			format!(
+				r#"<pre class="notebook-error">{}</pre>"#,
+				escape_xml(&traceback)
+			)
+		}
+	};
+
+	Ok(format!(r#"<div class="notebook-output">{output}</div>"#))
+}
+
+#[inline]
+fn read_notebook_json(path: &Path) -> Result<Value> {
+	let source = map_err!(
+		fs::read_to_string(path),
+		IoError(format!("failed to read notebook {}", path.display())),
+	)?;
+
+	serde_json::from_str(&source).map_err(|error| {
+		Error::json_in_source(
+			format!("failed to parse notebook {}", path.display()),
+			path.display().to_string(),
+			&source,
+			error,
+		)
+	})
+}
+
+/// Text of a mime bundle value, which may be split into lines.
+#[inline]
+fn bundle_text(value: &Value) -> String {
+	match value {
+		Value::String(text) => text.clone(),
+		Value::Array(lines) => lines.iter().filter_map(Value::as_str).collect(),
+		value => value.to_string(),
+	}
+}
+
+/// Image data in notebooks is already base64 encoded, but may be wrapped over multiple lines.
+#[inline]
+fn data_uri(mime_type: &str, data: &str) -> String {
+	let data = data
+		.chars()
+		.filter(|c| !c.is_whitespace())
+		.collect::<String>();
+	format!("data:{mime_type};base64,{data}")
+}
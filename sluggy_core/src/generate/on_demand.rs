@@ -0,0 +1,260 @@
+use super::{
+	archives, blogroll, calendars, changes,
+	config::Config,
+	content::{loader::ContentLoader, Content},
+	encryption, entry_context, entry_file_path, entry_head_html, entry_pages, entry_template,
+	feeds, fingerprint, headers, loaded_entries, lock,
+	pagination::Paginator,
+	redirects, render_output, sitemap, source_links, syntect, taxonomies, template, trees,
+	Generator, EMBEDDED_BY,
+};
+use crate::{
+	error::{Error, Result},
+	map_err,
+	utils::{await_joinset, catch_panic, unlink_output},
+};
+use dashmap::DashMap;
+use std::{
+	collections::BTreeMap,
+	fs,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+use tokio::task::JoinSet;
+use tracing::instrument;
+
+/// The site's content, loaded once, whose entries are rendered when they're requested instead of
+/// being written to `out_dir`. Assets, and pages which aren't entries, such as feeds and taxonomy
+/// terms, are still written. Rendered pages aren't postprocessed or compressed.
+#[derive(Debug)]
+pub struct OnDemand {
+	content: Arc<Content>,
+	/// Path of each page within `out_dir` -> the entry it's rendered from, and its page of the
+	/// section when the section is paginated.
+	pages: BTreeMap<PathBuf, (PathBuf, Option<Paginator>)>,
+	/// Path of each entry without a layout which has been requested -> the name and source of its
+	/// once-off template, which is parsed on the first request rather than on every request.
+	templates: DashMap<PathBuf, (String, String)>,
+}
+
+impl OnDemand {
+	/// Loads the content of the site, and keeps the template engine set up for rendering its
+	/// entries. Versioned trees are generated as usual, as the template engine is shared.
+	#[instrument(skip(config))]
+	pub async fn load(config: Arc<Config>) -> Result<Self> {
+		Self::load_with_templates(config, DashMap::new()).await
+	}
+
+	/// Loads the content of the site again after `changed` files have changed. When nothing but
+	/// content has changed, the once-off templates of the entries whose files haven't changed are
+	/// kept, so that only the entries affected by the change are rendered and parsed again.
+	#[instrument(skip(self))]
+	pub async fn reload(&self, changed: &[PathBuf]) -> Result<Self> {
+		let config = &self.content.config;
+		let templates = DashMap::new();
+
+		let is_content_only = !changed.is_empty()
+			&& changed
+				.iter()
+				.all(|path| path.starts_with(&config.content_dir));
+		if is_content_only {
+			for entry in self.content.entries.iter() {
+				if changed.contains(&entry.file_path) {
+					continue;
+				}
+				if let Some(template) = self.templates.get(entry.key()) {
+					templates.insert(entry.key().clone(), template.clone());
+				}
+			}
+		}
+
+		Self::load_with_templates(config.clone(), templates).await
+	}
+
+	#[inline]
+	async fn load_with_templates(
+		config: Arc<Config>,
+		templates: DashMap<PathBuf, (String, String)>,
+	) -> Result<Self> {
+		let _lock = lock::lock_out_dir(&config).await?;
+
+		EMBEDDED_BY.clear();
+		changes::clear();
+		template::clear_fragments();
+		fingerprint::clear();
+		headers::clear_headers();
+		redirects::clear_redirects();
+
+		for tree_config in trees(&config).into_iter().skip(1) {
+			Generator::generate_tree(tree_config, None).await?;
+		}
+
+		let generator = Generator {
+			config: config.clone(),
+		};
+
+		syntect::load_syntaxes(&config)?;
+
+		let content_loader = ContentLoader::new(config.clone());
+		content_loader.load().await?;
+
+		// Static files are copied first, as in a build, so that the css points at their
+		// fingerprinted names.
+		let mut asset_join_set = JoinSet::new();
+		generator.copy_static_files(&mut asset_join_set).await?;
+		generator.bundle_css(&mut asset_join_set)?;
+		generator.bundle_js(&mut asset_join_set)?;
+		await_joinset(asset_join_set).await?;
+
+		fingerprint::write_manifest(&config)?;
+
+		let mut content = Content {
+			entries: loaded_entries(&content_loader, &config)?,
+			sections: content_loader.sections.clone(),
+			taxonomies: content_loader.taxonomies.clone(),
+			config: config.clone(),
+		};
+		config.hooks.run_after_content_load(&mut content)?;
+		let content = Arc::new(content);
+
+		headers::collect_headers(&content)?;
+		redirects::collect_redirects(&content)?;
+		encryption::collect_passwords(&content)?;
+		source_links::collect_source_urls(&content)?;
+		blogroll::load_blogroll(&config).await?;
+
+		template::setup_template_engine(&content)?;
+
+		// Entries which are gone, or which have a layout now, don't keep their templates.
+		templates.retain(|entry_path, _| {
+			content
+				.entries
+				.get(entry_path)
+				.is_some_and(|entry| entry.layout.is_none())
+		});
+		template::add_once_off_templates(&Self::parsed_templates(&templates))?;
+
+		let mut pages = BTreeMap::new();
+		for entry in content.entries.iter() {
+			let entry_path = entry.key();
+
+			if !entry.is_renderable {
+				unlink_output(&config.out_dir.join(entry_path))?;
+				map_err!(
+					fs::copy(&entry.file_path, config.out_dir.join(entry_path)),
+					IoError(format!("Failed to copy file {}", entry_path.display())),
+				)?;
+				continue;
+			}
+
+			let file_path = entry_file_path(entry_path, entry.value());
+			for (file_path, paginator) in entry_pages(entry_path, file_path, &content)? {
+				pages.insert(file_path, (entry_path.clone(), paginator));
+			}
+		}
+
+		let mut join_set = JoinSet::new();
+		feeds::generate_feeds(&content, &mut join_set)?;
+		calendars::generate_calendars(&content, &mut join_set)?;
+		blogroll::generate_opml(&content, &mut join_set)?;
+		taxonomies::generate_taxonomies(&content, &mut join_set)?;
+		archives::generate_archives(&content, &mut join_set)?;
+		sitemap::generate_sitemap(&content, &mut join_set)?;
+		await_joinset(join_set).await?;
+
+		headers::write_headers_file(&config)?;
+		redirects::write_redirects_file(&config)?;
+		redirects::write_redirect_pages(&config)?;
+
+		Ok(Self {
+			content,
+			pages,
+			templates,
+		})
+	}
+
+	/// Path of the page which is served at `path`, relative to `out_dir`, if it's rendered on
+	/// request.
+	#[inline]
+	pub fn resolve(&self, path: &Path) -> Option<PathBuf> {
+		if self.pages.contains_key(path) {
+			return Some(path.to_path_buf());
+		}
+
+		let index = path.join("index.html");
+		self.pages.contains_key(&index).then_some(index)
+	}
+
+	/// Renders the page at `path`, relative to `out_dir`, as it would be written by a build.
+	#[instrument(level = "info", skip(self))]
+	pub fn render(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+		let Some((entry_path, paginator)) = self.pages.get(path) else {
+			return Ok(None);
+		};
+		let Some(entry) = self.content.entries.get(entry_path) else {
+			return Ok(None);
+		};
+		let config = &self.content.config;
+
+		let parsed = entry
+			.layout
+			.is_none()
+			.then(|| self.templates.get(entry_path))
+			.flatten()
+			.map(|template| template.0.clone());
+		let template_name = match parsed {
+			Some(template_name) => template_name,
+			None => {
+				let (template_name, template_raw) = entry_template(&entry, || {
+					catch_panic(entry_path, || {
+						if entry.file_type.is_generated() {
+							entry.generate(config)
+						} else {
+							entry.raw()
+						}
+					})
+				})?;
+				if let Some(template_raw) = template_raw {
+					template::add_once_off_template(&template_name, &template_raw)?;
+					self.templates
+						.insert(entry_path.clone(), (template_name.clone(), template_raw));
+				}
+				template_name
+			}
+		};
+
+		let head_html = entry_head_html(&entry, path, &self.content)?;
+
+		let mut context = entry_context(entry_path, paginator.as_ref(), config);
+		config
+			.hooks
+			.run_before_render_entry(entry_path, &mut context)?;
+
+		render_output(
+			&config.out_dir.join(path),
+			entry_path,
+			&template_name,
+			context,
+			head_html.as_deref(),
+			config,
+		)
+		.map(Some)
+	}
+
+	/// Parses the templates again, for when nothing but templates have changed, so that the
+	/// content doesn't have to be loaded again. The once-off templates of entries which have been
+	/// requested are parsed again from their sources, without rendering the entries again.
+	#[instrument(skip(self))]
+	pub fn reload_templates(&self) -> Result<()> {
+		template::setup_template_engine(&self.content)?;
+		template::add_once_off_templates(&Self::parsed_templates(&self.templates))
+	}
+
+	#[inline]
+	fn parsed_templates(templates: &DashMap<PathBuf, (String, String)>) -> Vec<(String, String)> {
+		templates
+			.iter()
+			.map(|template| template.value().clone())
+			.collect()
+	}
+}
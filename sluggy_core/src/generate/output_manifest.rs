@@ -0,0 +1,465 @@
+use super::{
+	compressed_path,
+	config::{Config, StaleOutputs},
+	postprocess::glob_pattern,
+};
+use crate::{
+	encoding::ContentEncoding,
+	err,
+	error::{Error, Result},
+	lazyfn::LazyFn,
+	map_err,
+	utils::{cached_regex, hex_digest, unlink_output},
+};
+use dashmap::DashMap;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+	collections::{BTreeMap, BTreeSet, HashSet},
+	fs,
+	path::{Path, PathBuf},
+};
+use tracing::instrument;
+
+/// Output file -> the file it's generated from, when there's one.
+static OUTPUTS: LazyFn<DashMap<PathBuf, Option<PathBuf>>> = LazyFn::new(DashMap::new);
+
+/// The files which a build emitted, written to `output_manifest`.
+#[derive(Debug, Serialize, Deserialize)]
+struct OutputManifest {
+	files: Vec<OutputFile>,
+	/// Files of the previous manifest which this build didn't emit, and which can be removed.
+	#[serde(default)]
+	removed: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OutputFile {
+	/// Path of the file within `out_dir`.
+	path: PathBuf,
+	/// The entry, stylesheet, script or asset which the file is generated from. Pages such as
+	/// feeds and taxonomy terms have none.
+	source: Option<PathBuf>,
+	/// SHA-256 of the content of the file.
+	hash: String,
+	size: u64,
+	/// Size of each compressed variant of the file, by its content encoding.
+	#[serde(default)]
+	compressed: BTreeMap<String, u64>,
+}
+
+/// Records that `out_file` was emitted by this build, from `source`. Files recorded without a
+/// source keep the source they were recorded with before.
+#[inline]
+pub(crate) fn record(out_file: &Path, source: Option<&Path>) {
+	match source {
+		Some(source) => {
+			OUTPUTS.insert(out_file.to_path_buf(), Some(source.to_path_buf()));
+		}
+		None => {
+			OUTPUTS.entry(out_file.to_path_buf()).or_insert(None);
+		}
+	}
+}
+
+/// Whether `out_file` was recorded as emitted by this build.
+#[cfg(test)]
+#[inline]
+pub(crate) fn is_recorded(out_file: &Path) -> bool {
+	OUTPUTS.contains_key(out_file)
+}
+
+/// Forgets the emitted files, at the start of a build.
+#[inline]
+pub(crate) fn clear() {
+	OUTPUTS.clear();
+}
+
+/// Writes the files which were emitted to `output_manifest`, once every tree is generated and
+/// compressed. Files which were in the last manifest, but weren't emitted again, are listed as
+/// removed.
+#[instrument(skip(config))]
+pub(crate) fn write_manifest(config: &Config) -> Result<()> {
+	let Some(manifest_path) = &config.output_manifest else {
+		return Ok(());
+	};
+
+	let mut outputs = OUTPUTS
+		.iter()
+		.map(|output| (output.key().clone(), output.value().clone()))
+		.collect::<Vec<_>>();
+	outputs.sort();
+
+	let mut files = vec![];
+	for (out_file, source) in outputs {
+		// Outputs which were removed again while generating, such as pages replaced by redirects,
+		// aren't listed.
+		let Ok(content) = fs::read(&out_file) else {
+			continue;
+		};
+		let path = out_file
+			.strip_prefix(&config.out_dir)
+			.unwrap_or(&out_file)
+			.to_path_buf();
+
+		let compressed = ContentEncoding::COMPRESSED
+			.iter()
+			.filter_map(|encoding| {
+				let size = fs::metadata(compressed_path(&path, encoding, config))
+					.ok()?
+					.len();
+				Some((encoding.to_header_value().to_str().ok()?.to_string(), size))
+			})
+			.collect();
+
+		files.push(OutputFile {
+			hash: hex_digest(&content),
+			size: content.len() as u64,
+			path,
+			source,
+			compressed,
+		});
+	}
+
+	let emitted = files
+		.iter()
+		.map(|file| file.path.clone())
+		.collect::<BTreeSet<_>>();
+	let removed = fs::read_to_string(manifest_path)
+		.ok()
+		.and_then(|source| serde_json::from_str::<OutputManifest>(&source).ok())
+		.map(|previous| {
+			previous
+				.files
+				.into_iter()
+				.map(|file| file.path)
+				.filter(|path| !emitted.contains(path))
+				.collect()
+		})
+		.unwrap_or_default();
+
+	let json = map_err!(
+		serde_json::to_vec_pretty(&OutputManifest { files, removed }),
+		SerdeJsonError("failed to serialize output manifest"),
+	)?;
+	if let Some(parent) = manifest_path
+		.parent()
+		.filter(|parent| !parent.as_os_str().is_empty())
+	{
+		map_err!(
+			fs::create_dir_all(parent),
+			IoError(format!("failed to create {}", parent.display())),
+		)?;
+	}
+	unlink_output(manifest_path)?;
+	map_err!(
+		fs::write(manifest_path, json),
+		IoError(format!("failed to write {}", manifest_path.display())),
+	)?;
+
+	Ok(())
+}
+
+/// Refuses to remove stale outputs when `out_dir` is, or contains, the project or one of its
+/// source directories, as their files would be removed along with the stale outputs.
+#[inline]
+pub(crate) fn check_stale_outputs(config: &Config) -> Result<()> {
+	if config.stale_outputs != StaleOutputs::Remove {
+		return Ok(());
+	}
+
+	let out_dir = absolute(&config.out_dir);
+	let current_dir = map_err!(
+		std::env::current_dir(),
+		IoError("failed to read the current dir"),
+	)?;
+	let dirs = [
+		&current_dir,
+		&config.content_dir,
+		&config.css_dir,
+		&config.js_dir,
+		&config.template_dir,
+		&config.assets_dir,
+		&config.data_dir,
+		&config.tests_dir,
+		&config.syntaxes_dir,
+	]
+	.into_iter()
+	.chain(config.versions.iter().map(|version| &version.content_dir));
+
+	for dir in dirs {
+		if absolute(dir).starts_with(&out_dir) {
+			return Err(err!(Validation(format!(
+				"`stale_outputs = \"remove\"` would remove the files of {}, which is within out_dir {}",
+				dir.display(),
+				config.out_dir.display()
+			))));
+		}
+	}
+
+	Ok(())
+}
+
+/// `path`, canonicalized when it exists, or joined to the current dir.
+#[inline]
+fn absolute(path: &Path) -> PathBuf {
+	path.canonicalize().unwrap_or_else(|_| {
+		std::env::current_dir()
+			.map(|current_dir| current_dir.join(path))
+			.unwrap_or_else(|_| path.to_path_buf())
+	})
+}
+
+/// Reports or removes the files of `out_dir` which weren't emitted by this build, such as the
+/// pages of renamed entries, once every tree is generated. Compressed variants are stale along
+/// with the files they're compressed from. Hidden files, like `.git`, are left alone.
+#[instrument(skip(config))]
+pub(crate) fn clean_stale_outputs(config: &Config) -> Result<()> {
+	if config.stale_outputs == StaleOutputs::Keep {
+		return Ok(());
+	}
+
+	let keep = config
+		.keep_outputs
+		.iter()
+		.map(|pattern| {
+			map_err!(
+				cached_regex(&glob_pattern(pattern)),
+				RegexError(format!(
+					"failed to parse keep_outputs pattern \"{pattern}\""
+				)),
+			)
+		})
+		.collect::<Result<Vec<_>>>()?;
+
+	let mut emitted = HashSet::new();
+	for output in OUTPUTS.iter() {
+		let out_file = output.key();
+		let path = out_file.strip_prefix(&config.out_dir).unwrap_or(out_file);
+		for encoding in &ContentEncoding::COMPRESSED {
+			emitted.insert(compressed_path(path, encoding, config));
+		}
+		emitted.insert(out_file.clone());
+	}
+	emitted.extend(config.output_manifest.clone());
+
+	let mut files = vec![];
+	find_files(&config.out_dir, &mut files)?;
+
+	for file in files {
+		if emitted.contains(&file) {
+			continue;
+		}
+
+		let path = file.strip_prefix(&config.out_dir).unwrap_or(&file);
+		// Compressed variants are kept by the patterns of the files they're compressed from.
+		let name = path
+			.strip_prefix(&config.compressed_content_dir)
+			.unwrap_or(path)
+			.to_string_lossy();
+		if keep.iter().any(|pattern| pattern.is_match(&name)) {
+			continue;
+		}
+
+		match config.stale_outputs {
+			StaleOutputs::Remove => {
+				map_err!(
+					fs::remove_file(&file),
+					IoError(format!("failed to remove {}", file.display())),
+				)?;
+				tracing::info!(path = %path.display(), "Removed stale output");
+			}
+			_ => tracing::warn!(path = %path.display(), "Stale output"),
+		}
+	}
+
+	if config.stale_outputs == StaleOutputs::Remove {
+		remove_empty_dirs(&config.out_dir)?;
+	}
+
+	Ok(())
+}
+
+/// Files within `dir`, leaving out hidden files and directories.
+#[inline]
+fn find_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+	for entry in map_err!(
+		fs::read_dir(dir),
+		IoError(format!("failed to read dir {}", dir.display())),
+	)? {
+		let path = map_err!(entry, IoError("dir entry failed"))?.path();
+		if is_hidden(&path) {
+			continue;
+		}
+
+		if path.is_dir() {
+			find_files(&path, files)?;
+		} else {
+			files.push(path);
+		}
+	}
+
+	Ok(())
+}
+
+/// Removes the directories within `dir` which are left empty after removing stale outputs.
+/// Returns whether `dir` itself is empty.
+#[inline]
+fn remove_empty_dirs(dir: &Path) -> Result<bool> {
+	let mut is_empty = true;
+	for entry in map_err!(
+		fs::read_dir(dir),
+		IoError(format!("failed to read dir {}", dir.display())),
+	)? {
+		let path = map_err!(entry, IoError("dir entry failed"))?.path();
+		if !is_hidden(&path) && path.is_dir() && remove_empty_dirs(&path)? {
+			map_err!(
+				fs::remove_dir(&path),
+				IoError(format!("failed to remove {}", path.display())),
+			)?;
+		} else {
+			is_empty = false;
+		}
+	}
+
+	Ok(is_empty)
+}
+
+#[inline]
+fn is_hidden(path: &Path) -> bool {
+	path.file_name()
+		.is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tempfile::TempDir;
+
+	fn out_dir() -> (TempDir, PathBuf) {
+		let temp_dir = tempfile::tempdir().unwrap();
+		let dir = temp_dir.path().join("out");
+		for file in [
+			"index.html",
+			"posts/old/index.html",
+			"keep/verification.txt",
+			".git/HEAD",
+			"compressed/index.html.gz",
+			"compressed/posts/old/index.html.gz",
+		] {
+			let path = dir.join(file);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, file).unwrap();
+		}
+		(temp_dir, dir)
+	}
+
+	fn config(out_dir: &Path, stale_outputs: StaleOutputs) -> Config {
+		Config {
+			out_dir: out_dir.to_path_buf(),
+			compressed_content_dir: PathBuf::from("compressed"),
+			stale_outputs,
+			keep_outputs: vec!["keep/**".into()],
+			..Config::default()
+		}
+	}
+
+	#[test]
+	fn stale_outputs_are_removed() {
+		let (_temp_dir, out_dir) = out_dir();
+		record(&out_dir.join("index.html"), None);
+
+		clean_stale_outputs(&config(&out_dir, StaleOutputs::Remove)).unwrap();
+
+		assert!(out_dir.join("index.html").exists());
+		assert!(out_dir.join("compressed/index.html.gz").exists());
+		assert!(!out_dir.join("posts").exists());
+		assert!(!out_dir.join("compressed/posts").exists());
+		// Kept and hidden files are left alone.
+		assert!(out_dir.join("keep/verification.txt").exists());
+		assert!(out_dir.join(".git/HEAD").exists());
+	}
+
+	#[test]
+	fn stale_outputs_are_reported() {
+		let (_temp_dir, out_dir) = out_dir();
+		record(&out_dir.join("index.html"), None);
+
+		clean_stale_outputs(&config(&out_dir, StaleOutputs::Report)).unwrap();
+		assert!(out_dir.join("posts/old/index.html").exists());
+	}
+
+	#[test]
+	fn manifest_of_outputs() {
+		let (temp_dir, out_dir) = out_dir();
+		let manifest_path = temp_dir.path().join("outputs.json");
+		fs::write(
+			&manifest_path,
+			r#"{ "files": [{ "path": "gone.html", "source": null, "hash": "", "size": 0 }] }"#,
+		)
+		.unwrap();
+		record(
+			&out_dir.join("index.html"),
+			Some(Path::new("content/index.md")),
+		);
+		// Recording a file again without a source keeps its source.
+		record(&out_dir.join("index.html"), None);
+
+		let config = Config {
+			output_manifest: Some(manifest_path.clone()),
+			..config(&out_dir, StaleOutputs::Keep)
+		};
+		write_manifest(&config).unwrap();
+
+		let manifest =
+			serde_json::from_str::<OutputManifest>(&fs::read_to_string(&manifest_path).unwrap())
+				.unwrap();
+		let file = manifest
+			.files
+			.iter()
+			.find(|file| file.path == Path::new("index.html"))
+			.unwrap();
+		assert_eq!(file.source.as_deref(), Some(Path::new("content/index.md")));
+		assert_eq!(file.hash, hex_digest("index.html"));
+		assert_eq!(file.size, "index.html".len() as u64);
+		assert_eq!(
+			file.compressed,
+			BTreeMap::from([("gzip".to_string(), "compressed/index.html.gz".len() as u64)])
+		);
+		assert_eq!(manifest.removed, vec![PathBuf::from("gone.html")]);
+	}
+
+	#[test]
+	fn removing_stale_outputs_of_the_project_is_refused() {
+		let config = Config {
+			stale_outputs: StaleOutputs::Remove,
+			out_dir: PathBuf::from("."),
+			..Config::default()
+		};
+		assert!(check_stale_outputs(&config).is_err());
+
+		let config = Config {
+			stale_outputs: StaleOutputs::Remove,
+			out_dir: PathBuf::from("public"),
+			content_dir: PathBuf::from("public/content"),
+			..Config::default()
+		};
+		assert!(check_stale_outputs(&config).is_err());
+	}
+
+	#[test]
+	fn removing_stale_outputs_of_a_separate_out_dir_is_allowed() {
+		let config = Config {
+			stale_outputs: StaleOutputs::Remove,
+			out_dir: PathBuf::from("public"),
+			..Config::default()
+		};
+		assert!(check_stale_outputs(&config).is_ok());
+
+		let config = Config {
+			stale_outputs: StaleOutputs::Report,
+			out_dir: PathBuf::from("."),
+			..Config::default()
+		};
+		assert!(check_stale_outputs(&config).is_ok());
+	}
+}
@@ -0,0 +1,210 @@
+use super::content::Content;
+use crate::{
+	err,
+	error::{Error, Result},
+};
+use serde_derive::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Path segment of pages after the first when a section doesn't set its own, e.g. `/blog/page/2/`.
+pub(crate) const DEFAULT_PAGINATE_PATH: &str = "page";
+
+/// One page of a paginated listing, which is passed to templates as `paginator`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Paginator {
+	/// 1-based number of this page.
+	pub current_page: usize,
+	pub total_pages: usize,
+	pub paginate_by: usize,
+	/// Number of entries across all of the pages.
+	pub total_entries: usize,
+	/// Paths of the entries on this page.
+	pub entries: Vec<PathBuf>,
+	pub current_url: String,
+	pub first_url: String,
+	pub last_url: String,
+	pub prev_url: Option<String>,
+	pub next_url: Option<String>,
+	/// File this page is rendered to, relative to `out_dir`.
+	#[serde(skip)]
+	pub file_path: PathBuf,
+}
+
+/// Splits `entries` into pages of `paginate_by`. The first page is at `path`, and the rest at
+/// `{path}/{paginate_path}/{n}/`. There is always at least 1 page, even if it has no entries.
+#[inline]
+pub(crate) fn paginate(
+	entries: &[PathBuf],
+	paginate_by: usize,
+	path: &Path,
+	paginate_path: &str,
+	base_url: &str,
+) -> Result<Vec<Paginator>> {
+	if paginate_by == 0 {
+		return Err(err!(Validation(format!(
+			"`paginate_by` of {} must be greater than 0",
+			path.display()
+		))));
+	}
+
+	let total_pages = entries.len().div_ceil(paginate_by).max(1);
+
+	let page_path = |page: usize| {
+		if page == 1 {
+			path.to_path_buf()
+		} else {
+			path.join(paginate_path).join(page.to_string())
+		}
+	};
+	let page_url = |page: usize| {
+		let page_path = page_path(page).to_string_lossy().to_string();
+		if page_path.is_empty() {
+			base_url.to_string()
+		} else {
+			format!("{base_url}{page_path}/")
+		}
+	};
+
+	Ok((1..=total_pages)
+		.map(|page| Paginator {
+			current_page: page,
+			total_pages,
+			paginate_by,
+			total_entries: entries.len(),
+			entries: entries
+				.iter()
+				.skip((page - 1) * paginate_by)
+				.take(paginate_by)
+				.cloned()
+				.collect(),
+			current_url: page_url(page),
+			first_url: page_url(1),
+			last_url: page_url(total_pages),
+			prev_url: (page > 1).then(|| page_url(page - 1)),
+			next_url: (page < total_pages).then(|| page_url(page + 1)),
+			file_path: page_path(page).join("index.html"),
+		})
+		.collect())
+}
+
+/// Pages of the section which `entry_path` is the index of, if the section is paginated. Entries
+/// are paged in the order of the section.
+#[inline]
+pub(crate) fn section_pages(
+	content: &Content,
+	entry_path: &Path,
+) -> Result<Option<Vec<Paginator>>> {
+	let Some(section_handle) = content
+		.entries
+		.get(entry_path)
+		.and_then(|entry| entry.section_handle.clone())
+	else {
+		return Ok(None);
+	};
+	let Some(section) = content.sections.get(&section_handle) else {
+		return Ok(None);
+	};
+	let Some(paginate_by) = section.paginate_by else {
+		return Ok(None);
+	};
+
+	if entry_path != section.prefix.join("index") {
+		return Ok(None);
+	}
+
+	let entries = section
+		.entries
+		.iter()
+		.filter(|path| path.as_path() != entry_path)
+		.cloned()
+		.collect::<Vec<_>>();
+
+	let paginate_path = section
+		.paginate_path
+		.as_deref()
+		.unwrap_or(DEFAULT_PAGINATE_PATH);
+
+	paginate(
+		&entries,
+		paginate_by,
+		&section.prefix,
+		paginate_path,
+		&content.config.base_url,
+	)
+	.map(Some)
+}
+
+/// Paths of the entries at `paths`, newest first, followed by undated entries by path.
+#[inline]
+pub(crate) fn newest_first<'p>(
+	content: &Content,
+	paths: impl Iterator<Item = &'p PathBuf>,
+) -> Vec<PathBuf> {
+	let mut entries = paths
+		.filter_map(|path| content.entries.get(path).map(|entry| entry.value().clone()))
+		.collect::<Vec<_>>();
+	entries.sort_by(|a, b| {
+		b.published
+			.cmp(&a.published)
+			.then_with(|| a.path.cmp(&b.path))
+	});
+
+	entries.into_iter().map(|entry| entry.path).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn paths(count: usize) -> Vec<PathBuf> {
+		(1..=count)
+			.map(|n| PathBuf::from(format!("blog/{n}")))
+			.collect()
+	}
+
+	#[test]
+	fn pages_of_entries() {
+		let pages = paginate(&paths(5), 2, Path::new("blog"), "page", "/").unwrap();
+		assert_eq!(pages.len(), 3);
+
+		let first = &pages[0];
+		assert_eq!(first.current_page, 1);
+		assert_eq!(first.total_pages, 3);
+		assert_eq!(first.total_entries, 5);
+		assert_eq!(first.entries, paths(2));
+		assert_eq!(first.current_url, "/blog/");
+		assert_eq!(first.prev_url, None);
+		assert_eq!(first.next_url.as_deref(), Some("/blog/page/2/"));
+		assert_eq!(first.file_path, PathBuf::from("blog/index.html"));
+
+		let last = &pages[2];
+		assert_eq!(last.entries, vec![PathBuf::from("blog/5")]);
+		assert_eq!(last.current_url, "/blog/page/3/");
+		assert_eq!(last.first_url, "/blog/");
+		assert_eq!(last.last_url, "/blog/page/3/");
+		assert_eq!(last.prev_url.as_deref(), Some("/blog/page/2/"));
+		assert_eq!(last.next_url, None);
+		assert_eq!(last.file_path, PathBuf::from("blog/page/3/index.html"));
+	}
+
+	#[test]
+	fn one_page_without_entries() {
+		let pages = paginate(&[], 10, Path::new("blog"), "page", "/").unwrap();
+		assert_eq!(pages.len(), 1);
+		assert!(pages[0].entries.is_empty());
+		assert_eq!(pages[0].next_url, None);
+	}
+
+	#[test]
+	fn pages_of_the_root() {
+		let pages = paginate(&paths(3), 2, Path::new(""), "p", "https://example.com/").unwrap();
+		assert_eq!(pages[0].current_url, "https://example.com/");
+		assert_eq!(pages[1].current_url, "https://example.com/p/2/");
+		assert_eq!(pages[1].file_path, PathBuf::from("p/2/index.html"));
+	}
+
+	#[test]
+	fn paginate_by_zero() {
+		assert!(paginate(&paths(3), 0, Path::new("blog"), "page", "/").is_err());
+	}
+}
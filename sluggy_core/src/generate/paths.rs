@@ -0,0 +1,109 @@
+use super::config::Config;
+use crate::{
+	err,
+	error::{Error, Result},
+	map_err,
+};
+use std::path::{Component, Path, PathBuf};
+use url::Url;
+
+/// Resolves a local path against `relative_to` or `assets_dir`, ensuring that it is within the
+/// project.
+#[inline]
+pub(crate) fn resolve_path(
+	source: &str,
+	relative_to: Option<&Path>,
+	config: &Config,
+) -> Result<PathBuf> {
+	let path = Path::new(source);
+	let path = if path.is_absolute() {
+		path.to_path_buf()
+	} else if let Some(relative_to) = relative_to {
+		let relative_to = config.content_dir.join(relative_to);
+		let dir = if relative_to.is_dir() {
+			relative_to.as_path()
+		} else {
+			relative_to.parent().unwrap_or(&relative_to)
+		};
+		dir.join(path)
+	} else {
+		config.assets_dir.join(path)
+	};
+
+	let path = map_err!(
+		path.canonicalize(),
+		IoError(format!("file not found: {}", path.display())),
+	)?;
+
+	if !config.is_project_path(&path) {
+		return Err(err!(Validation(format!(
+			"{} is outside of the project",
+			path.display()
+		))));
+	}
+
+	Ok(path)
+}
+
+/// `path` without `.` and `..` components, so that the paths links resolve to match the paths of
+/// files. `..` of the root is the root, as it is for the file system.
+#[inline]
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+	let mut normalized = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir
+				if matches!(
+					normalized.components().next_back(),
+					Some(Component::Normal(_))
+				) =>
+			{
+				normalized.pop();
+			}
+			Component::ParentDir if normalized.has_root() => {}
+			component => normalized.push(component),
+		}
+	}
+	normalized
+}
+
+/// Path of `url` from the root of the site, which is where hosts match the rules of `_headers` and
+/// `_redirects` from.
+#[inline]
+pub(crate) fn url_path(url: &str) -> String {
+	match Url::parse(url) {
+		Ok(url) => url.path().to_string(),
+		Err(_) => format!("/{}", url.trim_start_matches('/')),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn normalized_paths() {
+		for (path, normalized) in [
+			("content/posts/../about.md", "content/about.md"),
+			("content/./posts/a.md", "content/posts/a.md"),
+			("./a/b/../../c", "c"),
+			("../a/../b", "../b"),
+			("/a/../../b", "/b"),
+		] {
+			assert_eq!(
+				normalize_path(Path::new(path)),
+				PathBuf::from(normalized),
+				"{path}"
+			);
+		}
+	}
+
+	#[test]
+	fn url_paths() {
+		assert_eq!(url_path("https://example.com/blog/old/"), "/blog/old/");
+		assert_eq!(url_path("https://example.com"), "/");
+		assert_eq!(url_path("old/"), "/old/");
+		assert_eq!(url_path("/old/"), "/old/");
+	}
+}
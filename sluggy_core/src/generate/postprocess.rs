@@ -0,0 +1,160 @@
+use super::config::{Config, PostprocessCommand, PostprocessFailure};
+use crate::{
+	err,
+	error::{Error, Result},
+	lazyfn::LazyFn,
+	map_err,
+	utils::{cached_regex, unlink_output},
+};
+use dashmap::DashMap;
+use std::{path::Path, process::Stdio, sync::Arc};
+use tokio::{fs, io::AsyncWriteExt, process::Command, sync::Semaphore};
+use tracing::instrument;
+
+/// Limits the commands which run at the same time, keyed on the configured concurrency.
+static PERMITS: LazyFn<DashMap<usize, Arc<Semaphore>>> = LazyFn::new(DashMap::new);
+
+/// The commands whose pattern matches `path`, relative to `out_dir`, in the order they're
+/// configured.
+#[inline]
+pub(crate) fn matching_commands<'c>(
+	path: &Path,
+	config: &'c Config,
+) -> Result<Vec<&'c PostprocessCommand>> {
+	let path = path.to_string_lossy();
+
+	let mut commands = vec![];
+	for command in &config.postprocess.commands {
+		let pattern_re = map_err!(
+			cached_regex(&glob_pattern(&command.pattern)),
+			RegexError(format!(
+				"failed to parse postprocess pattern \"{}\"",
+				command.pattern
+			)),
+		)?;
+
+		if pattern_re.is_match(&path) {
+			commands.push(command);
+		}
+	}
+
+	Ok(commands)
+}
+
+/// Pipes the file at `path` through each of `commands`, replacing it with the output of the last.
+/// When a command fails with `on_failure = "warn"`, the file is left as the last command which
+/// succeeded wrote it.
+#[instrument(level = "debug", skip(commands, config))]
+pub(crate) async fn postprocess_file(
+	path: &Path,
+	commands: &[PostprocessCommand],
+	config: &Config,
+) -> Result<()> {
+	let permits = PERMITS
+		.entry(config.postprocess.concurrency.max(1))
+		.or_insert_with(|| Arc::new(Semaphore::new(config.postprocess.concurrency.max(1))))
+		.clone();
+
+	let mut contents = map_err!(
+		fs::read(path).await,
+		IoError(format!("failed to read {}", path.display())),
+	)?;
+
+	for command in commands {
+		let _permit = permits
+			.acquire()
+			.await
+			.map_err(|_| err!(Validation("postprocess commands were cancelled")))?;
+
+		match run_command(&command.command, &contents, path).await {
+			Ok(output) => contents = output,
+			Err(error) if config.postprocess.on_failure == PostprocessFailure::Warn => {
+				tracing::warn!(path = %path.display(), %error, "Postprocess command failed");
+			}
+			Err(error) => return Err(error),
+		}
+	}
+
+	unlink_output(path)?;
+	map_err!(
+		fs::write(path, contents).await,
+		IoError(format!("failed to write {}", path.display())),
+	)?;
+
+	Ok(())
+}
+
+#[inline]
+async fn run_command(command: &[String], contents: &[u8], path: &Path) -> Result<Vec<u8>> {
+	let Some((program, args)) = command.split_first() else {
+		return Err(err!(Validation("postprocess command is empty")));
+	};
+
+	let mut child = map_err!(
+		Command::new(program)
+			.args(args)
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn(),
+		IoError(format!("failed to run postprocess command {program}")),
+	)?;
+
+	// Stdin is written while the output is read, so that large files can't fill up the pipes.
+	let stdin = child.stdin.take();
+	let write_stdin = async {
+		if let Some(mut stdin) = stdin {
+			stdin.write_all(contents).await?;
+		}
+		Ok::<_, std::io::Error>(())
+	};
+
+	let (write_result, output) = tokio::join!(write_stdin, child.wait_with_output());
+	let output = map_err!(
+		output,
+		IoError(format!("failed to run postprocess command {program}")),
+	)?;
+	map_err!(
+		write_result,
+		IoError(format!("failed to write to postprocess command {program}")),
+	)?;
+
+	if !output.status.success() {
+		return Err(err!(Validation(format!(
+			"postprocess command {program} failed on {}: {}",
+			path.display(),
+			String::from_utf8_lossy(&output.stderr).trim()
+		))));
+	}
+
+	Ok(output.stdout)
+}
+
+/// Converts a glob to a regex pattern. `**` matches across directories, `*` and `?` only within a
+/// single path segment.
+#[inline]
+pub(crate) fn glob_pattern(glob: &str) -> String {
+	let mut pattern = String::from("^");
+
+	let mut chars = glob.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'*' if chars.peek() == Some(&'*') => {
+				chars.next();
+				// `**/` also matches no directories at all.
+				if chars.peek() == Some(&'/') {
+					chars.next();
+					pattern.push_str("(?:.*/)?");
+				} else {
+					pattern.push_str(".*");
+				}
+			}
+			'*' => pattern.push_str("[^/]*"),
+			'?' => pattern.push_str("[^/]"),
+			c => pattern.push_str(&regex::escape(&c.to_string())),
+		}
+	}
+
+	pattern.push('$');
+	pattern
+}
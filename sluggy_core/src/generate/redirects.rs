@@ -0,0 +1,326 @@
+use super::{
+	config::Config, content::Content, html::links::base_path, output_manifest, paths::url_path,
+};
+use crate::{
+	common::http::{DEFAULT_REDIRECT_STATUS, REDIRECTS_FILE},
+	err,
+	error::{Error, Result},
+	lazyfn::LazyFn,
+	map_err,
+	utils::{escape_xml, unlink_output},
+};
+use dashmap::DashMap;
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fmt::Write,
+	fs,
+	io::Read,
+	path::{Component, Path, PathBuf},
+};
+use tracing::instrument;
+
+/// Statuses which redirects can be served with.
+const REDIRECT_STATUSES: [u16; 4] = [301, 302, 307, 308];
+
+/// The pages of redirects written by the last build are listed in this file under `cache_dir`, so
+/// that the pages of redirects which are gone can be removed.
+const REDIRECT_PAGES_FILE: &str = "redirect_pages.json";
+
+/// Redirect pages start with this, which tells them apart from the pages of entries.
+const REDIRECT_PAGE_HEAD: &str =
+	r#"<!DOCTYPE html><html><head><meta charset="utf-8"><title>Redirecting&hellip;</title>"#;
+
+/// Where each path across all of the trees redirects to, and the status it's redirected with.
+static REDIRECTS: LazyFn<DashMap<String, (String, u16)>> = LazyFn::new(DashMap::new);
+
+#[inline]
+pub(crate) fn clear_redirects() {
+	REDIRECTS.clear();
+}
+
+/// Collects the configured redirects of a tree, and the `aliases` of each of its entries.
+#[instrument(level = "debug", skip_all)]
+pub(crate) fn collect_redirects(content: &Content) -> Result<()> {
+	let config = &content.config;
+
+	for redirect in &config.redirects {
+		if !REDIRECT_STATUSES.contains(&redirect.status) {
+			return Err(err!(Validation(format!(
+				"invalid status {} of the redirect from {}",
+				redirect.status, redirect.from
+			))));
+		}
+		if has_parent_dir(&redirect.from) {
+			return Err(err!(Validation(format!(
+				"the redirect from {} can't contain `..`",
+				redirect.from
+			))));
+		}
+
+		let to = if redirect.to.contains("://") {
+			redirect.to.clone()
+		} else {
+			site_path(&redirect.to, config)
+		};
+		REDIRECTS.insert(site_path(&redirect.from, config), (to, redirect.status));
+	}
+
+	for entry in content.entries.iter() {
+		let entry = entry.value();
+		for alias in &entry.aliases {
+			// The page of the alias would be written outside of `out_dir`.
+			if has_parent_dir(alias) {
+				return Err(err!(Validation(format!(
+					"the alias {alias} of {} can't contain `..`",
+					entry.path.display()
+				))));
+			}
+
+			REDIRECTS.insert(
+				site_path(alias, config),
+				(url_path(&entry.url), DEFAULT_REDIRECT_STATUS),
+			);
+		}
+	}
+
+	Ok(())
+}
+
+/// Writes the collected redirects to the `_redirects` file at the root of `out_dir`, after the
+/// rules of `_redirects` in `content_dir`, if there is one.
+#[instrument(level = "debug", skip_all)]
+pub(crate) fn write_redirects_file(config: &Config) -> Result<()> {
+	if REDIRECTS.is_empty() {
+		return Ok(());
+	}
+
+	let mut redirects_file = fs::read_to_string(config.content_dir.join(REDIRECTS_FILE))
+		.map(|source| format!("{}\n", source.trim_end()))
+		.unwrap_or_default();
+
+	let redirects = REDIRECTS
+		.iter()
+		.map(|redirect| (redirect.key().clone(), redirect.value().clone()))
+		.collect::<BTreeMap<_, _>>();
+
+	for (from, (to, status)) in redirects {
+		let _ = writeln!(redirects_file, "{from} {to} {status}");
+	}
+
+	let redirects_path = config.out_dir.join(REDIRECTS_FILE);
+	unlink_output(&redirects_path)?;
+	map_err!(
+		fs::write(&redirects_path, redirects_file),
+		IoError(format!("failed to write {}", redirects_path.display())),
+	)?;
+	output_manifest::record(&redirects_path, None);
+
+	Ok(())
+}
+
+/// Writes a page which redirects with a meta refresh at the path of each collected redirect, for
+/// hosts which don't read `_redirects`. Paths with a `*`, and those which already have the page of
+/// an entry, are skipped. The pages of redirects which the last build wrote, but which are gone,
+/// are removed.
+#[instrument(level = "debug", skip_all)]
+pub(crate) fn write_redirect_pages(config: &Config) -> Result<()> {
+	let pages_path = config.cache_dir.join(REDIRECT_PAGES_FILE);
+	let previous_pages = fs::read_to_string(&pages_path)
+		.ok()
+		.and_then(|source| serde_json::from_str::<BTreeSet<PathBuf>>(&source).ok())
+		.unwrap_or_default();
+
+	let mut pages = BTreeSet::new();
+	if config.redirect_pages {
+		let base_path = base_path(&config.base_url);
+		for redirect in REDIRECTS.iter() {
+			let (from, (to, _)) = redirect.pair();
+			if from.contains('*') {
+				continue;
+			}
+
+			let Some(page_path) = redirect_page_path(from, &base_path) else {
+				continue;
+			};
+			let out_file = config.out_dir.join(&page_path);
+			if out_file.exists() && !is_redirect_page(&out_file) {
+				continue;
+			}
+
+			if let Some(page_dir) = out_file.parent() {
+				map_err!(
+					fs::create_dir_all(page_dir),
+					IoError(format!("failed to create dirs for {}", page_dir.display())),
+				)?;
+			}
+			let page = redirect_page(to);
+			if fs::read_to_string(&out_file).ok().as_deref() != Some(page.as_str()) {
+				unlink_output(&out_file)?;
+				map_err!(
+					fs::write(&out_file, page),
+					IoError(format!("failed to write {}", out_file.display())),
+				)?;
+			}
+			output_manifest::record(&out_file, None);
+			pages.insert(page_path);
+		}
+	}
+
+	for page_path in previous_pages.difference(&pages) {
+		let out_file = config.out_dir.join(page_path);
+		// Entries may have been written to the paths of redirects which are gone.
+		if is_redirect_page(&out_file) {
+			map_err!(
+				fs::remove_file(&out_file),
+				IoError(format!("failed to remove {}", out_file.display())),
+			)?;
+			tracing::info!(path = %page_path.display(), "Removed the page of a redirect");
+		}
+	}
+
+	if pages.is_empty() && previous_pages.is_empty() {
+		return Ok(());
+	}
+	let json = map_err!(
+		serde_json::to_vec(&pages),
+		SerdeJsonError("failed to serialize redirect pages"),
+	)?;
+	map_err!(
+		fs::create_dir_all(&config.cache_dir),
+		IoError(format!(
+			"failed to create dirs for {}",
+			config.cache_dir.display()
+		)),
+	)?;
+	map_err!(
+		fs::write(&pages_path, json),
+		IoError(format!("failed to write {}", pages_path.display())),
+	)?;
+
+	Ok(())
+}
+
+/// Whether the file at `path` is the page of a redirect.
+#[inline]
+fn is_redirect_page(path: &Path) -> bool {
+	let mut head = vec![];
+	fs::File::open(path)
+		.and_then(|file| {
+			file.take(REDIRECT_PAGE_HEAD.len() as u64)
+				.read_to_end(&mut head)
+		})
+		.is_ok_and(|_| head == REDIRECT_PAGE_HEAD.as_bytes())
+}
+
+/// Whether `path` has a `..` segment, which could reach outside of `out_dir`.
+#[inline]
+fn has_parent_dir(path: &str) -> bool {
+	path.split(['/', '\\']).any(|segment| segment == "..")
+}
+
+/// Path within `out_dir` of the page of a redirect from `from`, e.g. `old/index.html` for `/old/`.
+/// Paths outside of `base_url`, or with segments such as `..`, have none.
+#[inline]
+fn redirect_page_path(from: &str, base_path: &str) -> Option<PathBuf> {
+	let path = PathBuf::from(from.strip_prefix(base_path)?);
+	if !path
+		.components()
+		.all(|component| matches!(component, Component::Normal(_)))
+	{
+		return None;
+	}
+
+	if path
+		.extension()
+		.is_some_and(|extension| extension == "html")
+	{
+		Some(path)
+	} else {
+		Some(path.join("index.html"))
+	}
+}
+
+#[inline]
+fn redirect_page(to: &str) -> String {
+	let to = escape_xml(to);
+	format!(
+		r#"{REDIRECT_PAGE_HEAD}<link rel="canonical" href="{to}"><meta name="robots" content="noindex"><meta http-equiv="refresh" content="0; url={to}"></head><body><p>This page has moved to <a href="{to}">{to}</a>.</p></body></html>"#
+	)
+}
+
+/// Path from the root of the site of `path`, which is relative to `base_url`.
+#[inline]
+fn site_path(path: &str, config: &Config) -> String {
+	url_path(&format!(
+		"{}{}",
+		config.base_url,
+		path.trim_start_matches('/')
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::generate::config::RedirectConfig;
+	use std::sync::Arc;
+
+	fn config(dir: &Path) -> Config {
+		Config {
+			base_url: "/".into(),
+			out_dir: dir.join("out"),
+			cache_dir: dir.join("cache"),
+			redirect_pages: true,
+			..Config::default()
+		}
+	}
+
+	#[test]
+	fn redirects_can_not_leave_the_site() {
+		let dir = tempfile::tempdir().unwrap();
+		let content = Content {
+			entries: DashMap::new(),
+			sections: DashMap::new(),
+			taxonomies: DashMap::new(),
+			config: Arc::new(Config {
+				redirects: vec![RedirectConfig {
+					from: "old/../../outside".into(),
+					to: "new/".into(),
+					status: DEFAULT_REDIRECT_STATUS,
+				}],
+				..config(dir.path())
+			}),
+		};
+		assert!(collect_redirects(&content).is_err());
+
+		assert_eq!(redirect_page_path("/../outside/", "/"), None);
+		assert_eq!(
+			redirect_page_path("/old/", "/"),
+			Some(PathBuf::from("old/index.html"))
+		);
+	}
+
+	#[test]
+	fn pages_of_removed_redirects_are_removed() {
+		let dir = tempfile::tempdir().unwrap();
+		let config = config(dir.path());
+		let entry_page = config.out_dir.join("test-entry/index.html");
+		fs::create_dir_all(entry_page.parent().unwrap()).unwrap();
+		fs::write(&entry_page, "<p>entry</p>").unwrap();
+
+		REDIRECTS.insert("/test-old/".into(), ("/test-new/".into(), 301));
+		REDIRECTS.insert("/test-entry/".into(), ("/test-new/".into(), 301));
+		write_redirect_pages(&config).unwrap();
+
+		let old_page = config.out_dir.join("test-old/index.html");
+		assert!(is_redirect_page(&old_page));
+		// Pages of entries aren't replaced.
+		assert_eq!(fs::read_to_string(&entry_page).unwrap(), "<p>entry</p>");
+
+		REDIRECTS.remove("/test-old/");
+		write_redirect_pages(&config).unwrap();
+		REDIRECTS.remove("/test-entry/");
+
+		assert!(!old_page.exists());
+		assert!(entry_page.exists());
+	}
+}
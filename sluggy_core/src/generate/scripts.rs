@@ -0,0 +1,61 @@
+use super::config::Config;
+use crate::{
+	err,
+	error::{Error, Result},
+	map_err,
+};
+use std::{ffi::OsStr, path::Path, process::Stdio};
+use tokio::process::Command;
+use tracing::instrument;
+
+/// Extensions of the scripts of `js_dir` which are bundled.
+const SCRIPT_EXTENSIONS: [&str; 5] = ["js", "mjs", "jsx", "ts", "tsx"];
+
+/// Whether `file` is a script which is bundled, rather than a partial (prefixed with `_`) or
+/// another kind of file.
+#[inline]
+pub(crate) fn is_entry_script(file: &Path) -> bool {
+	let is_script = file
+		.extension()
+		.and_then(OsStr::to_str)
+		.is_some_and(|extension| SCRIPT_EXTENSIONS.contains(&extension));
+	let is_partial = file
+		.file_name()
+		.is_some_and(|file_name| file_name.to_string_lossy().starts_with('_'));
+
+	is_script && !is_partial
+}
+
+/// Bundles the script with the configured bundler, minifying it when `minify` is enabled.
+#[instrument(level = "debug", skip(config))]
+pub(crate) async fn bundle_script(file: &Path, config: &Config) -> Result<String> {
+	let Some((program, args)) = config.js.bundler.split_first() else {
+		return Err(err!(Validation("js bundler has no command")));
+	};
+
+	let mut command = Command::new(program);
+	command.args(args);
+	if config.minify {
+		command.args(&config.js.minify_args);
+	}
+
+	let output = map_err!(
+		command
+			.arg(file)
+			.current_dir(&config.js_dir)
+			.stdin(Stdio::null())
+			.output()
+			.await,
+		IoError(format!("failed to run js bundler {program}")),
+	)?;
+
+	if !output.status.success() {
+		return Err(err!(Validation(format!(
+			"failed to bundle {}: {}",
+			file.display(),
+			String::from_utf8_lossy(&output.stderr).trim()
+		))));
+	}
+
+	Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -1,5 +1,9 @@
+use super::content::loader::EntryData;
 use serde_derive::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::{
+	cmp::Ordering,
+	path::{Path, PathBuf},
+};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SectionMetadata {
@@ -8,6 +12,88 @@ pub struct SectionMetadata {
 	pub link_text: Option<String>,
 	pub index_template: Option<String>,
 	pub slug_pattern: Option<String>,
+	/// Kind of the section's entries which don't set their own. Defaults to `post`.
+	pub kind: Option<String>,
+	/// Number of entries on each page of the section's index.
+	pub paginate_by: Option<usize>,
+	/// Path segment of pages after the first. Defaults to `page`, e.g. `/blog/page/2/`.
+	pub paginate_path: Option<String>,
+	/// Order of the section's entries. Defaults to `section_sort_by` of the config.
+	pub sort_by: Option<SortBy>,
+	/// Keep search engines from indexing the pages of the section and the sections within it,
+	/// with an `X-Robots-Tag: noindex` header, and leave them out of every listing.
+	#[serde(default)]
+	pub noindex: bool,
+	/// Listings which the entries of the section and the sections within it are left out of, e.g.
+	/// `["sitemap", "feeds"]`.
+	#[serde(default)]
+	pub exclude_from: Vec<Listing>,
+}
+
+/// Listings of the entries of the site, which sections can leave their entries out of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Listing {
+	Sitemap,
+	/// Feeds of sections and of taxonomy terms.
+	Feeds,
+	/// Indexes which templates build with `entries(listing = "search")`.
+	Search,
+}
+
+/// Order of the entries of a section. Ties are broken by path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+	/// Newest first, followed by undated entries.
+	#[default]
+	Date,
+	/// Lowest `weight` first, followed by entries without a weight.
+	Weight,
+	/// Alphabetical by `title`, ignoring case, falling back to the path.
+	Title,
+	Path,
+}
+
+impl SortBy {
+	#[inline]
+	pub(crate) fn compare(&self, a: &EntryData, b: &EntryData) -> Ordering {
+		let ordering = match self {
+			Self::Date => b.published.cmp(&a.published),
+			Self::Weight => {
+				let weight = |entry: &EntryData| {
+					entry
+						.frontmatter
+						.extra
+						.get("weight")
+						.and_then(|weight| weight.as_integer())
+				};
+
+				match (weight(a), weight(b)) {
+					(Some(a), Some(b)) => a.cmp(&b),
+					(Some(_), None) => Ordering::Less,
+					(None, Some(_)) => Ordering::Greater,
+					(None, None) => Ordering::Equal,
+				}
+			}
+			Self::Title => {
+				let title = |entry: &EntryData| {
+					entry
+						.frontmatter
+						.extra
+						.get("title")
+						.and_then(|title| title.as_str())
+						.map(str::to_lowercase)
+						.unwrap_or_else(|| entry.path.to_string_lossy().to_lowercase())
+				};
+
+				title(a).cmp(&title(b))
+			}
+			Self::Path => Ordering::Equal,
+		};
+
+		ordering.then_with(|| a.path.cmp(&b.path))
+	}
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
@@ -42,7 +128,13 @@ pub struct Section {
 	link_text: Option<String>,
 	#[serde(serialize_with = "add_postfix_slash")]
 	pub prefix: PathBuf,
+	pub kind: Option<String>,
 	pub entries: Vec<PathBuf>,
+	pub paginate_by: Option<usize>,
+	pub paginate_path: Option<String>,
+	pub sort_by: Option<SortBy>,
+	pub noindex: bool,
+	pub exclude_from: Vec<Listing>,
 }
 
 impl Section {
@@ -68,9 +160,21 @@ impl Section {
 			description: section_metadata.description.clone(),
 			link_text: section_metadata.link_text.clone(),
 			prefix,
+			kind: section_metadata.kind.clone(),
 			entries: vec![],
+			paginate_by: section_metadata.paginate_by,
+			paginate_path: section_metadata.paginate_path.clone(),
+			sort_by: section_metadata.sort_by,
+			noindex: section_metadata.noindex,
+			exclude_from: section_metadata.exclude_from.clone(),
 		}
 	}
+
+	/// Whether the section leaves its entries out of `listing`.
+	#[inline]
+	pub fn excludes(&self, listing: Listing) -> bool {
+		self.noindex || self.exclude_from.contains(&listing)
+	}
 }
 
 fn add_postfix_slash<S>(path: &Path, serializer: S) -> Result<S::Ok, S::Error>
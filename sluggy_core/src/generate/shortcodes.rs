@@ -0,0 +1,198 @@
+use super::{
+	content::Entry,
+	template::{has_template, render_fragment},
+};
+use crate::{
+	err,
+	error::{Error, Result},
+	lazyfn::LazyFn,
+	map_err,
+};
+use regex::{Captures, Regex};
+use serde_json::{Map, Value};
+use toml::Table;
+use tracing::instrument;
+
+/// Shortcodes are rendered with the templates of this directory of `template_dir`, e.g.
+/// `shortcodes/youtube.html`.
+const SHORTCODES_DIR: &str = "shortcodes";
+
+/// e.g. `{{ youtube(id="dQw4w9WgXcQ") }}`.
+const INLINE_PATTERN: &str = r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\(((?s:.*?))\)\s*\}\}";
+
+/// e.g. `{% callout(kind="warning") %}Markdown{% end %}`, whose template is given the markdown
+/// as `body`.
+const BLOCK_PATTERN: &str =
+	r"\{%\s*([A-Za-z_][A-Za-z0-9_]*)\(((?s:.*?))\)\s*%\}((?s:.*?))\{%\s*end\s*%\}";
+
+static INLINE_RE: LazyFn<Regex> = LazyFn::new(|| Regex::new(INLINE_PATTERN).unwrap());
+
+static BLOCK_RE: LazyFn<Regex> = LazyFn::new(|| Regex::new(BLOCK_PATTERN).unwrap());
+
+/// Replaces the shortcodes in the markdown of `entry` with their rendered templates. Templates are
+/// given the arguments of the shortcode, and the `entry`.
+///
+/// Shortcodes in fenced code blocks are left as they are, as are those without a template.
+#[instrument(level = "trace", skip(markdown, entry))]
+#[inline]
+pub(crate) fn render_shortcodes(markdown: String, entry: &Entry) -> Result<String> {
+	if !markdown.contains("{{") && !markdown.contains("{%") {
+		return Ok(markdown);
+	}
+
+	let mut output = String::with_capacity(markdown.len());
+	let mut text = String::new();
+	let mut fence: Option<(char, usize)> = None;
+	for line in markdown.split_inclusive('\n') {
+		let trimmed = line.trim_start();
+		let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~');
+		let marker_len = marker.map_or(0, |c| trimmed.chars().take_while(|m| *m == c).count());
+
+		match (fence, marker) {
+			(None, Some(c)) if marker_len >= 3 => {
+				output.push_str(&render_text(&text, entry)?);
+				text.clear();
+				fence = Some((c, marker_len));
+				output.push_str(line);
+			}
+			(Some((c, len)), Some(m)) if m == c && marker_len >= len => {
+				fence = None;
+				output.push_str(line);
+			}
+			(Some(_), _) => output.push_str(line),
+			(None, _) => text.push_str(line),
+		}
+	}
+	output.push_str(&render_text(&text, entry)?);
+
+	Ok(output)
+}
+
+/// Renders the shortcodes of markdown outside of code blocks. Inline shortcodes are rendered
+/// first, so that they can be used in the body of block shortcodes.
+#[inline]
+fn render_text(text: &str, entry: &Entry) -> Result<String> {
+	let text = replace(text, &INLINE_RE, |captures| {
+		render_shortcode(&captures[1], &captures[2], None, entry)
+	})?;
+	replace(&text, &BLOCK_RE, |captures| {
+		render_shortcode(&captures[1], &captures[2], Some(&captures[3]), entry)
+	})
+}
+
+/// Replaces the matches of `re` with the output of `render`, unless it's `None`.
+#[inline]
+fn replace(
+	text: &str,
+	re: &Regex,
+	render: impl Fn(&Captures) -> Result<Option<String>>,
+) -> Result<String> {
+	let mut output = String::with_capacity(text.len());
+	let mut last = 0;
+	for captures in re.captures_iter(text) {
+		let Some(matched) = captures.get(0) else {
+			continue;
+		};
+		if let Some(rendered) = render(&captures)? {
+			output.push_str(&text[last..matched.start()]);
+			output.push_str(&rendered);
+			last = matched.end();
+		}
+	}
+	output.push_str(&text[last..]);
+
+	Ok(output)
+}
+
+#[inline]
+fn render_shortcode(
+	name: &str,
+	args: &str,
+	body: Option<&str>,
+	entry: &Entry,
+) -> Result<Option<String>> {
+	let template_name = format!("{SHORTCODES_DIR}/{name}.html");
+	if !has_template(&template_name) {
+		return Ok(None);
+	}
+
+	let mut context = parse_args(args).ok_or_else(|| {
+		err!(Validation(format!(
+			"invalid arguments of shortcode `{name}` in {}: {args}",
+			entry.path.display()
+		)))
+	})?;
+	if let Some(body) = body {
+		context.insert("body".into(), Value::String(body.to_string()));
+	}
+	context.insert(
+		"entry".into(),
+		map_err!(
+			serde_json::to_value(entry),
+			SerdeJsonError("failed to serialize entry"),
+		)?,
+	);
+
+	render_fragment(&template_name, context).map(Some)
+}
+
+/// Arguments like `id="abc", width=640, autoplay=true`, whose values are TOML values.
+#[inline]
+fn parse_args(args: &str) -> Option<Map<String, Value>> {
+	let mut parsed = Map::new();
+
+	let mut rest = args.trim();
+	while !rest.is_empty() {
+		let (key, value) = rest.split_once('=')?;
+		let key = key.trim();
+		if key.is_empty() || !key.chars().all(|c| c.is_alphanumeric() || c == '_') {
+			return None;
+		}
+
+		let value = value.trim_start();
+		let end = value_end(value)?;
+		let table = toml::from_str::<Table>(&format!("value = {}", &value[..end])).ok()?;
+		parsed.insert(
+			key.to_string(),
+			serde_json::to_value(table.get("value")?).ok()?,
+		);
+
+		rest = value[end..].trim_start();
+		if !rest.is_empty() {
+			rest = rest.strip_prefix(',')?.trim_start();
+		}
+	}
+
+	Some(parsed)
+}
+
+/// Index of the end of the value at the start of `value`, the first comma which isn't in a string
+/// or an array.
+#[inline]
+fn value_end(value: &str) -> Option<usize> {
+	let mut quote = None;
+	let mut escaped = false;
+	let mut depth = 0usize;
+	for (index, c) in value.char_indices() {
+		match quote {
+			// Only basic strings have escapes.
+			Some('"') if escaped => escaped = false,
+			Some('"') if c == '\\' => escaped = true,
+			Some(q) if c == q => quote = None,
+			Some(_) => {}
+			None => match c {
+				'"' | '\'' => quote = Some(c),
+				'[' | '{' => depth += 1,
+				']' | '}' => depth = depth.checked_sub(1)?,
+				',' if depth == 0 => return Some(index),
+				_ => {}
+			},
+		}
+	}
+
+	if quote.is_some() || depth > 0 {
+		None
+	} else {
+		Some(value.trim_end().len())
+	}
+}
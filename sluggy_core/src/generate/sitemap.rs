@@ -0,0 +1,77 @@
+use super::{
+	content::{Content, Entry},
+	process_output,
+	sections::Listing,
+};
+use crate::{
+	error::{Error, Result},
+	map_err,
+	utils::{escape_xml, unlink_output},
+};
+use std::{fmt::Write, fs};
+use tokio::task::JoinSet;
+use tracing::instrument;
+
+const SITEMAP_FILE: &str = "sitemap.xml";
+
+/// Writes `sitemap.xml` with each of the pages of the tree to `out_dir`, when `sitemap` is enabled.
+#[instrument(skip_all)]
+pub(crate) fn generate_sitemap(
+	content: &Content,
+	join_set: &mut JoinSet<Result<()>>,
+) -> Result<()> {
+	let config = &content.config;
+	if !config.sitemap {
+		return Ok(());
+	}
+
+	let mut entries = content
+		.entries
+		.iter()
+		.filter(|entry| entry.is_renderable && entry.file_type.is_rendered_to_html())
+		.filter(|entry| !entry.is_error_page() && !entry.protected)
+		.filter(|entry| !content.is_excluded_from(entry, Listing::Sitemap))
+		.map(|entry| entry.value().clone())
+		.collect::<Vec<_>>();
+	entries.sort_by(|a, b| a.url.cmp(&b.url));
+
+	let sitemap_path = config.out_dir.join(SITEMAP_FILE);
+	let mut sitemap = render_sitemap(&entries).into_bytes();
+	config
+		.hooks
+		.run_before_write_output(&sitemap_path, &mut sitemap)?;
+	unlink_output(&sitemap_path)?;
+	map_err!(
+		fs::write(&sitemap_path, sitemap),
+		IoError(format!(
+			"failed to write sitemap {}",
+			sitemap_path.display()
+		)),
+	)?;
+
+	process_output(&sitemap_path, join_set, config.clone())?;
+
+	Ok(())
+}
+
+/// Renders a sitemap of `entries`, in the order given. Entries are dated by when they were last
+/// updated, or otherwise when they were published.
+#[instrument(level = "debug", skip_all)]
+pub fn render_sitemap<'e>(entries: impl IntoIterator<Item = &'e Entry>) -> String {
+	let mut xml = String::new();
+	xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+	xml.push_str(r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#);
+
+	for entry in entries {
+		xml.push_str("<url>");
+		let _ = write!(xml, "<loc>{}</loc>", escape_xml(&entry.url));
+		if let Some(modified) = entry.updated.or(entry.published) {
+			let _ = write!(xml, "<lastmod>{}</lastmod>", modified.format("%Y-%m-%d"));
+		}
+		xml.push_str("</url>");
+	}
+
+	xml.push_str("</urlset>");
+
+	xml
+}
@@ -0,0 +1,166 @@
+use super::{
+	config::{Config, SourceLinks},
+	content::{Content, FileType},
+	paths::normalize_path,
+};
+use crate::{
+	err,
+	error::{Error, Result},
+	lazyfn::LazyFn,
+};
+use comrak::nodes::{AstNode, NodeValue};
+use dashmap::DashMap;
+use std::path::{Path, PathBuf};
+use tracing::instrument;
+
+/// File of each entry of the tree being generated -> the entry's path, and the URL of its page.
+static SOURCE_URLS: LazyFn<DashMap<PathBuf, (PathBuf, String)>> = LazyFn::new(DashMap::new);
+
+/// Collects the URLs of the files of the entries of the tree, before they're rendered.
+#[instrument(skip_all)]
+pub(crate) fn collect_source_urls(content: &Content) -> Result<()> {
+	SOURCE_URLS.clear();
+
+	for entry in content.entries.iter() {
+		// Files which generate more than one entry link to the entry whose path sorts first.
+		let mut source = SOURCE_URLS
+			.entry(normalize_path(&entry.file_path))
+			.or_insert_with(|| (entry.key().clone(), entry.url.clone()));
+		if entry.key() < &source.0 {
+			*source = (entry.key().clone(), entry.url.clone());
+		}
+	}
+
+	Ok(())
+}
+
+/// Rewrites relative links to the `.md` and `.ipynb` files of other entries to the URLs of their
+/// pages, keeping any query and fragment. Only the markdown of entries, at `path`, is rewritten.
+#[instrument(level = "trace", skip(root, config))]
+pub(crate) fn rewrite_source_links<'a>(
+	root: &'a AstNode<'a>,
+	path: &Path,
+	config: &Config,
+) -> Result<()> {
+	if config.markdown.source_links == SourceLinks::Off {
+		return Ok(());
+	}
+	let Some(dir) = path.parent() else {
+		return Ok(());
+	};
+	if !SOURCE_URLS.contains_key(&normalize_path(path)) {
+		return Ok(());
+	}
+
+	for node in root.descendants() {
+		let mut data = node.data.borrow_mut();
+		let NodeValue::Link(link) = &mut data.value else {
+			continue;
+		};
+
+		let (target, suffix) = link
+			.url
+			.find(['#', '?'])
+			.map_or((link.url.as_str(), ""), |index| link.url.split_at(index));
+		if !is_source_link(target) {
+			continue;
+		}
+
+		match SOURCE_URLS.get(&normalize_path(&dir.join(target))) {
+			Some(source) => link.url = format!("{}{suffix}", source.1),
+			None => {
+				let message = format!(
+					"{} links to {target}, which isn't the file of an entry",
+					path.display()
+				);
+				match config.markdown.source_links {
+					SourceLinks::Error => return Err(err!(Validation(message))),
+					_ => tracing::warn!("{message}"),
+				}
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Whether `target` is a relative path to a file which is converted to HTML.
+#[inline]
+fn is_source_link(target: &str) -> bool {
+	if target.is_empty() || target.starts_with('/') || target.contains(':') {
+		return false;
+	}
+
+	Path::new(target).extension().is_some_and(|extension| {
+		FileType::from(extension.to_string_lossy().as_ref()).is_generated()
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use comrak::{Arena, ComrakOptions};
+
+	#[test]
+	fn source_links() {
+		assert!(is_source_link("post.md"));
+		assert!(is_source_link("../notebooks/analysis.ipynb"));
+		assert!(!is_source_link(""));
+		assert!(!is_source_link("/posts/post.md"));
+		assert!(!is_source_link("https://example.com/post.md"));
+		assert!(!is_source_link("image.png"));
+		assert!(!is_source_link("page.html"));
+		assert!(!is_source_link("README"));
+	}
+
+	/// URLs of the links of `markdown`, once they're rewritten as links of `path`.
+	fn rewritten(markdown: &str, path: &Path, config: &Config) -> Result<Vec<String>> {
+		let arena = Arena::new();
+		let root = comrak::parse_document(&arena, markdown, &ComrakOptions::default());
+		rewrite_source_links(root, path, config)?;
+
+		Ok(root
+			.descendants()
+			.filter_map(|node| match &node.data.borrow().value {
+				NodeValue::Link(link) => Some(link.url.clone()),
+				_ => None,
+			})
+			.collect())
+	}
+
+	#[test]
+	fn rewrite_links_to_entries() {
+		let dir = Path::new("content/source-links-test");
+		SOURCE_URLS.insert(
+			dir.join("posts/a.md"),
+			("source-links-test/posts/a".into(), "/posts/a/".into()),
+		);
+		SOURCE_URLS.insert(
+			dir.join("about.md"),
+			("source-links-test/about".into(), "/about/".into()),
+		);
+
+		let markdown = "[about](../about.md#team) [self](./a.md?x=1) [missing](b.md) \
+			[site](https://example.com/b.md)";
+		let path = dir.join("posts/a.md");
+		assert_eq!(
+			rewritten(markdown, &path, &Config::default()).unwrap(),
+			vec![
+				"/about/#team",
+				"/posts/a/?x=1",
+				"b.md",
+				"https://example.com/b.md"
+			]
+		);
+
+		let mut config = Config::default();
+		config.markdown.source_links = SourceLinks::Error;
+		assert!(rewritten(markdown, &path, &config).is_err());
+
+		config.markdown.source_links = SourceLinks::Off;
+		assert_eq!(
+			rewritten("[about](../about.md)", &path, &config).unwrap(),
+			vec!["../about.md"]
+		);
+	}
+}
@@ -0,0 +1,235 @@
+use super::{
+	config::SchemaType,
+	content::{Content, Entry},
+	sections::SectionHandle,
+};
+use crate::{
+	error::{Error, Result},
+	map_err,
+};
+use serde_json::{json, Map, Value};
+use std::{collections::BTreeMap, path::Path};
+use tracing::instrument;
+
+/// Frontmatter field which selects the schema types of an entry, overriding `structured_data`
+/// config.
+const FRONTMATTER_FIELD: &str = "structured_data";
+
+const ARTICLE_FIELDS: [(&str, &str); 7] = [
+	("headline", "title"),
+	("description", "description"),
+	("image", "image"),
+	("author", "author"),
+	("datePublished", "published"),
+	("dateModified", "updated"),
+	("url", "url"),
+];
+
+const EVENT_FIELDS: [(&str, &str); 7] = [
+	("name", "title"),
+	("description", "description"),
+	("image", "image"),
+	("startDate", "start"),
+	("endDate", "end"),
+	("location", "location"),
+	("url", "url"),
+];
+
+const PRODUCT_FIELDS: [(&str, &str); 8] = [
+	("name", "title"),
+	("description", "description"),
+	("image", "image"),
+	("sku", "sku"),
+	("brand", "brand"),
+	("price", "price"),
+	("priceCurrency", "currency"),
+	("url", "url"),
+];
+
+/// Renders the JSON-LD `<script>` tags for an entry, to be injected into the page head.
+#[instrument(level = "debug", skip(content))]
+pub(crate) fn render_json_ld(entry: &Entry, content: &Content) -> Result<Option<String>> {
+	let configs = &content.config.structured_data;
+
+	let schema_types = match entry.extra.get(FRONTMATTER_FIELD) {
+		Some(value) => {
+			let value = map_err!(
+				serde_json::to_value(value),
+				SerdeJsonError("failed to serialize structured data types"),
+			)?;
+			let schema_types = match value {
+				Value::Array(_) => serde_json::from_value::<Vec<SchemaType>>(value),
+				value => {
+					serde_json::from_value::<SchemaType>(value).map(|schema_type| vec![schema_type])
+				}
+			};
+			map_err!(
+				schema_types,
+				SerdeJsonError(format!(
+					"invalid `{FRONTMATTER_FIELD}` in {}",
+					entry.path.display()
+				)),
+			)?
+		}
+		None => configs
+			.iter()
+			.filter(|config| {
+				config.sections.iter().any(|section| {
+					entry.section_handle.as_ref() == Some(&SectionHandle::from(section.as_str()))
+				})
+			})
+			.map(|config| config.schema_type)
+			.collect(),
+	};
+
+	if schema_types.is_empty() {
+		return Ok(None);
+	}
+
+	let mut scripts = String::new();
+	for schema_type in schema_types {
+		let overrides = configs
+			.iter()
+			.find(|config| config.schema_type == schema_type)
+			.map(|config| &config.fields);
+
+		let data = match schema_type {
+			SchemaType::Article => map_fields(entry, "Article", &ARTICLE_FIELDS, overrides),
+			SchemaType::Event => map_fields(entry, "Event", &EVENT_FIELDS, overrides),
+			SchemaType::Product => {
+				let mut data = map_fields(entry, "Product", &PRODUCT_FIELDS, overrides);
+				let price = data.remove("price");
+				let currency = data.remove("priceCurrency");
+				if let Some(price) = price {
+					let mut offer = json!({ "@type": "Offer", "price": price });
+					if let Some(currency) = currency {
+						offer["priceCurrency"] = currency;
+					}
+					data.insert("offers".into(), offer);
+				}
+				data
+			}
+			SchemaType::BreadcrumbList => breadcrumbs(entry, content),
+		};
+
+		let json = map_err!(
+			serde_json::to_string(&data),
+			SerdeJsonError("failed to serialize structured data"),
+		)?;
+
+		// Prevent a value from closing the script tag early.
+		let json = json.replace("</", "<\\/");
+		scripts.push_str(&format!(
+			r#"<script type="application/ld+json">{json}</script>"#
+		));
+	}
+
+	Ok(Some(scripts))
+}
+
+/// Maps frontmatter fields onto schema properties. Properties with no value are omitted.
+#[inline]
+fn map_fields(
+	entry: &Entry,
+	schema_type: &str,
+	defaults: &[(&str, &str)],
+	overrides: Option<&BTreeMap<String, String>>,
+) -> Map<String, Value> {
+	let mut data = Map::new();
+	data.insert("@context".into(), json!("https://schema.org"));
+	data.insert("@type".into(), json!(schema_type));
+
+	let mut fields = defaults
+		.iter()
+		.map(|(property, field)| (property.to_string(), field.to_string()))
+		.collect::<BTreeMap<_, _>>();
+	if let Some(overrides) = overrides {
+		fields.extend(overrides.clone());
+	}
+
+	for (property, field) in fields {
+		let Some(value) = entry_value(entry, &field) else {
+			continue;
+		};
+
+		let value = match (property.as_str(), value) {
+			("author", Value::String(name)) => json!({ "@type": "Person", "name": name }),
+			("brand", Value::String(name)) => json!({ "@type": "Brand", "name": name }),
+			("location", Value::String(name)) => json!({ "@type": "Place", "name": name }),
+			(_, value) => value,
+		};
+
+		data.insert(property, value);
+	}
+
+	data
+}
+
+#[inline]
+fn entry_value(entry: &Entry, field: &str) -> Option<Value> {
+	match field {
+		"title" => Some(json!(entry.title())),
+		"url" => Some(json!(entry.url)),
+		"published" => entry
+			.published
+			.map(|published| json!(published.to_rfc3339())),
+		"updated" => entry.updated.map(|updated| json!(updated.to_rfc3339())),
+		field => match entry.extra.get(field)? {
+			toml::Value::Datetime(datetime) => Some(json!(datetime.to_string())),
+			value => serde_json::to_value(value).ok(),
+		},
+	}
+}
+
+/// Breadcrumbs from the root of the site, through each of the sections the entry is nested in.
+#[inline]
+fn breadcrumbs(entry: &Entry, content: &Content) -> Map<String, Value> {
+	let base_url = &content.config.base_url;
+
+	let mut crumbs = vec![(String::from("Home"), base_url.clone())];
+
+	let mut ancestors = entry
+		.path
+		.ancestors()
+		.skip(1)
+		.filter(|ancestor| *ancestor != Path::new(""))
+		.collect::<Vec<_>>();
+	ancestors.reverse();
+
+	for ancestor in ancestors {
+		if let Some(section) = content
+			.sections
+			.iter()
+			.find(|section| section.prefix == ancestor)
+		{
+			let name = section.title.clone().unwrap_or_else(|| {
+				ancestor
+					.file_name()
+					.map(|file_name| file_name.to_string_lossy().to_string())
+					.unwrap_or_default()
+			});
+			crumbs.push((name, format!("{base_url}{}/", ancestor.to_string_lossy())));
+		}
+	}
+
+	crumbs.push((entry.title(), entry.url.clone()));
+
+	let items = crumbs
+		.into_iter()
+		.enumerate()
+		.map(|(index, (name, url))| {
+			json!({
+				"@type": "ListItem",
+				"position": index + 1,
+				"name": name,
+				"item": url,
+			})
+		})
+		.collect::<Vec<_>>();
+
+	let mut data = Map::new();
+	data.insert("@context".into(), json!("https://schema.org"));
+	data.insert("@type".into(), json!("BreadcrumbList"));
+	data.insert("itemListElement".into(), Value::Array(items));
+	data
+}
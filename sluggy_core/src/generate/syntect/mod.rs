@@ -1,20 +1,99 @@
-use crate::lazyfn::LazyFn;
+use super::config::Config;
+use crate::error::Result;
+#[cfg(not(feature = "syntax-highlighting"))]
+use crate::utils::escape_xml;
+#[cfg(feature = "syntax-highlighting")]
+use crate::{err, error::Error, map_err};
+#[cfg(feature = "syntax-highlighting")]
 use comrak::adapters::SyntaxHighlighterAdapter;
-use std::{io, io::Write};
+#[cfg(feature = "syntax-highlighting")]
+use std::{ffi::OsStr, fs, io, io::Write, sync::OnceLock};
+#[cfg(feature = "syntax-highlighting")]
 use syntect::{
+	dumps::from_dump_file,
 	html::{ClassStyle, ClassedHTMLGenerator},
 	parsing::SyntaxSet,
 	util::LinesWithEndings,
 };
 use tracing::instrument;
 
-static SYNTAX_SET: LazyFn<SyntaxSet> = LazyFn::new(SyntaxSet::load_defaults_newlines);
-// LazyFn::new(|| from_binary(include_bytes!("./newlines.packdump")));
+/// Extension of binary syntax packs, as written by `syntect::dumps::dump_to_file`.
+#[cfg(feature = "syntax-highlighting")]
+const PACKDUMP_EXT: &str = "packdump";
+
+/// The default syntaxes, and those of `syntaxes_dir`. Set when the site is first generated.
+#[cfg(feature = "syntax-highlighting")]
+static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+
+#[cfg(feature = "syntax-highlighting")]
+#[inline]
+fn syntax_set() -> &'static SyntaxSet {
+	SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Merges the `.sublime-syntax` definitions of `syntaxes_dir`, and the syntaxes of the binary
+/// packs in it, with the default syntaxes. The syntaxes are only loaded once, so changes to them are
+/// picked up after a restart.
+#[cfg(feature = "syntax-highlighting")]
+#[instrument(skip(config))]
+pub(crate) fn load_syntaxes(config: &Config) -> Result<()> {
+	if SYNTAX_SET.get().is_some() || !config.syntaxes_dir.is_dir() {
+		return Ok(());
+	}
+
+	let syntaxes_dir = &config.syntaxes_dir;
+	let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+
+	let mut packs = map_err!(
+		fs::read_dir(syntaxes_dir),
+		IoError(format!("failed to read {}", syntaxes_dir.display())),
+	)?
+	.filter_map(|dir_entry| dir_entry.ok().map(|dir_entry| dir_entry.path()))
+	.filter(|path| path.extension() == Some(OsStr::new(PACKDUMP_EXT)))
+	.collect::<Vec<_>>();
+	// Syntaxes which are added later take precedence, so packs are merged in a stable order.
+	packs.sort();
+	for pack in packs {
+		let pack_set: SyntaxSet = from_dump_file(&pack).map_err(|error| {
+			err!(Validation(format!(
+				"failed to load syntax pack {}: {error}",
+				pack.display()
+			)))
+		})?;
+		for syntax in pack_set.into_builder().syntaxes() {
+			builder.add(syntax.clone());
+		}
+	}
+
+	builder
+		.add_from_folder(syntaxes_dir, true)
+		.map_err(|error| {
+			err!(Validation(format!(
+				"failed to load syntaxes from {}: {error}",
+				syntaxes_dir.display()
+			)))
+		})?;
+
+	let syntax_set = builder.build();
+	tracing::debug!(syntaxes = syntax_set.syntaxes().len(), "Loaded syntaxes");
+	// Only fails if the syntaxes were loaded meanwhile.
+	let _ = SYNTAX_SET.set(syntax_set);
+
+	Ok(())
+}
+
+#[cfg(not(feature = "syntax-highlighting"))]
+#[inline]
+pub(crate) fn load_syntaxes(_config: &Config) -> Result<()> {
+	Ok(())
+}
 
 // const THEME_SET: LazyFn<ThemeSet> =
 //     LazyFn::new(|| from_binary(include_bytes!("./all.themedump")));
+#[cfg(feature = "syntax-highlighting")]
 pub struct SyntectAdapter;
 
+#[cfg(feature = "syntax-highlighting")]
 fn map_lang(lang: Option<&str>) -> &str {
 	// TODO use enum or something so we can get default langs and shit. Check how zola does it
 	lang.map(|lang| {
@@ -32,6 +111,54 @@ fn map_lang(lang: Option<&str>) -> &str {
 	.unwrap_or("txt")
 }
 
+/// Highlights `code` into the same markup as fenced code blocks in markdown.
+///
+/// The language is guessed from `extension` when `lang` is not given.
+#[cfg(feature = "syntax-highlighting")]
+#[instrument(level = "trace", skip(code))]
+pub(crate) fn highlight_code_block(
+	lang: Option<&str>,
+	extension: Option<&str>,
+	code: &str,
+) -> String {
+	let syntax = lang
+		.and_then(|lang| syntax_set().find_syntax_by_token(map_lang(Some(lang))))
+		.or_else(|| {
+			extension.and_then(|extension| syntax_set().find_syntax_by_extension(extension))
+		})
+		.unwrap_or_else(|| syntax_set().find_syntax_plain_text());
+
+	let mut html_generator =
+		ClassedHTMLGenerator::new_with_class_style(syntax, syntax_set(), ClassStyle::Spaced);
+	for line in LinesWithEndings::from(code) {
+		// Only fails if the syntax definition is invalid.
+		let _ = html_generator.parse_html_for_line_which_includes_newline(line);
+	}
+
+	let lang = lang.or(extension).unwrap_or("txt");
+	format!(
+		"<pre lang=\"{lang}\"><code class=\"highlight code language-{lang}\">{}</code></pre>",
+		html_generator.finalize()
+	)
+}
+
+/// Without the `syntax-highlighting` feature, code is escaped into the same markup without
+/// highlighting.
+#[cfg(not(feature = "syntax-highlighting"))]
+#[instrument(level = "trace", skip(code))]
+pub(crate) fn highlight_code_block(
+	lang: Option<&str>,
+	extension: Option<&str>,
+	code: &str,
+) -> String {
+	let lang = lang.or(extension).unwrap_or("txt");
+	format!(
+		"<pre lang=\"{lang}\"><code class=\"highlight code language-{lang}\">{}</code></pre>",
+		escape_xml(code)
+	)
+}
+
+#[cfg(feature = "syntax-highlighting")]
 impl SyntaxHighlighterAdapter for SyntectAdapter {
 	#[inline]
 	#[instrument(level = "trace", skip(self, output, code))]
@@ -61,9 +188,12 @@ impl SyntaxHighlighterAdapter for SyntectAdapter {
 		// }
 		// panic!("oops");
 
-		if let Some(syntax) = SYNTAX_SET.find_syntax_by_token(map_lang(lang)) {
-			let mut html_generator =
-				ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAX_SET, ClassStyle::Spaced);
+		if let Some(syntax) = syntax_set().find_syntax_by_token(map_lang(lang)) {
+			let mut html_generator = ClassedHTMLGenerator::new_with_class_style(
+				syntax,
+				syntax_set(),
+				ClassStyle::Spaced,
+			);
 			for line in LinesWithEndings::from(code) {
 				html_generator
 					.parse_html_for_line_which_includes_newline(line)
@@ -0,0 +1,351 @@
+use super::{
+	config::{FeedConfig, TaxonomyConfig},
+	content::{Content, Entry},
+	create_dir_all,
+	feeds::{is_in_feeds, write_feed},
+	pagination::{newest_first, paginate, Paginator, DEFAULT_PAGINATE_PATH},
+	render_page,
+};
+use crate::{
+	err,
+	error::{Error, Result},
+	utils::{catch_panic_async, slugify},
+};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+	cmp::Reverse,
+	collections::{BTreeMap, HashSet},
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+use tokio::task::JoinSet;
+use tracing::instrument;
+
+/// Templates of a taxonomy's pages default to these, under a directory named after the taxonomy.
+const LIST_TEMPLATE: &str = "list.html";
+const TERM_TEMPLATE: &str = "single.html";
+
+/// File name of each term's feed, e.g. `/tags/rust/feed.xml`.
+const TERM_FEED: &str = "feed.xml";
+
+/// A term of a taxonomy, e.g. the `rust` tag.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Term {
+	pub name: String,
+	pub slug: String,
+	pub taxonomy: String,
+	/// Number of entries with the term.
+	pub count: usize,
+	/// URL of the term page, if the taxonomy's pages are rendered.
+	pub url: Option<String>,
+	/// URL of the term's feed, if the taxonomy has feeds.
+	pub feed_url: Option<String>,
+	/// Paths of the entries with the term, sorted.
+	pub entries: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TermOrder {
+	/// Alphabetical, ignoring case.
+	#[default]
+	Name,
+	/// Most entries first, then alphabetical.
+	Count,
+}
+
+/// Terms of `taxonomy`, or `None` if the taxonomy isn't configured.
+///
+/// Protected entries are left out of the terms, as their pages would list them, and terms which
+/// only protected entries have are left out.
+#[inline]
+pub(crate) fn terms(content: &Content, taxonomy: &str, order: TermOrder) -> Option<Vec<Term>> {
+	listed_terms(content, taxonomy, order, |_| false)
+}
+
+/// Terms of `taxonomy` with the entries which are listed, which are those that aren't protected,
+/// or that `is_listed` lists anyway.
+#[inline]
+fn listed_terms(
+	content: &Content,
+	taxonomy: &str,
+	order: TermOrder,
+	is_listed: impl Fn(&Path) -> bool,
+) -> Option<Vec<Term>> {
+	let terms = content.taxonomies.get(taxonomy)?;
+	let taxonomy_config = content.config.taxonomies.get(taxonomy);
+	let base_url = &content.config.base_url;
+
+	let mut terms = terms
+		.iter()
+		.filter_map(|term| {
+			let mut entries = term
+				.value()
+				.iter()
+				.filter(|path| {
+					is_listed(path)
+						|| content
+							.entries
+							.get(*path)
+							.is_none_or(|entry| !entry.protected)
+				})
+				.cloned()
+				.collect::<Vec<_>>();
+			if entries.is_empty() {
+				return None;
+			}
+			entries.sort();
+			entries.dedup();
+
+			let slug = slugify(term.key());
+			let url = format!("{base_url}{taxonomy}/{slug}/");
+
+			Some(Term {
+				name: term.key().clone(),
+				taxonomy: taxonomy.to_string(),
+				count: entries.len(),
+				feed_url: taxonomy_config
+					.filter(|taxonomy_config| taxonomy_config.feed)
+					.map(|_| format!("{url}{TERM_FEED}")),
+				url: taxonomy_config
+					.filter(|taxonomy_config| taxonomy_config.render)
+					.map(|_| url),
+				slug,
+				entries,
+			})
+		})
+		.collect::<Vec<_>>();
+
+	terms.sort_by(|a, b| {
+		let by_name = a
+			.name
+			.to_lowercase()
+			.cmp(&b.name.to_lowercase())
+			.then_with(|| a.name.cmp(&b.name));
+
+		match order {
+			TermOrder::Name => by_name,
+			TermOrder::Count => b.count.cmp(&a.count).then(by_name),
+		}
+	});
+
+	Some(terms)
+}
+
+/// Terms of each taxonomy which the entry at `path` has, sorted by name. The terms of a protected
+/// entry list it, so that its own page has them.
+#[inline]
+pub(crate) fn entry_terms(content: &Content, path: &Path) -> BTreeMap<String, Vec<Term>> {
+	let taxonomies = content
+		.taxonomies
+		.iter()
+		.map(|taxonomy| taxonomy.key().clone())
+		.collect::<Vec<_>>();
+
+	taxonomies
+		.into_iter()
+		.map(|taxonomy| {
+			let entry_terms =
+				listed_terms(content, &taxonomy, TermOrder::Name, |other| other == path)
+					.unwrap_or_default()
+					.into_iter()
+					.filter(|term| term.entries.iter().any(|entry| entry == path))
+					.collect::<Vec<_>>();
+
+			(taxonomy, entry_terms)
+		})
+		.collect()
+}
+
+/// Renders the pages and feeds of each taxonomy which has them enabled.
+///
+/// List pages get the taxonomy's `terms`, and term pages get the `term` and, when paginated, a
+/// `paginator` of its entries.
+#[instrument(skip_all)]
+pub(crate) fn generate_taxonomies(
+	content: &Arc<Content>,
+	join_set: &mut JoinSet<Result<()>>,
+) -> Result<()> {
+	for (taxonomy, taxonomy_config) in &content.config.taxonomies {
+		if !taxonomy_config.render && !taxonomy_config.feed {
+			continue;
+		}
+
+		let terms = terms(content, taxonomy, TermOrder::Name).unwrap_or_default();
+
+		let mut slugs = HashSet::new();
+		if let Some(term) = terms.iter().find(|term| !slugs.insert(&term.slug)) {
+			return Err(err!(Validation(format!(
+				"more than one term of `{taxonomy}` has the slug `{}`",
+				term.slug
+			))));
+		}
+
+		if taxonomy_config.render {
+			render_taxonomy_pages(content, taxonomy, taxonomy_config, &terms, join_set)?;
+		}
+
+		if taxonomy_config.feed {
+			for term in &terms {
+				write_term_feed(content, taxonomy_config, term, join_set)?;
+			}
+		}
+	}
+
+	Ok(())
+}
+
+#[inline]
+fn render_taxonomy_pages(
+	content: &Arc<Content>,
+	taxonomy: &str,
+	taxonomy_config: &TaxonomyConfig,
+	terms: &[Term],
+	join_set: &mut JoinSet<Result<()>>,
+) -> Result<()> {
+	let list_template = taxonomy_config
+		.list_template
+		.clone()
+		.unwrap_or_else(|| format!("{taxonomy}/{LIST_TEMPLATE}"));
+	spawn_page(
+		content,
+		Path::new(taxonomy).join("index.html"),
+		list_template,
+		json!({ "taxonomy": taxonomy, "terms": terms }),
+		join_set,
+	)?;
+
+	let term_template = taxonomy_config
+		.term_template
+		.clone()
+		.unwrap_or_else(|| format!("{taxonomy}/{TERM_TEMPLATE}"));
+
+	for term in terms {
+		for (file_path, paginator) in term_pages(content, taxonomy, taxonomy_config, term)? {
+			spawn_page(
+				content,
+				file_path,
+				term_template.clone(),
+				json!({ "taxonomy": taxonomy, "term": term, "paginator": paginator }),
+				join_set,
+			)?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Files of the pages of a term, relative to `out_dir`, with their paginators when the term's
+/// entries are paginated.
+#[inline]
+fn term_pages(
+	content: &Content,
+	taxonomy: &str,
+	taxonomy_config: &TaxonomyConfig,
+	term: &Term,
+) -> Result<Vec<(PathBuf, Option<Paginator>)>> {
+	let term_path = Path::new(taxonomy).join(&term.slug);
+	let paginate_path = taxonomy_config
+		.paginate_path
+		.as_deref()
+		.unwrap_or(DEFAULT_PAGINATE_PATH);
+
+	Ok(match taxonomy_config.paginate_by {
+		Some(paginate_by) => paginate(
+			&newest_first(content, term.entries.iter()),
+			paginate_by,
+			&term_path,
+			paginate_path,
+			&content.config.base_url,
+		)?
+		.into_iter()
+		.map(|paginator| (paginator.file_path.clone(), Some(paginator)))
+		.collect(),
+		None => vec![(term_path.join("index.html"), None)],
+	})
+}
+
+/// Files of the pages and feeds which [`generate_taxonomies`] writes, relative to `out_dir`, so
+/// that they can be checked for conflicts before anything is written.
+#[inline]
+pub(crate) fn output_paths(content: &Content) -> Result<Vec<PathBuf>> {
+	let mut paths = vec![];
+	for (taxonomy, taxonomy_config) in &content.config.taxonomies {
+		if !taxonomy_config.render && !taxonomy_config.feed {
+			continue;
+		}
+
+		if taxonomy_config.render {
+			paths.push(Path::new(taxonomy).join("index.html"));
+		}
+		for term in terms(content, taxonomy, TermOrder::Name).unwrap_or_default() {
+			if taxonomy_config.render {
+				for (file_path, _) in term_pages(content, taxonomy, taxonomy_config, &term)? {
+					paths.push(file_path);
+				}
+			}
+			if taxonomy_config.feed {
+				paths.push(Path::new(taxonomy).join(&term.slug).join(TERM_FEED));
+			}
+		}
+	}
+
+	Ok(paths)
+}
+
+/// Renders `template_name` to `file_path` in `out_dir`, with `data` in its context.
+#[inline]
+pub(crate) fn spawn_page(
+	content: &Arc<Content>,
+	file_path: PathBuf,
+	template_name: String,
+	data: serde_json::Value,
+	join_set: &mut JoinSet<Result<()>>,
+) -> Result<()> {
+	let config = &content.config;
+
+	if let Some(parent) = file_path.parent() {
+		create_dir_all(&config.out_dir, parent)?;
+	}
+
+	join_set.spawn(catch_panic_async(
+		file_path.clone(),
+		render_page(file_path, template_name, data, config.clone()),
+	));
+
+	Ok(())
+}
+
+/// Writes a feed of the term's dated entries whose kinds are in feeds, newest first.
+#[inline]
+fn write_term_feed(
+	content: &Content,
+	taxonomy_config: &TaxonomyConfig,
+	term: &Term,
+	join_set: &mut JoinSet<Result<()>>,
+) -> Result<()> {
+	let mut entries = term
+		.entries
+		.iter()
+		.filter_map(|path| content.entries.get(path).map(|entry| entry.value().clone()))
+		.filter(|entry| entry.published.is_some() && is_in_feeds(entry, content))
+		.collect::<Vec<Entry>>();
+	entries.sort_by_key(|entry| Reverse(entry.published));
+
+	if let Some(limit) = taxonomy_config.feed_limit {
+		entries.truncate(limit);
+	}
+
+	let feed_config = FeedConfig {
+		path: Path::new(&term.taxonomy).join(&term.slug).join(TERM_FEED),
+		title: term.name.clone(),
+		description: None,
+		language: None,
+		section: None,
+		limit: taxonomy_config.feed_limit,
+		podcast: None,
+	};
+
+	write_feed(&feed_config, &entries, &content.config, join_set)
+}
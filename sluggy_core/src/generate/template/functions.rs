@@ -1,38 +1,247 @@
 use base64::prelude::*;
+use dashmap::DashMap;
 use futures::executor::block_on;
-use imageless::{ImageOutputFormat, Operation};
-use serde::de::DeserializeOwned;
-use serde_json::Value;
-use sha2::{Digest, Sha256};
+#[cfg(feature = "images")]
+use imageless::Operation;
+#[cfg(feature = "http-loader")]
+use reqwest::Url;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
 use std::{
-	collections::HashMap,
-	fmt::Write,
-	fs::{self, File as FsFile},
-	io::BufWriter,
-	path::PathBuf,
-	str::FromStr,
-	sync::Arc,
+	cmp::Ordering,
+	collections::{BTreeMap, HashMap},
+	fs,
+	path::{Path, PathBuf},
+	sync::{Arc, OnceLock},
 };
 use tera::{Filter, Function, Tera};
+use thiserror::Error;
+#[cfg(feature = "http-loader")]
+use tokio::{runtime::Handle, task::block_in_place};
+use toml::Value as TomlValue;
 
+use super::render_fragment;
+#[cfg(feature = "sanitize")]
+use crate::generate::config::SanitizeConfig;
+#[cfg(feature = "images")]
+use crate::generate::images::{set_current_processor, ImageProcessor, ImageSource};
+#[cfg(feature = "http-loader")]
 use crate::generate::{
-	content::{Content, Entry},
-	sections::SectionHandle,
-	Image,
+	data::parse_data,
+	http_cache::{self, is_allowed_url},
 };
+#[cfg(feature = "media")]
+use crate::generate::{media::Media, paths::resolve_path};
+use crate::{
+	encoding::ContentEncoding,
+	generate::{
+		archives::archives,
+		blogroll::blogroll,
+		compressed_path,
+		content::{lookup::EntryLookup, render_markdown, Content, Entry},
+		data::{read_data, resolve_data_path, DataFormat},
+		fingerprint::{asset_url, resolve as resolve_fingerprint},
+		include::{extract_region, read_source, LineRange},
+		pagination::DEFAULT_PAGINATE_PATH,
+		sections::{Listing, SectionHandle},
+		syntect::highlight_code_block,
+		taxonomies::{entry_terms, terms, TermOrder},
+		wait_for_asset,
+	},
+	lazyfn::LazyFn,
+	utils::hex_digest,
+};
+
+/// Sanitizers of each `sanitize` config, which are built on first use. They borrow the allowlists
+/// of their config, so each config is kept for the life of the process.
+#[cfg(feature = "sanitize")]
+static SANITIZERS: LazyFn<DashMap<SanitizeConfig, &'static ammonia::Builder<'static>>> =
+	LazyFn::new(DashMap::new);
+
+/// Cached fragments are persisted in this directory under `cache_dir`.
+pub(super) const FRAGMENTS_CACHE_DIR: &str = "fragments";
+
+/// Fragments which `cache` has rendered or read since the build started, by digest.
+pub(super) static FRAGMENTS: LazyFn<DashMap<String, String>> = LazyFn::new(DashMap::new);
+
+/// Options of the build, and features sluggy was built with, which templates can check with
+/// `feature`.
+const FEATURES: &[&str] = &[
+	"drafts",
+	"future",
+	"minify",
+	"live_reload",
+	"fingerprint",
+	"compress",
+	"sitemap",
+	"check_links",
+	"http-loader",
+	"css",
+	"syntax-highlighting",
+	"images",
+	"media",
+	"encryption",
+	"sanitize",
+	"data-formats",
+];
 
 pub(super) fn register_builtin_functions(tera: &mut Tera, content: &Arc<Content>) {
 	tera.register_function(
 		"render_content",
 		make_render_content_fn(Arc::clone(content)),
 	);
+	tera.register_function("content_ast", make_content_ast_fn(Arc::clone(content)));
 	tera.register_function("sections", make_sections_fn(Arc::clone(content)));
+	tera.register_function("entries", make_entries_fn(Arc::clone(content)));
+	tera.register_function("env", make_env_fn(Arc::clone(content)));
+	tera.register_function("feature", make_feature_fn(Arc::clone(content)));
+	tera.register_function("is_serve_mode", make_is_serve_mode_fn(Arc::clone(content)));
 	tera.register_function("cr", carriage_return);
 	tera.register_function("lb", line_break);
-	tera.register_function("image", make_image_fn(Arc::clone(content)));
+	#[cfg(feature = "images")]
+	{
+		// Shared between `image` and `gallery` so that each variant is only processed once per
+		// build.
+		let image_processor = Arc::new(ImageProcessor::new(content.config.clone()));
+		set_current_processor(Some(Arc::clone(&image_processor)));
+
+		tera.register_function(
+			"image",
+			make_image_fn(Arc::clone(content), Arc::clone(&image_processor)),
+		);
+		tera.register_function(
+			"gallery",
+			make_gallery_fn(Arc::clone(content), Arc::clone(&image_processor)),
+		);
+	}
+	#[cfg(not(feature = "images"))]
+	{
+		tera.register_function("image", make_unavailable_fn("image", "images"));
+		tera.register_function("gallery", make_unavailable_fn("gallery", "images"));
+	}
+	#[cfg(feature = "media")]
+	tera.register_function("media", make_media_fn(Arc::clone(content)));
+	#[cfg(not(feature = "media"))]
+	tera.register_function("media", make_unavailable_fn("media", "media"));
 	tera.register_function("base64", make_base64_fn(Arc::clone(content)));
+	tera.register_function("asset_url", make_asset_url_fn(Arc::clone(content)));
+	tera.register_function("asset_info", make_asset_info_fn(Arc::clone(content)));
+	tera.register_function("include_code", make_include_code_fn(Arc::clone(content)));
+	tera.register_function("cache", make_cache_fn(Arc::clone(content)));
+	tera.register_function("load_data", make_load_data_fn(Arc::clone(content)));
+	#[cfg(feature = "http-loader")]
+	tera.register_function("fetch", make_fetch_fn(Arc::clone(content)));
+	#[cfg(not(feature = "http-loader"))]
+	tera.register_function("fetch", make_unavailable_fn("fetch", "http-loader"));
+	tera.register_function(
+		"taxonomy_terms",
+		make_taxonomy_terms_fn(Arc::clone(content)),
+	);
+	tera.register_function("entry_terms", make_entry_terms_fn(Arc::clone(content)));
+	tera.register_function("taxonomies", make_taxonomies_fn(Arc::clone(content)));
+	tera.register_function("taxonomy", make_taxonomy_fn(Arc::clone(content)));
+	tera.register_function("related", make_related_fn(Arc::clone(content)));
+	tera.register_function("blogroll", blogroll_fn);
+	tera.register_function("archives", make_archives_fn(Arc::clone(content)));
+	tera.register_function("paginate", paginate_fn);
 
 	tera.register_filter("entry", make_entry_filter(Arc::clone(content)));
+	tera.register_filter("markdown", make_markdown_filter(Arc::clone(content)));
+	#[cfg(feature = "sanitize")]
+	tera.register_filter("sanitize", make_sanitize_filter(Arc::clone(content)));
+	#[cfg(not(feature = "sanitize"))]
+	tera.register_filter("sanitize", make_unavailable_filter("sanitize", "sanitize"));
+}
+
+/// Errors raised by template functions and filters.
+#[derive(Debug, Error)]
+pub(crate) enum FunctionError {
+	#[error("`{function}`: missing argument `{name}`")]
+	MissingArg {
+		function: &'static str,
+		name: &'static str,
+	},
+	#[error("`{function}`: invalid argument `{name}` ({value}): {message}")]
+	InvalidArg {
+		function: &'static str,
+		name: &'static str,
+		/// The argument's value, as JSON.
+		value: String,
+		message: String,
+	},
+	#[error("`{function}`: {message}")]
+	InvalidInput {
+		function: &'static str,
+		message: String,
+	},
+	#[error("`{function}`: {message}")]
+	Failed {
+		function: &'static str,
+		message: String,
+	},
+}
+
+impl From<FunctionError> for tera::Error {
+	fn from(error: FunctionError) -> Self {
+		tera::Error::msg(error.to_string())
+	}
+}
+
+type FunctionResult<T> = Result<T, FunctionError>;
+
+/// Named arguments passed to a template function.
+struct Args<'a> {
+	function: &'static str,
+	args: &'a HashMap<String, Value>,
+}
+
+impl<'a> Args<'a> {
+	#[inline]
+	fn new(function: &'static str, args: &'a HashMap<String, Value>) -> Self {
+		Self { function, args }
+	}
+
+	#[inline]
+	fn is_empty(&self) -> bool {
+		self.args.is_empty()
+	}
+
+	#[inline]
+	fn required<T: DeserializeOwned>(&self, name: &'static str) -> FunctionResult<T> {
+		self.optional(name)?.ok_or(FunctionError::MissingArg {
+			function: self.function,
+			name,
+		})
+	}
+
+	#[inline]
+	fn optional<T: DeserializeOwned>(&self, name: &'static str) -> FunctionResult<Option<T>> {
+		match self.args.get(name) {
+			Some(value) => serde_json::from_value::<T>(value.clone())
+				.map(Some)
+				.map_err(|error| FunctionError::InvalidArg {
+					function: self.function,
+					name,
+					value: value.to_string(),
+					message: error.to_string(),
+				}),
+			None => Ok(None),
+		}
+	}
+
+	#[inline]
+	fn failed(&self, message: String) -> FunctionError {
+		FunctionError::Failed {
+			function: self.function,
+			message,
+		}
+	}
+
+	#[inline]
+	fn to_value(&self, value: impl Serialize) -> FunctionResult<Value> {
+		serde_json::to_value(value)
+			.map_err(|error| self.failed(format!("failed to serialize result: {error}")))
+	}
 }
 
 #[inline]
@@ -45,84 +254,199 @@ fn line_break(_args: &HashMap<String, Value>) -> tera::Result<Value> {
 	Ok(Value::String("\n".into()))
 }
 
-fn make_image_fn(content: Arc<Content>) -> impl Function {
+/// Value of the environment variable `name`, which has to be one of `template_env`, or `default`
+/// when it isn't set.
+fn make_env_fn(content: Arc<Content>) -> impl Function {
 	Box::new(
 		#[inline]
 		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
-			let in_path: String = get_arg("in", args)?;
-			let in_path = PathBuf::from(in_path);
+			let args = Args::new("env", args);
+			let name: String = args.required("name")?;
+			let default: Option<Value> = args.optional("default")?;
 
-			let out_format: ImageOutputFormat = get_arg("format", args)?;
-			let operations: Vec<Operation> = get_arg("operations", args)?;
+			if !content.config.template_env.contains(&name) {
+				return Err(args
+					.failed(format!("`{name}` is not one of `template_env`"))
+					.into());
+			}
 
-			let filename = in_path.file_name().ok_or(tera::Error::msg(format!(
-				"Could not get filename from path: {}",
-				in_path.display()
-			)))?;
+			Ok(match std::env::var(&name) {
+				Ok(value) => Value::String(value),
+				Err(_) => default.unwrap_or(Value::Null),
+			})
+		},
+	)
+}
 
+/// Whether the build option, e.g. `drafts` or `minify`, or the feature of sluggy, e.g. `images`,
+/// called `name` is enabled.
+fn make_feature_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("feature", args);
+			let name: String = args.required("name")?;
 			let config = &content.config;
 
-			let mut hasher = Sha256::default();
-			hasher.update(serde_json::to_string(&operations).unwrap());
-			let hash = hasher.finalize();
-			let mut hex = String::with_capacity(hash.len() * 2);
-			if let Err(error) = write!(hex, "{:x}", hash) {
-				return Err(tera::Error::msg(format!(
-					"Could not write hash to string: {}",
-					error
-				)));
-			}
+			let enabled = match name.as_str() {
+				// Entries published in the future are generated along with drafts.
+				"drafts" | "future" => config.drafts,
+				"minify" => config.minify,
+				"live_reload" => config.live_reload,
+				"fingerprint" => config.fingerprint.enabled,
+				"compress" => config.compress_content,
+				"sitemap" => config.sitemap,
+				"check_links" => config.check_links,
+				"http-loader" => cfg!(feature = "http-loader"),
+				"css" => cfg!(feature = "css"),
+				"syntax-highlighting" => cfg!(feature = "syntax-highlighting"),
+				"images" => cfg!(feature = "images"),
+				"media" => cfg!(feature = "media"),
+				"encryption" => cfg!(feature = "encryption"),
+				"sanitize" => cfg!(feature = "sanitize"),
+				"data-formats" => cfg!(feature = "data-formats"),
+				_ => {
+					return Err(args
+						.failed(format!(
+							"unknown feature `{name}`, expected one of {FEATURES:?}"
+						))
+						.into())
+				}
+			};
 
-			let out_path = PathBuf::from(filename)
-				.join(hex)
-				.with_extension(out_format.extension());
-
-			let full_out_path = config
-				.out_dir
-				.join(&config.processed_images_dir)
-				.join(out_path);
-
-			// TODO make less duplicated
-			if let Err(error) = fs::create_dir_all(
-				config
-					.out_dir
-					.join(&config.processed_images_dir)
-					.join(filename),
-			) {
-				return Err(tera::Error::msg(format!(
-					"Could not create directory: {}",
-					error
-				)));
-			}
+			Ok(Value::Bool(enabled))
+		},
+	)
+}
 
-			match imageless::process_file(config.assets_dir.join(&in_path), operations) {
-				Ok(image) => {
-					let out_file = FsFile::create(&full_out_path)?;
-					let mut out_buf = BufWriter::new(out_file);
-					if let Err(error) = image.write_to(&mut out_buf, out_format) {
-						return Err(tera::Error::msg(format!(
-							"Unable to write image: {}",
-							error
-						)));
-					}
+/// Whether the site is being served by `sluggy serve`, rather than built for publishing.
+fn make_is_serve_mode_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |_args: &HashMap<String, Value>| -> tera::Result<Value> {
+			Ok(Value::Bool(content.config.serve_mode))
+		},
+	)
+}
+
+/// Stands in for a function which was left out of the build, so that templates which call it fail
+/// with a clear message.
+#[cfg(any(
+	not(feature = "images"),
+	not(feature = "http-loader"),
+	not(feature = "media")
+))]
+fn make_unavailable_fn(name: &'static str, feature: &'static str) -> impl Function {
+	Box::new(
+		#[inline]
+		move |_args: &HashMap<String, Value>| -> tera::Result<Value> {
+			Err(tera::Error::msg(format!(
+				"`{name}` requires sluggy to be built with the `{feature}` feature"
+			)))
+		},
+	)
+}
 
-					let image = Image::new(
-						config,
-						image.width(),
-						image.height(),
-						full_out_path,
-						in_path,
-					);
+/// Stands in for a filter which was left out of the build, like [`make_unavailable_fn`].
+#[cfg(not(feature = "sanitize"))]
+fn make_unavailable_filter(name: &'static str, feature: &'static str) -> impl Filter {
+	Box::new(
+		#[inline]
+		move |_value: &Value, _args: &HashMap<String, Value>| -> tera::Result<Value> {
+			Err(tera::Error::msg(format!(
+				"`{name}` requires sluggy to be built with the `{feature}` feature"
+			)))
+		},
+	)
+}
 
-					serde_json::to_value(image).map_err(|error| {
-						tera::Error::msg(format!("Could not serialize image: {}", error))
-					})
+#[cfg(feature = "images")]
+fn make_image_fn(content: Arc<Content>, image_processor: Arc<ImageProcessor>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("image", args);
+
+			let source: String = args.required("in")?;
+			let relative_to: Option<PathBuf> = args.optional("relative_to")?;
+			let preset: Option<String> = args.optional("preset")?;
+
+			let (out_format, operations) = match preset {
+				Some(preset) => {
+					let (preset_format, mut operations) = image_processor
+						.preset(&preset)
+						.map_err(|error| args.failed(format!("{error}")))?;
+
+					let out_format = match args.optional("format")?.or(preset_format) {
+						Some(out_format) => out_format,
+						None => args.required("format")?,
+					};
+					let extra_operations: Option<Vec<Operation>> = args.optional("operations")?;
+					operations.extend(extra_operations.unwrap_or_default());
+
+					(out_format, operations)
 				}
-				Err(error) => Err(tera::Error::msg(format!(
-					"Failed to process image: {}",
-					error
-				))),
+				None => (args.required("format")?, args.required("operations")?),
+			};
+
+			let source = ImageSource::resolve(&source, relative_to.as_deref(), &content.config)
+				.map_err(|error| args.failed(format!("{error}")))?;
+
+			let image = image_processor
+				.process(source, out_format, operations)
+				.map_err(|error| args.failed(format!("{error}")))?;
+
+			Ok(args.to_value(image)?)
+		},
+	)
+}
+
+#[cfg(feature = "images")]
+fn make_gallery_fn(content: Arc<Content>, image_processor: Arc<ImageProcessor>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("gallery", args);
+
+			let dir: String = args.required("dir")?;
+			let relative_to: Option<PathBuf> = args.optional("relative_to")?;
+
+			let sources = ImageSource::resolve_dir(&dir, relative_to.as_deref(), &content.config)
+				.map_err(|error| args.failed(format!("{error}")))?;
+
+			let gallery = image_processor
+				.gallery(sources)
+				.map_err(|error| args.failed(format!("{error}")))?;
+
+			Ok(args.to_value(gallery)?)
+		},
+	)
+}
+
+#[cfg(feature = "media")]
+fn make_media_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("media", args);
+
+			let path: String = args.required("path")?;
+			let relative_to: Option<PathBuf> = args.optional("relative_to")?;
+			let poster: bool = args.optional("poster")?.unwrap_or(false);
+
+			let path = resolve_path(&path, relative_to.as_deref(), &content.config)
+				.map_err(|error| args.failed(format!("{error}")))?;
+
+			let mut media = Media::read(path, &content.config)
+				.map_err(|error| args.failed(format!("{error}")))?;
+
+			if poster {
+				media
+					.generate_poster(&content.config)
+					.map_err(|error| args.failed(format!("{error}")))?;
 			}
+
+			Ok(args.to_value(media)?)
 		},
 	)
 }
@@ -131,12 +455,14 @@ fn make_base64_fn(_content: Arc<Content>) -> impl Function {
 	Box::new(
 		#[inline]
 		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
-			let encoded = match get_arg::<String>("value", args).ok() {
+			let args = Args::new("base64", args);
+
+			let encoded = match args.optional::<String>("value")? {
 				Some(value) => BASE64_STANDARD.encode(value.as_bytes()),
 				None => {
-					let file = get_arg::<PathBuf>("file", args)?;
+					let file: PathBuf = args.required("file")?;
 					let contents = fs::read(&file).map_err(|error| {
-						tera::Error::msg(format!("Failed to read file: {file:?}: {error}",))
+						args.failed(format!("failed to read file {}: {error}", file.display()))
 					})?;
 					BASE64_STANDARD.encode(contents)
 				}
@@ -147,132 +473,1708 @@ fn make_base64_fn(_content: Arc<Content>) -> impl Function {
 	)
 }
 
-fn get_arg<T: DeserializeOwned>(name: &str, args: &HashMap<String, Value>) -> tera::Result<T> {
-	match args.get(name) {
-		Some(value) => serde_json::from_value::<T>(value.clone()).map_err(|error| {
-			let as_str = value.as_str().unwrap();
-			tera::Error::msg(format!(
-				"Invalid arg '{name}': {value:?} ({as_str}): {error:?}",
-			))
-		}),
-		_ => Err(tera::Error::msg(format!("Missing arg '{name}'"))),
-	}
+/// URL of a file of `out_dir`, e.g. `css/main.css`, with its fingerprinted name if it has one.
+fn make_asset_url_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("asset_url", args);
+
+			let path: String = args.required("path")?;
+			let path = path.strip_prefix("@/").unwrap_or(&path);
+
+			Ok(Value::String(asset_url(path, &content.config)))
+		},
+	)
 }
 
-fn make_render_content_fn(content: Arc<Content>) -> impl Function {
+/// Size in bytes of a file of `out_dir`, e.g. `css/main.css`, and of each of its compressed
+/// variants. Variants are `null` when the file isn't compressed in that encoding.
+fn make_asset_info_fn(content: Arc<Content>) -> impl Function {
 	Box::new(
 		#[inline]
 		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
-			let content = content.clone();
-			if let Some(path) = args.get("path") {
-				match path.as_str() {
-					Some(path) => block_on(async move {
-						let path = match PathBuf::from_str(path) {
-							Ok(path) => path,
-							Err(_) => return Err(tera::Error::msg("Invalid value for `path`")),
-						};
-
-						match Entry::render_by_path(&path, content).await {
-							Ok(content) => match content {
-								Some(content) => Ok(Value::String(content)),
-								None => Ok(Value::Null),
-							},
-							Err(error) => Err(tera::Error::msg(format!(
-								"Failed to generate content: {error:?}"
-							))),
-						}
-					}),
-					None => Err(tera::Error::msg("`path` param must be a string")),
-				}
-			} else {
-				Err(tera::Error::msg("Missing `path` param"))
+			let args = Args::new("asset_info", args);
+
+			let path: String = args.required("path")?;
+			let path = path.strip_prefix("@/").unwrap_or(&path);
+			let config = &content.config;
+
+			let name = resolve_fingerprint(Path::new(path), config);
+			// Static files are processed while entries are rendered.
+			wait_for_asset(&config.out_dir.join(&name));
+			let size = fs::metadata(config.out_dir.join(&name))
+				.map_err(|error| args.failed(format!("failed to read asset {path}: {error}")))?
+				.len();
+			let compressed_size = |content_encoding: ContentEncoding| {
+				fs::metadata(compressed_path(&name, &content_encoding, config))
+					.ok()
+					.filter(|_| config.compress_content)
+					.map(|metadata| metadata.len())
+			};
+
+			Ok(json!({
+				"path": name,
+				"url": asset_url(path, config),
+				"size": size,
+				"brotli": compressed_size(ContentEncoding::Brotli),
+				"gzip": compressed_size(ContentEncoding::Gzip),
+				"deflate": compressed_size(ContentEncoding::Deflate),
+			}))
+		},
+	)
+}
+
+fn make_include_code_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("include_code", args);
+
+			let path: String = args.required("path")?;
+			let relative_to: Option<PathBuf> = args.optional("relative_to")?;
+			let lines = args
+				.optional::<String>("lines")?
+				.map(|lines| lines.parse::<LineRange>())
+				.transpose()
+				.map_err(|error| args.failed(format!("{error}")))?;
+			let anchor: Option<String> = args.optional("anchor")?;
+			let lang: Option<String> = args.optional("lang")?;
+
+			let (path, source) = read_source(&path, relative_to.as_deref(), &content.config)
+				.map_err(|error| args.failed(format!("{error}")))?;
+
+			let code = extract_region(&source, lines, anchor.as_deref())
+				.map_err(|error| args.failed(format!("{}: {error}", path.display())))?;
+
+			let extension = path.extension().and_then(|extension| extension.to_str());
+			Ok(Value::String(highlight_code_block(
+				lang.as_deref(),
+				extension,
+				&code,
+			)))
+		},
+	)
+}
+
+fn make_load_data_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("load_data", args);
+
+			let path: String = args.required("path")?;
+			let format: Option<DataFormat> = args.optional("format")?;
+
+			let path = resolve_data_path(&path, &content.config)
+				.map_err(|error| args.failed(format!("{error}")))?;
+
+			Ok(read_data(&path, format).map_err(|error| args.failed(format!("{error}")))?)
+		},
+	)
+}
+
+/// Requests `url`, which must be on one of `fetch.allowed_domains`, and parses the response.
+#[cfg(feature = "http-loader")]
+fn make_fetch_fn(content: Arc<Content>) -> impl Function {
+	let client = http_cache::client(&content.config);
+
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("fetch", args);
+
+			let url: String = args.required("url")?;
+			let format: DataFormat = args.optional("format")?.unwrap_or(DataFormat::Json);
+
+			let url = Url::parse(&url)
+				.map_err(|error| args.failed(format!("invalid url {url}: {error}")))?;
+			if !is_allowed_url(&url, &content.config) {
+				return Err(args
+					.failed(format!("{url} is not on one of `fetch.allowed_domains`"))
+					.into());
 			}
+
+			let client = client
+				.as_ref()
+				.map_err(|error| args.failed(format!("{error}")))?;
+			let request = client
+				.get(url.clone())
+				.build()
+				.map_err(|error| args.failed(format!("failed to build request: {error}")))?;
+
+			let body = block_in_place(|| {
+				Handle::current().block_on(http_cache::execute(client, request, &content.config))
+			})
+			.map_err(|error| args.failed(format!("failed to fetch {url}: {error}")))?;
+
+			Ok(parse_data(&body, format, url.as_str())
+				.map_err(|error| args.failed(format!("{error}")))?)
 		},
 	)
 }
 
-fn make_entry_filter(content: Arc<Content>) -> impl Filter {
+/// Renders `template` once and reuses the output, both within a build and between builds.
+///
+/// The output is invalidated when the key, the template source, `deps`, any of the other arguments
+/// (which are passed to the template), the site config or any entry changes. Templates included by
+/// the fragment aren't tracked, so changes to them should be reflected in `deps`.
+fn make_cache_fn(content: Arc<Content>) -> impl Function {
+	let content_digest = OnceLock::<String>::new();
+
 	Box::new(
 		#[inline]
-		move |value: &Value, _args: &HashMap<String, Value>| -> tera::Result<Value> {
-			let content = content.clone();
-
-			// TODO make this fn less duplicatedededed
-
-			if let Some(paths) = value.as_array() {
-				let mut array = vec![];
-				for path in paths {
-					match path.as_str() {
-						Some(path) => {
-							let path = match PathBuf::from_str(path) {
-								Ok(path) => path,
-								Err(_) => return Err(tera::Error::msg("Invalid value for `path`")),
-							};
-
-							let entry = content
-								.entries
-								.get(&path)
-								.map(|entry| serde_json::to_value(entry.value()).unwrap())
-								.unwrap_or(Value::Null);
-
-							array.push(entry);
-						}
-						None => return Err(tera::Error::msg("each item must be a string")),
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("cache", args);
+
+			let key: String = args.required("key")?;
+			let template: String = args.required("template")?;
+			let deps: Option<Value> = args.optional("deps")?;
+			let context = args
+				.args
+				.iter()
+				.filter(|(name, _)| !["key", "template", "deps"].contains(&name.as_str()))
+				.collect::<BTreeMap<_, _>>();
+
+			let template_path = content.config.template_dir.join(&template);
+			let template_source = fs::read_to_string(&template_path).map_err(|error| {
+				args.failed(format!(
+					"failed to read template {}: {error}",
+					template_path.display()
+				))
+			})?;
+
+			if content_digest.get().is_none() {
+				let mut entries = content
+					.entries
+					.iter()
+					.map(|entry| entry.value().clone())
+					.collect::<Vec<_>>();
+				entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+				let data = serde_json::to_vec(&(&entries, &*content.config)).map_err(|error| {
+					args.failed(format!("failed to serialize content: {error}"))
+				})?;
+				let _ = content_digest.set(hex_digest(data));
+			}
+
+			let digest = hex_digest(
+				serde_json::to_vec(&json!({
+					"key": key,
+					"template": template_source,
+					"deps": deps,
+					"context": context,
+					"content": content_digest.get(),
+				}))
+				.map_err(|error| {
+					args.failed(format!("failed to serialize dependencies: {error}"))
+				})?,
+			);
+
+			if let Some(fragment) = FRAGMENTS.get(&digest) {
+				return Ok(Value::String(fragment.clone()));
+			}
+
+			let cache_path = content
+				.config
+				.cache_dir
+				.join(FRAGMENTS_CACHE_DIR)
+				.join(&digest)
+				.with_extension("html");
+
+			let fragment = match fs::read_to_string(&cache_path) {
+				Ok(fragment) => fragment,
+				Err(_) => {
+					let mut data = json!({
+						"site": *content.config,
+						"base": &content.config.base_url,
+					});
+					for (name, value) in context {
+						data[name.as_str()] = value.clone();
+					}
+
+					let fragment = render_fragment(&template, data)
+						.map_err(|error| args.failed(format!("`{key}`: {error}")))?;
+
+					if let Some(parent) = cache_path.parent() {
+						fs::create_dir_all(parent).map_err(|error| {
+							args.failed(format!(
+								"failed to create dirs for {}: {error}",
+								parent.display()
+							))
+						})?;
 					}
+					fs::write(&cache_path, &fragment).map_err(|error| {
+						args.failed(format!("failed to write {}: {error}", cache_path.display()))
+					})?;
+
+					fragment
 				}
+			};
 
-				Ok(Value::Array(array))
-			} else if let Some(path) = value.as_str() {
-				let path = match PathBuf::from_str(path) {
-					Ok(path) => path,
-					Err(_) => return Err(tera::Error::msg("Invalid value for `path`")),
-				};
+			FRAGMENTS.insert(digest, fragment.clone());
 
-				Ok(content
-					.entries
-					.get(&path)
-					.map(|entry| serde_json::to_value(entry.value()).unwrap())
-					.unwrap_or(Value::Null))
-			} else {
-				Err(tera::Error::msg(
-					"input value must be a string or an array of strings",
-				))
+			Ok(Value::String(fragment))
+		},
+	)
+}
+
+fn make_render_content_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("render_content", args);
+			let path: PathBuf = args.required("path")?;
+
+			// The content of protected entries is only readable on their own, encrypted, pages.
+			if content
+				.entries
+				.get(&path)
+				.is_some_and(|entry| entry.protected)
+			{
+				return Ok(Value::Null);
 			}
+
+			let rendered = block_on(Entry::render_by_path(&path, content.clone()))
+				.map_err(|error| args.failed(format!("failed to generate content: {error}")))?;
+
+			Ok(rendered.map(Value::String).unwrap_or(Value::Null))
 		},
 	)
 }
 
-fn make_sections_fn(content: Arc<Content>) -> impl Function {
+/// Top-level blocks of a markdown entry, e.g. to pull out its first image. `null` when there's no
+/// entry at `path`, or it's protected.
+fn make_content_ast_fn(content: Arc<Content>) -> impl Function {
 	Box::new(
 		#[inline]
 		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
-			let content = content.clone();
+			let args = Args::new("content_ast", args);
+			let path: PathBuf = args.required("path")?;
 
-			if args.is_empty() {
-				let list = content
-					.sections
-					.iter()
-					.map(|section| serde_json::to_value(section.value()).unwrap())
-					.collect();
-				Ok(Value::Array(list))
-			} else if let Some(handle) = args.get("handle") {
-				match handle.as_str() {
-					Some(handle) => {
-						let handle = SectionHandle::from(handle);
-
-						let result = Ok(content
-							.sections
-							.get(&handle)
-							.map(|section| serde_json::to_value(section.value()).unwrap())
-							.unwrap_or(Value::Null));
-
-						result
+			let Some(entry) = content.entries.get(&path).filter(|entry| !entry.protected) else {
+				return Ok(Value::Null);
+			};
+
+			let blocks = entry
+				.ast(&content.config)
+				.map_err(|error| args.failed(format!("failed to parse content: {error}")))?;
+
+			Ok(args.to_value(blocks)?)
+		},
+	)
+}
+
+/// Renders a markdown string the same way as the content of entries.
+///
+/// With `inline=true`, the paragraph which wraps text of a single paragraph is left out, e.g. for
+/// titles.
+fn make_markdown_filter(content: Arc<Content>) -> impl Filter {
+	Box::new(
+		#[inline]
+		move |value: &Value, args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("markdown", args);
+			let inline: bool = args.optional("inline")?.unwrap_or(false);
+
+			let markdown = match value {
+				Value::String(markdown) => markdown.as_str(),
+				Value::Null => "",
+				_ => {
+					return Err(FunctionError::InvalidInput {
+						function: "markdown",
+						message: format!("input value must be a string, got {value}"),
 					}
-					None => Err(tera::Error::msg("`handle` param must be a string")),
+					.into())
 				}
-			} else {
-				Err(tera::Error::msg("Missing `handle` param"))
+			};
+
+			let html = render_markdown(markdown, Path::new("markdown"), &content.config)
+				.map_err(|error| args.failed(format!("failed to render markdown: {error}")))?;
+
+			let html = match html
+				.trim_end()
+				.strip_prefix("<p>")
+				.and_then(|html| html.strip_suffix("</p>"))
+			{
+				Some(paragraph) if inline && !paragraph.contains("<p>") => paragraph.to_string(),
+				_ => html,
+			};
+
+			Ok(Value::String(html))
+		},
+	)
+}
+
+/// Removes whatever isn't on the allowlist of `sanitize` from HTML, e.g. scripts in comments which
+/// were loaded from other sites.
+#[cfg(feature = "sanitize")]
+fn make_sanitize_filter(content: Arc<Content>) -> impl Filter {
+	Box::new(
+		#[inline]
+		move |value: &Value, _args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let html = match value {
+				Value::String(html) => html.as_str(),
+				Value::Null => "",
+				_ => {
+					return Err(FunctionError::InvalidInput {
+						function: "sanitize",
+						message: format!("input value must be a string, got {value}"),
+					}
+					.into())
+				}
+			};
+
+			Ok(Value::String(sanitize_html(html, &content.config.sanitize)))
+		},
+	)
+}
+
+#[cfg(feature = "sanitize")]
+#[inline]
+fn sanitize_html(html: &str, sanitize: &SanitizeConfig) -> String {
+	let builder = *SANITIZERS.entry(sanitize.clone()).or_insert_with(|| {
+		let sanitize: &'static SanitizeConfig = Box::leak(Box::new(sanitize.clone()));
+		Box::leak(Box::new(sanitizer(sanitize)))
+	});
+
+	builder.clean(html).to_string()
+}
+
+#[cfg(feature = "sanitize")]
+#[inline]
+fn sanitizer(sanitize: &'static SanitizeConfig) -> ammonia::Builder<'static> {
+	let mut builder = ammonia::Builder::default();
+	if let Some(tags) = &sanitize.tags {
+		builder.tags(tags.iter().map(String::as_str).collect());
+	}
+	for (tag, attributes) in &sanitize.tag_attributes {
+		builder.add_tag_attributes(tag.as_str(), attributes.iter().map(String::as_str));
+	}
+	builder.add_generic_attributes(sanitize.generic_attributes.iter().map(String::as_str));
+	if let Some(url_schemes) = &sanitize.url_schemes {
+		builder.url_schemes(url_schemes.iter().map(String::as_str).collect());
+	}
+
+	// ammonia sets `rel` of links itself, unless it's allowed.
+	let allows_rel = sanitize.generic_attributes.iter().any(|name| name == "rel")
+		|| sanitize
+			.tag_attributes
+			.values()
+			.flatten()
+			.any(|name| name == "rel");
+	if allows_rel {
+		builder.link_rel(None);
+	}
+
+	builder
+}
+
+/// Looks up entries by path, slug or URL.
+///
+/// Fails when no entry matches, unless `optional=true` is passed, in which case it is `null`.
+fn make_entry_filter(content: Arc<Content>) -> impl Filter {
+	// Built on first use, once all entries have been loaded.
+	let entry_lookup = OnceLock::<EntryLookup>::new();
+
+	Box::new(
+		#[inline]
+		move |value: &Value, args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("entry", args);
+			let optional: bool = args.optional("optional")?.unwrap_or(false);
+			let entry_lookup = entry_lookup.get_or_init(|| EntryLookup::new(&content));
+
+			let as_path = |path: &Value| -> FunctionResult<String> {
+				path.as_str()
+					.map(str::to_string)
+					.ok_or_else(|| FunctionError::InvalidInput {
+						function: "entry",
+						message: format!("expected a path string, got {path}"),
+					})
+			};
+
+			let lookup = |path: String| -> FunctionResult<Value> {
+				let path = path.as_str();
+				let entry_path = match entry_lookup.resolve(path, &content) {
+					Ok(entry_path) => entry_path,
+					Err(crate::error::Error::NotFound(_)) if optional => return Ok(Value::Null),
+					Err(error) => return Err(args.failed(format!("{error}"))),
+				};
+
+				match content.entries.get(&entry_path) {
+					Some(entry) => {
+						serde_json::to_value(entry.value()).map_err(|error| FunctionError::Failed {
+							function: "entry",
+							message: format!("failed to serialize entry {path}: {error}"),
+						})
+					}
+					None => Ok(Value::Null),
+				}
+			};
+
+			match value {
+				// Every path is checked before any is resolved, so that invalid input is reported
+				// rather than the first entry which isn't found.
+				Value::Array(paths) => Ok(Value::Array(
+					paths
+						.iter()
+						.map(as_path)
+						.collect::<FunctionResult<Vec<_>>>()?
+						.into_iter()
+						.map(lookup)
+						.collect::<FunctionResult<_>>()?,
+				)),
+				Value::String(path) => Ok(lookup(path.clone())?),
+				_ => Err(FunctionError::InvalidInput {
+					function: "entry",
+					message: format!(
+						"input value must be a string or an array of strings, got {value}"
+					),
+				}
+				.into()),
 			}
 		},
 	)
 }
+
+fn make_taxonomy_terms_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("taxonomy_terms", args);
+
+			let taxonomy: String = args.required("taxonomy")?;
+			let order: TermOrder = args.optional("order")?.unwrap_or_default();
+			let limit: Option<usize> = args.optional("limit")?;
+
+			let mut terms = terms(&content, &taxonomy, order).ok_or_else(|| {
+				args.failed(format!(
+					"taxonomy `{taxonomy}` is not configured, expected one of {:?}",
+					content.config.taxonomies.keys().collect::<Vec<_>>()
+				))
+			})?;
+
+			if let Some(limit) = limit {
+				terms.truncate(limit);
+			}
+
+			Ok(args.to_value(terms)?)
+		},
+	)
+}
+
+/// Terms of each of the taxonomies, keyed on the taxonomy, e.g. for tag clouds.
+fn make_taxonomies_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("taxonomies", args);
+			let order: TermOrder = args.optional("order")?.unwrap_or_default();
+
+			let taxonomies = content
+				.taxonomies
+				.iter()
+				.map(|taxonomy| taxonomy.key().clone())
+				.collect::<Vec<_>>()
+				.into_iter()
+				.map(|taxonomy| {
+					let terms = terms(&content, &taxonomy, order).unwrap_or_default();
+					(taxonomy, terms)
+				})
+				.collect::<BTreeMap<_, _>>();
+
+			Ok(args.to_value(taxonomies)?)
+		},
+	)
+}
+
+/// A term of a taxonomy, by name or slug, with its entries newest first. `null` when no entry has
+/// the term.
+///
+/// `exclude` leaves out the entry at that path, and `limit` limits the entries, e.g. for related
+/// posts.
+fn make_taxonomy_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("taxonomy", args);
+
+			let taxonomy: String = args.required("name")?;
+			let term_name: String = args.required("term")?;
+			let exclude: Option<PathBuf> = args.optional("exclude")?;
+			let limit: Option<usize> = args.optional("limit")?;
+
+			let terms = terms(&content, &taxonomy, TermOrder::Name).ok_or_else(|| {
+				args.failed(format!(
+					"taxonomy `{taxonomy}` is not configured, expected one of {:?}",
+					content.config.taxonomies.keys().collect::<Vec<_>>()
+				))
+			})?;
+
+			let Some(term) = terms
+				.into_iter()
+				.find(|term| term.name == term_name || term.slug == term_name)
+			else {
+				return Ok(Value::Null);
+			};
+
+			let mut entries = term
+				.entries
+				.iter()
+				.filter(|path| exclude.as_ref() != Some(*path))
+				.filter_map(|path| content.entries.get(path).map(|entry| entry.value().clone()))
+				.collect::<Vec<_>>();
+			entries.sort_by(|a, b| {
+				b.published
+					.cmp(&a.published)
+					.then_with(|| a.path.cmp(&b.path))
+			});
+			if let Some(limit) = limit {
+				entries.truncate(limit);
+			}
+
+			let mut value = args.to_value(&term)?;
+			value["entries"] = args.to_value(entries)?;
+
+			Ok(value)
+		},
+	)
+}
+
+/// Other entries which share terms with the entry at `path`, those with the most shared terms
+/// first, then the newest, e.g. for "You may also like" sections.
+///
+/// All of the taxonomies are compared, unless `taxonomies` lists which are, and `limit` limits the
+/// entries, to 5 by default.
+fn make_related_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("related", args);
+
+			let path: PathBuf = args.required("path")?;
+			let only: Option<Vec<String>> = args.optional("taxonomies")?;
+			let limit: usize = args.optional("limit")?.unwrap_or(5);
+
+			let mut scores = HashMap::<PathBuf, usize>::new();
+			for taxonomy in content.taxonomies.iter() {
+				if let Some(only) = &only {
+					if !only.contains(taxonomy.key()) {
+						continue;
+					}
+				}
+
+				for term in taxonomy.value().iter() {
+					if !term.value().contains(&path) {
+						continue;
+					}
+					for other in term.value().iter().filter(|other| **other != path) {
+						*scores.entry(other.clone()).or_default() += 1;
+					}
+				}
+			}
+
+			let mut entries = scores
+				.into_iter()
+				.filter_map(|(path, score)| {
+					content
+						.entries
+						.get(&path)
+						.filter(|entry| !entry.protected)
+						.map(|entry| (score, entry.value().clone()))
+				})
+				.collect::<Vec<_>>();
+			entries.sort_by(|(a_score, a), (b_score, b)| {
+				b_score
+					.cmp(a_score)
+					.then_with(|| b.published.cmp(&a.published))
+					.then_with(|| a.path.cmp(&b.path))
+			});
+			entries.truncate(limit);
+
+			Ok(args.to_value(
+				entries
+					.into_iter()
+					.map(|(_, entry)| entry)
+					.collect::<Vec<_>>(),
+			)?)
+		},
+	)
+}
+
+/// The feeds of `blogroll.data`, in the order they're listed, or those of `category`.
+#[inline]
+fn blogroll_fn(args: &HashMap<String, Value>) -> tera::Result<Value> {
+	let args = Args::new("blogroll", args);
+	let category: Option<String> = args.optional("category")?;
+
+	let feeds = blogroll()
+		.into_iter()
+		.filter(|feed| category.is_none() || feed.category == category)
+		.collect::<Vec<_>>();
+
+	Ok(args.to_value(feeds)?)
+}
+
+/// Archives of each year with published entries, newest first, with the archives of their
+/// `months`, e.g. for a sidebar of links to the archive pages.
+fn make_archives_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("archives", args);
+			Ok(args.to_value(archives(&content))?)
+		},
+	)
+}
+
+/// Default number of items on each page of `paginate`.
+const DEFAULT_PER_PAGE: usize = 20;
+
+/// A page of the `items` passed to `paginate`.
+#[derive(Debug, Serialize)]
+struct ItemsPage {
+	/// Items on this page.
+	items: Vec<Value>,
+	/// 1-based number of this page.
+	current_page: usize,
+	total_pages: usize,
+	per_page: usize,
+	/// Number of items across all of the pages.
+	total_items: usize,
+	prev_page: Option<usize>,
+	next_page: Option<usize>,
+	/// URLs of the pages, when `url` is passed.
+	current_url: Option<String>,
+	first_url: Option<String>,
+	last_url: Option<String>,
+	prev_url: Option<String>,
+	next_url: Option<String>,
+}
+
+/// The `current` page, 1 by default, of `items` split into pages of `per_page`, e.g. for the
+/// pages of data which `generate_from` generates.
+///
+/// When `url` is the URL of the first page, the URLs of the others are
+/// `{url}{paginate_path}/{n}/`, like those of paginated sections.
+#[inline]
+fn paginate_fn(args: &HashMap<String, Value>) -> tera::Result<Value> {
+	let args = Args::new("paginate", args);
+	let items: Vec<Value> = args.required("items")?;
+	let per_page: usize = args.optional("per_page")?.unwrap_or(DEFAULT_PER_PAGE);
+	let current_page: usize = args.optional("current")?.unwrap_or(1);
+	let url: Option<String> = args.optional("url")?;
+	let paginate_path: String = args
+		.optional("paginate_path")?
+		.unwrap_or_else(|| DEFAULT_PAGINATE_PATH.to_string());
+
+	if per_page == 0 {
+		return Err(args
+			.failed("`per_page` must be greater than 0".into())
+			.into());
+	}
+
+	// There is always at least 1 page, even if it has no items.
+	let total_pages = items.len().div_ceil(per_page).max(1);
+	if current_page == 0 || current_page > total_pages {
+		return Err(args
+			.failed(format!(
+				"page {current_page} is out of range, there are {total_pages} pages"
+			))
+			.into());
+	}
+
+	let page_url = |page: usize| {
+		url.as_ref().map(|url| match page {
+			1 => url.clone(),
+			page => format!("{}/{paginate_path}/{page}/", url.trim_end_matches('/')),
+		})
+	};
+
+	let page = ItemsPage {
+		total_items: items.len(),
+		items: items
+			.into_iter()
+			.skip((current_page - 1) * per_page)
+			.take(per_page)
+			.collect(),
+		current_page,
+		total_pages,
+		per_page,
+		prev_page: (current_page > 1).then(|| current_page - 1),
+		next_page: (current_page < total_pages).then(|| current_page + 1),
+		current_url: page_url(current_page),
+		first_url: page_url(1),
+		last_url: page_url(total_pages),
+		prev_url: (current_page > 1)
+			.then(|| page_url(current_page - 1))
+			.flatten(),
+		next_url: (current_page < total_pages)
+			.then(|| page_url(current_page + 1))
+			.flatten(),
+	};
+
+	Ok(args.to_value(page)?)
+}
+
+fn make_entry_terms_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("entry_terms", args);
+			let path: PathBuf = args.required("path")?;
+
+			Ok(args.to_value(entry_terms(&content, &path))?)
+		},
+	)
+}
+
+/// Pages of the site, optionally only those of a section, sorted and filtered. Protected entries
+/// and error pages are left out.
+///
+/// `sort_by` is `date` (newest first, the default), `updated`, `title`, `path`, or any other
+/// frontmatter field, e.g. `weight`. `reverse=true` reverses the order, and entries without the
+/// field come last either way. `filter` matches entries by frontmatter fields, e.g.
+/// `filter={"featured": true}`, where array fields match if they contain the value. `offset` and
+/// `limit` then select a page of the entries.
+fn make_entries_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("entries", args);
+
+			let section: Option<String> = args.optional("section")?;
+			let sort_by: String = args.optional("sort_by")?.unwrap_or_else(|| "date".into());
+			let reverse: bool = args.optional("reverse")?.unwrap_or(false);
+			let filter: serde_json::Map<String, Value> =
+				args.optional("filter")?.unwrap_or_default();
+			let offset: usize = args.optional("offset")?.unwrap_or(0);
+			let limit: Option<usize> = args.optional("limit")?;
+			let listing: Option<Listing> = args.optional("listing")?;
+
+			let section = section.map(|section| SectionHandle::from(section.as_str()));
+			let mut entries = content
+				.entries
+				.iter()
+				.filter(|entry| {
+					entry.is_renderable
+						&& entry.file_type.is_rendered_to_html()
+						&& !entry.protected
+						&& !entry.is_error_page()
+				})
+				.filter(|entry| {
+					section.is_none() || entry.section_handle.as_ref() == section.as_ref()
+				})
+				.filter(|entry| {
+					filter
+						.iter()
+						.all(|(field, value)| matches_field(entry.extra.get(field), value))
+				})
+				.filter(|entry| {
+					listing.is_none_or(|listing| !content.is_excluded_from(entry, listing))
+				})
+				.map(|entry| entry.value().clone())
+				.collect::<Vec<_>>();
+
+			let is_missing = |entry: &Entry| match sort_by.as_str() {
+				"date" | "published" => entry.published.is_none(),
+				"updated" => entry.updated.is_none(),
+				"title" | "path" => false,
+				field => !entry.extra.contains_key(field),
+			};
+			entries.sort_by(|a, b| {
+				let ordering = match sort_by.as_str() {
+					"date" | "published" => b.published.cmp(&a.published),
+					"updated" => b.updated.cmp(&a.updated),
+					"title" => a.title().to_lowercase().cmp(&b.title().to_lowercase()),
+					"path" => Ordering::Equal,
+					field => compare_fields(a.extra.get(field), b.extra.get(field)),
+				}
+				.then_with(|| a.path.cmp(&b.path));
+				let ordering = if reverse {
+					ordering.reverse()
+				} else {
+					ordering
+				};
+
+				// Entries without the field stay last when the order is reversed.
+				is_missing(a).cmp(&is_missing(b)).then(ordering)
+			});
+
+			let entries = entries
+				.into_iter()
+				.skip(offset)
+				.take(limit.unwrap_or(usize::MAX))
+				.collect::<Vec<_>>();
+
+			Ok(args.to_value(entries)?)
+		},
+	)
+}
+
+/// Orders frontmatter values of the same type, with missing values last.
+#[inline]
+fn compare_fields(a: Option<&TomlValue>, b: Option<&TomlValue>) -> Ordering {
+	match (a, b) {
+		(Some(TomlValue::Integer(a)), Some(TomlValue::Integer(b))) => a.cmp(b),
+		(Some(TomlValue::Float(a)), Some(TomlValue::Float(b))) => a.total_cmp(b),
+		(Some(TomlValue::Integer(a)), Some(TomlValue::Float(b))) => (*a as f64).total_cmp(b),
+		(Some(TomlValue::Float(a)), Some(TomlValue::Integer(b))) => a.total_cmp(&(*b as f64)),
+		(Some(TomlValue::String(a)), Some(TomlValue::String(b))) => {
+			a.to_lowercase().cmp(&b.to_lowercase())
+		}
+		(Some(TomlValue::Boolean(a)), Some(TomlValue::Boolean(b))) => a.cmp(b),
+		(Some(TomlValue::Datetime(a)), Some(TomlValue::Datetime(b))) => {
+			a.to_string().cmp(&b.to_string())
+		}
+		(Some(_), None) => Ordering::Less,
+		(None, Some(_)) => Ordering::Greater,
+		_ => Ordering::Equal,
+	}
+}
+
+/// Whether a frontmatter value is `value`, or is an array which contains it.
+#[inline]
+fn matches_field(field: Option<&TomlValue>, value: &Value) -> bool {
+	let Some(field) = field.and_then(|field| serde_json::to_value(field).ok()) else {
+		return value.is_null();
+	};
+
+	match &field {
+		Value::Array(items) if !value.is_array() => items.contains(value),
+		field => field == value,
+	}
+}
+
+fn make_sections_fn(content: Arc<Content>) -> impl Function {
+	Box::new(
+		#[inline]
+		move |args: &HashMap<String, Value>| -> tera::Result<Value> {
+			let args = Args::new("sections", args);
+
+			if args.is_empty() {
+				let list = content
+					.sections
+					.iter()
+					.map(|section| args.to_value(section.value()))
+					.collect::<FunctionResult<_>>()?;
+				Ok(Value::Array(list))
+			} else {
+				let handle: String = args.required("handle")?;
+				let handle = SectionHandle::from(handle.as_str());
+
+				match content.sections.get(&handle) {
+					Some(section) => Ok(args.to_value(section.value())?),
+					None => Ok(Value::Null),
+				}
+			}
+		},
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::generate::{
+		config::{Config, TaxonomyConfig},
+		content::{ast::markdown_ast, FileType},
+		sections::Section,
+	};
+	use std::path::Path;
+
+	fn content() -> Arc<Content> {
+		Arc::new(Content {
+			entries: DashMap::new(),
+			sections: DashMap::new(),
+			taxonomies: DashMap::new(),
+			config: Arc::new(Config::default()),
+		})
+	}
+
+	fn content_with_config(config: Config) -> Arc<Content> {
+		Arc::new(Content {
+			entries: DashMap::new(),
+			sections: DashMap::new(),
+			taxonomies: DashMap::new(),
+			config: Arc::new(config),
+		})
+	}
+
+	fn content_with_entries(entries: &[(&str, &str)]) -> Arc<Content> {
+		let content = content();
+		for (path, file_path) in entries {
+			let path = PathBuf::from(path);
+			content.entries.insert(
+				path.clone(),
+				Entry {
+					slug: path
+						.file_name()
+						.map(|slug| slug.to_string_lossy().to_string()),
+					layout: None,
+					kind: "post".into(),
+					url: format!("/{}", path.display()),
+					external_url: None,
+					link: format!("/{}", path.display()),
+					path,
+					file_path: PathBuf::from(file_path),
+					file_type: FileType::Markdown,
+					published: None,
+					updated: None,
+					section_handle: None,
+					is_renderable: true,
+					draft: false,
+					headers: BTreeMap::new(),
+					aliases: vec![],
+					password: None,
+					protected: false,
+					extra: Default::default(),
+				},
+			);
+		}
+		content
+	}
+
+	#[cfg(feature = "images")]
+	fn image_processor() -> Arc<ImageProcessor> {
+		Arc::new(ImageProcessor::new(Arc::new(Config::default())))
+	}
+
+	fn args(values: Value) -> HashMap<String, Value> {
+		serde_json::from_value(values).unwrap()
+	}
+
+	fn error_message(result: tera::Result<Value>) -> String {
+		result.unwrap_err().to_string()
+	}
+
+	#[test]
+	#[cfg(feature = "images")]
+	fn image_missing_arg() {
+		let image = make_image_fn(content(), image_processor());
+		let message = error_message(image.call(&args(json!({}))));
+		assert_eq!(message, "`image`: missing argument `in`");
+	}
+
+	#[test]
+	#[cfg(feature = "images")]
+	fn image_invalid_arg() {
+		let image = make_image_fn(content(), image_processor());
+		let message = error_message(image.call(&args(json!({ "in": 1 }))));
+		assert!(message.starts_with("`image`: invalid argument `in` (1)"));
+	}
+
+	#[test]
+	#[cfg(feature = "images")]
+	fn image_invalid_operations() {
+		let image = make_image_fn(content(), image_processor());
+		let message = error_message(image.call(&args(json!({
+			"in": "image.jpg",
+			"format": "png",
+			"operations": "resize",
+		}))));
+		assert!(message.starts_with("`image`: invalid argument"));
+	}
+
+	#[test]
+	#[cfg(feature = "images")]
+	fn image_unknown_preset() {
+		let image = make_image_fn(content(), image_processor());
+		let message = error_message(image.call(&args(json!({
+			"in": "image.jpg",
+			"preset": "thumbnail",
+		}))));
+		assert_eq!(
+			message,
+			"`image`: no image preset `thumbnail` is configured in `images.presets`"
+		);
+	}
+
+	#[test]
+	#[cfg(feature = "images")]
+	fn gallery_missing_arg() {
+		let gallery = make_gallery_fn(content(), image_processor());
+		let message = error_message(gallery.call(&args(json!({}))));
+		assert_eq!(message, "`gallery`: missing argument `dir`");
+	}
+
+	#[test]
+	fn env_not_allowed() {
+		let env = make_env_fn(content());
+		let message = error_message(env.call(&args(json!({ "name": "HOME" }))));
+		assert_eq!(message, "`env`: `HOME` is not one of `template_env`");
+	}
+
+	#[test]
+	fn env_default() {
+		let env = make_env_fn(content_with_config(Config {
+			template_env: vec!["SLUGGY_TEST_UNSET".into()],
+			..Config::default()
+		}));
+		let value = env
+			.call(&args(
+				json!({ "name": "SLUGGY_TEST_UNSET", "default": "production" }),
+			))
+			.unwrap();
+		assert_eq!(value, json!("production"));
+	}
+
+	#[test]
+	fn feature_build_option() {
+		let feature = make_feature_fn(content_with_config(Config {
+			drafts: true,
+			..Config::default()
+		}));
+		assert_eq!(
+			feature.call(&args(json!({ "name": "drafts" }))).unwrap(),
+			json!(true)
+		);
+		assert_eq!(
+			feature.call(&args(json!({ "name": "minify" }))).unwrap(),
+			json!(false)
+		);
+	}
+
+	#[test]
+	fn feature_unknown() {
+		let feature = make_feature_fn(content());
+		let message = error_message(feature.call(&args(json!({ "name": "wasm" }))));
+		assert!(message.starts_with("`feature`: unknown feature `wasm`, expected one of"));
+	}
+
+	#[test]
+	fn is_serve_mode() {
+		let is_serve_mode = make_is_serve_mode_fn(content_with_config(Config {
+			serve_mode: true,
+			..Config::default()
+		}));
+		assert_eq!(is_serve_mode.call(&args(json!({}))).unwrap(), json!(true));
+	}
+
+	#[cfg(feature = "sanitize")]
+	#[test]
+	fn sanitize_script() {
+		let sanitize = make_sanitize_filter(content());
+		let value = sanitize
+			.filter(
+				&json!(r#"<p onclick="steal()">Hi<script>steal()</script></p>"#),
+				&HashMap::new(),
+			)
+			.unwrap();
+		assert_eq!(value, json!("<p>Hi</p>"));
+	}
+
+	#[cfg(feature = "sanitize")]
+	#[test]
+	fn sanitize_allowlist() {
+		let sanitize = make_sanitize_filter(content_with_config(Config {
+			sanitize: SanitizeConfig {
+				tags: Some(vec!["em".into()]),
+				generic_attributes: vec!["class".into()],
+				..SanitizeConfig::default()
+			},
+			..Config::default()
+		}));
+		let value = sanitize
+			.filter(&json!(r#"<p><em class="a">Hi</em></p>"#), &HashMap::new())
+			.unwrap();
+		assert_eq!(value, json!(r#"<em class="a">Hi</em>"#));
+	}
+
+	#[cfg(feature = "media")]
+	#[test]
+	fn media_missing_arg() {
+		let media = make_media_fn(content());
+		let message = error_message(media.call(&args(json!({}))));
+		assert_eq!(message, "`media`: missing argument `path`");
+	}
+
+	#[test]
+	fn base64_value() {
+		let base64 = make_base64_fn(content());
+		let value = base64.call(&args(json!({ "value": "sluggy" }))).unwrap();
+		assert_eq!(value, json!("c2x1Z2d5"));
+	}
+
+	#[test]
+	fn base64_missing_arg() {
+		let base64 = make_base64_fn(content());
+		let message = error_message(base64.call(&args(json!({}))));
+		assert_eq!(message, "`base64`: missing argument `file`");
+	}
+
+	#[test]
+	fn base64_missing_file() {
+		let base64 = make_base64_fn(content());
+		let message = error_message(base64.call(&args(json!({ "file": "/does/not/exist" }))));
+		assert!(message.starts_with("`base64`: failed to read file /does/not/exist"));
+	}
+
+	#[test]
+	fn asset_info_missing_asset() {
+		let asset_info = make_asset_info_fn(content());
+		let message = error_message(asset_info.call(&args(json!({ "path": "@/css/missing.css" }))));
+		assert!(message.starts_with("`asset_info`: failed to read asset css/missing.css"));
+	}
+
+	#[test]
+	fn include_code_invalid_lines() {
+		let include_code = make_include_code_fn(content());
+		let message = error_message(include_code.call(&args(json!({
+			"path": "Cargo.toml",
+			"lines": "a-b",
+		}))));
+		assert_eq!(message, "`include_code`: invalid line range \"a-b\"");
+	}
+
+	#[test]
+	fn render_content_missing_arg() {
+		let render_content = make_render_content_fn(content());
+		let message = error_message(render_content.call(&args(json!({}))));
+		assert_eq!(message, "`render_content`: missing argument `path`");
+	}
+
+	#[test]
+	fn render_content_invalid_arg() {
+		let render_content = make_render_content_fn(content());
+		let message = error_message(render_content.call(&args(json!({ "path": [] }))));
+		assert!(message.starts_with("`render_content`: invalid argument `path` ([])"));
+	}
+
+	#[test]
+	fn render_content_not_found() {
+		let render_content = make_render_content_fn(content());
+		let value = render_content
+			.call(&args(json!({ "path": "missing" })))
+			.unwrap();
+		assert_eq!(value, Value::Null);
+	}
+
+	#[test]
+	fn content_ast_missing_arg() {
+		let content_ast = make_content_ast_fn(content());
+		let message = error_message(content_ast.call(&args(json!({}))));
+		assert_eq!(message, "`content_ast`: missing argument `path`");
+	}
+
+	#[test]
+	fn content_ast_not_found() {
+		let content_ast = make_content_ast_fn(content());
+		let value = content_ast
+			.call(&args(json!({ "path": "missing" })))
+			.unwrap();
+		assert_eq!(value, Value::Null);
+	}
+
+	#[test]
+	fn content_ast_blocks() {
+		let blocks = markdown_ast(
+			"# Title\n\n![Hero](hero.png)\n\nSome *text*.\n\n```rust\nfn main() {}\n```\n",
+			Path::new("post"),
+			&Config::default(),
+		)
+		.unwrap();
+		let blocks = serde_json::to_value(blocks).unwrap();
+
+		assert_eq!(blocks[0]["type"], "heading");
+		assert_eq!(blocks[0]["id"], "title");
+		assert_eq!(blocks[1]["type"], "image");
+		assert_eq!(blocks[1]["url"], "hero.png");
+		assert_eq!(blocks[1]["alt"], "Hero");
+		assert_eq!(blocks[2]["type"], "paragraph");
+		assert_eq!(blocks[2]["text"], "Some text.");
+		assert_eq!(blocks[3]["type"], "code_block");
+		assert_eq!(blocks[3]["lang"], "rust");
+		assert_eq!(blocks[3]["code"], "fn main() {}\n");
+	}
+
+	#[test]
+	fn entry_invalid_input() {
+		let entry = make_entry_filter(content());
+		let message = error_message(entry.filter(&json!(1), &HashMap::new()));
+		assert_eq!(
+			message,
+			"`entry`: input value must be a string or an array of strings, got 1"
+		);
+	}
+
+	#[test]
+	fn entry_invalid_array_item() {
+		let entry = make_entry_filter(content());
+		let message = error_message(entry.filter(&json!(["a", 1]), &HashMap::new()));
+		assert_eq!(message, "`entry`: expected a path string, got 1");
+	}
+
+	#[test]
+	fn entry_not_found() {
+		let entry = make_entry_filter(content());
+		let message = error_message(entry.filter(&json!(["a", "b"]), &HashMap::new()));
+		assert_eq!(message, "`entry`: no entry found for \"a\"");
+	}
+
+	#[test]
+	fn entry_not_found_optional() {
+		let entry = make_entry_filter(content());
+		let value = entry
+			.filter(&json!(["a", "b"]), &args(json!({ "optional": true })))
+			.unwrap();
+		assert_eq!(value, json!([null, null]));
+	}
+
+	#[test]
+	fn entry_resolves_aliases() {
+		let content =
+			content_with_entries(&[("posts/hello-world", "posts/2023-01-01-hello-world.md")]);
+		let entry = make_entry_filter(content);
+
+		for query in [
+			"posts/hello-world",
+			"/posts/hello-world/",
+			"posts/2023-01-01-hello-world.md",
+			"posts/2023-01-01-hello-world",
+			"hello-world",
+		] {
+			let value = entry.filter(&json!(query), &HashMap::new()).unwrap();
+			assert_eq!(value["path"], "posts/hello-world", "{query}");
+		}
+	}
+
+	#[test]
+	fn markdown_renders_string() {
+		let markdown = make_markdown_filter(content());
+		let value = markdown
+			.filter(&json!("Some *text*."), &HashMap::new())
+			.unwrap();
+		assert_eq!(value, json!("<p>Some <em>text</em>.</p>\n"));
+	}
+
+	#[test]
+	fn markdown_inline() {
+		let markdown = make_markdown_filter(content());
+		let value = markdown
+			.filter(&json!("Some *text*."), &args(json!({ "inline": true })))
+			.unwrap();
+		assert_eq!(value, json!("Some <em>text</em>."));
+
+		let value = markdown
+			.filter(&json!("One\n\nTwo"), &args(json!({ "inline": true })))
+			.unwrap();
+		assert_eq!(value, json!("<p>One</p>\n<p>Two</p>\n"));
+	}
+
+	#[test]
+	fn markdown_invalid_input() {
+		let markdown = make_markdown_filter(content());
+		let message = error_message(markdown.filter(&json!(1), &HashMap::new()));
+		assert_eq!(message, "`markdown`: input value must be a string, got 1");
+	}
+
+	#[test]
+	fn entry_near_matches() {
+		let content = content_with_entries(&[("posts/hello-world", "posts/hello-world.md")]);
+		let entry = make_entry_filter(content);
+		let message = error_message(entry.filter(&json!("posts/helo-world"), &HashMap::new()));
+		assert_eq!(
+			message,
+			"`entry`: no entry found for \"posts/helo-world\", did you mean \"posts/hello-world\"?"
+		);
+	}
+
+	#[test]
+	fn sections_invalid_arg() {
+		let sections = make_sections_fn(content());
+		let message = error_message(sections.call(&args(json!({ "handle": 1 }))));
+		assert!(message.starts_with("`sections`: invalid argument `handle` (1)"));
+	}
+
+	#[test]
+	fn sections_missing_arg() {
+		let sections = make_sections_fn(content());
+		let message = error_message(sections.call(&args(json!({ "name": "posts" }))));
+		assert_eq!(message, "`sections`: missing argument `handle`");
+	}
+
+	#[test]
+	fn sections_empty() {
+		let sections = make_sections_fn(content());
+		let value = sections.call(&HashMap::new()).unwrap();
+		assert_eq!(value, json!([]));
+	}
+
+	#[test]
+	fn cache_missing_arg() {
+		let cache = make_cache_fn(content());
+		let message = error_message(cache.call(&args(json!({ "key": "popular" }))));
+		assert_eq!(message, "`cache`: missing argument `template`");
+	}
+
+	#[test]
+	fn cache_missing_template() {
+		let cache = make_cache_fn(content());
+		let message = error_message(cache.call(&args(json!({
+			"key": "popular",
+			"template": "does-not-exist.html",
+		}))));
+		assert!(message.starts_with("`cache`: failed to read template"));
+	}
+
+	#[test]
+	fn taxonomy_terms_sorted() {
+		let content = content();
+		let tags = DashMap::new();
+		tags.insert(
+			"rust".to_string(),
+			vec![PathBuf::from("b"), PathBuf::from("a")],
+		);
+		tags.insert("Go".to_string(), vec![PathBuf::from("a")]);
+		content.taxonomies.insert("tags".to_string(), tags);
+
+		let taxonomy_terms = make_taxonomy_terms_fn(Arc::clone(&content));
+		let value = taxonomy_terms
+			.call(&args(json!({ "taxonomy": "tags" })))
+			.unwrap();
+		assert_eq!(value[0]["name"], "Go");
+		assert_eq!(value[1]["entries"], json!(["a", "b"]));
+
+		let value = taxonomy_terms
+			.call(&args(
+				json!({ "taxonomy": "tags", "order": "count", "limit": 1 }),
+			))
+			.unwrap();
+		assert_eq!(value.as_array().unwrap().len(), 1);
+		assert_eq!(value[0]["name"], "rust");
+		assert_eq!(value[0]["count"], 2);
+
+		let entry_terms = make_entry_terms_fn(content);
+		let value = entry_terms.call(&args(json!({ "path": "b" }))).unwrap();
+		assert_eq!(value["tags"][0]["slug"], "rust");
+	}
+
+	#[test]
+	fn taxonomy_terms_urls() {
+		let config = Config {
+			base_url: "/".into(),
+			taxonomies: BTreeMap::from([(
+				"tags".to_string(),
+				TaxonomyConfig {
+					feed: true,
+					..TaxonomyConfig::default()
+				},
+			)]),
+			..Config::default()
+		};
+		let content = Arc::new(Content {
+			entries: DashMap::new(),
+			sections: DashMap::new(),
+			taxonomies: DashMap::new(),
+			config: Arc::new(config),
+		});
+		let tags = DashMap::new();
+		tags.insert("Rust Lang".to_string(), vec![PathBuf::from("a")]);
+		content.taxonomies.insert("tags".to_string(), tags);
+
+		let taxonomy_terms = make_taxonomy_terms_fn(content);
+		let value = taxonomy_terms
+			.call(&args(json!({ "taxonomy": "tags" })))
+			.unwrap();
+		assert_eq!(value[0]["url"], "/tags/rust-lang/");
+		assert_eq!(value[0]["feed_url"], "/tags/rust-lang/feed.xml");
+	}
+
+	#[test]
+	fn taxonomy_terms_unknown_taxonomy() {
+		let taxonomy_terms = make_taxonomy_terms_fn(content());
+		let message = error_message(taxonomy_terms.call(&args(json!({ "taxonomy": "tags" }))));
+		assert!(message.starts_with("`taxonomy_terms`: taxonomy `tags` is not configured"));
+	}
+
+	#[test]
+	fn entries_sorted_and_filtered() {
+		let content = content_with_entries(&[
+			("posts/a", "posts/a.md"),
+			("posts/b", "posts/b.md"),
+			("posts/c", "posts/c.md"),
+			("about", "about.md"),
+		]);
+		for (path, weight, featured) in [("posts/a", 2, true), ("posts/b", 1, true)] {
+			let mut entry = content.entries.get_mut(Path::new(path)).unwrap();
+			entry.section_handle = Some(SectionHandle::from("posts"));
+			entry.extra.insert("weight".into(), weight.into());
+			entry.extra.insert("featured".into(), featured.into());
+		}
+		content
+			.entries
+			.get_mut(Path::new("posts/c"))
+			.unwrap()
+			.section_handle = Some(SectionHandle::from("posts"));
+
+		let entries = make_entries_fn(content);
+		let value = entries
+			.call(&args(json!({ "section": "posts", "sort_by": "weight" })))
+			.unwrap();
+		let paths = value
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|entry| entry["path"].clone())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			paths,
+			vec![json!("posts/b"), json!("posts/a"), json!("posts/c")]
+		);
+
+		let value = entries
+			.call(&args(json!({
+				"filter": { "featured": true },
+				"sort_by": "weight",
+				"reverse": true,
+				"limit": 1,
+			})))
+			.unwrap();
+		assert_eq!(value.as_array().unwrap().len(), 1);
+		assert_eq!(value[0]["path"], "posts/a");
+
+		let value = entries
+			.call(&args(json!({
+				"section": "posts",
+				"sort_by": "weight",
+				"reverse": true,
+			})))
+			.unwrap();
+		let paths = value
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|entry| entry["path"].clone())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			paths,
+			vec![json!("posts/a"), json!("posts/b"), json!("posts/c")]
+		);
+
+		let value = entries.call(&args(json!({ "offset": 3 }))).unwrap();
+		assert_eq!(value.as_array().unwrap().len(), 1);
+	}
+
+	#[test]
+	fn protected_entries_not_listed() {
+		let content = content_with_entries(&[("posts/a", "posts/a.md"), ("posts/b", "posts/b.md")]);
+		content
+			.entries
+			.get_mut(Path::new("posts/b"))
+			.unwrap()
+			.protected = true;
+
+		let value = make_entries_fn(content.clone())
+			.call(&args(json!({})))
+			.unwrap();
+		assert_eq!(value.as_array().unwrap().len(), 1);
+		assert_eq!(value[0]["path"], "posts/a");
+
+		let render_content = make_render_content_fn(content.clone());
+		let value = render_content
+			.call(&args(json!({ "path": "posts/b" })))
+			.unwrap();
+		assert_eq!(value, Value::Null);
+
+		let content_ast = make_content_ast_fn(content);
+		let value = content_ast
+			.call(&args(json!({ "path": "posts/b" })))
+			.unwrap();
+		assert_eq!(value, Value::Null);
+	}
+
+	#[test]
+	fn error_pages_not_listed() {
+		let content =
+			content_with_entries(&[("posts/a", "posts/a.md"), ("_error/404", "_error/404.md")]);
+		let entries = make_entries_fn(content);
+
+		for args_value in [json!({}), json!({ "listing": "search" })] {
+			let value = entries.call(&args(args_value)).unwrap();
+			assert_eq!(value.as_array().unwrap().len(), 1);
+			assert_eq!(value[0]["path"], "posts/a");
+		}
+	}
+
+	#[test]
+	fn entries_excluded_from_listing() {
+		let content = content_with_entries(&[
+			("about", "about.md"),
+			("private/a", "private/a.md"),
+			("private/notes/b", "private/notes/b.md"),
+		]);
+		for (prefix, manifest) in [
+			("private", r#"exclude_from = ["search"]"#),
+			("private/notes", ""),
+		] {
+			let section = Section::new(PathBuf::from(prefix), &toml::from_str(manifest).unwrap());
+			content.sections.insert(section.handle.clone(), section);
+		}
+		for (path, handle) in [
+			("private/a", "private"),
+			("private/notes/b", "private_notes"),
+		] {
+			content
+				.entries
+				.get_mut(Path::new(path))
+				.unwrap()
+				.section_handle = Some(SectionHandle::from(handle));
+		}
+
+		let entries = make_entries_fn(content);
+		let value = entries.call(&args(json!({ "listing": "search" }))).unwrap();
+		assert_eq!(value.as_array().unwrap().len(), 1);
+		assert_eq!(value[0]["path"], "about");
+
+		let value = entries.call(&args(json!({ "listing": "feeds" }))).unwrap();
+		assert_eq!(value.as_array().unwrap().len(), 3);
+	}
+
+	#[test]
+	fn taxonomies_terms() {
+		let content = content();
+		let tags = DashMap::new();
+		tags.insert("rust".to_string(), vec![PathBuf::from("a")]);
+		content.taxonomies.insert("tags".to_string(), tags);
+
+		let taxonomies = make_taxonomies_fn(content);
+		let value = taxonomies.call(&HashMap::new()).unwrap();
+		assert_eq!(value["tags"][0]["name"], "rust");
+		assert_eq!(value["tags"][0]["count"], 1);
+	}
+
+	#[test]
+	fn taxonomy_term_entries() {
+		let content = content_with_entries(&[("a", "a.md"), ("b", "b.md"), ("c", "c.md")]);
+		let tags = DashMap::new();
+		tags.insert(
+			"Rust Lang".to_string(),
+			vec![PathBuf::from("c"), PathBuf::from("a"), PathBuf::from("b")],
+		);
+		content.taxonomies.insert("tags".to_string(), tags);
+
+		let taxonomy = make_taxonomy_fn(content);
+		let value = taxonomy
+			.call(&args(
+				json!({ "name": "tags", "term": "rust-lang", "exclude": "a", "limit": 1 }),
+			))
+			.unwrap();
+		assert_eq!(value["name"], "Rust Lang");
+		assert_eq!(value["count"], 3);
+		assert_eq!(value["entries"].as_array().unwrap().len(), 1);
+		assert_eq!(value["entries"][0]["path"], "b");
+
+		let value = taxonomy
+			.call(&args(json!({ "name": "tags", "term": "go" })))
+			.unwrap();
+		assert_eq!(value, Value::Null);
+	}
+
+	#[test]
+	fn related_by_shared_terms() {
+		let content =
+			content_with_entries(&[("a", "a.md"), ("b", "b.md"), ("c", "c.md"), ("d", "d.md")]);
+		let tags = DashMap::new();
+		tags.insert(
+			"rust".to_string(),
+			vec![PathBuf::from("a"), PathBuf::from("b"), PathBuf::from("c")],
+		);
+		tags.insert(
+			"web".to_string(),
+			vec![PathBuf::from("a"), PathBuf::from("c")],
+		);
+		content.taxonomies.insert("tags".to_string(), tags);
+		let categories = DashMap::new();
+		categories.insert(
+			"notes".to_string(),
+			vec![PathBuf::from("b"), PathBuf::from("d")],
+		);
+		content
+			.taxonomies
+			.insert("categories".to_string(), categories);
+
+		let related = make_related_fn(content);
+		let value = related.call(&args(json!({ "path": "a" }))).unwrap();
+		let paths = value
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|entry| entry["path"].clone())
+			.collect::<Vec<_>>();
+		assert_eq!(paths, vec![json!("c"), json!("b")]);
+
+		let value = related
+			.call(&args(
+				json!({ "path": "b", "taxonomies": ["categories"], "limit": 1 }),
+			))
+			.unwrap();
+		assert_eq!(value.as_array().unwrap().len(), 1);
+		assert_eq!(value[0]["path"], "d");
+	}
+
+	#[test]
+	fn taxonomy_unknown_taxonomy() {
+		let taxonomy = make_taxonomy_fn(content());
+		let message =
+			error_message(taxonomy.call(&args(json!({ "name": "tags", "term": "rust" }))));
+		assert!(message.starts_with("`taxonomy`: taxonomy `tags` is not configured"));
+	}
+
+	#[test]
+	fn load_data_invalid_format() {
+		let load_data = make_load_data_fn(content());
+		let message = error_message(load_data.call(&args(json!({
+			"path": "Cargo.toml",
+			"format": "xml",
+		}))));
+		assert!(message.starts_with("`load_data`: invalid argument `format` (\"xml\")"));
+	}
+
+	#[test]
+	fn load_data_toml() {
+		let load_data = make_load_data_fn(content());
+		let value = load_data
+			.call(&args(json!({ "path": "Cargo.toml" })))
+			.unwrap();
+		assert_eq!(value["package"]["name"], "sluggy_core");
+	}
+
+	#[test]
+	fn paginate_items() {
+		let value = paginate_fn(&args(json!({
+			"items": [1, 2, 3, 4, 5],
+			"per_page": 2,
+			"current": 2,
+			"url": "/products/",
+		})))
+		.unwrap();
+		assert_eq!(value["items"], json!([3, 4]));
+		assert_eq!(value["total_pages"], 3);
+		assert_eq!(value["prev_page"], 1);
+		assert_eq!(value["next_page"], 3);
+		assert_eq!(value["prev_url"], "/products/");
+		assert_eq!(value["next_url"], "/products/page/3/");
+	}
+
+	#[test]
+	fn paginate_out_of_range() {
+		let message = error_message(paginate_fn(&args(json!({
+			"items": [1, 2, 3],
+			"current": 2,
+		}))));
+		assert_eq!(
+			message,
+			"`paginate`: page 2 is out of range, there are 1 pages"
+		);
+	}
+
+	#[test]
+	#[cfg(feature = "http-loader")]
+	fn fetch_disallowed_domain() {
+		let fetch = make_fetch_fn(content());
+		let message = error_message(fetch.call(&args(json!({ "url": "https://example.com/" }))));
+		assert_eq!(
+			message,
+			"`fetch`: https://example.com/ is not on one of `fetch.allowed_domains`"
+		);
+	}
+}
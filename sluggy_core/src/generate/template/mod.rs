@@ -1,6 +1,15 @@
-use crate::{error::Result, lazyfn::LazyFn, utils::LockResultExt};
+use crate::{
+	err,
+	error::{Error, Result},
+	lazyfn::LazyFn,
+	map_err,
+	utils::LockResultExt,
+};
+use regex::Regex;
 use serde::Serialize;
 use std::{
+	error::Error as StdError,
+	fs,
 	io::Write,
 	mem::MaybeUninit,
 	sync::{Arc, RwLock},
@@ -8,23 +17,31 @@ use std::{
 use tera::{Context as TeraContext, Tera};
 use tracing::instrument;
 
-use crate::generate::content::Content;
+use crate::generate::{config::Config, content::Content};
 
-use self::functions::register_builtin_functions;
+use self::functions::{register_builtin_functions, FRAGMENTS, FRAGMENTS_CACHE_DIR};
 
 pub(crate) mod functions;
+pub(crate) mod testing;
 
 static TEMPLATE_ENGINE: LazyFn<Arc<RwLock<MaybeUninit<Tera>>>> =
 	LazyFn::new(|| Arc::new(RwLock::new(MaybeUninit::uninit())));
 
+/// Copy of the template engine used to render cached fragments. The main engine is locked while
+/// the template which calls `cache` is being rendered, so it can't render the fragment itself.
+static FRAGMENT_ENGINE: LazyFn<Arc<RwLock<Option<Tera>>>> =
+	LazyFn::new(|| Arc::new(RwLock::new(None)));
+
 pub(crate) fn setup_template_engine(content: &Arc<Content>) -> Result<()> {
-	let mut tera = Tera::new(&format!("{}/**/*", content.config.template_dir.display()))?;
+	let mut tera = Tera::new(&format!("{}/**/*", content.config.template_dir.display()))
+		.map_err(parse_error)?;
 
 	// Disable auto-escaping.
 	tera.autoescape_on(vec![]);
 
 	register_builtin_functions(&mut tera, content);
 
+	*FRAGMENT_ENGINE.write().acquire() = Some(tera.clone());
 	TEMPLATE_ENGINE.write().acquire().write(tera);
 
 	Ok(())
@@ -32,6 +49,41 @@ pub(crate) fn setup_template_engine(content: &Arc<Content>) -> Result<()> {
 
 pub(crate) fn unset_template_engine() {
 	*TEMPLATE_ENGINE.write().acquire() = MaybeUninit::uninit();
+	*FRAGMENT_ENGINE.write().acquire() = None;
+	#[cfg(feature = "images")]
+	crate::generate::images::set_current_processor(None);
+}
+
+/// Forgets the fragments which `cache` rendered or read during the last build.
+#[inline]
+pub(crate) fn clear_fragments() {
+	FRAGMENTS.clear();
+}
+
+/// Removes the fragments which the build didn't use from `cache_dir`, so that the fragments of
+/// content which has since changed don't pile up.
+#[instrument(level = "debug", skip_all)]
+pub(crate) fn remove_unused_fragments(config: &Config) -> Result<()> {
+	let fragments_dir = config.cache_dir.join(FRAGMENTS_CACHE_DIR);
+	let Ok(fragment_files) = fs::read_dir(&fragments_dir) else {
+		return Ok(());
+	};
+
+	for fragment_file in fragment_files {
+		let path = map_err!(fragment_file, IoError("dir entry failed"))?.path();
+		let is_used = path
+			.file_stem()
+			.and_then(|digest| digest.to_str())
+			.is_some_and(|digest| FRAGMENTS.contains_key(digest));
+		if !is_used {
+			map_err!(
+				fs::remove_file(&path),
+				IoError(format!("failed to remove {}", path.display())),
+			)?;
+		}
+	}
+
+	Ok(())
 }
 
 #[instrument(level = "trace", skip(raw))]
@@ -39,7 +91,23 @@ pub(crate) fn unset_template_engine() {
 pub(crate) fn add_once_off_template(name: &str, raw: &str) -> Result<()> {
 	let mut engine_lock = TEMPLATE_ENGINE.write().acquire();
 	let engine = unsafe { &mut engine_lock.assume_init_mut() };
-	Ok(engine.add_raw_template(name, raw)?)
+	engine.add_raw_template(name, raw).map_err(parse_error)
+}
+
+/// Adds the once-off templates of entries which were parsed before the engine was set up again, in
+/// one go, so that their entries don't have to be rendered again.
+#[instrument(level = "trace", skip(templates))]
+#[inline]
+pub(crate) fn add_once_off_templates(templates: &[(String, String)]) -> Result<()> {
+	let mut engine_lock = TEMPLATE_ENGINE.write().acquire();
+	let engine = unsafe { &mut engine_lock.assume_init_mut() };
+	engine
+		.add_raw_templates(
+			templates
+				.iter()
+				.map(|(name, raw)| (name.as_str(), raw.as_str())),
+		)
+		.map_err(parse_error)
 }
 
 #[instrument(level = "debug", skip(data, write))]
@@ -51,5 +119,118 @@ pub(crate) fn render_template(
 ) -> Result<()> {
 	let engine_lock = TEMPLATE_ENGINE.read().acquire();
 	let engine = unsafe { engine_lock.assume_init_ref() };
-	Ok(engine.render_to(template_name, &TeraContext::from_serialize(data)?, write)?)
+	engine
+		.render_to(template_name, &TeraContext::from_serialize(data)?, write)
+		.map_err(|error| render_error(engine, template_name, error))
+}
+
+/// Whether there's a template named `template_name`, such as `shortcodes/youtube.html`.
+#[inline]
+pub(crate) fn has_template(template_name: &str) -> bool {
+	FRAGMENT_ENGINE
+		.read()
+		.acquire()
+		.as_ref()
+		.is_some_and(|engine| engine.templates.contains_key(template_name))
+}
+
+#[instrument(level = "debug", skip(data))]
+#[inline]
+pub(crate) fn render_fragment(template_name: &str, data: impl Serialize) -> Result<String> {
+	let engine_lock = FRAGMENT_ENGINE.read().acquire();
+	let engine = engine_lock
+		.as_ref()
+		.ok_or(err!(Validation("template engine has not been set up")))?;
+	engine
+		.render(template_name, &TeraContext::from_serialize(data)?)
+		.map_err(|error| render_error(engine, template_name, error))
+}
+
+/// Names the file of the template which failed to render, since Tera only reports its name.
+#[inline]
+fn render_error(engine: &Tera, template_name: &str, error: tera::Error) -> Error {
+	let template = engine
+		.templates
+		.get(template_name)
+		.and_then(|template| template.path.clone())
+		.unwrap_or_else(|| template_name.to_string());
+
+	Error::Template {
+		template,
+		source: error,
+	}
+}
+
+/// Tera reports syntax errors as a message with the line and column of the error, which is turned
+/// into a label on the template's source.
+#[inline]
+fn parse_error(error: tera::Error) -> Error {
+	let (Ok(location_re), Ok(expected_re)) = (
+		Regex::new(r"-->\s*(\d+):(\d+)"),
+		Regex::new(r"(?m)^\s*= (.+)$"),
+	) else {
+		return Error::TemplateRenderError(error);
+	};
+
+	let mut path = None;
+	let mut location = None;
+	let mut cause: Option<&dyn StdError> = Some(&error);
+	while let Some(current) = cause {
+		let message = current.to_string();
+		if let Some(failed_path) = message.strip_prefix("Failed to parse ") {
+			path = Some(failed_path.trim_matches('"').to_string());
+		} else if let Some(captures) = location_re.captures(&message) {
+			let label = expected_re
+				.captures(&message)
+				.map(|captures| captures[1].to_string())
+				.unwrap_or_else(|| "syntax error".into());
+			location = Some((
+				captures[1].parse::<usize>().unwrap_or(1),
+				captures[2].parse::<usize>().unwrap_or(1),
+				label,
+			));
+		}
+		cause = current.source();
+	}
+
+	let (Some(path), Some((line, column, label))) = (path, location) else {
+		return Error::TemplateRenderError(error);
+	};
+	let Ok(source) = fs::read_to_string(&path) else {
+		return Error::TemplateRenderError(error);
+	};
+
+	Error::at_line(
+		format!("failed to parse template {path}"),
+		&path,
+		source,
+		line,
+		column,
+		label,
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn unused_fragments_are_removed() {
+		let cache_dir = tempfile::tempdir().unwrap();
+		let fragments_dir = cache_dir.path().join(FRAGMENTS_CACHE_DIR);
+		fs::create_dir_all(&fragments_dir).unwrap();
+		fs::write(fragments_dir.join("used-digest.html"), "used").unwrap();
+		fs::write(fragments_dir.join("unused-digest.html"), "unused").unwrap();
+
+		FRAGMENTS.insert("used-digest".into(), "used".into());
+		remove_unused_fragments(&Config {
+			cache_dir: cache_dir.path().to_path_buf(),
+			..Config::default()
+		})
+		.unwrap();
+		FRAGMENTS.remove("used-digest");
+
+		assert!(fragments_dir.join("used-digest.html").exists());
+		assert!(!fragments_dir.join("unused-digest.html").exists());
+	}
 }
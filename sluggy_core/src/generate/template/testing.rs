@@ -0,0 +1,285 @@
+use super::{render_template, setup_template_engine, unset_template_engine};
+use crate::{
+	error::{Error, Result},
+	generate::{config::Config, content::Content},
+	map_err,
+};
+use dashmap::DashMap;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use std::{
+	error::Error as StdError,
+	ffi::OsStr,
+	fs,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+use tracing::instrument;
+
+/// A file of `tests_dir` with one `[[test]]` table for each test case.
+#[derive(Debug, Deserialize)]
+struct TemplateTestFile {
+	#[serde(default, rename = "test")]
+	tests: Vec<TemplateTest>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TemplateTest {
+	/// Defaults to the name of the template.
+	name: Option<String>,
+	/// Name of the template in `template_dir`, e.g. `_layout/layout.html`.
+	template: String,
+	/// JSON or TOML file with the context of the template, relative to the test file.
+	fixture: Option<PathBuf>,
+	/// Context of the template, which is merged over the fixture.
+	context: Option<toml::Value>,
+	/// Snippets which the output must contain.
+	#[serde(default)]
+	contains: Vec<String>,
+	/// Snippets which the output must not contain.
+	#[serde(default)]
+	not_contains: Vec<String>,
+	/// File with the expected output, relative to the test file. It's written with the output when
+	/// it doesn't exist yet.
+	snapshot: Option<PathBuf>,
+}
+
+/// Outcome of a template test case.
+#[derive(Debug)]
+pub struct TemplateTestResult {
+	/// Test file of the case, relative to `tests_dir`.
+	pub file: PathBuf,
+	pub name: String,
+	pub failures: Vec<String>,
+}
+
+impl TemplateTestResult {
+	#[inline]
+	pub fn passed(&self) -> bool {
+		self.failures.is_empty()
+	}
+}
+
+/// Runs each of the test cases of the `*.toml` files in `tests_dir`. Templates are rendered with
+/// no content loaded, so functions which look up entries only find what the context provides.
+#[instrument(skip(config))]
+pub(crate) fn run_template_tests(
+	config: Arc<Config>,
+	update_snapshots: bool,
+) -> Result<Vec<TemplateTestResult>> {
+	let mut test_files = vec![];
+	if config.tests_dir.is_dir() {
+		for entry in map_err!(
+			fs::read_dir(&config.tests_dir),
+			IoError(format!("failed to read dir {}", config.tests_dir.display())),
+		)? {
+			let path = map_err!(entry, IoError("dir entry failed"))?.path();
+			if path.is_file() && path.extension() == Some(OsStr::new("toml")) {
+				test_files.push(path);
+			}
+		}
+	}
+	test_files.sort();
+
+	let content = Arc::new(Content {
+		entries: DashMap::new(),
+		sections: DashMap::new(),
+		taxonomies: DashMap::new(),
+		config: config.clone(),
+	});
+	setup_template_engine(&content)?;
+
+	let results = test_files
+		.iter()
+		.map(|test_file| run_test_file(test_file, &config, update_snapshots))
+		.collect::<Result<Vec<_>>>();
+
+	unset_template_engine();
+
+	Ok(results?.into_iter().flatten().collect())
+}
+
+#[inline]
+fn run_test_file(
+	test_file: &Path,
+	config: &Config,
+	update_snapshots: bool,
+) -> Result<Vec<TemplateTestResult>> {
+	let source = map_err!(
+		fs::read_to_string(test_file),
+		IoError(format!("failed to read {}", test_file.display())),
+	)?;
+	let tests = toml::from_str::<TemplateTestFile>(&source)
+		.map_err(|error| {
+			Error::toml_in_source(
+				format!("failed to parse {}", test_file.display()),
+				test_file.display().to_string(),
+				&source,
+				error,
+			)
+		})?
+		.tests;
+
+	let file = test_file
+		.strip_prefix(&config.tests_dir)
+		.unwrap_or(test_file)
+		.to_path_buf();
+	let test_dir = test_file.parent().unwrap_or(&config.tests_dir);
+
+	tests
+		.into_iter()
+		.map(|test| {
+			let name = test.name.clone().unwrap_or_else(|| test.template.clone());
+			let failures = match run_test(&test, test_dir, config, update_snapshots) {
+				Ok(failures) => failures,
+				Err(error) => vec![error_message(&error)],
+			};
+
+			Ok(TemplateTestResult {
+				file: file.clone(),
+				name,
+				failures,
+			})
+		})
+		.collect()
+}
+
+/// Renders the template of the test, returning the expectations which it didn't meet.
+#[inline]
+fn run_test(
+	test: &TemplateTest,
+	test_dir: &Path,
+	config: &Config,
+	update_snapshots: bool,
+) -> Result<Vec<String>> {
+	let mut data = json!({
+		"site": config,
+		"base": &config.base_url,
+	});
+	if let Some(fixture) = &test.fixture {
+		merge(&mut data, read_fixture(&test_dir.join(fixture))?);
+	}
+	if let Some(context) = &test.context {
+		merge(&mut data, json!(context));
+	}
+
+	let mut output = vec![];
+	render_template(&test.template, data, &mut output)?;
+	let output = String::from_utf8_lossy(&output).to_string();
+
+	let mut failures = vec![];
+	for snippet in &test.contains {
+		if !output.contains(snippet.as_str()) {
+			failures.push(format!("output doesn't contain `{snippet}`"));
+		}
+	}
+	for snippet in &test.not_contains {
+		if output.contains(snippet.as_str()) {
+			failures.push(format!("output contains `{snippet}`"));
+		}
+	}
+
+	if let Some(snapshot) = &test.snapshot {
+		let snapshot_path = test_dir.join(snapshot);
+		if update_snapshots || !snapshot_path.exists() {
+			if let Some(parent) = snapshot_path.parent() {
+				map_err!(
+					fs::create_dir_all(parent),
+					IoError(format!("failed to create dirs for {}", parent.display())),
+				)?;
+			}
+			map_err!(
+				fs::write(&snapshot_path, &output),
+				IoError(format!("failed to write {}", snapshot_path.display())),
+			)?;
+		} else {
+			let expected = map_err!(
+				fs::read_to_string(&snapshot_path),
+				IoError(format!("failed to read {}", snapshot_path.display())),
+			)?;
+			if let Some(difference) = snapshot_difference(&expected, &output) {
+				failures.push(format!(
+					"output doesn't match {}: {difference}",
+					snapshot.display()
+				));
+			}
+		}
+	}
+
+	Ok(failures)
+}
+
+#[inline]
+fn read_fixture(path: &Path) -> Result<Value> {
+	let source = map_err!(
+		fs::read_to_string(path),
+		IoError(format!("failed to read fixture {}", path.display())),
+	)?;
+
+	if path.extension() == Some(OsStr::new("toml")) {
+		toml::from_str::<toml::Value>(&source)
+			.map(|value| json!(value))
+			.map_err(|error| {
+				Error::toml_in_source(
+					format!("failed to parse fixture {}", path.display()),
+					path.display().to_string(),
+					&source,
+					error,
+				)
+			})
+	} else {
+		serde_json::from_str(&source).map_err(|error| {
+			Error::json_in_source(
+				format!("failed to parse fixture {}", path.display()),
+				path.display().to_string(),
+				&source,
+				error,
+			)
+		})
+	}
+}
+
+/// Merges the fields of `other` over those of `data`.
+#[inline]
+fn merge(data: &mut Value, other: Value) {
+	match (data.as_object_mut(), other) {
+		(Some(data), Value::Object(other)) => data.extend(other),
+		(_, other) => *data = other,
+	}
+}
+
+/// The first line where the output differs from the snapshot. Trailing whitespace at the end of
+/// either is ignored.
+#[inline]
+fn snapshot_difference(expected: &str, output: &str) -> Option<String> {
+	let mut expected_lines = expected.trim_end().lines();
+	let mut output_lines = output.trim_end().lines();
+
+	let mut line = 1;
+	loop {
+		match (expected_lines.next(), output_lines.next()) {
+			(None, None) => return None,
+			(expected, output) if expected != output => {
+				return Some(format!(
+					"line {line} is `{}`, expected `{}`",
+					output.unwrap_or_default(),
+					expected.unwrap_or_default()
+				));
+			}
+			_ => line += 1,
+		}
+	}
+}
+
+/// The error of a failed render along with its causes, as Tera nests the reason for the failure.
+#[inline]
+fn error_message(error: &Error) -> String {
+	let mut message = error.to_string();
+	let mut cause = error.source();
+	while let Some(current) = cause {
+		message.push_str(": ");
+		message.push_str(&current.to_string());
+		cause = current.source();
+	}
+	message
+}
@@ -0,0 +1,379 @@
+use super::{
+	config::{Config, TransformConfig, TransformStage},
+	sections::SectionHandle,
+};
+use crate::{
+	err,
+	error::{Error, Result},
+	map_err,
+	utils::{cached_regex, escape_xml},
+};
+use lol_html::{doc_text, element, html_content::ContentType, HtmlRewriter, Settings};
+use regex::{NoExpand, Regex};
+use std::{
+	borrow::Cow,
+	cell::{Cell, RefCell},
+	collections::BTreeMap,
+	rc::Rc,
+};
+use tracing::instrument;
+
+/// The text of these elements is left as it is.
+const SKIPPED_ELEMENTS: &str = "code, pre, kbd, samp, script, style";
+
+struct CompiledTransform<'t> {
+	replacements: Vec<(Regex, &'t str, bool)>,
+	typography: bool,
+	abbreviations: Option<(Regex, &'t BTreeMap<String, String>)>,
+}
+
+/// Applies the replacements of the markdown stage transforms of the section to the source of an
+/// entry.
+#[instrument(level = "trace", skip(markdown, config))]
+#[inline]
+pub(crate) fn transform_markdown(
+	mut markdown: String,
+	section_handle: Option<&SectionHandle>,
+	config: &Config,
+) -> Result<String> {
+	let transforms = compile(section_handle, TransformStage::Markdown, config)?;
+	for transform in &transforms {
+		markdown = replace(&markdown, transform).into_owned();
+	}
+
+	Ok(markdown)
+}
+
+/// Applies the html stage transforms of the section to the text of the rendered content of an
+/// entry.
+#[instrument(level = "trace", skip(html, config))]
+#[inline]
+pub(crate) fn transform_html(
+	html: String,
+	section_handle: Option<&SectionHandle>,
+	config: &Config,
+) -> Result<String> {
+	let transforms = compile(section_handle, TransformStage::Html, config)?;
+	if transforms.is_empty() {
+		return Ok(html);
+	}
+
+	let skip_depth = Rc::new(Cell::new(0usize));
+	// Text is collected until the end of each text node, as it can be split into chunks.
+	let text = RefCell::new(String::new());
+	// The last character of the previous text, so that quotes after inline elements curl the
+	// right way.
+	let previous = RefCell::new(None::<char>);
+
+	let mut output = vec![];
+	let mut rewriter = HtmlRewriter::new(
+		Settings {
+			element_content_handlers: vec![element!(SKIPPED_ELEMENTS, |el| {
+				skip_depth.set(skip_depth.get() + 1);
+				let skip_depth = skip_depth.clone();
+				if let Some(end_tag_handlers) = el.end_tag_handlers() {
+					end_tag_handlers.push(Box::new(move |_| {
+						skip_depth.set(skip_depth.get() - 1);
+						Ok(())
+					}));
+				}
+				Ok(())
+			})],
+			document_content_handlers: vec![doc_text!(|chunk| {
+				if skip_depth.get() > 0 {
+					if let Some(last) = chunk.as_str().chars().last() {
+						*previous.borrow_mut() = Some(last);
+					}
+					return Ok(());
+				}
+
+				text.borrow_mut().push_str(chunk.as_str());
+				if chunk.last_in_text_node() {
+					let text = std::mem::take(&mut *text.borrow_mut());
+					let mut previous = previous.borrow_mut();
+					let mut transformed = text;
+					for transform in &transforms {
+						transformed = transform_text(&transformed, transform, &mut previous);
+					}
+					chunk.replace(&transformed, ContentType::Html);
+				} else {
+					chunk.remove();
+				}
+
+				Ok(())
+			})],
+			..Settings::default()
+		},
+		|bytes: &[u8]| output.extend_from_slice(bytes),
+	);
+
+	rewriter
+		.write(html.as_bytes())
+		.and_then(|_| rewriter.end())
+		.map_err(|error| err!(Validation(format!("failed to transform content: {error}"))))?;
+
+	Ok(String::from_utf8_lossy(&output).to_string())
+}
+
+/// Transforms of `stage` which apply to entries of the section.
+#[inline]
+fn compile<'c>(
+	section_handle: Option<&SectionHandle>,
+	stage: TransformStage,
+	config: &'c Config,
+) -> Result<Vec<CompiledTransform<'c>>> {
+	config
+		.transforms
+		.iter()
+		.filter(|transform| transform.stage == stage && applies_to(transform, section_handle))
+		.map(|transform| {
+			let replacements = transform
+				.replace
+				.iter()
+				.map(|replacement| {
+					let pattern = if replacement.regex {
+						replacement.find.clone()
+					} else {
+						regex::escape(&replacement.find)
+					};
+					Ok((
+						regex(&pattern)?,
+						replacement.with.as_str(),
+						replacement.regex,
+					))
+				})
+				.collect::<Result<_>>()?;
+
+			// Only whole words are abbreviations, and longer ones take precedence.
+			let abbreviations = if transform.abbreviations.is_empty() {
+				None
+			} else {
+				let mut words = transform.abbreviations.keys().collect::<Vec<_>>();
+				words.sort_by_key(|word| std::cmp::Reverse(word.len()));
+				let pattern = format!(
+					r"\b(?:{})\b",
+					words
+						.iter()
+						.map(|word| regex::escape(word))
+						.collect::<Vec<_>>()
+						.join("|")
+				);
+				Some((regex(&pattern)?, &transform.abbreviations))
+			};
+
+			Ok(CompiledTransform {
+				replacements,
+				typography: transform.typography,
+				abbreviations,
+			})
+		})
+		.collect()
+}
+
+#[inline]
+fn applies_to(transform: &TransformConfig, section_handle: Option<&SectionHandle>) -> bool {
+	transform.sections.is_empty()
+		|| section_handle.is_some_and(|section_handle| {
+			transform
+				.sections
+				.iter()
+				.any(|section| SectionHandle::from(section.as_str()) == *section_handle)
+		})
+}
+
+#[inline]
+fn regex(pattern: &str) -> Result<Regex> {
+	map_err!(
+		cached_regex(pattern),
+		RegexError(format!("failed to parse transform pattern \"{pattern}\"")),
+	)
+}
+
+#[inline]
+fn replace<'a>(text: &'a str, transform: &CompiledTransform) -> Cow<'a, str> {
+	let mut text = Cow::Borrowed(text);
+	for (regex, with, expand) in &transform.replacements {
+		let replaced = if *expand {
+			regex.replace_all(&text, *with)
+		} else {
+			regex.replace_all(&text, NoExpand(with))
+		};
+		if let Cow::Owned(replaced) = replaced {
+			text = Cow::Owned(replaced);
+		}
+	}
+	text
+}
+
+/// Replacements, followed by typography and then abbreviations, so that the quotes of the
+/// `title` of abbreviations aren't curled.
+#[inline]
+fn transform_text(
+	text: &str,
+	transform: &CompiledTransform,
+	previous: &mut Option<char>,
+) -> String {
+	let mut text = replace(text, transform).into_owned();
+
+	if transform.typography {
+		text = typography(&text, previous);
+	} else if let Some(last) = text.chars().last() {
+		*previous = Some(last);
+	}
+
+	if let Some((regex, abbreviations)) = &transform.abbreviations {
+		text = regex
+			.replace_all(&text, |captures: &regex::Captures| {
+				let word = &captures[0];
+				match abbreviations.get(word) {
+					Some(title) => format!(r#"<abbr title="{}">{word}</abbr>"#, escape_xml(title)),
+					None => word.to_string(),
+				}
+			})
+			.into_owned();
+	}
+
+	text
+}
+
+/// Curls straight quotes, and turns `---` into an em dash, `--` into an en dash and `...` into an
+/// ellipsis.
+#[inline]
+fn typography(text: &str, previous: &mut Option<char>) -> String {
+	let text = text
+		.replace("---", "\u{2014}")
+		.replace("--", "\u{2013}")
+		.replace("...", "\u{2026}");
+
+	let mut curled = String::with_capacity(text.len());
+	for c in text.chars() {
+		// Quotes open at the start of text, and after spaces, brackets, dashes and other quotes.
+		let opens = previous.is_none_or(|previous| {
+			previous.is_whitespace()
+				|| matches!(
+					previous,
+					'(' | '[' | '{' | '\u{201C}' | '\u{2018}' | '\u{2013}' | '\u{2014}'
+				)
+		});
+
+		let c = match c {
+			'"' if opens => '\u{201C}',
+			'"' => '\u{201D}',
+			'\'' if opens => '\u{2018}',
+			'\'' => '\u{2019}',
+			c => c,
+		};
+
+		curled.push(c);
+		*previous = Some(c);
+	}
+
+	curled
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::generate::config::Replacement;
+
+	fn config(transform: TransformConfig) -> Config {
+		Config {
+			transforms: vec![transform],
+			..Config::default()
+		}
+	}
+
+	fn typography_config() -> Config {
+		config(TransformConfig {
+			typography: true,
+			..TransformConfig::default()
+		})
+	}
+
+	#[test]
+	fn typography_of_text() {
+		let mut previous = None;
+		assert_eq!(
+			typography(r#""Quotes" -- and 'dashes'---..."#, &mut previous),
+			"\u{201C}Quotes\u{201D} \u{2013} and \u{2018}dashes\u{2019}\u{2014}\u{2026}"
+		);
+		assert_eq!(previous, Some('\u{2026}'));
+
+		let mut previous = None;
+		assert_eq!(
+			typography("it's (\"a\")", &mut previous),
+			"it\u{2019}s (\u{201C}a\u{201D})"
+		);
+	}
+
+	#[test]
+	fn typography_of_html() {
+		let html = transform_html(
+			r#"<p>"<em>Curled</em>" <code>"straight"</code> <a href="/a/">'link'</a></p>"#.into(),
+			None,
+			&typography_config(),
+		)
+		.unwrap();
+		assert_eq!(
+			html,
+			"<p>\u{201C}<em>Curled</em>\u{201D} <code>\"straight\"</code> <a href=\"/a/\">\u{2018}link\u{2019}</a></p>"
+		);
+	}
+
+	#[test]
+	fn abbreviations() {
+		let config = config(TransformConfig {
+			abbreviations: BTreeMap::from([
+				(
+					"HTML".to_string(),
+					"HyperText \"Markup\" Language".to_string(),
+				),
+				("HTML5".to_string(), "HTML version 5".to_string()),
+			]),
+			..TransformConfig::default()
+		});
+		let html =
+			transform_html("<p>HTML5 and HTML, not XHTML</p>".into(), None, &config).unwrap();
+		assert_eq!(
+			html,
+			r#"<p><abbr title="HTML version 5">HTML5</abbr> and <abbr title="HyperText &quot;Markup&quot; Language">HTML</abbr>, not XHTML</p>"#
+		);
+	}
+
+	#[test]
+	fn markdown_replacements() {
+		let config = config(TransformConfig {
+			stage: TransformStage::Markdown,
+			sections: vec!["posts".into()],
+			replace: vec![
+				Replacement {
+					find: "(c)".into(),
+					with: "\u{a9}".into(),
+					regex: false,
+				},
+				Replacement {
+					find: r"TODO\((\w+)\)".into(),
+					with: "**$1**".into(),
+					regex: true,
+				},
+			],
+			..TransformConfig::default()
+		});
+		let posts = SectionHandle::from("posts");
+		assert_eq!(
+			transform_markdown("(c) TODO(me)".into(), Some(&posts), &config).unwrap(),
+			"\u{a9} **me**"
+		);
+
+		// Transforms only apply to the entries of their sections.
+		let pages = SectionHandle::from("pages");
+		assert_eq!(
+			transform_markdown("(c) TODO(me)".into(), Some(&pages), &config).unwrap(),
+			"(c) TODO(me)"
+		);
+		assert_eq!(
+			transform_html("<p>(c)</p>".into(), Some(&posts), &config).unwrap(),
+			"<p>(c)</p>"
+		);
+	}
+}
@@ -1,4 +1,5 @@
 pub mod common;
+pub mod encoding;
 pub mod error;
 pub mod generate;
 pub(crate) mod lazyfn;
@@ -11,20 +11,25 @@ pub trait Cache<K, V> {
 
 	fn insert(&self, key: K, value: V);
 
+	fn invalidate(&self, key: &K);
+
 	fn invalidate_all(&self);
 }
 
+#[cfg(feature = "serve-store")]
 #[derive(Clone, Debug)]
 pub struct InMemoryStore<K: Hash + Eq, V> {
 	store: DashMap<K, V>,
 }
 
+#[cfg(feature = "serve-store")]
 impl<K: Hash + Eq, V> Default for InMemoryStore<K, V> {
 	fn default() -> Self {
 		Self::new()
 	}
 }
 
+#[cfg(feature = "serve-store")]
 impl<K: Hash + Eq, V> InMemoryStore<K, V> {
 	pub fn new() -> Self {
 		Self {
@@ -33,6 +38,7 @@ impl<K: Hash + Eq, V> InMemoryStore<K, V> {
 	}
 }
 
+#[cfg(feature = "serve-store")]
 impl<K, V> Cache<K, V> for InMemoryStore<K, V>
 where
 	K: Hash + Eq + Clone + std::fmt::Debug,
@@ -52,6 +58,12 @@ where
 		self.store.insert(key, value);
 	}
 
+	#[instrument(skip(self))]
+	#[inline]
+	fn invalidate(&self, key: &K) {
+		self.store.remove(key);
+	}
+
 	#[instrument(skip(self))]
 	#[inline]
 	fn invalidate_all(&self) {
@@ -95,6 +107,10 @@ where
 	#[inline]
 	fn insert(&self, _key: K, _value: V) {}
 
+	#[instrument(skip(self))]
+	#[inline]
+	fn invalidate(&self, _key: &K) {}
+
 	#[instrument(skip(self))]
 	#[inline]
 	fn invalidate_all(&self) {}
@@ -1,9 +1,31 @@
-use crate::error::Result;
+use crate::{
+	error::{Error, Result},
+	lazyfn::LazyFn,
+	map_err,
+};
+use dashmap::DashMap;
+use futures::FutureExt;
 use http::HeaderValue;
-use std::{ffi::OsStr, os::unix::prelude::OsStrExt, path::Path, sync::LockResult};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::{
+	any::Any,
+	ffi::OsStr,
+	fs,
+	future::Future,
+	io,
+	os::unix::prelude::OsStrExt,
+	panic::{catch_unwind, AssertUnwindSafe},
+	path::{Path, PathBuf},
+	sync::LockResult,
+};
 use tokio::task::JoinSet;
 use tracing::instrument;
 
+/// Compiled patterns, keyed on the pattern, so that those of the config are compiled once rather
+/// than for each file they're matched against.
+static REGEXES: LazyFn<DashMap<String, Regex>> = LazyFn::new(DashMap::new);
+
 pub trait LockResultExt<T> {
 	fn acquire(self) -> T;
 }
@@ -39,6 +61,130 @@ pub async fn await_joinset(mut join_set: JoinSet<Result<()>>) -> Result<()> {
 	Ok(())
 }
 
+/// Awaits every task in the join set, collecting errors instead of returning on the first.
+#[inline]
+#[instrument(level = "trace", skip_all)]
+pub async fn await_joinset_all(mut join_set: JoinSet<Result<()>>) -> Vec<Error> {
+	let mut errors = vec![];
+	while let Some(result) = join_set.join_next().await {
+		match result {
+			Ok(Ok(_)) => {}
+			Ok(Err(error)) => errors.push(error),
+			Err(error) => errors.push(error.into()),
+		}
+	}
+
+	errors
+}
+
+/// Runs `f`, converting a panic into an [`Error::RenderPanic`] for `path`.
+#[inline]
+pub fn catch_panic<T>(path: &Path, f: impl FnOnce() -> Result<T>) -> Result<T> {
+	match catch_unwind(AssertUnwindSafe(f)) {
+		Ok(result) => result,
+		Err(panic) => Err(Error::RenderPanic {
+			message: panic_message(&*panic),
+			path: path.to_path_buf(),
+		}),
+	}
+}
+
+/// Awaits `future`, converting a panic into an [`Error::RenderPanic`] for `path`.
+#[inline]
+pub async fn catch_panic_async<T>(
+	path: PathBuf,
+	future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+	match AssertUnwindSafe(future).catch_unwind().await {
+		Ok(result) => result,
+		Err(panic) => Err(Error::RenderPanic {
+			message: panic_message(&*panic),
+			path,
+		}),
+	}
+}
+
+#[inline]
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+	if let Some(message) = panic.downcast_ref::<&str>() {
+		message.to_string()
+	} else if let Some(message) = panic.downcast_ref::<String>() {
+		message.clone()
+	} else {
+		"unknown panic".into()
+	}
+}
+
+/// `pattern`, compiled the first time it's used.
+#[inline]
+pub fn cached_regex(pattern: &str) -> std::result::Result<Regex, regex::Error> {
+	if let Some(regex) = REGEXES.get(pattern) {
+		return Ok(regex.clone());
+	}
+
+	let regex = Regex::new(pattern)?;
+	REGEXES.insert(pattern.to_string(), regex.clone());
+
+	Ok(regex)
+}
+
+/// Removes the output at `path` before it's written, so that it's written as a new file rather than
+/// rewritten in place. The files of a staged build are hard links to those of the last build, and
+/// files may be mapped while they're served, so neither sees the new content.
+#[inline]
+pub fn unlink_output(path: &Path) -> Result<()> {
+	match fs::remove_file(path) {
+		Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+		result => map_err!(
+			result,
+			IoError(format!("failed to remove {}", path.display())),
+		),
+	}
+}
+
+/// Hex encoded SHA-256 digest of `data`.
+#[inline]
+pub fn hex_digest(data: impl AsRef<[u8]>) -> String {
+	format!("{:x}", Sha256::digest(data))
+}
+
+/// Hex encoded SHA-256 digest of the content of the file at `path`, which is read a chunk at a
+/// time rather than all at once.
+#[inline]
+pub fn hex_digest_file(path: &Path) -> io::Result<String> {
+	let mut hasher = Sha256::new();
+	io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+	Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Escapes text for use in XML or HTML content and attribute values.
+#[inline]
+pub fn escape_xml(value: &str) -> String {
+	let mut escaped = String::with_capacity(value.len());
+	for c in value.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&apos;"),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+/// Lowercases `value`, replacing each run of non-alphanumeric characters with a `-`.
+#[inline]
+pub fn slugify(value: &str) -> String {
+	value
+		.split(|c: char| !c.is_alphanumeric())
+		.filter(|part| !part.is_empty())
+		.map(str::to_lowercase)
+		.collect::<Vec<_>>()
+		.join("-")
+}
+
 #[inline]
 pub fn can_compress<P: AsRef<Path> + std::fmt::Debug>(path: P) -> bool {
 	mime_guess::from_path(&path)
@@ -47,6 +193,37 @@ pub fn can_compress<P: AsRef<Path> + std::fmt::Debug>(path: P) -> bool {
 		.unwrap_or(true)
 }
 
+/// Offsets and magic bytes of formats which are already compressed.
+const COMPRESSED_SIGNATURES: [(usize, &[u8]); 17] = [
+	(0, b"\x1f\x8b"),           // gzip
+	(0, b"PK\x03\x04"),         // zip, jar, docx, etc.
+	(0, b"\x28\xb5\x2f\xfd"),   // zstd
+	(0, b"\xfd7zXZ\x00"),       // xz
+	(0, b"BZh"),                // bzip2
+	(0, b"7z\xbc\xaf\x27\x1c"), // 7z
+	(0, b"Rar!\x1a\x07"),       // rar
+	(0, b"wOFF"),               // woff
+	(0, b"wOF2"),               // woff2
+	(0, b"\x89PNG\r\n\x1a\n"),  // png
+	(0, b"\xff\xd8\xff"),       // jpeg
+	(0, b"GIF8"),               // gif
+	(8, b"WEBP"),               // webp
+	(4, b"ftyp"),               // mp4, m4a, avif, heic, etc.
+	(0, b"OggS"),               // ogg
+	(0, b"ID3"),                // mp3
+	(0, b"\x1a\x45\xdf\xa3"),   // webm, mkv
+];
+
+/// Sniffs `buf` for the signature of an already-compressed format.
+#[inline]
+pub fn is_compressed_content(buf: &[u8]) -> bool {
+	COMPRESSED_SIGNATURES.iter().any(|(offset, signature)| {
+		buf.get(*offset..offset + signature.len())
+			.map(|bytes| bytes == *signature)
+			.unwrap_or(false)
+	})
+}
+
 const RENDERABLE_MIME_TYPES: [&str; 12] = [
 	"text/plain",
 	"text/html",
@@ -64,8 +241,8 @@ const RENDERABLE_MIME_TYPES: [&str; 12] = [
 
 #[inline]
 pub fn is_renderable<P: AsRef<Path>>(path: P) -> bool {
-	if is_template_ext(&path) {
-		// Return early if the extension is `tpl`
+	if is_template_ext(&path) || is_notebook_ext(&path) {
+		// Return early if the extension is `tpl` or `ipynb`
 		return true;
 	}
 
@@ -86,6 +263,17 @@ pub fn is_template_ext<P: AsRef<Path>>(path: P) -> bool {
 	false
 }
 
+#[inline]
+pub fn is_notebook_ext<P: AsRef<Path>>(path: P) -> bool {
+	if let Some(ext) = path.as_ref().extension() {
+		if ext == OsStr::from_bytes(b"ipynb") {
+			return true;
+		}
+	}
+
+	false
+}
+
 #[inline]
 pub fn path_to_content_type<P: AsRef<Path>>(path: P) -> HeaderValue {
 	let guess = mime_guess::from_path(path);